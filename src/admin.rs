@@ -0,0 +1,428 @@
+use crate::cs::CentralSystem;
+use crate::error::CsError;
+use crate::faults::Fault;
+use crate::ocpp::{Message, MessageType};
+use crate::pending::Completion;
+use crate::stats::Stats;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+/* Longer than the call timeout of the central system, so the completion
+ * reports the timeout first */
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/* HTTP API triggering calls to connected charge points:
+ *
+ * GET  /cp                     ids of the connected charge points
+ * POST /cp/{id}/reset          {"type": "Hard" | "Soft"}
+ * POST /cp/{id}/remote-start   {"idTag": "...", "connectorId": 1}
+ * POST /cp/{id}/trigger        {"requestedMessage": "...", "connectorId": 1}
+ * POST /cp/{id}/accept         accepts a Pending station and triggers its boot
+ * POST /cp/{id}/clear-cache    clears the authorization caches of the charge
+ *                              point and of the central system
+ * POST /cp/{id}/data-transfer  {"vendorId": "...", "messageId": "...", "data": "..."}
+ * GET  /cp/{id}/connectors
+ * GET  /cp/{id}/stats          messages exchanged over the current connection
+ *                              and the connectors
+ *
+ * GET /transactions/{id}       the transaction as stored, with its summary once
+ *                              stopped
+ *
+ * GET    /quotas               transactions started and allowed per idTag
+ * DELETE /quotas, DELETE /quotas/{idTag}  resets the usage
+ *
+ * GET /ca/bundle               PEM of the issuers and the valid certificates they
+ *                              issued, ?all=true adds revoked and expired ones
+ *
+ * POST   /faults/{action}      {"status": "Rejected", "count": 1} or
+ *                              {"errorCode": "SecurityError", "errorDescription": "...",
+ *                               "errorDetails": {...}}
+ * DELETE /faults/{action}
+ * GET    /faults, DELETE /faults
+ *
+ * POSTs wait for the answer of the charge point and report it as
+ * {"connected": true, "messageId": "...", "status": "Accepted"}, plus the
+ * "data" of the answer if any, or with an "error" instead of the status. */
+pub fn serve(
+    address: &str,
+    token: Option<String>,
+    cs: Arc<Mutex<CentralSystem>>,
+    stats: Stats,
+) -> Result<(), CsError> {
+    let server = Server::http(address).map_err(|e| CsError::InvalidArgument(e.to_string()))?;
+    if token.is_none() {
+        warn!("admin API on {} is not protected by a token", address);
+    }
+
+    spawn(move || {
+        for request in server.incoming_requests() {
+            let cs = Arc::clone(&cs);
+            let stats = stats.clone();
+            let token = token.clone();
+            spawn(move || {
+                if !is_authorized(&request, token.as_deref()) {
+                    respond(request, 401, object! { error : "unauthorized" });
+                    return;
+                }
+                handle(request, cs, stats);
+            });
+        }
+    });
+    Ok(())
+}
+
+fn is_authorized(request: &Request, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(|value| value.trim() == token)
+        .unwrap_or(false)
+}
+
+/* What a route answers, whatever the transport */
+pub(crate) enum Reply {
+    Json(u16, json::JsonValue),
+    Pem(String),
+}
+
+fn respond(request: Request, code: u16, body: json::JsonValue) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body.dump())
+        .with_status_code(code)
+        .with_header(header);
+    let _ = request.respond(response);
+}
+
+fn respond_pem(request: Request, pem: String) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/x-pem-file"[..]).unwrap();
+    let _ = request.respond(Response::from_string(pem).with_header(header));
+}
+
+/* true for ?name=true or a bare ?name */
+fn query_flag(url: &str, name: &str) -> bool {
+    url.split_once('?')
+        .map(|(_, query)| {
+            query.split('&').any(|pair| match pair.split_once('=') {
+                Some((key, value)) => key == name && value == "true",
+                None => pair == name,
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn handle(mut request: Request, cs: Arc<Mutex<CentralSystem>>, stats: Stats) {
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let body = if body.trim().is_empty() {
+        json::JsonValue::new_object()
+    } else {
+        match json::parse(body.as_str()) {
+            Ok(body) => body,
+            Err(e) => return respond(request, 400, object! { error : e.to_string() }),
+        }
+    };
+
+    info!("admin {} {}", request.method(), request.url());
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    match route(&method, &url, &body, &cs, &stats) {
+        Reply::Json(code, body) => respond(request, code, body),
+        Reply::Pem(pem) => respond_pem(request, pem),
+    }
+}
+
+/* The url may carry a query */
+pub(crate) fn route(
+    method: &Method,
+    url: &str,
+    body: &json::JsonValue,
+    cs: &Arc<Mutex<CentralSystem>>,
+    stats: &Stats,
+) -> Reply {
+    let segments: Vec<String> = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+
+    let (cp_id, action) = match segments.as_slice() {
+        [cp] if cp == "cp" && *method == Method::Get => {
+            let connected = cs.lock().unwrap().connected_charge_points();
+            return Reply::Json(200, object! { chargePoints : connected });
+        }
+        [cp, cp_id, action] if cp == "cp" => (cp_id.clone(), action.clone()),
+        [ca, bundle] if ca == "ca" && bundle == "bundle" && *method == Method::Get => {
+            let all = query_flag(url, "all");
+            let bundle = cs.lock().unwrap().export_bundle(all);
+            return match bundle {
+                Ok(bundle) => Reply::Pem(bundle),
+                Err(e) => Reply::Json(500, object! { error : e.to_string() }),
+            };
+        }
+        [transactions, id] if transactions == "transactions" && *method == Method::Get => {
+            let (code, body) = transaction(&cs.lock().unwrap(), id);
+            return Reply::Json(code, body);
+        }
+        [quotas, ..] if quotas == "quotas" => {
+            let (code, body) = handle_quotas(method, &segments[1..], cs);
+            return Reply::Json(code, body);
+        }
+        [faults, ..] if faults == "faults" => {
+            let (code, body) = handle_faults(method, &segments[1..], body, cs);
+            return Reply::Json(code, body);
+        }
+        _ => return Reply::Json(404, object! { error : "not found" }),
+    };
+
+    let (code, body) = match (method, action.as_str()) {
+        (Method::Get, "connectors") => connectors(&cs.lock().unwrap(), &cp_id),
+        (Method::Get, "stats") => match stats.get(&cp_id) {
+            Some(stats) => {
+                let mut body = json::JsonValue::from(&stats);
+                let (_, station) = connectors(&cs.lock().unwrap(), &cp_id);
+                body["connectors"] = station["connectors"].clone();
+                (200, body)
+            }
+            None => (404, object! { connected : false, error : "not connected" }),
+        },
+        (Method::Post, "reset") => {
+            let hard = body["type"].as_str() == Some("Hard");
+            call(|completion| cs.lock().unwrap().reset(&cp_id, hard, completion))
+        }
+        (Method::Post, "remote-start") => {
+            let id_tag = body["idTag"].as_str().unwrap_or_default().to_string();
+            let connector_id = body["connectorId"].as_u32();
+            call(|completion| {
+                cs.lock().unwrap().remote_start_transaction(
+                    &cp_id,
+                    &id_tag,
+                    connector_id,
+                    completion,
+                )
+            })
+        }
+        (Method::Post, "accept") => {
+            call(|completion| cs.lock().unwrap().accept(&cp_id, completion))
+        }
+        (Method::Post, "clear-cache") => {
+            call(|completion| cs.lock().unwrap().clear_cache(&cp_id, completion))
+        }
+        (Method::Post, "data-transfer") => {
+            let vendor_id = body["vendorId"].as_str().unwrap_or_default().to_string();
+            let message_id = body["messageId"].as_str().map(String::from);
+            let data = body["data"].as_str().map(String::from);
+            call(|completion| {
+                cs.lock().unwrap().data_transfer(
+                    &cp_id,
+                    &vendor_id,
+                    message_id.as_deref(),
+                    data.as_deref(),
+                    completion,
+                )
+            })
+        }
+        (Method::Post, "trigger") => {
+            let requested = body["requestedMessage"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            let connector_id = body["connectorId"].as_u32();
+            call(|completion| {
+                cs.lock()
+                    .unwrap()
+                    .trigger_message(&cp_id, &requested, connector_id, completion)
+            })
+        }
+        _ => (404, object! { error : "not found" }),
+    };
+    Reply::Json(code, body)
+}
+
+/* GET and DELETE /faults, POST and DELETE /faults/{action} */
+fn handle_faults(
+    method: &Method,
+    path: &[String],
+    body: &json::JsonValue,
+    cs: &Arc<Mutex<CentralSystem>>,
+) -> (u16, json::JsonValue) {
+    let mut cs = cs.lock().unwrap();
+    let faults = cs.faults();
+    match (method, path) {
+        (Method::Get, []) => (200, faults.faults()),
+        (Method::Delete, []) => {
+            faults.clear_all();
+            (200, faults.faults())
+        }
+        (Method::Post, [action]) => match Fault::parse(body) {
+            Some(fault) => {
+                faults.inject(action, fault, body["count"].as_u32());
+                (200, faults.faults())
+            }
+            None => (
+                400,
+                object! { error : "either status or a known errorCode is required" },
+            ),
+        },
+        (Method::Delete, [action]) => {
+            faults.clear(action);
+            (200, faults.faults())
+        }
+        _ => (404, object! { error : "not found" }),
+    }
+}
+
+/* GET and DELETE /quotas, DELETE /quotas/{idTag} */
+fn handle_quotas(
+    method: &Method,
+    path: &[String],
+    cs: &Arc<Mutex<CentralSystem>>,
+) -> (u16, json::JsonValue) {
+    let mut cs = cs.lock().unwrap();
+    let quotas = cs.quotas();
+    match (method, path) {
+        (Method::Get, []) => (200, quotas.usage()),
+        (Method::Delete, []) => {
+            quotas.reset(None);
+            (200, quotas.usage())
+        }
+        (Method::Delete, [id_tag]) => {
+            quotas.reset(Some(id_tag));
+            (200, quotas.usage())
+        }
+        _ => (404, object! { error : "not found" }),
+    }
+}
+
+fn transaction(cs: &CentralSystem, id: &str) -> (u16, json::JsonValue) {
+    let transaction = match id.parse().ok().and_then(|id| cs.transaction(id)) {
+        Some(transaction) => transaction,
+        None => return (404, object! { error : "unknown transaction" }),
+    };
+    let mut body = json::JsonValue::from(&transaction);
+    if let Some(summary) = cs.transaction_summary(transaction.id) {
+        body["energyWh"] = summary.energy_wh.into();
+        body["samples"] = summary.samples.into();
+    }
+    (200, body)
+}
+
+fn connectors(cs: &CentralSystem, cp_id: &str) -> (u16, json::JsonValue) {
+    let cp = match cs.charge_point(cp_id) {
+        Some(cp) => cp,
+        None => {
+            return (
+                404,
+                object! { connected : false, error : "unknown charge point" },
+            )
+        }
+    };
+
+    let mut ids: Vec<&u32> = cp.connectors.keys().collect();
+    ids.sort();
+    let mut connectors = json::JsonValue::new_array();
+    for id in ids {
+        let connector = &cp.connectors[id];
+        let error_code = cp
+            .errors
+            .get(id)
+            .and_then(|e| e.current.as_ref())
+            .map(|e| e.error_code.clone());
+        let _ = connectors.push(object! {
+            connectorId : *id,
+            status : connector.status.clone(),
+            availability : connector.availability.as_str(),
+            errorCode : error_code,
+            transactionId : cp.active_transactions.get(id).copied(),
+        });
+    }
+    let mut evse_ids: Vec<&(u32, u32)> = cp.evse_connectors.keys().collect();
+    evse_ids.sort();
+    for id in evse_ids {
+        let connector = &cp.evse_connectors[id];
+        let _ = connectors.push(object! {
+            evseId : id.0,
+            connectorId : id.1,
+            status : connector.status.clone(),
+            availability : connector.availability.as_str(),
+        });
+    }
+    (
+        200,
+        object! {
+            connected : cs.is_connected(cp_id),
+            status : cp.status.clone(),
+            availability : cp.availability.as_str(),
+            connectors : connectors,
+        },
+    )
+}
+
+/* Sends a call and waits for the charge point to answer it */
+fn call<F>(send: F) -> (u16, json::JsonValue)
+where
+    F: FnOnce(Option<Completion>) -> Result<String, CsError>,
+{
+    let (tx, rx) = channel();
+    let completion: Completion = Box::new(move |result: Result<&Message, CsError>| {
+        let _ = tx.send(result.cloned());
+    });
+
+    let id = match send(Some(completion)) {
+        Ok(id) => id,
+        Err(e @ CsError::UnknownChargePoint(_)) | Err(e @ CsError::Disconnected(_)) => {
+            return (404, object! { connected : false, error : e.to_string() })
+        }
+        Err(e @ CsError::QueueFull(_)) => {
+            return (503, object! { connected : true, error : e.to_string() })
+        }
+        Err(e) => return (400, object! { connected : true, error : e.to_string() }),
+    };
+
+    match rx.recv_timeout(ANSWER_TIMEOUT) {
+        Ok(Ok(answer)) if answer.role == MessageType::CallError => {
+            let (code, description) = answer
+                .error()
+                .map(|error| (error.code, error.description))
+                .unwrap_or_default();
+            (
+                502,
+                object! {
+                    connected : true,
+                    messageId : id,
+                    error : code,
+                    description : description,
+                },
+            )
+        }
+        Ok(Ok(answer)) => {
+            let payload = answer.payload.unwrap_or(json::JsonValue::Null);
+            let mut body =
+                object! { connected : true, messageId : id, status : payload["status"].clone() };
+            if !payload["data"].is_null() {
+                body["data"] = payload["data"].clone();
+            }
+            (200, body)
+        }
+        Ok(Err(e)) => (
+            504,
+            object! { connected : true, messageId : id, error : e.to_string() },
+        ),
+        Err(_) => (
+            504,
+            object! { connected : true, messageId : id, error : CsError::Timeout.to_string() },
+        ),
+    }
+}
@@ -0,0 +1,255 @@
+use crate::ocpp::Status;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdateType {
+    Full,
+    Differential,
+}
+
+impl UpdateType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateType::Full => "Full",
+            UpdateType::Differential => "Differential",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct IdTagInfo {
+    pub status: Status,
+    pub expiry_date: Option<String>,
+    pub parent_id_tag: Option<String>,
+}
+
+impl From<&IdTagInfo> for json::JsonValue {
+    fn from(info: &IdTagInfo) -> Self {
+        let mut value = object! { status : info.status };
+        if let Some(expiry_date) = info.expiry_date.as_ref() {
+            value["expiryDate"] = expiry_date.as_str().into();
+        }
+        if let Some(parent_id_tag) = info.parent_id_tag.as_ref() {
+            value["parentIdTag"] = parent_id_tag.as_str().into();
+        }
+        value
+    }
+}
+
+/* An entry without idTagInfo removes the idTag in a differential update */
+#[derive(Clone, PartialEq, Debug)]
+pub struct LocalAuthorizationEntry {
+    pub id_tag: String,
+    pub id_tag_info: Option<IdTagInfo>,
+}
+
+impl From<&LocalAuthorizationEntry> for json::JsonValue {
+    fn from(entry: &LocalAuthorizationEntry) -> Self {
+        let mut value = object! { idTag : entry.id_tag.as_str() };
+        if let Some(info) = entry.id_tag_info.as_ref() {
+            value["idTagInfo"] = info.into();
+        }
+        value
+    }
+}
+
+/* Far enough for clients to never see an expired idTag by default */
+const DEFAULT_TTL_DAYS: i64 = 3650;
+
+pub struct AuthorizationList {
+    entries: HashMap<String, Status>,
+    default: Status,
+    ttls: HashMap<String, Duration>,
+    default_ttl: Duration,
+    /* Group idTags, cards with the same parent may stop each other's
+     * transactions */
+    parents: HashMap<String, String>,
+}
+
+impl AuthorizationList {
+    pub fn new(default: Status) -> AuthorizationList {
+        AuthorizationList {
+            entries: HashMap::new(),
+            default,
+            ttls: HashMap::new(),
+            default_ttl: Duration::days(DEFAULT_TTL_DAYS),
+            parents: HashMap::new(),
+        }
+    }
+
+    pub fn set_ttl(&mut self, id_tag: &str, ttl: Duration) {
+        self.ttls.insert(id_tag.to_string(), ttl);
+    }
+
+    pub fn set_default_ttl(&mut self, ttl: Duration) {
+        self.default_ttl = ttl;
+    }
+
+    /* When the authorization of the idTag given at `now` expires */
+    pub fn expiry(&self, id_tag: Option<&str>, now: DateTime<Utc>) -> DateTime<Utc> {
+        let ttl = id_tag
+            .and_then(|id_tag| self.ttls.get(id_tag))
+            .unwrap_or(&self.default_ttl);
+        now + *ttl
+    }
+
+    pub fn set(&mut self, id_tag: &str, status: Status) {
+        self.entries.insert(id_tag.to_string(), status);
+    }
+
+    pub fn remove(&mut self, id_tag: &str) {
+        self.entries.remove(id_tag);
+        self.ttls.remove(id_tag);
+        self.parents.remove(id_tag);
+    }
+
+    pub fn status(&self, id_tag: &str) -> Status {
+        *self.entries.get(id_tag).unwrap_or(&self.default)
+    }
+
+    pub fn set_parent(&mut self, id_tag: &str, parent_id_tag: &str) {
+        self.parents
+            .insert(id_tag.to_string(), parent_id_tag.to_string());
+    }
+
+    pub fn parent(&self, id_tag: &str) -> Option<&str> {
+        self.parents.get(id_tag).map(String::as_str)
+    }
+
+    pub fn local_list(&self) -> Vec<LocalAuthorizationEntry> {
+        self.entries
+            .iter()
+            .map(|(id_tag, status)| LocalAuthorizationEntry {
+                id_tag: id_tag.clone(),
+                id_tag_info: Some(IdTagInfo {
+                    status: *status,
+                    expiry_date: None,
+                    parent_id_tag: self.parents.get(id_tag).cloned(),
+                }),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CachedAuthorization {
+    pub status: Status,
+    pub expiry: DateTime<Utc>,
+}
+
+/* Decisions of earlier lookups by idTag, served until they expire like the
+ * authorization cache of a charge point */
+#[derive(Default)]
+pub struct AuthorizationCache {
+    entries: HashMap<String, CachedAuthorization>,
+}
+
+impl AuthorizationCache {
+    pub fn get(&self, id_tag: &str, now: DateTime<Utc>) -> Option<CachedAuthorization> {
+        self.entries
+            .get(id_tag)
+            .filter(|cached| cached.expiry > now)
+            .copied()
+    }
+
+    pub fn insert(&mut self, id_tag: &str, status: Status, expiry: DateTime<Utc>) {
+        self.entries
+            .insert(id_tag.to_string(), CachedAuthorization { status, expiry });
+    }
+
+    /* Returns the number of dropped entries */
+    pub fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+
+    pub fn entries(&self) -> &HashMap<String, CachedAuthorization> {
+        &self.entries
+    }
+}
+
+/* Transactions an idTag may start, like a prepaid card. Usage counts until
+ * it is reset, or for a window of time when one is set. */
+#[derive(Clone, Default)]
+pub struct TransactionQuotas {
+    limits: HashMap<String, u32>,
+    used: HashMap<String, u32>,
+    window: Option<Duration>,
+    window_start: Option<DateTime<Utc>>,
+}
+
+impl TransactionQuotas {
+    pub fn set_limit(&mut self, id_tag: &str, limit: u32) {
+        self.limits.insert(id_tag.to_string(), limit);
+    }
+
+    pub fn remove_limit(&mut self, id_tag: &str) {
+        self.limits.remove(id_tag);
+        self.used.remove(id_tag);
+    }
+
+    /* Usage starts over every window, None keeps it until reset */
+    pub fn set_window(&mut self, window: Option<Duration>) {
+        self.window = window;
+        self.window_start = None;
+    }
+
+    pub fn is_exhausted(&mut self, id_tag: &str, now: DateTime<Utc>) -> bool {
+        self.roll_window(now);
+        match self.limits.get(id_tag) {
+            Some(limit) => self.used.get(id_tag).copied().unwrap_or(0) >= *limit,
+            None => false,
+        }
+    }
+
+    /* Counts a transaction started by the idTag */
+    pub fn record(&mut self, id_tag: &str, now: DateTime<Utc>) {
+        self.roll_window(now);
+        if self.limits.contains_key(id_tag) {
+            *self.used.entry(id_tag.to_string()).or_default() += 1;
+        }
+    }
+
+    /* None resets every idTag */
+    pub fn reset(&mut self, id_tag: Option<&str>) {
+        match id_tag {
+            Some(id_tag) => {
+                self.used.remove(id_tag);
+            }
+            None => self.used.clear(),
+        }
+    }
+
+    pub fn usage(&self) -> json::JsonValue {
+        let mut usage = json::JsonValue::new_object();
+        for (id_tag, limit) in self.limits.iter() {
+            usage[id_tag.as_str()] = object! {
+                limit : *limit,
+                used : self.used.get(id_tag).copied().unwrap_or(0),
+            };
+        }
+        usage
+    }
+
+    fn roll_window(&mut self, now: DateTime<Utc>) {
+        let window = match self.window {
+            Some(window) => window,
+            None => return,
+        };
+        match self.window_start {
+            Some(start) if now - start < window => {}
+            _ => {
+                self.used.clear();
+                self.window_start = Some(now);
+            }
+        }
+    }
+}
+
+impl Default for AuthorizationList {
+    fn default() -> Self {
+        AuthorizationList::new(Status::Accepted)
+    }
+}
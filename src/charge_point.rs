@@ -0,0 +1,490 @@
+use crate::charging::{ChargingProfile, ClearChargingProfile, CompositeSchedule};
+use crate::metering::Readings;
+use crate::ocpp::{format_timestamp, ProtocolVersion, RegistrationStatus, DEFAULT_SECONDS_FORMAT};
+use crate::trust_store::TrustStore;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, VecDeque};
+use tracing::{info, warn};
+
+/* How calls reusing a recent message id of the same connection are handled */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum DuplicateIds {
+    Off,
+    #[default]
+    Log,
+    /* Answered with a ProtocolError CallError */
+    Reject,
+}
+
+impl DuplicateIds {
+    pub fn parse(value: &str) -> Option<DuplicateIds> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" => Some(DuplicateIds::Off),
+            "log" => Some(DuplicateIds::Log),
+            "reject" => Some(DuplicateIds::Reject),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigurationKey {
+    pub key: String,
+    pub readonly: bool,
+    pub value: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Configuration {
+    pub keys: HashMap<String, ConfigurationKey>,
+    pub unknown_keys: Vec<String>,
+    pub reboot_required: bool,
+}
+
+impl Configuration {
+    /* Merges a GetConfiguration.conf payload */
+    pub fn update(&mut self, payload: &json::JsonValue) {
+        for entry in payload["configurationKey"].members() {
+            if let Some(key) = entry["key"].as_str() {
+                let parsed = ConfigurationKey {
+                    key: key.to_string(),
+                    readonly: entry["readonly"].as_bool().unwrap_or(false),
+                    value: entry["value"].as_str().map(String::from),
+                };
+                self.keys.insert(key.to_string(), parsed);
+            }
+        }
+
+        self.unknown_keys = payload["unknownKey"]
+            .members()
+            .filter_map(|k| k.as_str())
+            .map(String::from)
+            .collect();
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let entry = self
+            .keys
+            .entry(key.to_string())
+            .or_insert_with(|| ConfigurationKey {
+                key: key.to_string(),
+                readonly: false,
+                value: None,
+            });
+        entry.value = Some(value.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.keys.get(key).and_then(|k| k.value.as_deref())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Availability {
+    #[default]
+    Operative,
+    Inoperative,
+}
+
+impl Availability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Availability::Operative => "Operative",
+            Availability::Inoperative => "Inoperative",
+        }
+    }
+}
+
+/* connectorId 0 addresses the whole charge point: its StatusNotification
+ * reports the station status and ChangeAvailability applies to every
+ * connector. Commands acting on one physical connector, like
+ * UnlockConnector, can't target it. */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConnectorTarget {
+    Station,
+    Connector(u32),
+}
+
+impl ConnectorTarget {
+    pub fn from_id(connector_id: u32) -> ConnectorTarget {
+        match connector_id {
+            0 => ConnectorTarget::Station,
+            id => ConnectorTarget::Connector(id),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            ConnectorTarget::Station => 0,
+            ConnectorTarget::Connector(id) => *id,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Connector {
+    pub availability: Availability,
+    pub status: Option<String>,
+}
+
+pub const NO_ERROR: &str = "NoError";
+/* errorCode transitions kept per connector */
+const ERROR_HISTORY_LEN: usize = 16;
+
+/* The errorCode part of a StatusNotification */
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConnectorError {
+    pub error_code: String,
+    pub info: Option<String>,
+    pub vendor_error_code: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl ConnectorError {
+    pub fn parse(value: &json::JsonValue) -> Option<ConnectorError> {
+        Some(ConnectorError {
+            error_code: value["errorCode"].as_str()?.to_string(),
+            info: value["info"].as_str().map(String::from),
+            vendor_error_code: value["vendorErrorCode"].as_str().map(String::from),
+            timestamp: value["timestamp"]
+                .as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc)),
+        })
+    }
+
+    pub fn is_fault(&self) -> bool {
+        self.error_code != NO_ERROR
+    }
+}
+
+#[derive(Default)]
+pub struct ConnectorErrors {
+    pub current: Option<ConnectorError>,
+    /* Onsets, changes and clearances, oldest first */
+    pub history: VecDeque<ConnectorError>,
+}
+
+impl ConnectorErrors {
+    pub fn is_faulted(&self) -> bool {
+        self.current.as_ref().is_some_and(|e| e.is_fault())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reservation {
+    pub reservation_id: i32,
+    pub connector_id: u32,
+    pub expiry_date: DateTime<Utc>,
+    pub id_tag: String,
+    pub parent_id_tag: Option<String>,
+}
+
+impl Reservation {
+    pub fn parse(value: &json::JsonValue) -> Option<Reservation> {
+        let expiry_date = DateTime::parse_from_rfc3339(value["expiryDate"].as_str()?).ok()?;
+        Some(Reservation {
+            reservation_id: value["reservationId"].as_i32()?,
+            connector_id: value["connectorId"].as_u32()?,
+            expiry_date: expiry_date.with_timezone(&Utc),
+            id_tag: value["idTag"].as_str()?.to_string(),
+            parent_id_tag: value["parentIdTag"].as_str().map(String::from),
+        })
+    }
+}
+
+impl From<&Reservation> for json::JsonValue {
+    fn from(reservation: &Reservation) -> Self {
+        let mut value = object! {
+            connectorId : reservation.connector_id,
+            expiryDate : format_timestamp(&reservation.expiry_date, DEFAULT_SECONDS_FORMAT),
+            idTag : reservation.id_tag.as_str(),
+            reservationId : reservation.reservation_id,
+        };
+        if let Some(parent_id_tag) = reservation.parent_id_tag.as_ref() {
+            value["parentIdTag"] = parent_id_tag.as_str().into();
+        }
+        value
+    }
+}
+
+/* How a BootNotification relates to the previous one of the charge point */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Boot {
+    First,
+    /* Same vendor, model, serial number and firmware, e.g. after a reconnect */
+    Repeated,
+    /* Describes what changed */
+    Changed(String),
+}
+
+#[derive(Default)]
+pub struct ChargePoint {
+    pub protocol: ProtocolVersion,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub firmware_version: Option<String>,
+    pub last_seen: Option<DateTime<Utc>>,
+    /* Answer to the last BootNotification, None before the first one */
+    pub registration: Option<RegistrationStatus>,
+    /* Sent with the registration, None keeps the default of the central
+     * system */
+    pub heartbeat_interval: Option<u32>,
+    pub configuration: Configuration,
+    pub availability: Availability,
+    /* Reported with connectorId 0 */
+    pub status: Option<String>,
+    pub connectors: HashMap<u32, Connector>,
+    /* Connectors of OCPP 2.0.1 stations by evseId and connectorId */
+    pub evse_connectors: HashMap<(u32, u32), Connector>,
+    /* Reported errorCodes per connector, 0 is the charge point itself */
+    pub errors: HashMap<u32, ConnectorErrors>,
+    pub charging_profiles: HashMap<u32, Vec<ChargingProfile>>,
+    pub composite_schedules: HashMap<u32, CompositeSchedule>,
+    pub reservations: HashMap<i32, Reservation>,
+    /* Transactions in progress per connector, at most one each */
+    pub active_transactions: HashMap<u32, u32>,
+    pub local_list_version: Option<i32>,
+    pub firmware_status: Option<String>,
+    pub diagnostics_status: Option<String>,
+    pub diagnostics_file: Option<String>,
+    /* Where the diagnostics uploaded last were stored */
+    pub diagnostics_upload: Option<String>,
+    pub trust_store: TrustStore,
+    /* The latest MeterValues samples per connector */
+    pub meter_readings: HashMap<u32, Readings>,
+    /* Ids of the calls received over the current connection */
+    pub recent_call_ids: HashMap<String, DateTime<Utc>>,
+}
+
+impl ChargePoint {
+    pub fn touch(&mut self, now: DateTime<Utc>) {
+        self.last_seen = Some(now);
+    }
+
+    /* Remembers the id of a call. Returns false when the id was already
+     * used within the window. */
+    pub fn register_call_id(&mut self, id: &str, now: DateTime<Utc>, window: Duration) -> bool {
+        self.recent_call_ids.retain(|_, seen| now - *seen <= window);
+        self.recent_call_ids.insert(id.to_string(), now).is_none()
+    }
+
+    /* Pending and Rejected stations may only boot. Stations that booted
+     * before the central system was restarted are let through. */
+    pub fn is_registered(&self) -> bool {
+        !matches!(
+            self.registration,
+            Some(RegistrationStatus::Pending) | Some(RegistrationStatus::Rejected)
+        )
+    }
+
+    /* a station is considered offline after missing two heartbeats */
+    pub fn is_online(&self, heartbeat_interval: u32, now: DateTime<Utc>) -> bool {
+        match self.last_seen {
+            Some(last_seen) => now - last_seen <= Duration::seconds(2 * heartbeat_interval as i64),
+            None => false,
+        }
+    }
+
+    /* connector 0 addresses the whole charge point */
+    pub fn set_availability(&mut self, target: ConnectorTarget, availability: Availability) {
+        match target {
+            ConnectorTarget::Station => {
+                self.availability = availability;
+                for connector in self.connectors.values_mut() {
+                    connector.availability = availability;
+                }
+                for connector in self.evse_connectors.values_mut() {
+                    connector.availability = availability;
+                }
+            }
+            ConnectorTarget::Connector(connector_id) => {
+                self.connectors
+                    .entry(connector_id)
+                    .or_default()
+                    .availability = availability;
+            }
+        }
+    }
+
+    pub fn update_status(&mut self, target: ConnectorTarget, status: &str) {
+        let connector_id = match target {
+            ConnectorTarget::Station => {
+                if self.availability == Availability::Inoperative && status != "Unavailable" {
+                    warn!("inoperative charge point reported {}", status);
+                }
+                self.status = Some(status.to_string());
+                return;
+            }
+            ConnectorTarget::Connector(connector_id) => connector_id,
+        };
+
+        let availability = self.availability;
+        let connector = self
+            .connectors
+            .entry(connector_id)
+            .or_insert_with(|| Connector {
+                availability,
+                status: None,
+            });
+        if connector.availability == Availability::Inoperative && status != "Unavailable" {
+            warn!("inoperative connector {} reported {}", connector_id, status);
+        }
+        connector.status = Some(status.to_string());
+    }
+
+    pub fn update_evse_status(&mut self, evse_id: u32, connector_id: u32, status: &str) {
+        let availability = self.availability;
+        let connector = self
+            .evse_connectors
+            .entry((evse_id, connector_id))
+            .or_insert_with(|| Connector {
+                availability,
+                status: None,
+            });
+        if connector.availability == Availability::Inoperative && status != "Unavailable" {
+            warn!(
+                "inoperative connector {} of evse {} reported {}",
+                connector_id, evse_id, status
+            );
+        }
+        connector.status = Some(status.to_string());
+    }
+
+    /* A connector reported Reserved becomes Available again once none of
+     * its reservations is left */
+    pub fn release_reservation(&mut self, reservation_id: i32) -> Option<Reservation> {
+        let reservation = self.reservations.remove(&reservation_id)?;
+        let connector_id = reservation.connector_id;
+        let reserved = self
+            .reservations
+            .values()
+            .any(|r| r.connector_id == connector_id);
+        let status = match ConnectorTarget::from_id(connector_id) {
+            ConnectorTarget::Station => self.status.as_mut(),
+            ConnectorTarget::Connector(id) => {
+                self.connectors.get_mut(&id).and_then(|c| c.status.as_mut())
+            }
+        };
+        if let Some(status) = status {
+            if !reserved && status == "Reserved" {
+                *status = "Available".to_string();
+            }
+        }
+        Some(reservation)
+    }
+
+    /* Releases the reservations whose expiryDate has passed */
+    pub fn expire_reservations(&mut self, now: DateTime<Utc>) -> Vec<Reservation> {
+        let expired: Vec<i32> = self
+            .reservations
+            .values()
+            .filter(|r| r.expiry_date <= now)
+            .map(|r| r.reservation_id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.release_reservation(id))
+            .collect()
+    }
+
+    pub fn update_error(&mut self, connector_id: u32, error: ConnectorError) {
+        let errors = self.errors.entry(connector_id).or_default();
+        let previous = errors
+            .current
+            .as_ref()
+            .map(|e| e.error_code.as_str())
+            .unwrap_or(NO_ERROR);
+        if previous != error.error_code {
+            if error.is_fault() {
+                warn!(
+                    "connector {} fault {} (info: {:?}, vendor: {:?})",
+                    connector_id, error.error_code, error.info, error.vendor_error_code
+                );
+            } else {
+                info!("connector {} fault {} cleared", connector_id, previous);
+            }
+            if errors.history.len() == ERROR_HISTORY_LEN {
+                errors.history.pop_front();
+            }
+            errors.history.push_back(error.clone());
+        }
+        errors.current = Some(error);
+    }
+
+    /* A profile replaces the one with the same id, or with the same purpose
+     * and stack level on the connector */
+    pub fn apply_charging_profile(&mut self, connector_id: u32, profile: ChargingProfile) {
+        for profiles in self.charging_profiles.values_mut() {
+            profiles.retain(|p| p.charging_profile_id != profile.charging_profile_id);
+        }
+
+        let profiles = self.charging_profiles.entry(connector_id).or_default();
+        profiles.retain(|p| {
+            p.charging_profile_purpose != profile.charging_profile_purpose
+                || p.stack_level != profile.stack_level
+        });
+        profiles.push(profile);
+    }
+
+    pub fn clear_charging_profiles(&mut self, criteria: &ClearChargingProfile) -> usize {
+        let mut removed = 0;
+        for (connector_id, profiles) in self.charging_profiles.iter_mut() {
+            let before = profiles.len();
+            profiles.retain(|p| !criteria.matches(*connector_id, p));
+            removed += before - profiles.len();
+        }
+        removed
+    }
+
+    /* Other hardware or firmware starts over with the state of a new charge
+     * point, only the connection is kept */
+    pub fn update_boot_info(
+        &mut self,
+        vendor: &str,
+        model: &str,
+        serial_number: Option<&str>,
+        firmware_version: Option<&str>,
+    ) -> Boot {
+        let previous = [
+            ("vendor", self.vendor.as_deref()),
+            ("model", self.model.as_deref()),
+            ("serial number", self.serial_number.as_deref()),
+            ("firmware", self.firmware_version.as_deref()),
+        ];
+        let current = [Some(vendor), Some(model), serial_number, firmware_version];
+        let changes: Vec<String> = previous
+            .iter()
+            .zip(current.iter())
+            .filter(|((_, before), now)| before != *now)
+            .map(|((name, before), now)| {
+                format!(
+                    "{} {} -> {}",
+                    name,
+                    before.unwrap_or("none"),
+                    now.unwrap_or("none")
+                )
+            })
+            .collect();
+
+        let boot = if self.vendor.is_none() {
+            Boot::First
+        } else if changes.is_empty() {
+            return Boot::Repeated;
+        } else {
+            *self = ChargePoint {
+                protocol: self.protocol,
+                last_seen: self.last_seen,
+                recent_call_ids: std::mem::take(&mut self.recent_call_ids),
+                ..ChargePoint::default()
+            };
+            Boot::Changed(changes.join(", "))
+        };
+        self.vendor = Some(vendor.to_string());
+        self.model = Some(model.to_string());
+        self.serial_number = serial_number.map(String::from);
+        self.firmware_version = firmware_version.map(String::from);
+        boot
+    }
+}
@@ -0,0 +1,405 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChargingProfilePurpose {
+    ChargePointMaxProfile,
+    TxDefaultProfile,
+    TxProfile,
+}
+
+impl ChargingProfilePurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChargingProfilePurpose::ChargePointMaxProfile => "ChargePointMaxProfile",
+            ChargingProfilePurpose::TxDefaultProfile => "TxDefaultProfile",
+            ChargingProfilePurpose::TxProfile => "TxProfile",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ChargingProfilePurpose> {
+        match value {
+            "ChargePointMaxProfile" => Some(ChargingProfilePurpose::ChargePointMaxProfile),
+            "TxDefaultProfile" => Some(ChargingProfilePurpose::TxDefaultProfile),
+            "TxProfile" => Some(ChargingProfilePurpose::TxProfile),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChargingProfileKind {
+    Absolute,
+    Recurring,
+    Relative,
+}
+
+impl ChargingProfileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChargingProfileKind::Absolute => "Absolute",
+            ChargingProfileKind::Recurring => "Recurring",
+            ChargingProfileKind::Relative => "Relative",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ChargingProfileKind> {
+        match value {
+            "Absolute" => Some(ChargingProfileKind::Absolute),
+            "Recurring" => Some(ChargingProfileKind::Recurring),
+            "Relative" => Some(ChargingProfileKind::Relative),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecurrencyKind {
+    Daily,
+    Weekly,
+}
+
+impl RecurrencyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecurrencyKind::Daily => "Daily",
+            RecurrencyKind::Weekly => "Weekly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<RecurrencyKind> {
+        match value {
+            "Daily" => Some(RecurrencyKind::Daily),
+            "Weekly" => Some(RecurrencyKind::Weekly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChargingRateUnit {
+    W,
+    A,
+}
+
+impl ChargingRateUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChargingRateUnit::W => "W",
+            ChargingRateUnit::A => "A",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ChargingRateUnit> {
+        match value {
+            "W" => Some(ChargingRateUnit::W),
+            "A" => Some(ChargingRateUnit::A),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChargingSchedulePeriod {
+    pub start_period: u32,
+    pub limit: f64,
+    pub number_phases: Option<u32>,
+}
+
+impl ChargingSchedulePeriod {
+    pub fn parse(value: &json::JsonValue) -> Option<ChargingSchedulePeriod> {
+        Some(ChargingSchedulePeriod {
+            start_period: value["startPeriod"].as_u32()?,
+            limit: value["limit"].as_f64()?,
+            number_phases: value["numberPhases"].as_u32(),
+        })
+    }
+}
+
+impl From<&ChargingSchedulePeriod> for json::JsonValue {
+    fn from(period: &ChargingSchedulePeriod) -> Self {
+        let mut value = object! {
+            startPeriod : period.start_period,
+            limit : period.limit,
+        };
+        if let Some(number_phases) = period.number_phases {
+            value["numberPhases"] = number_phases.into();
+        }
+        value
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChargingSchedule {
+    pub duration: Option<u32>,
+    pub start_schedule: Option<String>,
+    pub charging_rate_unit: ChargingRateUnit,
+    pub charging_schedule_period: Vec<ChargingSchedulePeriod>,
+    pub min_charging_rate: Option<f64>,
+}
+
+impl ChargingSchedule {
+    pub fn parse(value: &json::JsonValue) -> Option<ChargingSchedule> {
+        let mut periods = Vec::new();
+        for period in value["chargingSchedulePeriod"].members() {
+            periods.push(ChargingSchedulePeriod::parse(period)?);
+        }
+
+        Some(ChargingSchedule {
+            duration: value["duration"].as_u32(),
+            start_schedule: value["startSchedule"].as_str().map(String::from),
+            charging_rate_unit: ChargingRateUnit::parse(value["chargingRateUnit"].as_str()?)?,
+            charging_schedule_period: periods,
+            min_charging_rate: value["minChargingRate"].as_f64(),
+        })
+    }
+}
+
+impl From<&ChargingSchedule> for json::JsonValue {
+    fn from(schedule: &ChargingSchedule) -> Self {
+        let periods: Vec<json::JsonValue> = schedule
+            .charging_schedule_period
+            .iter()
+            .map(json::JsonValue::from)
+            .collect();
+
+        let mut value = object! {
+            chargingRateUnit : schedule.charging_rate_unit.as_str(),
+            chargingSchedulePeriod : periods,
+        };
+        if let Some(duration) = schedule.duration {
+            value["duration"] = duration.into();
+        }
+        if let Some(start_schedule) = schedule.start_schedule.as_ref() {
+            value["startSchedule"] = start_schedule.as_str().into();
+        }
+        if let Some(min_charging_rate) = schedule.min_charging_rate {
+            value["minChargingRate"] = min_charging_rate.into();
+        }
+        value
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChargingProfile {
+    pub charging_profile_id: i32,
+    pub transaction_id: Option<i32>,
+    pub stack_level: u32,
+    pub charging_profile_purpose: ChargingProfilePurpose,
+    pub charging_profile_kind: ChargingProfileKind,
+    pub recurrency_kind: Option<RecurrencyKind>,
+    pub valid_from: Option<String>,
+    pub valid_to: Option<String>,
+    pub charging_schedule: ChargingSchedule,
+}
+
+impl ChargingProfile {
+    pub fn parse(value: &json::JsonValue) -> Option<ChargingProfile> {
+        let recurrency_kind = match value["recurrencyKind"].as_str() {
+            Some(kind) => Some(RecurrencyKind::parse(kind)?),
+            None => None,
+        };
+
+        Some(ChargingProfile {
+            charging_profile_id: value["chargingProfileId"].as_i32()?,
+            transaction_id: value["transactionId"].as_i32(),
+            stack_level: value["stackLevel"].as_u32()?,
+            charging_profile_purpose: ChargingProfilePurpose::parse(
+                value["chargingProfilePurpose"].as_str()?,
+            )?,
+            charging_profile_kind: ChargingProfileKind::parse(
+                value["chargingProfileKind"].as_str()?,
+            )?,
+            recurrency_kind,
+            valid_from: value["validFrom"].as_str().map(String::from),
+            valid_to: value["validTo"].as_str().map(String::from),
+            charging_schedule: ChargingSchedule::parse(&value["chargingSchedule"])?,
+        })
+    }
+}
+
+impl From<&ChargingProfile> for json::JsonValue {
+    fn from(profile: &ChargingProfile) -> Self {
+        let mut value = object! {
+            chargingProfileId : profile.charging_profile_id,
+            stackLevel : profile.stack_level,
+            chargingProfilePurpose : profile.charging_profile_purpose.as_str(),
+            chargingProfileKind : profile.charging_profile_kind.as_str(),
+            chargingSchedule : json::JsonValue::from(&profile.charging_schedule),
+        };
+        if let Some(transaction_id) = profile.transaction_id {
+            value["transactionId"] = transaction_id.into();
+        }
+        if let Some(recurrency_kind) = profile.recurrency_kind {
+            value["recurrencyKind"] = recurrency_kind.as_str().into();
+        }
+        if let Some(valid_from) = profile.valid_from.as_ref() {
+            value["validFrom"] = valid_from.as_str().into();
+        }
+        if let Some(valid_to) = profile.valid_to.as_ref() {
+            value["validTo"] = valid_to.as_str().into();
+        }
+        value
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompositeSchedule {
+    pub connector_id: u32,
+    pub schedule_start: Option<String>,
+    pub charging_schedule: ChargingSchedule,
+}
+
+impl CompositeSchedule {
+    /* Parses GetCompositeSchedule.conf, a rejected request carries no schedule */
+    pub fn parse(value: &json::JsonValue) -> Option<CompositeSchedule> {
+        Some(CompositeSchedule {
+            connector_id: value["connectorId"].as_u32()?,
+            schedule_start: value["scheduleStart"].as_str().map(String::from),
+            charging_schedule: ChargingSchedule::parse(&value["chargingSchedule"])?,
+        })
+    }
+}
+
+/* Criteria of ClearChargingProfile, unset fields match everything */
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ClearChargingProfile {
+    pub id: Option<i32>,
+    pub connector_id: Option<u32>,
+    pub charging_profile_purpose: Option<ChargingProfilePurpose>,
+    pub stack_level: Option<u32>,
+}
+
+impl ClearChargingProfile {
+    pub fn parse(value: &json::JsonValue) -> ClearChargingProfile {
+        ClearChargingProfile {
+            id: value["id"].as_i32(),
+            connector_id: value["connectorId"].as_u32(),
+            charging_profile_purpose: value["chargingProfilePurpose"]
+                .as_str()
+                .and_then(ChargingProfilePurpose::parse),
+            stack_level: value["stackLevel"].as_u32(),
+        }
+    }
+
+    pub fn matches(&self, connector_id: u32, profile: &ChargingProfile) -> bool {
+        self.id.is_none_or(|id| id == profile.charging_profile_id)
+            && self.connector_id.is_none_or(|c| c == connector_id)
+            && self
+                .charging_profile_purpose
+                .is_none_or(|p| p == profile.charging_profile_purpose)
+            && self.stack_level.is_none_or(|s| s == profile.stack_level)
+    }
+}
+
+impl From<&ClearChargingProfile> for json::JsonValue {
+    fn from(criteria: &ClearChargingProfile) -> Self {
+        let mut value = object! {};
+        if let Some(id) = criteria.id {
+            value["id"] = id.into();
+        }
+        if let Some(connector_id) = criteria.connector_id {
+            value["connectorId"] = connector_id.into();
+        }
+        if let Some(purpose) = criteria.charging_profile_purpose {
+            value["chargingProfilePurpose"] = purpose.as_str().into();
+        }
+        if let Some(stack_level) = criteria.stack_level {
+            value["stackLevel"] = stack_level.into();
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx_profile() -> ChargingProfile {
+        ChargingProfile {
+            charging_profile_id: 7,
+            transaction_id: Some(42),
+            stack_level: 1,
+            charging_profile_purpose: ChargingProfilePurpose::TxProfile,
+            charging_profile_kind: ChargingProfileKind::Absolute,
+            recurrency_kind: None,
+            valid_from: None,
+            valid_to: None,
+            charging_schedule: ChargingSchedule {
+                duration: Some(3600),
+                start_schedule: Some("2024-01-01T00:00:00Z".to_string()),
+                charging_rate_unit: ChargingRateUnit::A,
+                charging_schedule_period: vec![
+                    ChargingSchedulePeriod {
+                        start_period: 0,
+                        limit: 16.0,
+                        number_phases: Some(3),
+                    },
+                    ChargingSchedulePeriod {
+                        start_period: 1800,
+                        limit: 8.5,
+                        number_phases: None,
+                    },
+                ],
+                min_charging_rate: None,
+            },
+        }
+    }
+
+    #[test]
+    fn tx_profile_is_serialized_to_the_ocpp_shape() {
+        assert_eq!(
+            json::JsonValue::from(&tx_profile()),
+            object! {
+                chargingProfileId : 7,
+                stackLevel : 1,
+                chargingProfilePurpose : "TxProfile",
+                chargingProfileKind : "Absolute",
+                chargingSchedule : object! {
+                    chargingRateUnit : "A",
+                    chargingSchedulePeriod : array![
+                        object! { startPeriod : 0, limit : 16.0, numberPhases : 3 },
+                        object! { startPeriod : 1800, limit : 8.5 },
+                    ],
+                    duration : 3600,
+                    startSchedule : "2024-01-01T00:00:00Z",
+                },
+                transactionId : 42,
+            }
+        );
+    }
+
+    #[test]
+    fn tx_profile_survives_a_round_trip() {
+        let profile = tx_profile();
+        let parsed = ChargingProfile::parse(&json::JsonValue::from(&profile)).unwrap();
+        assert_eq!(parsed, profile);
+    }
+
+    #[test]
+    fn profile_with_unknown_purpose_is_refused() {
+        let mut value = json::JsonValue::from(&tx_profile());
+        value["chargingProfilePurpose"] = "Whatever".into();
+        assert_eq!(ChargingProfile::parse(&value), None);
+    }
+
+    #[test]
+    fn clear_criteria_match_by_id_purpose_or_stack_level() {
+        let profile = tx_profile();
+        let by_id = ClearChargingProfile {
+            id: Some(7),
+            ..ClearChargingProfile::default()
+        };
+        assert!(by_id.matches(1, &profile));
+        let by_purpose = ClearChargingProfile {
+            charging_profile_purpose: Some(ChargingProfilePurpose::TxDefaultProfile),
+            ..ClearChargingProfile::default()
+        };
+        assert!(!by_purpose.matches(1, &profile));
+        let by_stack_level = ClearChargingProfile {
+            connector_id: Some(1),
+            stack_level: Some(1),
+            ..ClearChargingProfile::default()
+        };
+        assert!(by_stack_level.matches(1, &profile));
+        assert!(!by_stack_level.matches(2, &profile));
+        assert!(ClearChargingProfile::default().matches(2, &profile));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::error::CsError;
+use crate::ocpp::{pack_message, unpack_message, Command, Message, MessageType, ProtocolVersion};
+use chrono::Utc;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+
+use tokio::time::timeout;
+use tracing::debug;
+
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/* Payloads of the calls a charge point sends, good enough to get them
+ * accepted by the central system */
+pub fn default_payload(command: &Command) -> Option<json::JsonValue> {
+    let now = Utc::now().to_rfc3339();
+    let payload = match command {
+        Command::BootNotification => object! {
+            chargePointVendor : "dummy",
+            chargePointModel : "client",
+        },
+        Command::Heartbeat => object! {},
+        Command::Authorize => object! { idTag : "client" },
+        Command::StatusNotification => object! {
+            connectorId : 1,
+            errorCode : "NoError",
+            status : "Available",
+        },
+        Command::StartTransaction => object! {
+            connectorId : 1,
+            idTag : "client",
+            meterStart : 0,
+            timestamp : now.clone(),
+        },
+        Command::StopTransaction => object! {
+            transactionId : 1,
+            meterStop : 0,
+            timestamp : now.clone(),
+        },
+        Command::MeterValues => object! {
+            connectorId : 1,
+            meterValue : [{ timestamp : now.clone(), sampledValue : [{ value : "0" }] }],
+        },
+        _ => return None,
+    };
+    Some(payload)
+}
+
+/* Connects to url as a charge point, sends one call and returns the answer
+ * to it. Calls of the central system received meanwhile are ignored. */
+pub async fn call(
+    url: &str,
+    protocol: ProtocolVersion,
+    action: &str,
+    payload: Option<json::JsonValue>,
+) -> Result<Message, CsError> {
+    let command =
+        Command::try_from(action).map_err(|_| CsError::UnsupportedCommand(action.to_string()))?;
+    let payload = match payload {
+        Some(payload) => payload,
+        None => default_payload(&command).ok_or_else(|| {
+            CsError::InvalidArgument(format!("{} has no default payload", action))
+        })?,
+    };
+
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| CsError::InvalidArgument(e.to_string()))?;
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        protocol.subprotocol().parse().unwrap(),
+    );
+    let (mut websocket, _) = connect_async(request)
+        .await
+        .map_err(|e| CsError::InvalidArgument(format!("can't connect to {}: {}", url, e)))?;
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let call = Message::new(MessageType::Call, id.clone(), Some(command), Some(payload));
+    let frame = pack_message(call)?;
+    debug!("send {}", frame);
+    websocket
+        .send(WsMessage::Text(frame))
+        .await
+        .map_err(|e| CsError::Disconnected(e.to_string()))?;
+
+    let answer = timeout(ANSWER_TIMEOUT, async {
+        while let Some(frame) = websocket.next().await {
+            let frame = frame.map_err(|e| CsError::Disconnected(e.to_string()))?;
+            let text = match frame {
+                WsMessage::Text(text) => text,
+                _ => continue,
+            };
+            debug!("received {}", text);
+            let message = unpack_message(&text)?;
+            if message.id == id && message.role != MessageType::Call {
+                return Ok(message);
+            }
+        }
+        Err(CsError::Disconnected(url.to_string()))
+    })
+    .await
+    .map_err(|_| CsError::Timeout)?;
+
+    let _ = websocket.close(None).await;
+    answer
+}
@@ -0,0 +1,179 @@
+use crate::admin::{route, Reply};
+use crate::cs::CentralSystem;
+use crate::error::CsError;
+use crate::stats::Stats;
+use std::fs::{create_dir_all, remove_file, set_permissions, Permissions};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use tiny_http::Method;
+use tracing::{info, warn};
+
+pub const DEFAULT_PATH: &str = "/tmp/dummy-central-system/control.sock";
+
+/* Requests longer than that close the connection */
+const MAX_REQUEST: u64 = 1024 * 1024;
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/* The routes of the admin API over a Unix socket, one JSON request per line:
+ *
+ * {"id": 1, "method": "reset", "params": {"chargePoint": "cp1", "type": "Hard"}}
+ *
+ * connections                       GET  /cp
+ * connectors, stats                 GET  /cp/{chargePoint}/{method}
+ * reset, remote-start, trigger,     POST /cp/{chargePoint}/{method}, the
+ * accept, clear-cache,              params are the body
+ * data-transfer
+ * transaction                       GET  /transactions/{transactionId}
+ * quotas, faults                    GET  /{method}
+ *
+ * Each request gets a line with the same id and either the "result", the
+ * body the admin API answers with, or an "error" {"code", "message", "data"}.
+ * The code is the HTTP status of the admin API, or the JSON-RPC code of
+ * requests that can't be routed. Clients are served on their own thread, the
+ * requests of a client in order. */
+pub fn serve(path: &str, cs: Arc<Mutex<CentralSystem>>, stats: Stats) -> Result<(), CsError> {
+    if let Some(directory) = Path::new(path).parent() {
+        create_dir_all(directory)?;
+    }
+    remove_stale(path)?;
+    let listener = UnixListener::bind(path)?;
+    /* Anyone able to connect controls the central system */
+    set_permissions(path, Permissions::from_mode(0o600))?;
+
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let cs = Arc::clone(&cs);
+                    let stats = stats.clone();
+                    spawn(move || serve_client(stream, &cs, &stats));
+                }
+                Err(e) => warn!("control socket: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/* A socket left by an instance that is gone is replaced, one in use is not */
+fn remove_stale(path: &str) -> Result<(), CsError> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(CsError::Config(format!("{} is not a socket", path)));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(CsError::Config(format!("{} is in use", path)));
+    }
+    Ok(remove_file(path)?)
+}
+
+fn serve_client(stream: UnixStream, cs: &Arc<Mutex<CentralSystem>>, stats: &Stats) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => return warn!("control socket: {}", e),
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = Vec::new();
+        match reader
+            .by_ref()
+            .take(MAX_REQUEST + 1)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(0) => return,
+            Ok(_) => (),
+            Err(e) => return warn!("control socket: {}", e),
+        }
+        let too_long = line.len() as u64 > MAX_REQUEST;
+        let reply = if too_long {
+            error(
+                json::JsonValue::Null,
+                INVALID_REQUEST,
+                "request is too long",
+            )
+        } else {
+            match std::str::from_utf8(&line) {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => answer(line, cs, stats),
+                Err(e) => error(json::JsonValue::Null, PARSE_ERROR, &e.to_string()),
+            }
+        };
+        if writeln!(writer, "{}", reply.dump()).is_err() || too_long {
+            return;
+        }
+    }
+}
+
+fn answer(line: &str, cs: &Arc<Mutex<CentralSystem>>, stats: &Stats) -> json::JsonValue {
+    let request = match json::parse(line) {
+        Ok(request) => request,
+        Err(e) => return error(json::JsonValue::Null, PARSE_ERROR, &e.to_string()),
+    };
+    let id = request["id"].clone();
+    let method = match request["method"].as_str() {
+        Some(method) if request.is_object() => method,
+        _ => return error(id, INVALID_REQUEST, "method is missing"),
+    };
+    let params = match &request["params"] {
+        json::JsonValue::Null => json::JsonValue::new_object(),
+        params if params.is_object() => params.clone(),
+        _ => return error(id, INVALID_PARAMS, "params is not an object"),
+    };
+
+    let (http_method, url) = match target(method, &params) {
+        Ok(target) => target,
+        Err((code, message)) => return error(id, code, &message),
+    };
+    info!("control {} {}", http_method, url);
+    match route(&http_method, &url, &params, cs, stats) {
+        Reply::Json(code, body) if code >= 400 => {
+            let message = body["error"].as_str().unwrap_or("failed").to_string();
+            object! {
+                id : id,
+                error : object! { code : code, message : message, data : body },
+            }
+        }
+        Reply::Json(_, body) => object! { id : id, result : body },
+        Reply::Pem(pem) => object! { id : id, result : pem },
+    }
+}
+
+/* The admin route of the method */
+fn target(method: &str, params: &json::JsonValue) -> Result<(Method, String), (i32, String)> {
+    let cp_id = || match params["chargePoint"].as_str() {
+        Some(cp_id) if !cp_id.is_empty() && !cp_id.contains(['/', '?']) => Ok(cp_id),
+        _ => Err((INVALID_PARAMS, "chargePoint is invalid".to_string())),
+    };
+    match method {
+        "connections" => Ok((Method::Get, "/cp".to_string())),
+        "connectors" | "stats" => Ok((Method::Get, format!("/cp/{}/{}", cp_id()?, method))),
+        "reset" | "remote-start" | "trigger" | "accept" | "clear-cache" | "data-transfer" => {
+            Ok((Method::Post, format!("/cp/{}/{}", cp_id()?, method)))
+        }
+        "transaction" => match params["transactionId"].as_u32() {
+            Some(id) => Ok((Method::Get, format!("/transactions/{}", id))),
+            None => Err((INVALID_PARAMS, "transactionId is invalid".to_string())),
+        },
+        "quotas" | "faults" => Ok((Method::Get, format!("/{}", method))),
+        method => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+fn error(id: json::JsonValue, code: i32, message: &str) -> json::JsonValue {
+    object! {
+        id : id,
+        error : object! { code : code, message : message },
+    }
+}
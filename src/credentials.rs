@@ -0,0 +1,81 @@
+use openssl::base64;
+use openssl::memcmp;
+use std::collections::HashMap;
+
+/* Passwords of the charge points for HTTP Basic authentication on the
+ * handshake. The user name is the charge point id, as in OCPP security
+ * profile 1. Without any password every charge point is let in. */
+#[derive(Clone, Default)]
+pub struct Credentials {
+    passwords: HashMap<String, String>,
+}
+
+impl Credentials {
+    pub fn new(passwords: HashMap<String, String>) -> Credentials {
+        Credentials { passwords }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.passwords.is_empty()
+    }
+
+    /* Checks the value of the Authorization header */
+    pub fn check(&self, cp_id: &str, authorization: Option<&str>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let expected = match self.passwords.get(cp_id) {
+            Some(password) => password,
+            None => return false,
+        };
+        let decoded = authorization
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| base64::decode_block(encoded.trim()).ok())
+            .and_then(|raw| String::from_utf8(raw).ok());
+        match decoded.as_deref().and_then(|d| d.split_once(':')) {
+            Some((user, password)) => {
+                user == cp_id
+                    && password.len() == expected.len()
+                    && memcmp::eq(password.as_bytes(), expected.as_bytes())
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(user_password: &str) -> String {
+        format!("Basic {}", base64::encode_block(user_password.as_bytes()))
+    }
+
+    fn credentials() -> Credentials {
+        let mut passwords = HashMap::new();
+        passwords.insert("cp1".to_string(), "secret".to_string());
+        Credentials::new(passwords)
+    }
+
+    #[test]
+    fn everyone_is_let_in_without_passwords() {
+        assert!(Credentials::default().check("cp1", None));
+    }
+
+    #[test]
+    fn password_of_the_charge_point_is_accepted() {
+        assert!(credentials().check("cp1", Some(&basic("cp1:secret"))));
+    }
+
+    #[test]
+    fn missing_or_wrong_credentials_are_refused() {
+        let credentials = credentials();
+        assert!(!credentials.check("cp1", None));
+        assert!(!credentials.check("cp1", Some(&basic("cp1:secrets"))));
+        assert!(!credentials.check("cp1", Some(&basic("cp2:secret"))));
+        assert!(!credentials.check("cp1", Some("Bearer secret")));
+        assert!(!credentials.check("cp1", Some("Basic !!")));
+        /* Charge points without a password */
+        assert!(!credentials.check("cp2", Some(&basic("cp2:secret"))));
+    }
+}
@@ -1,176 +1,5614 @@
-use crate::ocpp::{CentralSystem as OcppCentralSystem, Command, Message, MessageType, Status};
+use crate::authorization::{
+    AuthorizationCache, AuthorizationList, LocalAuthorizationEntry, TransactionQuotas, UpdateType,
+};
+use crate::charge_point::{
+    Availability, Boot, ChargePoint, Configuration, ConnectorError, ConnectorErrors,
+    ConnectorTarget, DuplicateIds, Reservation,
+};
+use crate::charging::{ChargingProfile, ChargingRateUnit, ClearChargingProfile, CompositeSchedule};
+use crate::clock::{Clock, SystemClock};
+use crate::error::CsError;
+use crate::faults::{Fault, FaultInjector};
+use crate::maintenance::{DiagnosticsRequest, FirmwareUpdate};
+use crate::messages::{
+    self, AuthorizeRequest, AuthorizeRequestV201, AuthorizeResponse, AuthorizeResponseV201,
+    BootNotificationRequest, BootNotificationRequestV201, BootNotificationResponse,
+    BootNotificationResponseV201, HeartbeatResponse, IdTagInfo, IdToken, IdTokenInfo, IdTokenType,
+    StatusInfo, StatusNotificationRequestV201,
+};
+use crate::metering::{parse_meter_values, MeterValue, Readings};
+use crate::metrics;
+use crate::ocpp::{
+    format_timestamp, pack_message, unpack_message, CentralSystem as OcppCentralSystem, Command,
+    Message, MessageType, OcppErrorCode, ProtocolVersion, RegistrationStatus, Status, Work,
+    DEFAULT_SECONDS_FORMAT,
+};
+use crate::pending::{Completion, PendingCall, PendingCalls, UnmatchedResponses};
+use crate::scenario::Scenario;
+use crate::schema::{SchemaValidator, Validation};
+use crate::settings::{HeartbeatIntervals, Settings};
+use crate::transaction::{
+    MemoryTransactionStore, Transaction, TransactionStore, TransactionSummary,
+};
+use crate::trust_store::{CertificateHashData, CertificateType, InstalledCertificate};
 use crate::x509::{
-    CertificateSignRequest, CertificationAuthority, DefaultCertificationAuthority,
-    DefaultCertificationAuthoritySettings, Format,
+    Certificate, CertificateSignRequest, CertificateSigningUse, CertificationAuthority,
+    DefaultCertificationAuthority, DefaultCertificationAuthoritySettings, Format,
+    InMemoryCertificationAuthority,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
+use openssl::base64;
+use openssl::sha::Sha256;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+pub const DEFAULT_HEARTBEAT_INTERVAL: u32 = 60;
+/* The charge point evaluated frames come from */
+pub const EVAL_CP_ID: &str = "eval";
+const PENDING_INTERVAL: u32 = 10;
+const DEFAULT_CALL_TIMEOUT: u32 = 30;
+/* Seconds within which a charge point shouldn't reuse a message id */
+const DUPLICATE_ID_WINDOW: i64 = 300;
+/* Seconds within which a retried CSR gets the certificate already issued */
+const SIGN_RETRY_WINDOW: i64 = 60;
+/* Notifications that are only acknowledged, their conf has no fields */
+const ACKNOWLEDGED_NOTIFICATIONS: [&str; 12] = [
+    "ClearedChargingLimit",
+    "LogStatusNotification",
+    "NotifyChargingLimit",
+    "NotifyCustomerInformation",
+    "NotifyDisplayMessages",
+    "NotifyEvent",
+    "NotifyMonitoringReport",
+    "NotifyReport",
+    "PublishFirmwareStatusNotification",
+    "ReportChargingProfiles",
+    "SecurityEventNotification",
+    "SignedFirmwareStatusNotification",
+];
+/* Bounded strings of calls: action, path in the payload and the maximum
+ * length */
+type ConstrainedString = (&'static str, &'static [&'static str], usize);
+const CONSTRAINED_STRINGS_16: &[ConstrainedString] = &[
+    ("Authorize", &["idTag"], 20),
+    ("StartTransaction", &["idTag"], 20),
+    ("StopTransaction", &["idTag"], 20),
+];
+const CONSTRAINED_STRINGS_201: &[ConstrainedString] = &[
+    ("Authorize", &["idToken", "idToken"], 36),
+    ("TransactionEvent", &["idToken", "idToken"], 36),
+];
+const TRIGGERABLE_MESSAGES: [&str; 6] = [
+    "BootNotification",
+    "DiagnosticsStatusNotification",
+    "FirmwareStatusNotification",
+    "Heartbeat",
+    "MeterValues",
+    "StatusNotification",
+];
+
+/* Answers a call of a charge point in place of the central system */
+pub type Handler = Box<dyn Fn(&Message) -> Result<Vec<Message>, CsError> + Send>;
 
 pub struct CentralSystem {
-    ca: Box<dyn CertificationAuthority + Send>,
+    clock: Arc<dyn Clock>,
+    /* Shared with the signings that run without the central system */
+    ca: Arc<RwLock<Box<dyn CertificationAuthority + Send + Sync>>>,
+    transactions: Box<dyn TransactionStore + Send>,
+    authorization: AuthorizationList,
+    authorization_cache: AuthorizationCache,
+    /* Not cached, a card may run out while its authorization is */
+    quotas: TransactionQuotas,
+    registration_status: RegistrationStatus,
+    heartbeat_interval: u32,
+    heartbeat_intervals: HeartbeatIntervals,
+    /* Seconds between triggered Heartbeats, 0 disables them */
+    heartbeat_trigger_interval: u32,
+    /* When Heartbeats were last triggered on connected charge points */
+    heartbeat_triggers: HashMap<String, DateTime<Utc>>,
+    /* Seconds a transaction may run before it is stopped remotely, 0 for
+     * no limit */
+    max_transaction_duration: u32,
+    /* When the central system accepted the active transactions */
+    transaction_starts: HashMap<u32, DateTime<Utc>>,
+    /* Overdue transactions a RemoteStopTransaction was sent for */
+    auto_stops: HashSet<u32>,
+    charge_points: HashMap<String, ChargePoint>,
+    pending: PendingCalls,
+    call_timeout: u32,
+    /* Outbound queues and session generations of the connected charge points */
+    connections: HashMap<String, (u64, SyncSender<Message>)>,
+    schemas: SchemaValidator,
+    validation: Validation,
+    timestamp_format: SecondsFormat,
+    faults: FaultInjector,
+    /* Answer calls instead of the built-in handlers, keyed by action */
+    handlers: HashMap<String, Handler>,
+    /* Answers the calls no handler knows */
+    default_handler: Option<Handler>,
+    scenario: Option<Scenario>,
+    duplicate_ids: DuplicateIds,
+    unmatched_responses: UnmatchedResponses,
+    transaction_readings: HashMap<u32, Readings>,
+    transaction_summaries: HashMap<u32, TransactionSummary>,
+    /* Chains issued recently, keyed by the digest of type and CSR */
+    recent_signatures: HashMap<[u8; 32], (DateTime<Utc>, Vec<Certificate>)>,
+    /* The outcome of the signing prepare handed out, for the next
+     * make_response. Keyed as recent_signatures. */
+    presigned: Option<Presigned>,
 }
 
-impl CentralSystem {
-    pub fn build() -> Result<Box<dyn OcppCentralSystem + Send>, &'static str> {
-        let settings = DefaultCertificationAuthoritySettings {
-            directory: "/tmp/dummy-central-system/ca/".to_string(),
-            new: true,
-        };
-        let mut ca = Box::new(DefaultCertificationAuthority::new(settings));
-        let res = ca.init();
+/* The digest of a CSR and what signing it gave */
+type Presigned = ([u8; 32], Result<Vec<Certificate>, CsError>);
 
-        match res {
-            Ok(_) => {
-                let cs = CentralSystem { ca };
-                Ok(Box::new(cs))
-            }
-            Err(e) => {
-                println!("{}", e);
-                Err("failed to init Certification Authority")
+/* Identifies a CSR sent again, with the type it asks for */
+fn csr_digest(csr: &CertificateSignRequest) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(csr.certificate_type.as_str().as_bytes());
+    hasher.update(csr.data.as_slice());
+    hasher.finish()
+}
+
+/* CSRs are PEM, some OCPP 2.0.1 stations send the base64 of the DER */
+fn decode_csr(csr: &str) -> Option<(Vec<u8>, Format)> {
+    let csr = csr.trim();
+    if csr.starts_with("-----BEGIN") {
+        return Some((csr.as_bytes().to_vec(), Format::PEM));
+    }
+    let compact: String = csr.split_whitespace().collect();
+    base64::decode_block(&compact)
+        .ok()
+        .filter(|der| !der.is_empty())
+        .map(|der| (der, Format::DER))
+}
+
+/* Replaces the status of the answer to the given call */
+fn with_status(mut message: Message, id: &str, status: &str) -> Message {
+    if message.role != MessageType::CallResult || message.id != id {
+        return message;
+    }
+    if let Some(payload) = message.payload.as_mut() {
+        if payload.has_key("status") {
+            payload["status"] = status.into();
+        }
+        for info in ["idTagInfo", "idTokenInfo"].iter() {
+            if payload.has_key(info) {
+                payload[*info]["status"] = status.into();
             }
         }
     }
+    message
 }
 
-impl OcppCentralSystem for CentralSystem {
-    fn make_response(&mut self, request: Message) -> Result<Vec<Message>, &str> {
-        if request.command.is_none() {
-            return Err("command is empty");
+/* Configures a central system step by step, e.g. for embedding:
+ * CentralSystem::builder().heartbeat_interval(10).clock(clock).build() */
+#[derive(Default)]
+pub struct CentralSystemBuilder {
+    ca_settings: DefaultCertificationAuthoritySettings,
+    ca: Option<Box<dyn CertificationAuthority + Send + Sync>>,
+    heartbeat_interval: Option<u32>,
+    registration_status: Option<RegistrationStatus>,
+    clock: Option<Arc<dyn Clock>>,
+    authorization_list: Option<AuthorizationList>,
+}
+
+impl CentralSystemBuilder {
+    /* Settings of the default CA, which is created and initialized on build */
+    pub fn ca_settings(mut self, settings: DefaultCertificationAuthoritySettings) -> Self {
+        self.ca_settings = settings;
+        self
+    }
+
+    /* Takes precedence over ca_settings */
+    pub fn certification_authority(
+        mut self,
+        ca: Box<dyn CertificationAuthority + Send + Sync>,
+    ) -> Self {
+        self.ca = Some(ca);
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: u32) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    pub fn registration_status(mut self, status: RegistrationStatus) -> Self {
+        self.registration_status = Some(status);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn authorization_list(mut self, authorization: AuthorizationList) -> Self {
+        self.authorization_list = Some(authorization);
+        self
+    }
+
+    pub fn build(self) -> Result<CentralSystem, CsError> {
+        let mut cs = match self.ca {
+            Some(ca) => CentralSystem::with_certification_authority(ca)?,
+            None => CentralSystem::with_ca_settings(self.ca_settings)?,
+        };
+        if let Some(interval) = self.heartbeat_interval {
+            cs.set_heartbeat_interval(interval);
+        }
+        if let Some(status) = self.registration_status {
+            cs.set_registration_status(status);
+        }
+        if let Some(authorization) = self.authorization_list {
+            cs.set_authorization_list(authorization);
+        }
+        if let Some(clock) = self.clock {
+            cs.set_clock(clock);
         }
+        Ok(cs)
+    }
+}
 
-        match (&request.role, request.command.as_ref().unwrap()) {
-            (MessageType::Call, Command::BootNotification) => {
-                self.make_boot_notification_response(request)
-            }
-            (MessageType::Call, Command::StatusNotification) => {
-                self.make_status_notification_response(request)
-            }
-            (MessageType::Call, Command::Heartbeat) => self.make_heartbeat_response(request),
-            (MessageType::Call, Command::SignCertificate) => {
-                self.make_sign_certificate_response(request)
-            }
-            (MessageType::Call, Command::StartTransaction) => {
-                self.make_start_transaction_response(request)
+impl CentralSystem {
+    pub fn builder() -> CentralSystemBuilder {
+        CentralSystemBuilder::default()
+    }
+
+    pub fn build(settings: &Settings) -> Result<Box<dyn OcppCentralSystem + Send>, CsError> {
+        let cs = CentralSystem::with_settings(settings)?;
+        Ok(Box::new(cs))
+    }
+
+    pub fn new() -> Result<CentralSystem, CsError> {
+        CentralSystem::builder().build()
+    }
+
+    pub fn with_settings(settings: &Settings) -> Result<CentralSystem, CsError> {
+        let mut cs = CentralSystem::builder()
+            .ca_settings(settings.ca.clone())
+            .build()?;
+        cs.apply_settings(settings);
+        Ok(cs)
+    }
+
+    /* Same answers as with_settings, but certificates are signed in memory
+     * and transactions are not persisted */
+    pub fn dry_run(settings: &Settings) -> Result<CentralSystem, CsError> {
+        CentralSystem::dry_run_with_clock(settings, Arc::new(SystemClock))
+    }
+
+    /* dry_run whose roots, certificates and answers are dated by the clock */
+    pub fn dry_run_with_clock(
+        settings: &Settings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<CentralSystem, CsError> {
+        let ca =
+            InMemoryCertificationAuthority::with_clock(settings.ca.clone(), Arc::clone(&clock))?;
+        let mut cs = CentralSystem::with_certification_authority(Box::new(ca))?;
+        cs.apply_settings(settings);
+        cs.set_clock(clock);
+        Ok(cs)
+    }
+
+    fn apply_settings(&mut self, settings: &Settings) {
+        self.set_heartbeat_interval(settings.heartbeat_interval);
+        self.set_heartbeat_intervals(settings.heartbeat_intervals.clone());
+        self.set_heartbeat_trigger_interval(settings.heartbeat_trigger_interval);
+        self.set_max_transaction_duration(settings.max_transaction_duration);
+        self.set_registration_status(settings.registration_status);
+        self.set_authorization_list(settings.authorization.to_list());
+        self.quotas = settings.authorization.to_quotas();
+    }
+
+    pub fn with_ca_settings(
+        settings: DefaultCertificationAuthoritySettings,
+    ) -> Result<CentralSystem, CsError> {
+        let mut ca = Box::new(DefaultCertificationAuthority::new(settings));
+        ca.init()?;
+        CentralSystem::with_certification_authority(ca)
+    }
+
+    pub fn with_certification_authority(
+        ca: Box<dyn CertificationAuthority + Send + Sync>,
+    ) -> Result<CentralSystem, CsError> {
+        Ok(CentralSystem {
+            clock: Arc::new(SystemClock),
+            ca: Arc::new(RwLock::new(ca)),
+            transactions: Box::new(MemoryTransactionStore::new()),
+            authorization: AuthorizationList::default(),
+            authorization_cache: AuthorizationCache::default(),
+            quotas: TransactionQuotas::default(),
+            registration_status: RegistrationStatus::Accepted,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_intervals: HeartbeatIntervals::default(),
+            heartbeat_trigger_interval: 0,
+            heartbeat_triggers: HashMap::new(),
+            max_transaction_duration: 0,
+            transaction_starts: HashMap::new(),
+            auto_stops: HashSet::new(),
+            charge_points: HashMap::new(),
+            pending: PendingCalls::new(),
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            connections: HashMap::new(),
+            schemas: SchemaValidator::new()?,
+            validation: Validation::default(),
+            timestamp_format: DEFAULT_SECONDS_FORMAT,
+            faults: FaultInjector::default(),
+            handlers: HashMap::new(),
+            default_handler: None,
+            scenario: None,
+            duplicate_ids: DuplicateIds::default(),
+            unmatched_responses: UnmatchedResponses::default(),
+            transaction_readings: HashMap::new(),
+            transaction_summaries: HashMap::new(),
+            recent_signatures: HashMap::new(),
+            presigned: None,
+        })
+    }
+
+    pub fn root_certificate_pem(&self) -> Result<Vec<u8>, CsError> {
+        self.ca.read().unwrap().root_certificate_pem()
+    }
+
+    pub fn root_certificate_der(&self) -> Result<Vec<u8>, CsError> {
+        self.ca.read().unwrap().root_certificate_der()
+    }
+
+    pub fn ocsp_response(&self, request: &[u8]) -> Result<Vec<u8>, CsError> {
+        self.ca.read().unwrap().ocsp_response(request)
+    }
+
+    pub fn export_bundle(&self, include_invalid: bool) -> Result<String, CsError> {
+        self.ca.read().unwrap().export_bundle(include_invalid)
+    }
+
+    /* Shared with the CA, which dates revocations and limits validities */
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.ca.write().unwrap().set_clock(Arc::clone(&clock));
+        self.clock = clock;
+    }
+
+    pub fn set_transaction_store(&mut self, transactions: Box<dyn TransactionStore + Send>) {
+        self.transactions = transactions;
+    }
+
+    /* Transaction quotas of idTags, their usage can be read and reset */
+    pub fn quotas(&mut self) -> &mut TransactionQuotas {
+        &mut self.quotas
+    }
+
+    pub fn set_authorization_list(&mut self, authorization: AuthorizationList) {
+        self.authorization = authorization;
+        self.authorization_cache.clear();
+    }
+
+    /* Cached decisions are dropped as the list may change */
+    pub fn authorization_list(&mut self) -> &mut AuthorizationList {
+        self.authorization_cache.clear();
+        &mut self.authorization
+    }
+
+    pub fn authorization_cache(&self) -> &AuthorizationCache {
+        &self.authorization_cache
+    }
+
+    /* Returns the number of dropped decisions */
+    pub fn clear_authorization_cache(&mut self) -> usize {
+        self.authorization_cache.clear()
+    }
+
+    pub fn set_registration_status(&mut self, status: RegistrationStatus) {
+        self.registration_status = status;
+    }
+
+    pub fn set_heartbeat_interval(&mut self, interval: u32) {
+        self.heartbeat_interval = interval;
+    }
+
+    /* Intervals of some charge points, resolved on their BootNotification */
+    pub fn set_heartbeat_intervals(&mut self, intervals: HeartbeatIntervals) {
+        self.heartbeat_intervals = intervals;
+    }
+
+    /* Triggers a Heartbeat on every connected charge point that often, for
+     * stations syncing their clock with the answers. 0 disables it. */
+    pub fn set_heartbeat_trigger_interval(&mut self, interval: u32) {
+        self.heartbeat_trigger_interval = interval;
+    }
+
+    /* Transactions running longer are stopped with a RemoteStopTransaction,
+     * sent once while the charge point is connected. 0 disables it. */
+    pub fn set_max_transaction_duration(&mut self, seconds: u32) {
+        self.max_transaction_duration = seconds;
+    }
+
+    pub fn charge_point(&self, cp_id: &str) -> Option<&ChargePoint> {
+        self.charge_points.get(cp_id)
+    }
+
+    pub fn set_duplicate_ids(&mut self, duplicate_ids: DuplicateIds) {
+        self.duplicate_ids = duplicate_ids;
+    }
+
+    pub fn set_unmatched_responses(&mut self, unmatched_responses: UnmatchedResponses) {
+        self.unmatched_responses = unmatched_responses;
+    }
+
+    /* errorCodes reported for a connector, 0 for the charge point */
+    pub fn connector_errors(&self, cp_id: &str, connector_id: u32) -> Option<&ConnectorErrors> {
+        self.charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.errors.get(&connector_id))
+    }
+
+    /* The latest samples reported for a connector */
+    pub fn meter_readings(&self, cp_id: &str, connector_id: u32) -> Option<&Readings> {
+        self.charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.meter_readings.get(&connector_id))
+    }
+
+    pub fn transaction_readings(&self, transaction_id: u32) -> Option<&Readings> {
+        self.transaction_readings.get(&transaction_id)
+    }
+
+    pub fn transaction(&self, transaction_id: u32) -> Option<Transaction> {
+        self.transactions.find(transaction_id)
+    }
+
+    /* Set once the transaction is stopped */
+    pub fn transaction_summary(&self, transaction_id: u32) -> Option<&TransactionSummary> {
+        self.transaction_summaries.get(&transaction_id)
+    }
+
+    pub fn faults(&mut self) -> &mut FaultInjector {
+        &mut self.faults
+    }
+
+    /* Calls of the action get the answer of the handler instead of the
+     * built-in one. Duplicate ids, validation, registration and faults are
+     * checked first. */
+    pub fn register_handler(&mut self, command: Command, handler: Handler) {
+        self.handlers.insert(command.to_string(), handler);
+    }
+
+    pub fn unregister_handler(&mut self, command: &Command) -> bool {
+        self.handlers.remove(&command.to_string()).is_some()
+    }
+
+    /* Answers the calls that are otherwise answered with a NotImplemented
+     * or NotSupported CallError */
+    pub fn set_default_handler(&mut self, handler: Option<Handler>) {
+        self.default_handler = handler;
+    }
+
+    /* Scripted calls following the answers to charge points */
+    pub fn set_scenario(&mut self, scenario: Option<Scenario>) {
+        self.scenario = scenario;
+    }
+
+    pub fn set_call_timeout(&mut self, timeout: u32) {
+        self.call_timeout = timeout;
+    }
+
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    /* Precision of the timestamps sent to charge points */
+    pub fn set_timestamp_format(&mut self, format: SecondsFormat) {
+        self.timestamp_format = format;
+    }
+
+    /* Answers a raw frame as if EVAL_CP_ID had sent it, without a
+     * connection. Returns the frames that would be sent back. */
+    pub fn evaluate(&mut self, raw: &str) -> Result<Vec<String>, CsError> {
+        let request = unpack_message(raw)?;
+        self.make_response(EVAL_CP_ID, request)?
+            .into_iter()
+            .map(pack_message)
+            .collect()
+    }
+
+    pub fn register_call(
+        &mut self,
+        cp_id: &str,
+        call: &Message,
+        completion: Option<Completion>,
+    ) -> Result<(), CsError> {
+        self.pending
+            .register(cp_id, call, self.call_timeout, completion, self.clock.now())
+    }
+
+    pub fn is_connected(&self, cp_id: &str) -> bool {
+        self.connections.contains_key(cp_id)
+    }
+
+    pub fn connected_charge_points(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.connections.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /* Sends a call to the charge point and awaits its response. Returns the
+     * id of the sent message. */
+    pub fn send_call(
+        &mut self,
+        cp_id: &str,
+        command: Command,
+        payload: json::JsonValue,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        if !self.connections.contains_key(cp_id) {
+            return Err(CsError::UnknownChargePoint(cp_id.to_string()));
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let call = Message::new(MessageType::Call, id.clone(), Some(command), Some(payload));
+        self.register_call(cp_id, &call, completion)?;
+
+        /* The response can't be handled before registration as the
+         * connection needs the same lock to process it. A full queue sheds
+         * the call rather than holding the lock until the station reads. */
+        match self.connections[cp_id].1.try_send(call) {
+            Ok(()) => Ok(id),
+            Err(TrySendError::Full(_)) => {
+                self.pending.cancel(&id);
+                warn!("outbound queue of {} is full, drop {}", cp_id, id);
+                Err(CsError::QueueFull(cp_id.to_string()))
             }
-            (MessageType::Call, Command::MeterValues) => self.make_meter_values_response(request),
-            (MessageType::Call, Command::StopTransaction) => {
-                self.make_stop_transaction_response(request)
+            Err(TrySendError::Disconnected(_)) => {
+                self.connections.remove(cp_id);
+                Err(CsError::Disconnected(cp_id.to_string()))
             }
-            (MessageType::Call, Command::Authorize) => self.make_authorize_response(request),
-            (MessageType::Call, _) => self.make_default_answer(request),
-            (_, _) => Err("no response"),
         }
     }
-}
 
-impl CentralSystem {
-    fn make_boot_notification_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let payload = object! {
-            status : Status::Accepted,
-            currentTime : self.make_timestamp(),
-            interval:60
+    pub fn reset(
+        &mut self,
+        cp_id: &str,
+        hard: bool,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let reset_type = if hard { "Hard" } else { "Soft" };
+        let payload = object! { type : reset_type };
+        self.send_call(cp_id, Command::Reset, payload, completion)
+    }
+
+    pub fn remote_start_transaction(
+        &mut self,
+        cp_id: &str,
+        id_tag: &str,
+        connector_id: Option<u32>,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        if id_tag.is_empty() {
+            return Err(CsError::InvalidArgument("idTag is empty".to_string()));
+        }
+        messages::check_ci_string("idTag", id_tag, 20)?;
+
+        let mut payload = object! { idTag : id_tag };
+        if let Some(connector_id) = connector_id {
+            payload["connectorId"] = connector_id.into();
+        }
+        self.send_call(cp_id, Command::RemoteStartTransaction, payload, completion)
+    }
+
+    pub fn remote_stop_transaction(
+        &mut self,
+        cp_id: &str,
+        transaction_id: u32,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let payload = object! { transactionId : transaction_id };
+        self.send_call(cp_id, Command::RemoteStopTransaction, payload, completion)
+    }
+
+    pub fn get_configuration(&mut self, cp_id: &str, keys: Vec<String>) -> Result<String, CsError> {
+        let payload = if keys.is_empty() {
+            object! {}
+        } else {
+            object! { key : keys }
         };
+        self.send_call(cp_id, Command::GetConfiguration, payload, None)
+    }
 
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
-        Ok(vec![response])
+    pub fn change_configuration(
+        &mut self,
+        cp_id: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<String, CsError> {
+        let payload = object! { key : key, value : value };
+        self.send_call(cp_id, Command::ChangeConfiguration, payload, None)
     }
 
-    fn make_status_notification_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
-        Ok(vec![response])
+    pub fn trigger_message(
+        &mut self,
+        cp_id: &str,
+        requested: &str,
+        connector: Option<u32>,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        if !TRIGGERABLE_MESSAGES.contains(&requested) {
+            return Err(CsError::InvalidArgument(
+                "message can't be triggered".to_string(),
+            ));
+        }
+
+        let mut payload = object! { requestedMessage : requested };
+        if let Some(connector) = connector {
+            payload["connectorId"] = connector.into();
+        }
+        self.send_call(cp_id, Command::TriggerMessage, payload, completion)
     }
 
-    fn make_start_transaction_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let ts = (Utc::now().timestamp_millis() / 1000) as u32;
-        let tag_info =
-            object! { status : Status::Accepted , expiryDate : "2030-12-31T11:59:59.000000Z"};
-        let status = object! { transactionId: ts, idTagInfo : tag_info };
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(status));
-        Ok(vec![response])
+    /* Clears the authorization cache of the charge point and the one of the
+     * central system, so the next lookups see the current list */
+    pub fn clear_cache(
+        &mut self,
+        cp_id: &str,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let id = self.send_call(cp_id, Command::ClearCache, object! {}, completion)?;
+        let cleared = self.authorization_cache.clear();
+        info!("{} cached authorization(s) cleared", cleared);
+        Ok(id)
     }
 
-    fn make_stop_transaction_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let tag_info =
-            object! { status : Status::Accepted , expiryDate : "2030-12-31T11:59:59.000000Z"};
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(tag_info));
-        Ok(vec![response])
+    /* Vendor specific call, the answer carries a status and optional data */
+    pub fn data_transfer(
+        &mut self,
+        cp_id: &str,
+        vendor_id: &str,
+        message_id: Option<&str>,
+        data: Option<&str>,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        if vendor_id.is_empty() {
+            return Err(CsError::InvalidArgument("vendorId is empty".to_string()));
+        }
+        messages::check_ci_string("vendorId", vendor_id, 255)?;
+        if let Some(message_id) = message_id {
+            messages::check_ci_string("messageId", message_id, 50)?;
+        }
+
+        let mut payload = object! { vendorId : vendor_id };
+        if let Some(message_id) = message_id {
+            payload["messageId"] = message_id.into();
+        }
+        if let Some(data) = data {
+            payload["data"] = data.into();
+        }
+        self.send_call(cp_id, Command::DataTransfer, payload, completion)
     }
 
-    fn make_authorize_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let req_payload = request.payload.unwrap();
-        let evses = &req_payload["evseId"];
-        let token_info = object! { status : Status::Accepted , cacheExpiryDateTime : "2030-12-31T11:59:59.000000Z"};
-        let data = object! { evseId : evses.clone(), idTokenInfo : token_info };
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(data));
-        Ok(vec![response])
+    /* Promotes a Pending station and triggers the BootNotification that
+     * gets it accepted */
+    pub fn accept(
+        &mut self,
+        cp_id: &str,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let cp = self
+            .charge_points
+            .get_mut(cp_id)
+            .ok_or_else(|| CsError::UnknownChargePoint(cp_id.to_string()))?;
+        cp.registration = Some(RegistrationStatus::Accepted);
+        info!("{} is accepted", cp_id);
+        self.trigger_message(cp_id, "BootNotification", None, completion)
     }
 
-    fn make_meter_values_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
-        Ok(vec![response])
+    pub fn change_availability(
+        &mut self,
+        cp_id: &str,
+        connector_id: u32,
+        operative: bool,
+    ) -> Result<String, CsError> {
+        let availability = if operative {
+            Availability::Operative
+        } else {
+            Availability::Inoperative
+        };
+        let payload = object! { connectorId : connector_id, type : availability.as_str() };
+        self.send_call(cp_id, Command::ChangeAvailability, payload, None)
+    }
+
+    pub fn unlock_connector(
+        &mut self,
+        cp_id: &str,
+        connector_id: u32,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        if ConnectorTarget::from_id(connector_id) == ConnectorTarget::Station {
+            return Err(CsError::InvalidArgument(
+                "can't unlock the whole charge point".to_string(),
+            ));
+        }
+
+        let payload = object! { connectorId : connector_id };
+        self.send_call(cp_id, Command::UnlockConnector, payload, completion)
     }
 
-    fn make_heartbeat_response(&self, request: Message) -> Result<Vec<Message>, &str> {
+    pub fn set_charging_profile(
+        &mut self,
+        cp_id: &str,
+        connector_id: u32,
+        profile: &ChargingProfile,
+    ) -> Result<String, CsError> {
         let payload = object! {
-            currentTime : self.make_timestamp(),
+            connectorId : connector_id,
+            csChargingProfiles : json::JsonValue::from(profile),
         };
+        self.send_call(cp_id, Command::SetChargingProfile, payload, None)
+    }
 
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
-        Ok(vec![response])
+    pub fn clear_charging_profile(
+        &mut self,
+        cp_id: &str,
+        criteria: &ClearChargingProfile,
+    ) -> Result<String, CsError> {
+        let payload = json::JsonValue::from(criteria);
+        self.send_call(cp_id, Command::ClearChargingProfile, payload, None)
     }
 
-    fn make_sign_certificate_response(&self, request: Message) -> Result<Vec<Message>, &str> {
-        if request.payload.is_none() {
-            return Err("payload is empty");
+    pub fn get_composite_schedule(
+        &mut self,
+        cp_id: &str,
+        connector_id: u32,
+        duration: u32,
+        unit: Option<ChargingRateUnit>,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let mut payload = object! { connectorId : connector_id, duration : duration };
+        if let Some(unit) = unit {
+            payload["chargingRateUnit"] = unit.as_str().into();
         }
+        self.send_call(cp_id, Command::GetCompositeSchedule, payload, completion)
+    }
 
-        let mut result = Vec::<Message>::new();
+    pub fn install_certificate(
+        &mut self,
+        cp_id: &str,
+        certificate_type: CertificateType,
+        certificate: &str,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        CertificateHashData::from_pem(certificate)?;
+        let payload = object! {
+            certificateType : certificate_type.as_str(),
+            certificate : certificate,
+        };
+        self.send_call(cp_id, Command::InstallCertificate, payload, completion)
+    }
 
-        /* ACK */
-        let ack_payload = object! { status : Status::Accepted };
-        let ack = Message::new(MessageType::CallResult, request.id, None, Some(ack_payload));
-        result.push(ack);
+    pub fn delete_certificate(
+        &mut self,
+        cp_id: &str,
+        hash_data: &CertificateHashData,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let payload = object! { certificateHashData : hash_data };
+        self.send_call(cp_id, Command::DeleteCertificate, payload, completion)
+    }
 
-        /* Read CSR */
-        let req_payload = request.payload.unwrap();
-        let cert_type = req_payload["typeOfCertificate"]
-            .as_str()
-            .unwrap()
-            .to_string();
-        let csr_payload = req_payload["csr"].as_str().unwrap().to_string();
+    /* OCPP 1.6 asks for exactly one type, 2.0.1 for any number of them */
+    pub fn get_installed_certificate_ids(
+        &mut self,
+        cp_id: &str,
+        types: &[CertificateType],
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let payload = match (self.protocol(cp_id), types) {
+            (ProtocolVersion::Ocpp16, [certificate_type]) => {
+                object! { certificateType : certificate_type.as_str() }
+            }
+            (ProtocolVersion::Ocpp16, _) => {
+                return Err(CsError::InvalidArgument(
+                    "exactly one certificate type is expected".to_string(),
+                ))
+            }
+            (ProtocolVersion::Ocpp201, []) => object! {},
+            (ProtocolVersion::Ocpp201, _) => {
+                let types: Vec<&str> = types.iter().map(|t| t.as_str()).collect();
+                object! { certificateType : types }
+            }
+        };
+        self.send_call(
+            cp_id,
+            Command::GetInstalledCertificateIds,
+            payload,
+            completion,
+        )
+    }
 
-        println!("{} certificate requested", cert_type);
+    pub fn installed_certificates(&self, cp_id: &str) -> Vec<InstalledCertificate> {
+        self.charge_points
+            .get(cp_id)
+            .map(|cp| cp.trust_store.certificates().to_vec())
+            .unwrap_or_default()
+    }
 
-        let csr = CertificateSignRequest {
-            data: Vec::from(csr_payload.as_bytes()),
-            format: Format::PEM,
-        };
+    pub fn composite_schedule(&self, cp_id: &str, connector_id: u32) -> Option<&CompositeSchedule> {
+        self.charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.composite_schedules.get(&connector_id))
+    }
 
-        /* Generate certificate */
-        if let Ok(cert) = self.ca.sign(csr) {
-            let id = uuid::Uuid::new_v4().to_string();
-            let resp_payload = object! {
-                cert: array![hex::encode(cert[0].data.as_slice())],
-                typeOfCertificate: cert_type,
-            };
-            let response = Message::new(
-                MessageType::Call,
-                id,
-                Some(Command::CertificateSigned),
-                Some(resp_payload),
-            );
-            result.push(response);
-            return Ok(result);
+    pub fn reserve_now(
+        &mut self,
+        cp_id: &str,
+        reservation: &Reservation,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let payload = json::JsonValue::from(reservation);
+        self.send_call(cp_id, Command::ReserveNow, payload, completion)
+    }
+
+    pub fn cancel_reservation(
+        &mut self,
+        cp_id: &str,
+        reservation_id: i32,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        let known = self
+            .charge_points
+            .get(cp_id)
+            .map(|cp| cp.reservations.contains_key(&reservation_id))
+            .unwrap_or(false);
+        if !known {
+            return Err(CsError::InvalidArgument("unknown reservation".to_string()));
         }
-        Err("")
+
+        let payload = object! { reservationId : reservation_id };
+        self.send_call(cp_id, Command::CancelReservation, payload, completion)
     }
 
-    fn make_default_answer(&self, request: Message) -> Result<Vec<Message>, &str> {
-        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
-        Ok(vec![response])
+    pub fn reservations(&self, cp_id: &str) -> Vec<Reservation> {
+        self.charge_points
+            .get(cp_id)
+            .map(|cp| cp.reservations.values().cloned().collect())
+            .unwrap_or_default()
     }
 
-    fn make_timestamp(&self) -> String {
-        let now: DateTime<Utc> = Utc::now();
-        now.to_rfc3339()
+    pub fn send_local_list(
+        &mut self,
+        cp_id: &str,
+        version: i32,
+        update_type: UpdateType,
+        entries: &[LocalAuthorizationEntry],
+    ) -> Result<String, CsError> {
+        let stored = self
+            .charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.local_list_version);
+        if let (UpdateType::Differential, Some(stored)) = (update_type, stored) {
+            if version != stored + 1 {
+                warn!(
+                    "differential local list {} for {} doesn't follow version {}",
+                    version, cp_id, stored
+                );
+            }
+        }
+
+        let list: Vec<json::JsonValue> = entries.iter().map(json::JsonValue::from).collect();
+        let payload = object! {
+            listVersion : version,
+            localAuthorizationList : list,
+            updateType : update_type.as_str(),
+        };
+        self.send_call(cp_id, Command::SendLocalList, payload, None)
+    }
+
+    pub fn get_local_list_version(&mut self, cp_id: &str) -> Result<String, CsError> {
+        self.send_call(cp_id, Command::GetLocalListVersion, object! {}, None)
+    }
+
+    pub fn local_list_version(&self, cp_id: &str) -> Option<i32> {
+        self.charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.local_list_version)
+    }
+
+    pub fn update_firmware(
+        &mut self,
+        cp_id: &str,
+        update: &FirmwareUpdate,
+    ) -> Result<String, CsError> {
+        update.validate()?;
+        let payload = json::JsonValue::from(update);
+        self.send_call(cp_id, Command::UpdateFirmware, payload, None)
+    }
+
+    pub fn get_diagnostics(
+        &mut self,
+        cp_id: &str,
+        request: &DiagnosticsRequest,
+        completion: Option<Completion>,
+    ) -> Result<String, CsError> {
+        request.validate()?;
+        let payload = json::JsonValue::from(request);
+        self.send_call(cp_id, Command::GetDiagnostics, payload, completion)
+    }
+
+    /* Diagnostics the charge point uploaded were stored at path */
+    pub fn diagnostics_uploaded(&mut self, cp_id: &str, path: &str) {
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        cp.diagnostics_status = Some("Uploaded".to_string());
+        cp.diagnostics_upload = Some(path.to_string());
+    }
+
+    pub fn charging_profiles(&self, cp_id: &str, connector_id: u32) -> Vec<ChargingProfile> {
+        self.charge_points
+            .get(cp_id)
+            .and_then(|cp| cp.charging_profiles.get(&connector_id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn configuration(&self, cp_id: &str) -> Option<&Configuration> {
+        self.charge_points.get(cp_id).map(|cp| &cp.configuration)
+    }
+
+    pub fn protocol(&self, cp_id: &str) -> ProtocolVersion {
+        self.charge_points
+            .get(cp_id)
+            .map(|cp| cp.protocol)
+            .unwrap_or_default()
+    }
+
+    pub fn is_online(&self, cp_id: &str) -> bool {
+        self.charge_points
+            .get(cp_id)
+            .map(|cp| {
+                let interval = cp.heartbeat_interval.unwrap_or(self.heartbeat_interval);
+                cp.is_online(interval, self.clock.now())
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl OcppCentralSystem for CentralSystem {
+    fn make_response(&mut self, cp_id: &str, request: Message) -> Result<Vec<Message>, CsError> {
+        let presigned = self.presigned.take();
+        let now = self.clock.now();
+        self.charge_points
+            .entry(cp_id.to_string())
+            .or_default()
+            .touch(now);
+
+        if request.role != MessageType::Call {
+            return self.handle_call_response(cp_id, request);
+        }
+
+        if request.command.is_none() {
+            return Err(CsError::ParseError("command is empty".to_string()));
+        }
+        metrics::message_received(&request.command.as_ref().unwrap().to_string());
+
+        if let Some(error) = self.check_duplicate_id(cp_id, &request) {
+            return Ok(vec![error]);
+        }
+
+        if let Some(error) = self.validate_request(cp_id, &request) {
+            return Ok(vec![error]);
+        }
+
+        if let Some(error) = self.check_constrained_strings(cp_id, &request) {
+            return Ok(vec![error]);
+        }
+
+        if let Some(error) = self.check_registration(cp_id, &request) {
+            return Ok(vec![error]);
+        }
+
+        /* Injected faults replace the normal answer */
+        let trigger = self.scenario.as_ref().map(|_| request.clone());
+        let action = request.command.as_ref().unwrap().to_string();
+        let request_id = request.id.clone();
+        let fault = self.faults.take(&action);
+        if let Some(Fault::CallError {
+            code,
+            description,
+            details,
+        }) = &fault
+        {
+            warn!("inject {} into {} from {}", code, action, cp_id);
+            return Ok(vec![Message::call_error(
+                request.id,
+                *code,
+                description,
+                details.clone(),
+            )]);
+        }
+
+        let response = match (&request.role, request.command.as_ref().unwrap()) {
+            (MessageType::Call, _) if self.handlers.contains_key(&action) => {
+                info!(
+                    "{} from {} is answered by a registered handler",
+                    action, cp_id
+                );
+                (self.handlers[&action])(&request)
+            }
+            (MessageType::Call, Command::BootNotification) => {
+                self.make_boot_notification_response(cp_id, request)
+            }
+            (MessageType::Call, Command::StatusNotification) => {
+                self.make_status_notification_response(cp_id, request)
+            }
+            (MessageType::Call, Command::Heartbeat) => self.make_heartbeat_response(request),
+            (MessageType::Call, Command::SignCertificate) => {
+                self.make_sign_certificate_response(cp_id, request, presigned)
+            }
+            (MessageType::Call, Command::StartTransaction) => {
+                self.make_start_transaction_response(cp_id, request)
+            }
+            (MessageType::Call, Command::MeterValues) => {
+                self.make_meter_values_response(cp_id, request)
+            }
+            (MessageType::Call, Command::StopTransaction) => {
+                self.make_stop_transaction_response(cp_id, request)
+            }
+            (MessageType::Call, Command::Authorize) => self.make_authorize_response(cp_id, request),
+            (MessageType::Call, Command::FirmwareStatusNotification) => {
+                self.make_firmware_status_notification_response(cp_id, request)
+            }
+            (MessageType::Call, Command::DiagnosticsStatusNotification) => {
+                self.make_diagnostics_status_notification_response(cp_id, request)
+            }
+            (MessageType::Call, Command::Get15118EVCertificate) => {
+                self.make_get_15118_ev_certificate_response(cp_id, request)
+            }
+            (MessageType::Call, Command::DataTransfer) => {
+                self.make_data_transfer_response(cp_id, request)
+            }
+            (MessageType::Call, Command::Unknown(action))
+                if ACKNOWLEDGED_NOTIFICATIONS.contains(&action.as_str()) =>
+            {
+                info!("{} from {}", action, cp_id);
+                self.make_default_answer(request)
+            }
+            (MessageType::Call, _) if self.default_handler.is_some() => {
+                info!(
+                    "{} from {} is answered by the default handler",
+                    action, cp_id
+                );
+                (self.default_handler.as_ref().unwrap())(&request)
+            }
+            (MessageType::Call, Command::Unknown(action)) => {
+                let error = CsError::UnsupportedCommand(action.clone());
+                warn!("{} from {}", error, cp_id);
+                Ok(vec![Message::call_error(
+                    request.id,
+                    OcppErrorCode::NotImplemented,
+                    &error.to_string(),
+                    object! {},
+                )])
+            }
+            /* Known actions the central system only sends */
+            (_, command) => {
+                let description = format!("{} is not handled by the central system", command);
+                warn!("{} from {}", description, cp_id);
+                Ok(vec![Message::call_error(
+                    request.id,
+                    OcppErrorCode::NotSupported,
+                    &description,
+                    object! {},
+                )])
+            }
+        };
+
+        let response = match fault {
+            Some(Fault::Status(status)) => {
+                warn!("inject {} status into {} from {}", status, action, cp_id);
+                response.map(|messages| {
+                    messages
+                        .into_iter()
+                        .map(|m| with_status(m, &request_id, &status))
+                        .collect()
+                })
+            }
+            _ => response,
+        };
+
+        /* Scripted calls follow an answer that isn't a CallError */
+        let mut response = response;
+        if let (Ok(messages), Some(scenario), Some(trigger)) =
+            (response.as_mut(), self.scenario.as_mut(), trigger)
+        {
+            if messages.iter().all(|m| m.role != MessageType::CallError) {
+                for call in scenario.calls(cp_id, &trigger) {
+                    info!(
+                        "scenario sends {} to {} after {}",
+                        call.command.as_ref().unwrap(),
+                        cp_id,
+                        action
+                    );
+                    messages.push(call);
+                }
+            }
+        }
+
+        /* Calls following the answer, like CertificateSigned, await their
+         * confirmation as the calls of send_call do. They are registered
+         * before the connection writes them. */
+        if let Ok(messages) = response.as_ref() {
+            for call in messages.iter().filter(|m| m.role == MessageType::Call) {
+                if let Err(e) = self.register_call(cp_id, call, None) {
+                    warn!("can't await call {} to {}: {}", call.id, cp_id, e);
+                }
+            }
+        }
+        response
+    }
+
+    /* Signing runs openssl and may retry, the other charge points are
+     * served meanwhile. Requests make_response refuses before signing aren't
+     * signed. */
+    fn prepare(&self, cp_id: &str, request: &Message) -> Option<Work<Self>> {
+        if request.role != MessageType::Call
+            || request.command != Some(Command::SignCertificate)
+            || self.handlers.contains_key("SignCertificate")
+            || self.faults.is_injected("SignCertificate")
+            || self.validate_request(cp_id, request).is_some()
+            || self.check_constrained_strings(cp_id, request).is_some()
+            || self.check_registration(cp_id, request).is_some()
+        {
+            return None;
+        }
+        let (csr, _) = self.sign_certificate_request(cp_id, request).ok()?;
+        let digest = csr_digest(&csr);
+        let window = chrono::Duration::seconds(SIGN_RETRY_WINDOW);
+        if let Some((signed_at, _)) = self.recent_signatures.get(&digest) {
+            if self.clock.now() - *signed_at < window {
+                return None;
+            }
+        }
+
+        let ca = Arc::clone(&self.ca);
+        Some(Box::new(move || {
+            let chain = ca.read().unwrap().sign(csr);
+            Box::new(move |cs: &mut CentralSystem| cs.presigned = Some((digest, chain)))
+        }))
+    }
+
+    fn connect(
+        &mut self,
+        cp_id: &str,
+        version: ProtocolVersion,
+        outbound: SyncSender<Message>,
+        generation: u64,
+    ) {
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        cp.protocol = version;
+        cp.recent_call_ids.clear();
+        self.connections
+            .insert(cp_id.to_string(), (generation, outbound));
+        /* The first Heartbeat is triggered an interval after the connection
+         * opens, whether the station booted or not */
+        self.heartbeat_triggers
+            .insert(cp_id.to_string(), self.clock.now());
+    }
+
+    fn disconnect(&mut self, cp_id: &str, generation: u64) {
+        /* A session replaced by a reconnection leaves the new one alone */
+        if self.connections.get(cp_id).map(|(g, _)| *g) != Some(generation) {
+            return;
+        }
+        self.connections.remove(cp_id);
+        self.heartbeat_triggers.remove(cp_id);
+    }
+
+    fn sweep(&mut self) {
+        let now = self.clock.now();
+        self.pending.expire(now);
+        let window = chrono::Duration::seconds(SIGN_RETRY_WINDOW);
+        self.recent_signatures
+            .retain(|_, (signed_at, _)| now - *signed_at < window);
+        self.trigger_heartbeats(now);
+        self.expire_reservations(now);
+        self.stop_overdue_transactions(now);
+    }
+}
+
+impl CentralSystem {
+    fn make_boot_notification_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let version = self.request_protocol(cp_id, &request);
+        let (vendor, model, serial_number, firmware_version) = match version {
+            ProtocolVersion::Ocpp16 => {
+                let req: BootNotificationRequest = match self.parse_payload(cp_id, &request) {
+                    Ok(req) => req,
+                    Err(error) => return Ok(vec![error]),
+                };
+                (
+                    req.charge_point_vendor,
+                    req.charge_point_model,
+                    req.charge_point_serial_number
+                        .or(req.charge_box_serial_number),
+                    req.firmware_version,
+                )
+            }
+            ProtocolVersion::Ocpp201 => {
+                let req: BootNotificationRequestV201 = match self.parse_payload(cp_id, &request) {
+                    Ok(req) => req,
+                    Err(error) => return Ok(vec![error]),
+                };
+                info!("{} boot reason: {}", cp_id, req.reason);
+                let station = req.charging_station;
+                (
+                    station.vendor_name,
+                    station.model,
+                    station.serial_number,
+                    station.firmware_version,
+                )
+            }
+        };
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        match cp.update_boot_info(
+            &vendor,
+            &model,
+            serial_number.as_deref(),
+            firmware_version.as_deref(),
+        ) {
+            Boot::First => info!("{} boots as {} {}", cp_id, vendor, model),
+            Boot::Repeated => info!("{} boots again, its state is kept", cp_id),
+            Boot::Changed(changes) => warn!("{} changed {}, its state is reset", cp_id, changes),
+        }
+
+        /* Accepted stations stay accepted, others get the default again */
+        let status = match cp.registration {
+            Some(RegistrationStatus::Accepted) => RegistrationStatus::Accepted,
+            _ => self.registration_status,
+        };
+        if cp.registration != Some(status) {
+            info!("{} registration: {}", cp_id, status);
+        }
+        cp.registration = Some(status);
+        let interval = match status {
+            RegistrationStatus::Pending => PENDING_INTERVAL,
+            _ => self
+                .heartbeat_intervals
+                .resolve(cp_id, &vendor, &model)
+                .unwrap_or(self.heartbeat_interval),
+        };
+        cp.heartbeat_interval = Some(interval);
+
+        let current_time = self.make_timestamp();
+        let payload = match version {
+            ProtocolVersion::Ocpp16 => messages::to_json(&BootNotificationResponse {
+                status,
+                current_time,
+                interval,
+            }),
+            ProtocolVersion::Ocpp201 => {
+                let status_info = match status {
+                    RegistrationStatus::Accepted => None,
+                    RegistrationStatus::Pending => Some(StatusInfo {
+                        reason_code: "Pending".to_string(),
+                        additional_info: Some("awaiting acceptance".to_string()),
+                    }),
+                    RegistrationStatus::Rejected => Some(StatusInfo {
+                        reason_code: "Rejected".to_string(),
+                        additional_info: None,
+                    }),
+                };
+                messages::to_json(&BootNotificationResponseV201 {
+                    current_time,
+                    interval,
+                    status,
+                    status_info,
+                })
+            }
+        };
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
+        Ok(vec![response])
+    }
+
+    fn make_status_notification_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        if self.request_protocol(cp_id, &request) == ProtocolVersion::Ocpp201 {
+            let req: StatusNotificationRequestV201 = match self.parse_payload(cp_id, &request) {
+                Ok(req) => req,
+                Err(error) => return Ok(vec![error]),
+            };
+            self.charge_points
+                .entry(cp_id.to_string())
+                .or_default()
+                .update_evse_status(req.evse_id, req.connector_id, req.connector_status.as_str());
+        } else if let Some(req_payload) = request.payload.as_ref() {
+            if let (Some(connector_id), Some(status)) = (
+                req_payload["connectorId"].as_u32(),
+                req_payload["status"].as_str(),
+            ) {
+                let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+                cp.update_status(ConnectorTarget::from_id(connector_id), status);
+                if let Some(error) = ConnectorError::parse(req_payload) {
+                    cp.update_error(connector_id, error);
+                }
+            }
+        }
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
+        Ok(vec![response])
+    }
+
+    fn make_firmware_status_notification_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let status = request
+            .payload
+            .as_ref()
+            .and_then(|p| p["status"].as_str())
+            .map(String::from);
+        info!("firmware status of {}: {:?}", cp_id, status);
+        self.charge_points
+            .entry(cp_id.to_string())
+            .or_default()
+            .firmware_status = status;
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
+        Ok(vec![response])
+    }
+
+    fn make_diagnostics_status_notification_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let status = request
+            .payload
+            .as_ref()
+            .and_then(|p| p["status"].as_str())
+            .map(String::from);
+        info!("diagnostics status of {}: {:?}", cp_id, status);
+        self.charge_points
+            .entry(cp_id.to_string())
+            .or_default()
+            .diagnostics_status = status;
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
+        Ok(vec![response])
+    }
+
+    fn make_start_transaction_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        if request.payload.is_none() {
+            return Err(CsError::ParseError("payload is empty".to_string()));
+        }
+
+        let req_payload = request.payload.unwrap();
+        let connector_id = req_payload["connectorId"].as_u32().unwrap_or(0);
+        let mut status = self.authorize(&req_payload);
+        if status == Status::Accepted {
+            if let Some((refusal, reason)) =
+                self.connector_conflict(cp_id, connector_id, &req_payload)
+            {
+                warn!(
+                    "transaction on connector {} of {} refused: {}",
+                    connector_id, cp_id, reason
+                );
+                status = refusal;
+            }
+        }
+        let id = if status == Status::Accepted {
+            let id = self
+                .transactions
+                .begin(cp_id, &req_payload, self.clock.now())?;
+            metrics::transaction_started();
+            if let Some(id_tag) = CentralSystem::id_tag(&req_payload) {
+                self.quotas.record(id_tag, self.clock.now());
+            }
+            info!(
+                "transaction {} started on connector {} of {} by {}, meter {}",
+                id, connector_id, cp_id, req_payload["idTag"], req_payload["meterStart"]
+            );
+            self.occupy_connector(cp_id, connector_id, id, &req_payload);
+            id
+        } else {
+            0
+        };
+
+        let mut tag_info = object! { status : status , expiryDate : self.expiry_date(&req_payload)};
+        if let Some(parent_id_tag) =
+            CentralSystem::id_tag(&req_payload).and_then(|id_tag| self.authorization.parent(id_tag))
+        {
+            tag_info["parentIdTag"] = parent_id_tag.into();
+        }
+        let status = object! { transactionId: id, idTagInfo : tag_info };
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(status));
+        Ok(vec![response])
+    }
+
+    /* A connector runs one transaction at a time, ConcurrentTx otherwise,
+     * and a reservation keeps it for its idTag, Invalid for the others */
+    fn connector_conflict(
+        &self,
+        cp_id: &str,
+        connector_id: u32,
+        payload: &json::JsonValue,
+    ) -> Option<(Status, String)> {
+        let cp = self.charge_points.get(cp_id)?;
+        if let Some(transaction_id) = cp.active_transactions.get(&connector_id) {
+            return Some((
+                Status::ConcurrentTx,
+                format!("transaction {} is in progress", transaction_id),
+            ));
+        }
+        let now = self.clock.now();
+        let id_tag = payload["idTag"].as_str().unwrap_or_default();
+        let reservation_id = payload["reservationId"].as_i32();
+        cp.reservations
+            .values()
+            .find(|r| {
+                r.connector_id == connector_id
+                    && r.expiry_date > now
+                    && r.id_tag != id_tag
+                    && Some(r.reservation_id) != reservation_id
+            })
+            .map(|r| {
+                (
+                    Status::Invalid,
+                    format!("reserved by {} for {}", r.reservation_id, r.id_tag),
+                )
+            })
+    }
+
+    /* The transaction uses up the reservation it was started for */
+    fn occupy_connector(
+        &mut self,
+        cp_id: &str,
+        connector_id: u32,
+        transaction_id: u32,
+        payload: &json::JsonValue,
+    ) {
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        cp.active_transactions.insert(connector_id, transaction_id);
+        self.transaction_starts
+            .insert(transaction_id, self.clock.now());
+        let id_tag = payload["idTag"].as_str().unwrap_or_default();
+        let reservation_id = payload["reservationId"].as_i32();
+        cp.reservations.retain(|id, r| {
+            let used = r.connector_id == connector_id
+                && (r.id_tag == id_tag || Some(r.reservation_id) == reservation_id);
+            if used {
+                info!("reservation {} on {} is used", id, cp_id);
+            }
+            !used
+        });
+    }
+
+    fn make_stop_transaction_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        if request.payload.is_none() {
+            return Err(CsError::ParseError("payload is empty".to_string()));
+        }
+
+        let samples = match self.parse_samples(cp_id, &request, "transactionData") {
+            Ok(samples) => samples,
+            Err(error) => return Ok(vec![error]),
+        };
+        let req_payload = request.payload.unwrap();
+        let res = match req_payload["transactionId"].as_u32() {
+            Some(id) => self
+                .transactions
+                .finish(id, cp_id, &req_payload, self.clock.now())
+                .map(|_| id),
+            None => Err(CsError::ParseError("transactionId is invalid".to_string())),
+        };
+
+        /* Only the owner stopping an open transaction frees its connector */
+        let status = match res {
+            Ok(id) => {
+                if let Some(cp) = self.charge_points.get_mut(cp_id) {
+                    cp.active_transactions.retain(|_, active| *active != id);
+                }
+                self.transaction_starts.remove(&id);
+                self.auto_stops.remove(&id);
+                metrics::transaction_stopped();
+                self.summarize_transaction(cp_id, id, &req_payload, samples);
+                Status::Accepted
+            }
+            Err(e) => {
+                warn!("transaction {}: {}", req_payload["transactionId"], e);
+                Status::Invalid
+            }
+        };
+
+        let tag_info = object! { status : status , expiryDate : self.expiry_date(&req_payload)};
+        let payload = object! { idTagInfo : tag_info };
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
+        Ok(vec![response])
+    }
+
+    fn make_authorize_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let payload = match self.request_protocol(cp_id, &request) {
+            ProtocolVersion::Ocpp16 => {
+                let req: AuthorizeRequest = match self.parse_payload(cp_id, &request) {
+                    Ok(req) => req,
+                    Err(error) => return Ok(vec![error]),
+                };
+                let response = AuthorizeResponse {
+                    id_tag_info: IdTagInfo {
+                        status: self.authorize_id_tag(&req.id_tag),
+                        expiry_date: Some(self.id_tag_expiry(&req.id_tag)),
+                        parent_id_tag: self.authorization.parent(&req.id_tag).map(String::from),
+                    },
+                };
+                messages::to_json(&response)
+            }
+            ProtocolVersion::Ocpp201 => {
+                let req: AuthorizeRequestV201 = match self.parse_payload(cp_id, &request) {
+                    Ok(req) => req,
+                    Err(error) => return Ok(vec![error]),
+                };
+                if IdTokenType::parse(&req.id_token.kind).is_none() {
+                    warn!(
+                        "{} sent idToken {} of unknown type {}",
+                        cp_id, req.id_token.id_token, req.id_token.kind
+                    );
+                    return Ok(vec![Message::call_error(
+                        request.id,
+                        OcppErrorCode::PropertyConstraintViolation,
+                        &format!("unknown idToken type {}", req.id_token.kind),
+                        object! {},
+                    )]);
+                }
+                let response = AuthorizeResponseV201 {
+                    id_token_info: IdTokenInfo {
+                        status: self.authorize_id_tag(&req.id_token.id_token),
+                        cache_expiry_date_time: Some(self.id_tag_expiry(&req.id_token.id_token)),
+                        group_id_token: self.authorization.parent(&req.id_token.id_token).map(
+                            |parent| IdToken {
+                                id_token: parent.to_string(),
+                                kind: req.id_token.kind.clone(),
+                            },
+                        ),
+                    },
+                };
+                messages::to_json(&response)
+            }
+        };
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
+        Ok(vec![response])
+    }
+
+    fn make_meter_values_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let req_payload = match request.payload.as_ref() {
+            Some(req_payload) => req_payload,
+            None => return Err(CsError::ParseError("payload is empty".to_string())),
+        };
+
+        let connector_id = req_payload["connectorId"].as_u32().unwrap_or(0);
+        let meter_values = match self.parse_samples(cp_id, &request, "meterValue") {
+            Ok(meter_values) => meter_values,
+            Err(error) => return Ok(vec![error]),
+        };
+
+        /* Samples of unknown or finished transactions only count for the
+         * connector */
+        let mut transaction_id = None;
+        if let Some(id) = req_payload["transactionId"].as_u32() {
+            match self.transactions.find(id) {
+                Some(tx) if tx.active && tx.charge_point == cp_id => transaction_id = Some(id),
+                _ => warn!("MeterValues of {} for inactive transaction {}", cp_id, id),
+            }
+            if let Err(e) =
+                self.transactions
+                    .add_meter_values(id, cp_id, req_payload, self.clock.now())
+            {
+                warn!("transaction {}: {}", id, e);
+            }
+        }
+
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        let readings = cp.meter_readings.entry(connector_id).or_default();
+        meter_values.iter().for_each(|v| readings.update(v));
+        if let Some(energy) = readings.energy_wh() {
+            info!("connector {} of {}: {} Wh", connector_id, cp_id, energy);
+        }
+
+        if let Some(id) = transaction_id {
+            let readings = self.transaction_readings.entry(id).or_default();
+            meter_values.iter().for_each(|v| readings.update(v));
+            let consumed = readings
+                .energy_wh()
+                .map(|energy| energy - self.meter_start(id).unwrap_or(0) as f64);
+            if let Some(consumed) = consumed {
+                info!("transaction {} consumed {} Wh", id, consumed);
+            }
+        }
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
+        Ok(vec![response])
+    }
+
+    fn meter_start(&self, transaction_id: u32) -> Option<i64> {
+        self.transactions
+            .find(transaction_id)
+            .and_then(|tx| tx.meter_start)
+    }
+
+    /* The final samples of transactionData count like MeterValues of the
+     * transaction */
+    fn summarize_transaction(
+        &mut self,
+        cp_id: &str,
+        transaction_id: u32,
+        payload: &json::JsonValue,
+        samples: Vec<MeterValue>,
+    ) {
+        let readings = self.transaction_readings.entry(transaction_id).or_default();
+        samples.iter().for_each(|v| readings.update(v));
+
+        let meter_start = self.meter_start(transaction_id);
+        if meter_start.is_none() {
+            warn!("transaction {} has no meterStart", transaction_id);
+        }
+        let summary = TransactionSummary::new(
+            transaction_id,
+            cp_id,
+            meter_start,
+            payload["meterStop"].as_i64(),
+            samples.len(),
+        );
+        match summary.energy_wh {
+            Some(energy) => info!(
+                "transaction {} of {} delivered {} Wh, {} final samples",
+                transaction_id, cp_id, energy, summary.samples
+            ),
+            None => info!(
+                "transaction {} of {} delivered unknown energy, {} final samples",
+                transaction_id, cp_id, summary.samples
+            ),
+        }
+        self.transaction_summaries.insert(transaction_id, summary);
+    }
+
+    fn make_heartbeat_response(&self, request: Message) -> Result<Vec<Message>, CsError> {
+        let payload = messages::to_json(&HeartbeatResponse {
+            current_time: self.make_timestamp(),
+        });
+
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
+        Ok(vec![response])
+    }
+
+    /* The CSR of a SignCertificate and its type as the answer names it, or
+     * the CallError refusing it */
+    fn sign_certificate_request(
+        &self,
+        cp_id: &str,
+        request: &Message,
+    ) -> Result<(CertificateSignRequest, &'static str), Message> {
+        let version = self.request_protocol(cp_id, request);
+        let req_payload = request.payload.as_ref().unwrap_or(&json::JsonValue::Null);
+        /* The type must be one of the known ones, missing is refused too */
+        let field = match version {
+            ProtocolVersion::Ocpp16 => "typeOfCertificate",
+            ProtocolVersion::Ocpp201 => "certificateType",
+        };
+        let cert_type = req_payload[field]
+            .as_str()
+            .and_then(CertificateSigningUse::normalize);
+        let (cert_type, certificate_type) =
+            match cert_type.and_then(|t| Some((t, CertificateSigningUse::parse(t)?))) {
+                Some(parsed) => parsed,
+                None => {
+                    return Err(Message::call_error(
+                        request.id.clone(),
+                        OcppErrorCode::PropertyConstraintViolation,
+                        &format!("{} is not a known certificate type", field),
+                        object! {},
+                    ));
+                }
+            };
+        let (data, format) = match decode_csr(req_payload["csr"].as_str().unwrap_or_default()) {
+            Some(decoded) => decoded,
+            None => {
+                return Err(Message::call_error(
+                    request.id.clone(),
+                    OcppErrorCode::FormationViolation.for_version(version),
+                    "csr is neither PEM nor base64",
+                    object! {},
+                ));
+            }
+        };
+        let csr = CertificateSignRequest {
+            data,
+            format,
+            certificate_type,
+            subject_alt_names: Vec::new(),
+        };
+        Ok((csr, cert_type))
+    }
+
+    fn make_sign_certificate_response(
+        &mut self,
+        cp_id: &str,
+        request: Message,
+        presigned: Option<Presigned>,
+    ) -> Result<Vec<Message>, CsError> {
+        let version = self.request_protocol(cp_id, &request);
+        if request.payload.is_none() {
+            return Err(CsError::ParseError("payload is empty".to_string()));
+        }
+
+        let (csr, cert_type) = match self.sign_certificate_request(cp_id, &request) {
+            Ok(read) => read,
+            Err(error) => {
+                warn!(
+                    "SignCertificate of {} is refused: {}",
+                    cp_id,
+                    error.error().map(|e| e.description).unwrap_or_default()
+                );
+                return Ok(vec![error]);
+            }
+        };
+
+        info!("{} certificate requested", cert_type);
+
+        /* Signed before answering, the charge point would otherwise wait for
+         * a CertificateSigned that never comes */
+        let certificates = match self.sign(csr, presigned) {
+            Ok(certificates) => certificates,
+            Err(e) => {
+                warn!("can't sign the CSR of {}: {}", cp_id, e);
+                let ack_payload = object! { status : Status::Rejected };
+                let ack =
+                    Message::new(MessageType::CallResult, request.id, None, Some(ack_payload));
+                return Ok(vec![ack]);
+            }
+        };
+
+        /* ACK */
+        let ack_payload = object! { status : Status::Accepted };
+        let ack = Message::new(MessageType::CallResult, request.id, None, Some(ack_payload));
+
+        /* Charge points expect PEM, the leaf first */
+        let id = uuid::Uuid::new_v4().to_string();
+        let chain = certificates
+            .iter()
+            .map(Certificate::to_pem)
+            .collect::<Result<Vec<String>, CsError>>()?;
+        let resp_payload = match version {
+            ProtocolVersion::Ocpp16 => object! {
+                cert: chain,
+                typeOfCertificate: cert_type,
+            },
+            ProtocolVersion::Ocpp201 => object! {
+                certificateChain: chain.concat(),
+                certificateType: cert_type,
+            },
+        };
+        let response = Message::new(
+            MessageType::Call,
+            id,
+            Some(Command::CertificateSigned),
+            Some(resp_payload),
+        );
+        Ok(vec![ack, response])
+    }
+
+    /* No vendor extension is known to the central system */
+    fn make_data_transfer_response(
+        &self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let payload = request.payload.unwrap_or(json::JsonValue::Null);
+        info!(
+            "DataTransfer {} {} from {}",
+            payload["vendorId"], payload["messageId"], cp_id
+        );
+        let status = object! { status : "UnknownVendorId" };
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(status));
+        Ok(vec![response])
+    }
+
+    /* The EXI stream is not decoded. The response carries the DER of the V2G
+     * issuer as a deterministic stand-in for the contract certificate. */
+    fn make_get_15118_ev_certificate_response(
+        &self,
+        cp_id: &str,
+        request: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let req_payload = match request.payload.as_ref() {
+            Some(req_payload) => req_payload,
+            None => return Err(CsError::ParseError("payload is empty".to_string())),
+        };
+
+        let schema_version = req_payload["iso15118SchemaVersion"]
+            .as_str()
+            .unwrap_or_default();
+        let action = req_payload["action"].as_str().unwrap_or_default();
+        let exi_request = req_payload["exiRequest"]
+            .as_str()
+            .map(base64::decode_block)
+            .and_then(Result::ok);
+        info!(
+            "{} requested 15118 contract certificate: {} {}",
+            cp_id, action, schema_version
+        );
+
+        let payload = match (action, exi_request) {
+            ("Install", Some(_)) | ("Update", Some(_)) => {
+                let certificate = self
+                    .ca
+                    .read()
+                    .unwrap()
+                    .issuer_certificate_der(CertificateSigningUse::V2G)?;
+                object! {
+                    status : Status::Accepted,
+                    exiResponse : base64::encode_block(certificate.as_slice()),
+                }
+            }
+            _ => {
+                warn!("invalid Get15118EVCertificate from {}", cp_id);
+                object! { status : Status::Failed, exiResponse : "" }
+            }
+        };
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(payload));
+        Ok(vec![response])
+    }
+
+    /* A charge point retrying while the CA is still busy sends the same CSR
+     * again, it gets the same certificate instead of a second serial. The
+     * CSR may already have been signed by the work of prepare. */
+    fn sign(
+        &mut self,
+        csr: CertificateSignRequest,
+        presigned: Option<Presigned>,
+    ) -> Result<Vec<Certificate>, CsError> {
+        let digest = csr_digest(&csr);
+        let now = self.clock.now();
+        let window = chrono::Duration::seconds(SIGN_RETRY_WINDOW);
+        if let Some((signed_at, chain)) = self.recent_signatures.get(&digest) {
+            if now - *signed_at < window {
+                info!("CSR was signed at {}, send the same certificate", signed_at);
+                return Ok(chain.clone());
+            }
+        }
+
+        let chain = match presigned {
+            Some((presigned, chain)) if presigned == digest => chain?,
+            _ => self.ca.read().unwrap().sign(csr)?,
+        };
+        metrics::certificate_signed();
+        self.recent_signatures.insert(digest, (now, chain.clone()));
+        Ok(chain)
+    }
+
+    /* Transactions of stations that were not accepted are refused */
+    fn check_registration(&self, cp_id: &str, request: &Message) -> Option<Message> {
+        let registered = self
+            .charge_points
+            .get(cp_id)
+            .map(|cp| cp.is_registered())
+            .unwrap_or(true);
+        if registered || request.role != MessageType::Call {
+            return None;
+        }
+
+        match request.command.as_ref() {
+            Some(Command::StartTransaction)
+            | Some(Command::StopTransaction)
+            | Some(Command::MeterValues) => {
+                warn!(
+                    "{} from {} which is not accepted",
+                    request.command.as_ref().unwrap(),
+                    cp_id
+                );
+                Some(Message::call_error(
+                    request.id.clone(),
+                    OcppErrorCode::SecurityError,
+                    "charge point is not accepted",
+                    object! {},
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn check_duplicate_id(&mut self, cp_id: &str, request: &Message) -> Option<Message> {
+        if self.duplicate_ids == DuplicateIds::Off {
+            return None;
+        }
+
+        let now = self.clock.now();
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+        let window = chrono::Duration::seconds(DUPLICATE_ID_WINDOW);
+        if cp.register_call_id(&request.id, now, window) {
+            return None;
+        }
+
+        warn!("{} reused message id {}", cp_id, request.id);
+        if self.duplicate_ids != DuplicateIds::Reject {
+            return None;
+        }
+        Some(Message::call_error(
+            request.id.clone(),
+            OcppErrorCode::ProtocolError,
+            "message id was already used",
+            object! {},
+        ))
+    }
+
+    /* Overlong or non-printable identifiers are refused whatever the
+     * validation setting, handlers take them as they are */
+    fn check_constrained_strings(&self, cp_id: &str, request: &Message) -> Option<Message> {
+        let fields = match self.request_protocol(cp_id, request) {
+            ProtocolVersion::Ocpp16 => CONSTRAINED_STRINGS_16,
+            ProtocolVersion::Ocpp201 => CONSTRAINED_STRINGS_201,
+        };
+        let action = request.command.as_ref()?.to_string();
+        let payload = request.payload.as_ref()?;
+        let error = fields
+            .iter()
+            .filter(|(name, _, _)| *name == action)
+            .find_map(|(_, path, max)| {
+                let value = path.iter().fold(payload, |value, key| &value[*key]);
+                let field = path.last().copied().unwrap_or_default();
+                messages::check_ci_string(field, value.as_str()?, *max).err()
+            })?;
+
+        warn!("{} from {}: {}", action, cp_id, error);
+        Some(Message::call_error(
+            request.id.clone(),
+            OcppErrorCode::TypeConstraintViolation,
+            &error.to_string(),
+            object! {},
+        ))
+    }
+
+    /* Only OCPP 1.6 schemas are bundled, other versions pass through */
+    fn validate_request(&self, cp_id: &str, request: &Message) -> Option<Message> {
+        if self.validation == Validation::Off
+            || self.request_protocol(cp_id, request) != ProtocolVersion::Ocpp16
+        {
+            return None;
+        }
+
+        let command = request.command.as_ref()?;
+        let description = self
+            .schemas
+            .validate(command, request.payload.as_ref())
+            .err()?;
+
+        warn!(
+            "{} from {} violates the schema: {}",
+            command, cp_id, description
+        );
+
+        if self.validation == Validation::Strict {
+            Some(Message::call_error(
+                request.id.clone(),
+                self.format_violation(cp_id, request),
+                &description,
+                object! {},
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn handle_call_response(
+        &mut self,
+        cp_id: &str,
+        response: Message,
+    ) -> Result<Vec<Message>, CsError> {
+        let call = match self.pending.complete(cp_id, &response) {
+            Some(call) => call,
+            None => {
+                let reason = format!(
+                    "{} {} from {} doesn't match any pending call",
+                    response.role, response.id, cp_id
+                );
+                return match self.unmatched_responses {
+                    UnmatchedResponses::Ignore => {
+                        info!("{}, ignored", reason);
+                        Ok(Vec::new())
+                    }
+                    UnmatchedResponses::Warn => {
+                        warn!("{}", reason);
+                        Ok(Vec::new())
+                    }
+                    UnmatchedResponses::Error => Err(CsError::ParseError(reason)),
+                };
+            }
+        };
+        if response.role == MessageType::CallResult {
+            self.apply_call_result(cp_id, call, &response);
+        }
+        Ok(Vec::new())
+    }
+
+    fn apply_call_result(&mut self, cp_id: &str, call: PendingCall, response: &Message) {
+        let payload = match response.payload.as_ref() {
+            Some(payload) => payload,
+            None => return,
+        };
+        let status = response.status();
+        let cp = self.charge_points.entry(cp_id.to_string()).or_default();
+
+        match call.command {
+            Command::Reset => {
+                info!(
+                    "{} reset of {}: {}",
+                    call.payload["type"], cp_id, payload["status"]
+                );
+            }
+            Command::RemoteStartTransaction => {
+                info!(
+                    "remote start for {} on {}: {}",
+                    call.payload["idTag"], cp_id, payload["status"]
+                );
+            }
+            Command::RemoteStopTransaction => {
+                info!(
+                    "remote stop of transaction {} on {}: {}",
+                    call.payload["transactionId"], cp_id, payload["status"]
+                );
+            }
+            Command::TriggerMessage => {
+                info!(
+                    "{} trigger on {}: {}",
+                    call.payload["requestedMessage"], cp_id, payload["status"]
+                );
+            }
+            Command::ChangeAvailability => {
+                let connector_id = call.payload["connectorId"].as_u32().unwrap_or(0);
+                let availability = match call.payload["type"].as_str() {
+                    Some("Inoperative") => Availability::Inoperative,
+                    _ => Availability::Operative,
+                };
+                info!(
+                    "connector {} of {} to {}: {}",
+                    connector_id,
+                    cp_id,
+                    availability.as_str(),
+                    payload["status"]
+                );
+                if status == Some(Status::Accepted) {
+                    cp.set_availability(ConnectorTarget::from_id(connector_id), availability);
+                }
+            }
+            Command::UnlockConnector => {
+                info!(
+                    "unlock of connector {} of {}: {}",
+                    call.payload["connectorId"], cp_id, payload["status"]
+                );
+            }
+            Command::SetChargingProfile => {
+                info!(
+                    "charging profile {} for {}: {}",
+                    call.payload["csChargingProfiles"]["chargingProfileId"],
+                    cp_id,
+                    payload["status"]
+                );
+                let connector_id = call.payload["connectorId"].as_u32().unwrap_or(0);
+                let profile = ChargingProfile::parse(&call.payload["csChargingProfiles"]);
+                if let (Some(Status::Accepted), Some(profile)) = (status, profile) {
+                    cp.apply_charging_profile(connector_id, profile);
+                }
+            }
+            Command::ClearChargingProfile => {
+                if status == Some(Status::Accepted) {
+                    let criteria = ClearChargingProfile::parse(&call.payload);
+                    let removed = cp.clear_charging_profiles(&criteria);
+                    info!("{} charging profile(s) of {} cleared", removed, cp_id);
+                } else {
+                    info!("clear charging profile of {}: {}", cp_id, payload["status"]);
+                }
+            }
+            Command::GetCompositeSchedule => {
+                let connector_id = call.payload["connectorId"].as_u32().unwrap_or(0);
+                match (status, CompositeSchedule::parse(payload)) {
+                    (Some(Status::Accepted), Some(schedule)) => {
+                        info!(
+                            "composite schedule of {} connector {}: {}",
+                            cp_id, connector_id, payload["chargingSchedule"]
+                        );
+                        cp.composite_schedules.insert(connector_id, schedule);
+                    }
+                    _ => {
+                        info!(
+                            "composite schedule of {} connector {}: {}",
+                            cp_id, connector_id, payload["status"]
+                        );
+                        cp.composite_schedules.remove(&connector_id);
+                    }
+                }
+            }
+            Command::ReserveNow => {
+                info!(
+                    "reservation {} on {}: {}",
+                    call.payload["reservationId"], cp_id, payload["status"]
+                );
+                let reservation = Reservation::parse(&call.payload);
+                if let (Some(Status::Accepted), Some(reservation)) = (status, reservation) {
+                    cp.reservations
+                        .insert(reservation.reservation_id, reservation);
+                }
+            }
+            Command::CancelReservation => {
+                info!(
+                    "reservation {} cancel on {}: {}",
+                    call.payload["reservationId"], cp_id, payload["status"]
+                );
+                if status == Some(Status::Accepted) {
+                    if let Some(id) = call.payload["reservationId"].as_i32() {
+                        cp.release_reservation(id);
+                    }
+                }
+            }
+            Command::SendLocalList => {
+                info!(
+                    "local list {} for {}: {}",
+                    call.payload["listVersion"], cp_id, payload["status"]
+                );
+                if status == Some(Status::Accepted) {
+                    cp.local_list_version = call.payload["listVersion"].as_i32();
+                }
+            }
+            Command::GetLocalListVersion => {
+                info!(
+                    "local list version of {}: {}",
+                    cp_id, payload["listVersion"]
+                );
+                cp.local_list_version = payload["listVersion"].as_i32();
+            }
+            Command::UpdateFirmware => {
+                info!(
+                    "firmware update of {} from {} accepted",
+                    cp_id, call.payload["location"]
+                );
+            }
+            Command::CertificateSigned => {
+                info!(
+                    "{} certificate of {}: {}",
+                    call.payload["typeOfCertificate"]
+                        .as_str()
+                        .or_else(|| call.payload["certificateType"].as_str())
+                        .unwrap_or_default(),
+                    cp_id,
+                    payload["status"]
+                );
+                if status != Some(Status::Accepted) {
+                    warn!("{} rejected the signed certificate", cp_id);
+                }
+            }
+            Command::ClearCache => {
+                info!("cache clear on {}: {}", cp_id, payload["status"]);
+            }
+            Command::DataTransfer => {
+                info!(
+                    "DataTransfer {} to {}: {} {}",
+                    call.payload["vendorId"], cp_id, payload["status"], payload["data"]
+                );
+            }
+            Command::GetDiagnostics => {
+                let file_name = payload["fileName"].as_str().map(String::from);
+                info!("diagnostics of {}: {:?}", cp_id, file_name);
+                cp.diagnostics_file = file_name;
+            }
+            Command::InstallCertificate => {
+                info!(
+                    "{} install on {}: {}",
+                    call.payload["certificateType"], cp_id, payload["status"]
+                );
+                let certificate_type = call.payload["certificateType"]
+                    .as_str()
+                    .and_then(CertificateType::parse);
+                let certificate = call.payload["certificate"].as_str().unwrap_or_default();
+                let hash_data = CertificateHashData::from_pem(certificate).ok();
+                if let (Some(Status::Accepted), Some(certificate_type), Some(hash_data)) =
+                    (status, certificate_type, hash_data)
+                {
+                    cp.trust_store.install(InstalledCertificate {
+                        certificate_type,
+                        hash_data,
+                        certificate: Some(certificate.to_string()),
+                    });
+                }
+            }
+            Command::DeleteCertificate => {
+                info!(
+                    "certificate {} delete on {}: {}",
+                    call.payload["certificateHashData"]["serialNumber"], cp_id, payload["status"]
+                );
+                let hash_data = CertificateHashData::parse(&call.payload["certificateHashData"]);
+                if let (Some(Status::Accepted), Some(hash_data)) = (status, hash_data) {
+                    cp.trust_store.remove(&hash_data);
+                }
+            }
+            Command::GetInstalledCertificateIds => {
+                info!("installed certificates of {}: {}", cp_id, payload.dump());
+                if status != Some(Status::Accepted) && status != Some(Status::NotFound) {
+                    return;
+                }
+
+                /* A single type in 1.6, a list in 2.0.1 */
+                let requested = &call.payload["certificateType"];
+                let names: Vec<&str> = if requested.is_array() {
+                    requested.members().filter_map(|t| t.as_str()).collect()
+                } else {
+                    requested.as_str().into_iter().collect()
+                };
+                let types: Vec<CertificateType> = names
+                    .into_iter()
+                    .filter_map(CertificateType::parse)
+                    .collect();
+
+                let reported = if payload["certificateHashDataChain"].is_array() {
+                    payload["certificateHashDataChain"]
+                        .members()
+                        .filter_map(|entry| {
+                            Some(InstalledCertificate {
+                                certificate_type: entry["certificateType"]
+                                    .as_str()
+                                    .and_then(CertificateType::parse)?,
+                                hash_data: CertificateHashData::parse(
+                                    &entry["certificateHashData"],
+                                )?,
+                                certificate: None,
+                            })
+                        })
+                        .collect()
+                } else {
+                    payload["certificateHashData"]
+                        .members()
+                        .filter_map(|entry| {
+                            Some(InstalledCertificate {
+                                certificate_type: *types.first()?,
+                                hash_data: CertificateHashData::parse(entry)?,
+                                certificate: None,
+                            })
+                        })
+                        .collect()
+                };
+                cp.trust_store.replace(&types, reported);
+            }
+            Command::GetConfiguration => {
+                cp.configuration.update(payload);
+            }
+            Command::ChangeConfiguration => {
+                let key = call.payload["key"].as_str().unwrap_or_default();
+                let value = call.payload["value"].as_str().unwrap_or_default();
+                info!("{} change of {}: {}", key, cp_id, payload["status"]);
+                match status {
+                    Some(Status::Accepted) => cp.configuration.set(key, value),
+                    Some(Status::RebootRequired) => {
+                        cp.configuration.set(key, value);
+                        cp.configuration.reboot_required = true;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn expire_reservations(&mut self, now: DateTime<Utc>) {
+        for (cp_id, cp) in self.charge_points.iter_mut() {
+            for reservation in cp.expire_reservations(now) {
+                info!(
+                    "reservation {} on {} connector {} for {} expired",
+                    reservation.reservation_id, cp_id, reservation.connector_id, reservation.id_tag
+                );
+            }
+        }
+    }
+
+    fn stop_overdue_transactions(&mut self, now: DateTime<Utc>) {
+        if self.max_transaction_duration == 0 {
+            return;
+        }
+        let limit = chrono::Duration::seconds(self.max_transaction_duration as i64);
+        let mut overdue = Vec::new();
+        for (cp_id, cp) in self.charge_points.iter() {
+            if !self.connections.contains_key(cp_id) {
+                continue;
+            }
+            for id in cp.active_transactions.values() {
+                let started = match self.transaction_starts.get(id) {
+                    Some(started) => *started,
+                    None => continue,
+                };
+                if now - started >= limit && !self.auto_stops.contains(id) {
+                    overdue.push((cp_id.clone(), *id, started));
+                }
+            }
+        }
+        for (cp_id, id, started) in overdue {
+            info!(
+                "transaction {} on {} runs since {}, longer than {}s: stopping it",
+                id,
+                cp_id,
+                started.to_rfc3339_opts(self.timestamp_format, true),
+                self.max_transaction_duration
+            );
+            match self.remote_stop_transaction(&cp_id, id, None) {
+                Ok(_) => {
+                    self.auto_stops.insert(id);
+                }
+                Err(e) => warn!("can't stop transaction {} on {}: {}", id, cp_id, e),
+            }
+        }
+    }
+
+    fn trigger_heartbeats(&mut self, now: DateTime<Utc>) {
+        if self.heartbeat_trigger_interval == 0 {
+            return;
+        }
+        let interval = chrono::Duration::seconds(self.heartbeat_trigger_interval as i64);
+        let due: Vec<String> = self
+            .connections
+            .keys()
+            .filter(|cp_id| {
+                self.heartbeat_triggers
+                    .get(*cp_id)
+                    .is_none_or(|last| now - *last >= interval)
+            })
+            .cloned()
+            .collect();
+        for cp_id in due {
+            self.heartbeat_triggers.insert(cp_id.clone(), now);
+            if let Err(e) = self.trigger_message(&cp_id, "Heartbeat", None, None) {
+                warn!("can't trigger a heartbeat on {}: {}", cp_id, e);
+            }
+        }
+    }
+
+    fn make_default_answer(&self, request: Message) -> Result<Vec<Message>, CsError> {
+        let response = Message::new(MessageType::CallResult, request.id, None, Some(object! {}));
+        Ok(vec![response])
+    }
+
+    fn id_tag(payload: &json::JsonValue) -> Option<&str> {
+        /* idTag in 1.6, idToken as a plain string or an object in 2.0 */
+        payload["idTag"]
+            .as_str()
+            .or_else(|| payload["idToken"].as_str())
+            .or_else(|| payload["idToken"]["idToken"].as_str())
+    }
+
+    fn authorize(&mut self, payload: &json::JsonValue) -> Status {
+        match CentralSystem::id_tag(payload) {
+            Some(id_tag) => self.authorize_id_tag(id_tag),
+            None => Status::Invalid,
+        }
+    }
+
+    fn authorize_id_tag(&mut self, id_tag: &str) -> Status {
+        let now = self.clock.now();
+        let status = match self.authorization_cache.get(id_tag, now) {
+            Some(cached) => {
+                info!("idTag {} authorization: {} (cached)", id_tag, cached.status);
+                cached.status
+            }
+            None => {
+                let status = self.authorization.status(id_tag);
+                let expiry = self.authorization.expiry(Some(id_tag), now);
+                self.authorization_cache.insert(id_tag, status, expiry);
+                info!("idTag {} authorization: {}", id_tag, status);
+                status
+            }
+        };
+
+        if status == Status::Accepted && self.quotas.is_exhausted(id_tag, now) {
+            info!("idTag {} used up its transactions", id_tag);
+            return Status::Blocked;
+        }
+        status
+    }
+
+    fn expiry_date(&self, payload: &json::JsonValue) -> String {
+        match CentralSystem::id_tag(payload) {
+            Some(id_tag) => self.id_tag_expiry(id_tag),
+            None => {
+                let expiry = self.authorization.expiry(None, self.clock.now());
+                format_timestamp(&expiry, self.timestamp_format)
+            }
+        }
+    }
+
+    fn id_tag_expiry(&self, id_tag: &str) -> String {
+        let now = self.clock.now();
+        let expiry = match self.authorization_cache.get(id_tag, now) {
+            Some(cached) => cached.expiry,
+            None => self.authorization.expiry(Some(id_tag), now),
+        };
+        format_timestamp(&expiry, self.timestamp_format)
+    }
+
+    /* The typed payload of a call, or the CallError answering a payload that
+     * doesn't fit it */
+    fn parse_payload<T: DeserializeOwned>(
+        &self,
+        cp_id: &str,
+        request: &Message,
+    ) -> Result<T, Message> {
+        let payload = request.payload.as_ref().unwrap_or(&json::JsonValue::Null);
+        messages::from_json(payload).map_err(|e| {
+            let action = request
+                .command
+                .as_ref()
+                .map(|c| c.to_string())
+                .unwrap_or_default();
+            warn!("{} of {}: {}", action, cp_id, e);
+            let code = self.format_violation(cp_id, request);
+            Message::call_error(request.id.clone(), code, &e.to_string(), object! {})
+        })
+    }
+
+    /* The samples of a meterValue or transactionData field. Strings outside
+     * the OCPP vocabularies are answered with a PropertyConstraintViolation
+     * CallError, otherwise malformed samples are only logged. */
+    fn parse_samples(
+        &self,
+        cp_id: &str,
+        request: &Message,
+        field: &str,
+    ) -> Result<Vec<MeterValue>, Message> {
+        let payload = request.payload.as_ref().unwrap_or(&json::JsonValue::Null);
+        match parse_meter_values(&payload[field]) {
+            Ok(samples) => Ok(samples),
+            Err(CsError::InvalidArgument(e)) => {
+                warn!("{} of {}: {}", field, cp_id, e);
+                Err(Message::call_error(
+                    request.id.clone(),
+                    OcppErrorCode::PropertyConstraintViolation,
+                    &e,
+                    object! {},
+                ))
+            }
+            Err(e) => {
+                warn!("{} of {}: {}", field, cp_id, e);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /* The error code was renamed in OCPP 2.0.1 */
+    fn format_violation(&self, cp_id: &str, request: &Message) -> OcppErrorCode {
+        OcppErrorCode::FormationViolation.for_version(self.request_protocol(cp_id, request))
+    }
+
+    /* The version of the connection the request arrived over, the last one
+     * of the charge point for requests that didn't arrive over one */
+    fn request_protocol(&self, cp_id: &str, request: &Message) -> ProtocolVersion {
+        request.protocol.unwrap_or_else(|| self.protocol(cp_id))
+    }
+
+    fn make_timestamp(&self) -> String {
+        let now = self.clock.now();
+        format_timestamp(&now, self.timestamp_format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use std::sync::mpsc::{sync_channel, Receiver};
+    use std::sync::Mutex;
+
+    fn central_system() -> (CentralSystem, Arc<FixedClock>) {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = Arc::new(FixedClock::new(now));
+        let cs = CentralSystem::dry_run_with_clock(&Settings::default(), clock.clone()).unwrap();
+        (cs, clock)
+    }
+
+    fn connect(cs: &mut CentralSystem, cp_id: &str, generation: u64) -> Receiver<Message> {
+        let (tx, rx) = sync_channel(16);
+        OcppCentralSystem::connect(cs, cp_id, ProtocolVersion::Ocpp16, tx, generation);
+        rx
+    }
+
+    fn call(
+        cs: &mut CentralSystem,
+        cp_id: &str,
+        command: Command,
+        payload: json::JsonValue,
+    ) -> Vec<Message> {
+        let request = Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(command),
+            Some(payload),
+        );
+        cs.make_response(cp_id, request).unwrap()
+    }
+
+    fn start_transaction(
+        cs: &mut CentralSystem,
+        cp_id: &str,
+        connector_id: u32,
+        meter_start: i64,
+    ) -> u32 {
+        let answer = call(
+            cs,
+            cp_id,
+            Command::StartTransaction,
+            object! {
+                connectorId : connector_id,
+                idTag : "TAG1",
+                meterStart : meter_start,
+                timestamp : "2024-01-01T00:00:00Z",
+            },
+        );
+        answer[0].payload.as_ref().unwrap()["transactionId"]
+            .as_u32()
+            .unwrap()
+    }
+
+    fn stop_transaction(
+        cs: &mut CentralSystem,
+        cp_id: &str,
+        id: u32,
+        meter_stop: i64,
+    ) -> json::JsonValue {
+        let answer = call(
+            cs,
+            cp_id,
+            Command::StopTransaction,
+            object! {
+                transactionId : id,
+                meterStop : meter_stop,
+                timestamp : "2024-01-01T01:00:00Z",
+            },
+        );
+        answer[0].payload.clone().unwrap()
+    }
+
+    fn boot(cs: &mut CentralSystem, cp_id: &str, model: &str) -> json::JsonValue {
+        let answer = call(
+            cs,
+            cp_id,
+            Command::BootNotification,
+            object! {
+                chargePointVendor : "dummy",
+                chargePointModel : model,
+                firmwareVersion : "1.0.0",
+            },
+        );
+        answer[0].payload.clone().unwrap()
+    }
+
+    fn reservation(reservation_id: i32, connector_id: u32) -> Reservation {
+        Reservation {
+            reservation_id,
+            connector_id,
+            expiry_date: DateTime::parse_from_rfc3339("2024-01-01T01:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            id_tag: "TAG1".to_string(),
+            parent_id_tag: None,
+        }
+    }
+
+    fn csr_pem(common_name: &str) -> String {
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+        use openssl::x509::{X509NameBuilder, X509Req};
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        name.append_entry_by_text("O", "dummy").unwrap();
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        String::from_utf8(builder.build().to_pem().unwrap()).unwrap()
+    }
+
+    /* Answers the call the charge point received */
+    fn answer(cs: &mut CentralSystem, cp_id: &str, call: &Message, payload: json::JsonValue) {
+        let result = Message::new(
+            MessageType::CallResult,
+            call.id.clone(),
+            None,
+            Some(payload),
+        );
+        cs.make_response(cp_id, result).unwrap();
+    }
+
+    #[test]
+    fn reset_is_sent_and_answered() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let completion: Completion = Box::new(move |result| {
+            let _ = tx.send(result.map(|m| m.payload.clone().unwrap()));
+        });
+        cs.reset("cp1", true, Some(completion)).unwrap();
+
+        let reset = outbound.try_recv().unwrap();
+        assert_eq!(reset.command, Some(Command::Reset));
+        assert_eq!(reset.payload.as_ref().unwrap()["type"], "Hard");
+        answer(&mut cs, "cp1", &reset, object! { status : "Accepted" });
+        assert_eq!(rx.try_recv().unwrap().unwrap()["status"], "Accepted");
+    }
+
+    #[test]
+    fn reset_of_unknown_charge_point_fails() {
+        let (mut cs, _) = central_system();
+        assert!(matches!(
+            cs.reset("cp1", false, None),
+            Err(CsError::UnknownChargePoint(_))
+        ));
+    }
+
+    #[test]
+    fn stale_session_keeps_reconnected_one() {
+        let (mut cs, _) = central_system();
+        let _old = connect(&mut cs, "cp1", 1);
+        let new = connect(&mut cs, "cp1", 2);
+        OcppCentralSystem::disconnect(&mut cs, "cp1", 1);
+
+        assert!(cs.is_connected("cp1"));
+        cs.reset("cp1", false, None).unwrap();
+        assert_eq!(new.try_recv().unwrap().command, Some(Command::Reset));
+
+        OcppCentralSystem::disconnect(&mut cs, "cp1", 2);
+        assert!(!cs.is_connected("cp1"));
+    }
+
+    #[test]
+    fn stop_transaction_totals_energy() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 1000);
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 8500)["idTagInfo"]["status"],
+            "Accepted"
+        );
+
+        let summary = cs.transaction_summary(id).unwrap();
+        assert_eq!(summary.energy_wh, Some(7500));
+        assert!(cs
+            .charge_point("cp1")
+            .unwrap()
+            .active_transactions
+            .is_empty());
+    }
+
+    #[test]
+    fn rejected_stop_keeps_the_transaction_of_the_owner() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+        assert_eq!(
+            stop_transaction(&mut cs, "cp2", id, 10)["idTagInfo"]["status"],
+            "Invalid"
+        );
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().active_transactions.get(&1),
+            Some(&id)
+        );
+        assert!(cs.transaction_starts.contains_key(&id));
+
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 10)["idTagInfo"]["status"],
+            "Accepted"
+        );
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 10)["idTagInfo"]["status"],
+            "Invalid"
+        );
+    }
+
+    #[test]
+    fn sign_certificate_without_type_is_refused() {
+        for (version, field) in [
+            (ProtocolVersion::Ocpp16, "typeOfCertificate"),
+            (ProtocolVersion::Ocpp201, "certificateType"),
+        ] {
+            let (mut cs, _) = central_system();
+            let request = Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::SignCertificate),
+                Some(object! { csr : csr_pem("cp1") }),
+            )
+            .with_protocol(version);
+            let answer = cs.make_response("cp1", request).unwrap();
+
+            assert_eq!(answer.len(), 1);
+            let error = answer[0].error().unwrap();
+            assert_eq!(error.code, "PropertyConstraintViolation");
+            assert!(error.description.starts_with(field));
+        }
+    }
+
+    #[test]
+    fn sign_certificate_accepts_known_types_in_any_case() {
+        for (version, field, given, expected) in [
+            (
+                ProtocolVersion::Ocpp16,
+                "typeOfCertificate",
+                "ChargePointCertificate",
+                "ChargePointCertificate",
+            ),
+            (
+                ProtocolVersion::Ocpp16,
+                "typeOfCertificate",
+                "chargepointcertificate",
+                "ChargePointCertificate",
+            ),
+            (
+                ProtocolVersion::Ocpp16,
+                "typeOfCertificate",
+                "ChargingStationCertificate",
+                "ChargingStationCertificate",
+            ),
+            (
+                ProtocolVersion::Ocpp16,
+                "typeOfCertificate",
+                "V2GCertificate",
+                "V2GCertificate",
+            ),
+            (
+                ProtocolVersion::Ocpp201,
+                "certificateType",
+                "CHARGINGSTATIONCERTIFICATE",
+                "ChargingStationCertificate",
+            ),
+            (
+                ProtocolVersion::Ocpp201,
+                "certificateType",
+                "v2gCertificate",
+                "V2GCertificate",
+            ),
+        ] {
+            let (mut cs, _) = central_system();
+            let mut payload = object! { csr : csr_pem("cp1") };
+            payload[field] = given.into();
+            let request = Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::SignCertificate),
+                Some(payload),
+            )
+            .with_protocol(version);
+            let answer = cs.make_response("cp1", request).unwrap();
+
+            assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+            assert_eq!(answer[1].command, Some(Command::CertificateSigned));
+            assert_eq!(answer[1].payload.as_ref().unwrap()[field], expected);
+        }
+    }
+
+    #[test]
+    fn boot_notification_answers_the_time_of_the_clock() {
+        let (mut cs, clock) = central_system();
+        clock.advance(chrono::Duration::seconds(90));
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::BootNotification,
+            object! { chargePointVendor : "dummy", chargePointModel : "m1" },
+        );
+        assert_eq!(
+            answer[0].payload.as_ref().unwrap()["currentTime"],
+            "2024-01-01T00:01:30.000Z"
+        );
+    }
+
+    #[test]
+    fn transaction_events_are_stamped_by_the_clock() {
+        let (mut cs, clock) = central_system();
+        let start = clock.now();
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+        clock.advance(chrono::Duration::minutes(5));
+        stop_transaction(&mut cs, "cp1", id, 10);
+
+        let timestamps: Vec<_> = cs
+            .transactions
+            .lifecycle(id)
+            .iter()
+            .map(|event| event.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![start, start + chrono::Duration::minutes(5)]
+        );
+    }
+
+    #[test]
+    fn stop_of_unknown_transaction_is_invalid() {
+        let (mut cs, _) = central_system();
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", 42, 10)["idTagInfo"]["status"],
+            "Invalid"
+        );
+    }
+
+    #[test]
+    fn stopped_transaction_keeps_meter_stop_and_cant_stop_again() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 100);
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 250)["idTagInfo"]["status"],
+            "Accepted"
+        );
+
+        let transaction = cs.transaction(id).unwrap();
+        assert!(!transaction.active);
+        assert_eq!(transaction.meter_stop, Some(250));
+        assert_eq!(
+            transaction.stop_timestamp.as_deref(),
+            Some("2024-01-01T01:00:00Z")
+        );
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 300)["idTagInfo"]["status"],
+            "Invalid"
+        );
+        assert_eq!(cs.transaction(id).unwrap().meter_stop, Some(250));
+    }
+
+    #[test]
+    fn authorization_list_statuses_are_answered() {
+        let (mut cs, _) = central_system();
+        for (id_tag, status) in [
+            ("BLOCKED", Status::Blocked),
+            ("EXPIRED", Status::Expired),
+            ("INVALID", Status::Invalid),
+        ] {
+            cs.authorization_list().set(id_tag, status);
+        }
+
+        for (connector_id, (id_tag, expected)) in [
+            ("TAG1", "Accepted"),
+            ("BLOCKED", "Blocked"),
+            ("EXPIRED", "Expired"),
+            ("INVALID", "Invalid"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::Authorize,
+                object! { idTag : *id_tag },
+            );
+            assert_eq!(
+                answer[0].payload.as_ref().unwrap()["idTagInfo"]["status"],
+                *expected
+            );
+
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::StartTransaction,
+                object! {
+                    connectorId : connector_id + 1,
+                    idTag : *id_tag,
+                    meterStart : 0,
+                    timestamp : "2024-01-01T00:00:00Z",
+                },
+            );
+            let payload = answer[0].payload.as_ref().unwrap();
+            assert_eq!(payload["idTagInfo"]["status"], *expected);
+            assert_eq!(payload["transactionId"] == 0, *expected != "Accepted");
+        }
+    }
+
+    #[test]
+    fn unlisted_id_tags_get_the_default_status() {
+        let (mut cs, _) = central_system();
+        cs.set_authorization_list(AuthorizationList::new(Status::Invalid));
+        cs.authorization_list().set("TAG1", Status::Accepted);
+        let status = |cs: &mut CentralSystem, id_tag: &str| {
+            call(cs, "cp1", Command::Authorize, object! { idTag : id_tag })[0]
+                .payload
+                .clone()
+                .unwrap()["idTagInfo"]["status"]
+                .clone()
+        };
+        assert_eq!(status(&mut cs, "TAG1"), "Accepted");
+        assert_eq!(status(&mut cs, "TAG2"), "Invalid");
+    }
+
+    #[test]
+    fn boots_get_the_registration_status_of_the_central_system() {
+        let (mut cs, _) = central_system();
+        let answer = boot(&mut cs, "cp1", "m1");
+        assert_eq!(answer["status"], "Accepted");
+        assert_eq!(answer["interval"], DEFAULT_HEARTBEAT_INTERVAL);
+
+        cs.set_registration_status(RegistrationStatus::Rejected);
+        let answer = boot(&mut cs, "cp2", "m1");
+        assert_eq!(answer["status"], "Rejected");
+        assert_eq!(answer["interval"], DEFAULT_HEARTBEAT_INTERVAL);
+
+        cs.set_registration_status(RegistrationStatus::Pending);
+        let answer = boot(&mut cs, "cp3", "m1");
+        assert_eq!(answer["status"], "Pending");
+        assert_eq!(answer["interval"], PENDING_INTERVAL);
+    }
+
+    #[test]
+    fn boot_info_is_stored() {
+        let (mut cs, _) = central_system();
+        boot(&mut cs, "cp1", "m1");
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.vendor.as_deref(), Some("dummy"));
+        assert_eq!(cp.model.as_deref(), Some("m1"));
+        assert_eq!(cp.firmware_version.as_deref(), Some("1.0.0"));
+        assert_eq!(cp.registration, Some(RegistrationStatus::Accepted));
+    }
+
+    #[test]
+    fn silent_station_goes_offline_after_two_intervals() {
+        let (mut cs, clock) = central_system();
+        cs.set_heartbeat_interval(30);
+        assert!(!cs.is_online("cp1"));
+        assert_eq!(boot(&mut cs, "cp1", "m1")["interval"], 30);
+        assert!(cs.is_online("cp1"));
+
+        clock.advance(chrono::Duration::seconds(59));
+        assert!(cs.is_online("cp1"));
+        clock.advance(chrono::Duration::seconds(2));
+        assert!(!cs.is_online("cp1"));
+
+        call(&mut cs, "cp1", Command::Heartbeat, object! {});
+        assert!(cs.is_online("cp1"));
+    }
+
+    #[test]
+    fn unanswered_reset_fails_after_the_call_timeout() {
+        let (mut cs, clock) = central_system();
+        cs.set_call_timeout(10);
+        let _rx = connect(&mut cs, "cp1", 1);
+        let timed_out = Arc::new(Mutex::new(false));
+        let flag = Arc::clone(&timed_out);
+        cs.reset(
+            "cp1",
+            false,
+            Some(Box::new(move |outcome| {
+                *flag.lock().unwrap() = matches!(outcome, Err(CsError::Timeout));
+            })),
+        )
+        .unwrap();
+
+        clock.advance(chrono::Duration::seconds(9));
+        cs.sweep();
+        assert!(!*timed_out.lock().unwrap());
+        clock.advance(chrono::Duration::seconds(1));
+        cs.sweep();
+        assert!(*timed_out.lock().unwrap());
+    }
+
+    #[test]
+    fn get_configuration_answer_is_kept() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.get_configuration(
+            "cp1",
+            vec!["HeartbeatInterval".to_string(), "Foo".to_string()],
+        )
+        .unwrap();
+
+        let get = outbound.try_recv().unwrap();
+        assert_eq!(get.command, Some(Command::GetConfiguration));
+        assert_eq!(
+            get.payload.as_ref().unwrap()["key"],
+            array!["HeartbeatInterval", "Foo"]
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &get,
+            object! {
+                configurationKey : array![
+                    object! { key : "HeartbeatInterval", readonly : false, value : "60" },
+                    object! { key : "ChargePointId", readonly : true, value : "cp1" },
+                ],
+                unknownKey : array!["Foo"],
+            },
+        );
+
+        let configuration = cs.configuration("cp1").unwrap();
+        assert_eq!(configuration.get("HeartbeatInterval"), Some("60"));
+        assert!(configuration.keys["ChargePointId"].readonly);
+        assert_eq!(configuration.unknown_keys, vec!["Foo"]);
+    }
+
+    #[test]
+    fn change_configuration_status_updates_the_configuration() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        for (key, status) in [
+            ("A", "Accepted"),
+            ("B", "Rejected"),
+            ("C", "RebootRequired"),
+        ] {
+            cs.change_configuration("cp1", key, "1").unwrap();
+            let change = outbound.try_recv().unwrap();
+            assert_eq!(
+                change.payload.as_ref().unwrap(),
+                &object! { key : key, value : "1" }
+            );
+            answer(&mut cs, "cp1", &change, object! { status : status });
+        }
+
+        let configuration = cs.configuration("cp1").unwrap();
+        assert_eq!(configuration.get("A"), Some("1"));
+        assert_eq!(configuration.get("B"), None);
+        assert_eq!(configuration.get("C"), Some("1"));
+        assert!(configuration.reboot_required);
+    }
+
+    #[test]
+    fn trigger_message_is_sent_with_its_connector() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.trigger_message("cp1", "StatusNotification", Some(2), None)
+            .unwrap();
+
+        let trigger = outbound.try_recv().unwrap();
+        assert_eq!(trigger.command, Some(Command::TriggerMessage));
+        assert_eq!(
+            trigger.payload.as_ref().unwrap(),
+            &object! { requestedMessage : "StatusNotification", connectorId : 2 }
+        );
+        answer(&mut cs, "cp1", &trigger, object! { status : "Accepted" });
+        assert!(!cs.pending.contains(&trigger.id));
+    }
+
+    #[test]
+    fn trigger_of_unsupported_message_is_refused_before_sending() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        assert!(matches!(
+            cs.trigger_message("cp1", "StartTransaction", None, None),
+            Err(CsError::InvalidArgument(_))
+        ));
+        assert!(outbound.try_recv().is_err());
+    }
+
+    #[test]
+    fn accepted_change_availability_updates_the_connector() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.change_availability("cp1", 1, false).unwrap();
+        let change = outbound.try_recv().unwrap();
+        assert_eq!(
+            change.payload.as_ref().unwrap(),
+            &object! { connectorId : 1, type : "Inoperative" }
+        );
+        answer(&mut cs, "cp1", &change, object! { status : "Accepted" });
+
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.connectors[&1].availability, Availability::Inoperative);
+        assert_eq!(cp.availability, Availability::Operative);
+    }
+
+    #[test]
+    fn rejected_change_availability_keeps_the_state() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.change_availability("cp1", 1, false).unwrap();
+        let change = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &change, object! { status : "Rejected" });
+
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_ne!(
+            cp.connectors.get(&1).map(|c| c.availability),
+            Some(Availability::Inoperative)
+        );
+    }
+
+    #[test]
+    fn change_availability_of_connector_0_covers_the_station() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.change_availability("cp1", 1, true).unwrap();
+        let change = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &change, object! { status : "Accepted" });
+        cs.change_availability("cp1", 0, false).unwrap();
+        let change = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &change, object! { status : "Scheduled" });
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().availability,
+            Availability::Operative
+        );
+
+        cs.change_availability("cp1", 0, false).unwrap();
+        let change = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &change, object! { status : "Accepted" });
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.availability, Availability::Inoperative);
+        assert_eq!(cp.connectors[&1].availability, Availability::Inoperative);
+    }
+
+    #[test]
+    fn unlock_connector_returns_the_status() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let completion: Completion = Box::new(move |result| {
+            let _ = tx.send(result.map(|m| m.payload.clone().unwrap()));
+        });
+        cs.unlock_connector("cp1", 2, Some(completion)).unwrap();
+        let unlock = outbound.try_recv().unwrap();
+        assert_eq!(
+            unlock.payload.as_ref().unwrap(),
+            &object! { connectorId : 2 }
+        );
+        answer(&mut cs, "cp1", &unlock, object! { status : "UnlockFailed" });
+        assert_eq!(rx.try_recv().unwrap().unwrap()["status"], "UnlockFailed");
+    }
+
+    #[test]
+    fn unlock_of_the_whole_station_is_refused() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        assert!(matches!(
+            cs.unlock_connector("cp1", 0, None),
+            Err(CsError::InvalidArgument(_))
+        ));
+        assert!(outbound.try_recv().is_err());
+    }
+
+    #[test]
+    fn status_notification_of_connector_0_updates_the_station() {
+        let (mut cs, _) = central_system();
+        for (connector_id, status) in [(1, "Charging"), (0, "Unavailable")] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::StatusNotification,
+                object! { connectorId : connector_id, errorCode : "NoError", status : status },
+            );
+            assert_eq!(answer[0].role, MessageType::CallResult);
+        }
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.status.as_deref(), Some("Unavailable"));
+        assert_eq!(cp.connectors[&1].status.as_deref(), Some("Charging"));
+        assert!(!cp.connectors.contains_key(&0));
+    }
+
+    #[test]
+    fn charging_profiles_are_kept_until_cleared() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let profile = ChargingProfile::parse(&object! {
+            chargingProfileId : 7,
+            stackLevel : 1,
+            chargingProfilePurpose : "TxDefaultProfile",
+            chargingProfileKind : "Relative",
+            chargingSchedule : object! {
+                chargingRateUnit : "W",
+                chargingSchedulePeriod : array![object! { startPeriod : 0, limit : 7400.0 }],
+            },
+        })
+        .unwrap();
+        cs.set_charging_profile("cp1", 1, &profile).unwrap();
+        let set = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &set, object! { status : "Accepted" });
+        assert_eq!(cs.charging_profiles("cp1", 1), vec![profile]);
+
+        let criteria = ClearChargingProfile {
+            id: Some(7),
+            ..ClearChargingProfile::default()
+        };
+        cs.clear_charging_profile("cp1", &criteria).unwrap();
+        let clear = outbound.try_recv().unwrap();
+        assert_eq!(clear.payload.as_ref().unwrap(), &object! { id : 7 });
+        answer(&mut cs, "cp1", &clear, object! { status : "Accepted" });
+        assert!(cs.charging_profiles("cp1", 1).is_empty());
+    }
+
+    #[test]
+    fn composite_schedule_is_parsed_or_dropped_when_rejected() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.get_composite_schedule("cp1", 1, 3600, Some(ChargingRateUnit::W), None)
+            .unwrap();
+        let get = outbound.try_recv().unwrap();
+        assert_eq!(
+            get.payload.as_ref().unwrap(),
+            &object! { connectorId : 1, duration : 3600, chargingRateUnit : "W" }
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &get,
+            object! {
+                status : "Accepted",
+                connectorId : 1,
+                scheduleStart : "2024-01-01T00:00:00Z",
+                chargingSchedule : object! {
+                    chargingRateUnit : "W",
+                    chargingSchedulePeriod : array![
+                        object! { startPeriod : 0, limit : 11000.0 },
+                        object! { startPeriod : 600, limit : 3700.0 },
+                    ],
+                },
+            },
+        );
+        let schedule = cs.composite_schedule("cp1", 1).unwrap();
+        let periods: Vec<_> = schedule
+            .charging_schedule
+            .charging_schedule_period
+            .iter()
+            .map(|p| (p.start_period, p.limit))
+            .collect();
+        assert_eq!(periods, vec![(0, 11000.0), (600, 3700.0)]);
+
+        cs.get_composite_schedule("cp1", 1, 3600, None, None)
+            .unwrap();
+        let get = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &get, object! { status : "Rejected" });
+        assert!(cs.composite_schedule("cp1", 1).is_none());
+    }
+
+    #[test]
+    fn reservation_is_kept_until_cancelled() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.reserve_now("cp1", &reservation(5, 1), None).unwrap();
+        let reserve = outbound.try_recv().unwrap();
+        assert_eq!(
+            reserve.payload.as_ref().unwrap(),
+            &object! {
+                connectorId : 1,
+                expiryDate : "2024-01-01T01:00:00.000Z",
+                idTag : "TAG1",
+                reservationId : 5,
+            }
+        );
+        answer(&mut cs, "cp1", &reserve, object! { status : "Accepted" });
+        assert_eq!(cs.reservations("cp1"), vec![reservation(5, 1)]);
+
+        cs.cancel_reservation("cp1", 5, None).unwrap();
+        let cancel = outbound.try_recv().unwrap();
+        assert_eq!(
+            cancel.payload.as_ref().unwrap(),
+            &object! { reservationId : 5 }
+        );
+        answer(&mut cs, "cp1", &cancel, object! { status : "Accepted" });
+        assert!(cs.reservations("cp1").is_empty());
+        assert!(matches!(
+            cs.cancel_reservation("cp1", 5, None),
+            Err(CsError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn refused_reservation_is_not_kept() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        for status in ["Faulted", "Occupied", "Rejected", "Unavailable"] {
+            cs.reserve_now("cp1", &reservation(5, 1), None).unwrap();
+            let reserve = outbound.try_recv().unwrap();
+            answer(&mut cs, "cp1", &reserve, object! { status : status });
+            assert!(cs.reservations("cp1").is_empty());
+        }
+    }
+
+    fn local_list() -> Vec<LocalAuthorizationEntry> {
+        vec![
+            LocalAuthorizationEntry {
+                id_tag: "TAG1".to_string(),
+                id_tag_info: Some(crate::authorization::IdTagInfo {
+                    status: Status::Accepted,
+                    expiry_date: Some("2024-02-01T00:00:00.000Z".to_string()),
+                    parent_id_tag: None,
+                }),
+            },
+            LocalAuthorizationEntry {
+                id_tag: "TAG2".to_string(),
+                id_tag_info: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn full_local_list_is_sent_and_its_version_kept() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.send_local_list("cp1", 3, UpdateType::Full, &local_list())
+            .unwrap();
+        let send = outbound.try_recv().unwrap();
+        assert_eq!(send.command, Some(Command::SendLocalList));
+        assert_eq!(
+            send.payload.as_ref().unwrap(),
+            &object! {
+                listVersion : 3,
+                localAuthorizationList : array![
+                    object! {
+                        idTag : "TAG1",
+                        idTagInfo : object! {
+                            status : "Accepted",
+                            expiryDate : "2024-02-01T00:00:00.000Z",
+                        },
+                    },
+                    object! { idTag : "TAG2" },
+                ],
+                updateType : "Full",
+            }
+        );
+        assert_eq!(cs.local_list_version("cp1"), None);
+        answer(&mut cs, "cp1", &send, object! { status : "Accepted" });
+        assert_eq!(cs.local_list_version("cp1"), Some(3));
+    }
+
+    #[test]
+    fn differential_local_list_updates_the_version() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.send_local_list("cp1", 3, UpdateType::Full, &local_list())
+            .unwrap();
+        let full = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &full, object! { status : "Accepted" });
+
+        cs.send_local_list("cp1", 4, UpdateType::Differential, &local_list()[1..])
+            .unwrap();
+        let differential = outbound.try_recv().unwrap();
+        assert_eq!(
+            differential.payload.as_ref().unwrap()["updateType"],
+            "Differential"
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &differential,
+            object! { status : "Accepted" },
+        );
+        assert_eq!(cs.local_list_version("cp1"), Some(4));
+
+        /* A refused update keeps the version the charge point has */
+        cs.send_local_list("cp1", 5, UpdateType::Differential, &local_list())
+            .unwrap();
+        let refused = outbound.try_recv().unwrap();
+        answer(
+            &mut cs,
+            "cp1",
+            &refused,
+            object! { status : "VersionMismatch" },
+        );
+        assert_eq!(cs.local_list_version("cp1"), Some(4));
+    }
+
+    #[test]
+    fn local_list_version_is_read_from_the_charge_point() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.get_local_list_version("cp1").unwrap();
+        let get = outbound.try_recv().unwrap();
+        assert_eq!(get.command, Some(Command::GetLocalListVersion));
+        answer(&mut cs, "cp1", &get, object! { listVersion : 7 });
+        assert_eq!(cs.local_list_version("cp1"), Some(7));
+    }
+
+    #[test]
+    fn last_firmware_and_diagnostics_statuses_are_kept() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        for status in ["Downloading", "Downloaded", "Installing", "Installed"] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::FirmwareStatusNotification,
+                object! { status : status },
+            );
+            assert_eq!(answer[0].payload.as_ref().unwrap(), &object! {});
+        }
+        for status in ["Uploading", "Uploaded"] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::DiagnosticsStatusNotification,
+                object! { status : status },
+            );
+            assert_eq!(answer[0].payload.as_ref().unwrap(), &object! {});
+        }
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.firmware_status.as_deref(), Some("Installed"));
+        assert_eq!(cp.diagnostics_status.as_deref(), Some("Uploaded"));
+    }
+
+    #[test]
+    fn firmware_update_is_followed_by_status_notifications() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let update = FirmwareUpdate {
+            location: "https://firmware.example.com/cp-2.0.bin".to_string(),
+            retrieve_date: clock.now(),
+            retries: Some(3),
+            retry_interval: None,
+        };
+        cs.update_firmware("cp1", &update).unwrap();
+        let call_sent = outbound.try_recv().unwrap();
+        assert_eq!(call_sent.command, Some(Command::UpdateFirmware));
+        assert_eq!(
+            call_sent.payload.as_ref().unwrap(),
+            &object! {
+                location : "https://firmware.example.com/cp-2.0.bin",
+                retrieveDate : "2024-01-01T00:00:00.000Z",
+                retries : 3,
+            }
+        );
+        answer(&mut cs, "cp1", &call_sent, object! {});
+
+        for status in ["Downloading", "Downloaded", "Installing", "Installed"] {
+            call(
+                &mut cs,
+                "cp1",
+                Command::FirmwareStatusNotification,
+                object! { status : status },
+            );
+            assert_eq!(
+                cs.charge_point("cp1").unwrap().firmware_status.as_deref(),
+                Some(status)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_locations_are_not_sent() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        for location in ["", "firmware.bin", "https://", "not a url"] {
+            let update = FirmwareUpdate {
+                location: location.to_string(),
+                retrieve_date: clock.now(),
+                retries: None,
+                retry_interval: None,
+            };
+            assert!(matches!(
+                cs.update_firmware("cp1", &update),
+                Err(CsError::InvalidArgument(_))
+            ));
+            let request = DiagnosticsRequest {
+                location: location.to_string(),
+                start_time: None,
+                stop_time: None,
+                retries: None,
+                retry_interval: None,
+            };
+            assert!(matches!(
+                cs.get_diagnostics("cp1", &request, None),
+                Err(CsError::InvalidArgument(_))
+            ));
+        }
+        assert!(outbound.try_recv().is_err());
+    }
+
+    #[test]
+    fn diagnostics_file_name_is_kept() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let request = DiagnosticsRequest {
+            location: "ftp://diagnostics.example.com/upload".to_string(),
+            start_time: Some(clock.now()),
+            stop_time: None,
+            retries: None,
+            retry_interval: Some(60),
+        };
+        cs.get_diagnostics("cp1", &request, None).unwrap();
+        let call_sent = outbound.try_recv().unwrap();
+        assert_eq!(
+            call_sent.payload.as_ref().unwrap(),
+            &object! {
+                location : "ftp://diagnostics.example.com/upload",
+                startTime : "2024-01-01T00:00:00.000Z",
+                retryInterval : 60,
+            }
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &call_sent,
+            object! { fileName : "diagnostics-cp1.zip" },
+        );
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().diagnostics_file.as_deref(),
+            Some("diagnostics-cp1.zip")
+        );
+    }
+
+    #[test]
+    fn strict_validation_refuses_schema_violations() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        cs.set_validation(Validation::Strict);
+
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::Authorize,
+            object! { idTag : "TAG1" },
+        );
+        assert_eq!(answer[0].role, MessageType::CallResult);
+
+        let answer = call(&mut cs, "cp1", Command::Authorize, object! { idTag : 42 });
+        assert_eq!(answer[0].role, MessageType::CallError);
+        let payload = answer[0].payload.as_ref().unwrap();
+        assert_eq!(payload["errorCode"], "FormationViolation");
+        assert!(payload["errorDescription"]
+            .as_str()
+            .unwrap()
+            .contains("idTag"));
+    }
+
+    #[test]
+    fn lenient_validation_processes_schema_violations() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        for validation in [Validation::Lenient, Validation::Off] {
+            cs.set_validation(validation);
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::Authorize,
+                object! { idTag : "TAG1", unexpected : true },
+            );
+            assert_eq!(answer[0].role, MessageType::CallResult);
+        }
+    }
+
+    #[test]
+    fn unknown_action_is_named_in_the_call_error() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::Unknown("SetDisplayMessage".to_string()),
+            object! {},
+        );
+        assert_eq!(answer[0].role, MessageType::CallError);
+        let payload = answer[0].payload.as_ref().unwrap();
+        assert_eq!(payload["errorCode"], "NotImplemented");
+        assert!(payload["errorDescription"]
+            .as_str()
+            .unwrap()
+            .contains("SetDisplayMessage"));
+    }
+
+    #[test]
+    fn certificate_signed_carries_the_chain_leaf_first() {
+        for version in [ProtocolVersion::Ocpp16, ProtocolVersion::Ocpp201] {
+            let (mut cs, _) = central_system();
+            let field = match version {
+                ProtocolVersion::Ocpp16 => "typeOfCertificate",
+                ProtocolVersion::Ocpp201 => "certificateType",
+            };
+            let mut payload = object! { csr : csr_pem("cp1") };
+            payload[field] = "ChargingStationCertificate".into();
+            let request = Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::SignCertificate),
+                Some(payload),
+            )
+            .with_protocol(version);
+            let answer = cs.make_response("cp1", request).unwrap();
+            let signed = answer[1].payload.as_ref().unwrap();
+
+            let pems: Vec<String> = match version {
+                ProtocolVersion::Ocpp16 => signed["cert"]
+                    .members()
+                    .map(|pem| pem.as_str().unwrap().to_string())
+                    .collect(),
+                ProtocolVersion::Ocpp201 => {
+                    let chain = signed["certificateChain"].as_str().unwrap();
+                    openssl::x509::X509::stack_from_pem(chain.as_bytes())
+                        .unwrap()
+                        .iter()
+                        .map(|c| String::from_utf8(c.to_pem().unwrap()).unwrap())
+                        .collect()
+                }
+            };
+            assert_eq!(pems.len(), 2);
+            let leaf = openssl::x509::X509::from_pem(pems[0].as_bytes()).unwrap();
+            let issuer = openssl::x509::X509::from_pem(pems[1].as_bytes()).unwrap();
+            assert!(leaf.verify(&issuer.public_key().unwrap()).unwrap());
+            assert_eq!(issuer.to_der().unwrap(), cs.root_certificate_der().unwrap());
+        }
+    }
+
+    #[test]
+    fn certificate_is_installed_listed_and_deleted() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let root = String::from_utf8(cs.root_certificate_pem().unwrap()).unwrap();
+        let hash_data = CertificateHashData::from_pem(&root).unwrap();
+
+        cs.install_certificate(
+            "cp1",
+            CertificateType::CentralSystemRootCertificate,
+            &root,
+            None,
+        )
+        .unwrap();
+        let install = outbound.try_recv().unwrap();
+        assert_eq!(install.command, Some(Command::InstallCertificate));
+        answer(&mut cs, "cp1", &install, object! { status : "Accepted" });
+        let installed = cs.installed_certificates("cp1");
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].hash_data, hash_data);
+        assert_eq!(installed[0].certificate.as_deref(), Some(root.as_str()));
+
+        cs.get_installed_certificate_ids(
+            "cp1",
+            &[CertificateType::CentralSystemRootCertificate],
+            None,
+        )
+        .unwrap();
+        let get = outbound.try_recv().unwrap();
+        assert_eq!(
+            get.payload.as_ref().unwrap(),
+            &object! { certificateType : "CentralSystemRootCertificate" }
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &get,
+            object! {
+                status : "Accepted",
+                certificateHashData : array![json::JsonValue::from(&hash_data)],
+            },
+        );
+        let listed = cs.installed_certificates("cp1");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].hash_data, hash_data);
+        /* The PEM installed through the central system is kept */
+        assert!(listed[0].certificate.is_some());
+
+        cs.delete_certificate("cp1", &hash_data, None).unwrap();
+        let delete = outbound.try_recv().unwrap();
+        assert_eq!(
+            delete.payload.as_ref().unwrap()["certificateHashData"],
+            json::JsonValue::from(&hash_data)
+        );
+        answer(&mut cs, "cp1", &delete, object! { status : "Accepted" });
+        assert!(cs.installed_certificates("cp1").is_empty());
+    }
+
+    #[test]
+    fn refused_or_invalid_certificates_are_not_installed() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let root = String::from_utf8(cs.root_certificate_pem().unwrap()).unwrap();
+        for status in ["Rejected", "Failed"] {
+            cs.install_certificate("cp1", CertificateType::CSMSRootCertificate, &root, None)
+                .unwrap();
+            let install = outbound.try_recv().unwrap();
+            answer(&mut cs, "cp1", &install, object! { status : status });
+        }
+        assert!(cs.installed_certificates("cp1").is_empty());
+
+        assert!(matches!(
+            cs.install_certificate(
+                "cp1",
+                CertificateType::CSMSRootCertificate,
+                "not a certificate",
+                None
+            ),
+            Err(CsError::InvalidArgument(_))
+        ));
+        assert!(outbound.try_recv().is_err());
+    }
+
+    fn authorize(cs: &mut CentralSystem, cp_id: &str, id_tag: &str) -> json::JsonValue {
+        let answer = call(cs, cp_id, Command::Authorize, object! { idTag : id_tag });
+        answer[0].payload.clone().unwrap()
+    }
+
+    #[test]
+    fn expiry_is_now_plus_the_ttl() {
+        let (mut cs, clock) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        cs.authorization_list()
+            .set_default_ttl(chrono::Duration::hours(1));
+        cs.authorization_list()
+            .set_ttl("TAG2", chrono::Duration::minutes(5));
+
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG1")["idTagInfo"]["expiryDate"],
+            "2024-01-01T01:00:00.000Z"
+        );
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG2")["idTagInfo"]["expiryDate"],
+            "2024-01-01T00:05:00.000Z"
+        );
+
+        clock.advance(chrono::Duration::minutes(30));
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::StartTransaction,
+            object! {
+                connectorId : 1,
+                idTag : "TAG3",
+                meterStart : 0,
+                timestamp : "2024-01-01T00:30:00Z",
+            },
+        );
+        assert_eq!(
+            answer[0].payload.as_ref().unwrap()["idTagInfo"]["expiryDate"],
+            "2024-01-01T01:30:00.000Z"
+        );
+    }
+
+    #[test]
+    fn default_expiry_is_far_in_the_future() {
+        let (mut cs, clock) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let expiry = authorize(&mut cs, "cp1", "TAG1")["idTagInfo"]["expiryDate"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let expiry = DateTime::parse_from_rfc3339(&expiry).unwrap();
+        assert!(expiry > clock.now() + chrono::Duration::days(365));
+    }
+
+    #[test]
+    fn boot_notification_time_has_the_configured_format() {
+        let (mut cs, clock) = central_system();
+        clock.advance(chrono::Duration::milliseconds(1500));
+        for (format, expected) in [
+            (SecondsFormat::Millis, "2024-01-01T00:00:01.500Z"),
+            (SecondsFormat::Secs, "2024-01-01T00:00:01Z"),
+            (SecondsFormat::Micros, "2024-01-01T00:00:01.500000Z"),
+        ] {
+            cs.set_timestamp_format(format);
+            assert_eq!(boot(&mut cs, "cp1", "m1")["currentTime"], expected);
+        }
+    }
+
+    #[test]
+    fn one_shot_rejected_boot_notification() {
+        let (mut cs, _) = central_system();
+        cs.faults().inject(
+            "BootNotification",
+            Fault::Status("Rejected".to_string()),
+            Some(1),
+        );
+        let rejected = boot(&mut cs, "cp1", "m1");
+        assert_eq!(rejected["status"], "Rejected");
+        assert!(rejected["currentTime"].is_string());
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Accepted");
+        assert_eq!(cs.faults().faults(), object! {});
+    }
+
+    #[test]
+    fn persistent_sign_certificate_error() {
+        let (mut cs, _) = central_system();
+        cs.faults().inject(
+            "SignCertificate",
+            Fault::CallError {
+                code: OcppErrorCode::SecurityError,
+                description: "no certificates today".to_string(),
+                details: object! {},
+            },
+            None,
+        );
+        for _ in 0..3 {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::SignCertificate,
+                object! {
+                    csr : csr_pem("cp1"),
+                    typeOfCertificate : "ChargePointCertificate",
+                },
+            );
+            /* No CertificateSigned follows */
+            assert_eq!(answer.len(), 1);
+            let error = answer[0].error().unwrap();
+            assert_eq!(error.code, "SecurityError");
+            assert_eq!(error.description, "no certificates today");
+        }
+
+        assert!(cs.faults().clear("SignCertificate"));
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::SignCertificate,
+            object! {
+                csr : csr_pem("cp1"),
+                typeOfCertificate : "ChargePointCertificate",
+            },
+        );
+        assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+        assert_eq!(answer[1].command, Some(Command::CertificateSigned));
+    }
+
+    fn heartbeat(cs: &mut CentralSystem, cp_id: &str, id: &str) -> Message {
+        let request = Message::new(
+            MessageType::Call,
+            id.to_string(),
+            Some(Command::Heartbeat),
+            Some(object! {}),
+        );
+        cs.make_response(cp_id, request).unwrap().remove(0)
+    }
+
+    #[test]
+    fn duplicate_id_is_rejected_within_the_window() {
+        let (mut cs, clock) = central_system();
+        cs.set_duplicate_ids(DuplicateIds::Reject);
+        assert_eq!(heartbeat(&mut cs, "cp1", "a").role, MessageType::CallResult);
+
+        let duplicate = heartbeat(&mut cs, "cp1", "a");
+        assert_eq!(duplicate.role, MessageType::CallError);
+        assert_eq!(duplicate.id, "a");
+        assert_eq!(duplicate.error().unwrap().code, "ProtocolError");
+
+        /* Ids are per charge point and expire with the window */
+        assert_eq!(heartbeat(&mut cs, "cp2", "a").role, MessageType::CallResult);
+        clock.advance(chrono::Duration::seconds(DUPLICATE_ID_WINDOW + 1));
+        assert_eq!(heartbeat(&mut cs, "cp1", "a").role, MessageType::CallResult);
+    }
+
+    #[test]
+    fn duplicate_id_is_answered_when_logged_or_off() {
+        for duplicate_ids in [DuplicateIds::Log, DuplicateIds::Off] {
+            let (mut cs, _) = central_system();
+            cs.set_duplicate_ids(duplicate_ids);
+            heartbeat(&mut cs, "cp1", "a");
+            assert_eq!(heartbeat(&mut cs, "cp1", "a").role, MessageType::CallResult);
+        }
+    }
+
+    fn meter_values(transaction_id: Option<u32>) -> json::JsonValue {
+        let mut payload = object! {
+            connectorId : 1,
+            meterValue : array![
+                object! {
+                    timestamp : "2024-01-01T00:10:00Z",
+                    sampledValue : array![object! {
+                        value : "1.5",
+                        measurand : "Energy.Active.Import.Register",
+                        unit : "kWh",
+                        context : "Sample.Periodic",
+                    }],
+                },
+                object! {
+                    timestamp : "2024-01-01T00:20:00Z",
+                    sampledValue : array![
+                        object! {
+                            value : "2500",
+                            measurand : "Energy.Active.Import.Register",
+                            unit : "Wh",
+                        },
+                        object! {
+                            value : "11000",
+                            measurand : "Power.Active.Import",
+                            unit : "W",
+                        },
+                    ],
+                },
+            ],
+        };
+        if let Some(id) = transaction_id {
+            payload["transactionId"] = id.into();
+        }
+        payload
+    }
+
+    #[test]
+    fn energy_samples_are_retained() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 1000);
+        let answer = call(&mut cs, "cp1", Command::MeterValues, meter_values(Some(id)));
+        assert_eq!(answer[0].payload.as_ref().unwrap(), &object! {});
+
+        let readings = cs.meter_readings("cp1", 1).unwrap();
+        assert_eq!(readings.energy_wh(), Some(2500.0));
+        assert_eq!(
+            readings
+                .get(crate::metering::Measurand::PowerActiveImport)
+                .unwrap()
+                .value,
+            "11000"
+        );
+        let readings = cs.transaction_readings(id).unwrap();
+        assert_eq!(readings.energy_wh(), Some(2500.0));
+        assert_eq!(
+            readings.timestamp.unwrap().to_rfc3339(),
+            "2024-01-01T00:20:00+00:00"
+        );
+    }
+
+    #[test]
+    fn samples_of_inactive_transactions_only_count_for_the_connector() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+        stop_transaction(&mut cs, "cp1", id, 100);
+        for transaction_id in [id, 4242] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::MeterValues,
+                meter_values(Some(transaction_id)),
+            );
+            assert_eq!(answer[0].role, MessageType::CallResult);
+            assert!(cs.transaction_readings(4242).is_none());
+        }
+        assert_eq!(
+            cs.meter_readings("cp1", 1).unwrap().energy_wh(),
+            Some(2500.0)
+        );
+    }
+
+    /* The issuer of the certificate signed for the type */
+    fn signed_issuer(cs: &mut CentralSystem, certificate_type: &str) -> openssl::x509::X509 {
+        let answer = call(
+            cs,
+            "cp1",
+            Command::SignCertificate,
+            object! { csr : csr_pem("cp1"), typeOfCertificate : certificate_type },
+        );
+        let signed = answer[1].payload.as_ref().unwrap();
+        assert_eq!(signed["typeOfCertificate"], certificate_type);
+        let leaf =
+            openssl::x509::X509::from_pem(signed["cert"][0].as_str().unwrap().as_bytes()).unwrap();
+        let issuer =
+            openssl::x509::X509::from_pem(signed["cert"][1].as_str().unwrap().as_bytes()).unwrap();
+        assert!(leaf.verify(&issuer.public_key().unwrap()).unwrap());
+        issuer
+    }
+
+    #[test]
+    fn certificate_types_are_signed_by_distinct_issuers() {
+        let (mut cs, _) = central_system();
+        let station = signed_issuer(&mut cs, "ChargingStationCertificate");
+        let v2g = signed_issuer(&mut cs, "V2GCertificate");
+        assert_ne!(station.to_der().unwrap(), v2g.to_der().unwrap());
+        assert_ne!(
+            station.subject_name().to_der().unwrap(),
+            v2g.subject_name().to_der().unwrap()
+        );
+        assert_eq!(
+            signed_issuer(&mut cs, "ChargePointCertificate")
+                .to_der()
+                .unwrap(),
+            station.to_der().unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_certificate_type_is_refused() {
+        let (mut cs, _) = central_system();
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::SignCertificate,
+            object! { csr : csr_pem("cp1"), typeOfCertificate : "ContractCertificate" },
+        );
+        assert_eq!(answer.len(), 1);
+        assert_eq!(
+            answer[0].error().unwrap().code,
+            "PropertyConstraintViolation"
+        );
+    }
+
+    fn ev_certificate(cs: &mut CentralSystem, action: &str, exi_request: &str) -> json::JsonValue {
+        let request = Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(Command::Get15118EVCertificate),
+            Some(object! {
+                iso15118SchemaVersion : "urn:iso:15118:2:2013:MsgDef",
+                action : action,
+                exiRequest : exi_request,
+            }),
+        )
+        .with_protocol(ProtocolVersion::Ocpp201);
+        cs.make_response("cp1", request).unwrap()[0]
+            .payload
+            .clone()
+            .unwrap()
+    }
+
+    #[test]
+    fn ev_certificate_round_trip() {
+        let (mut cs, _) = central_system();
+        let exi_request = base64::encode_block(b"exi stream");
+        for action in ["Install", "Update"] {
+            let answer = ev_certificate(&mut cs, action, &exi_request);
+            assert_eq!(answer["status"], "Accepted");
+            let exi_response =
+                base64::decode_block(answer["exiResponse"].as_str().unwrap()).unwrap();
+            let certificate = openssl::x509::X509::from_der(&exi_response).unwrap();
+            assert_eq!(
+                certificate.to_der().unwrap(),
+                cs.ca
+                    .read()
+                    .unwrap()
+                    .issuer_certificate_der(CertificateSigningUse::V2G)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_ev_certificate_request_fails() {
+        let (mut cs, _) = central_system();
+        let exi_request = base64::encode_block(b"exi stream");
+        for (action, exi_request) in [("Delete", exi_request.as_str()), ("Install", "not base64!")]
+        {
+            assert_eq!(
+                ev_certificate(&mut cs, action, exi_request),
+                object! { status : "Failed", exiResponse : "" }
+            );
+        }
+    }
+
+    #[test]
+    fn ca_that_cant_initialize_fails_the_build() {
+        let settings = DefaultCertificationAuthoritySettings {
+            directory: "/dev/null/ca".to_string(),
+            ..DefaultCertificationAuthoritySettings::default()
+        };
+        assert!(matches!(
+            CentralSystem::with_ca_settings(settings),
+            Err(CsError::CaError(_))
+        ));
+    }
+
+    fn leaf_serial(answer: &[Message]) -> u64 {
+        let signed = answer[1].payload.as_ref().unwrap();
+        let leaf =
+            openssl::x509::X509::from_pem(signed["cert"][0].as_str().unwrap().as_bytes()).unwrap();
+        let serial = leaf.serial_number().to_bn().unwrap();
+        serial.to_dec_str().unwrap().parse().unwrap()
+    }
+
+    fn sign_csr(cs: &mut CentralSystem, csr: &str) -> Vec<Message> {
+        call(
+            cs,
+            "cp1",
+            Command::SignCertificate,
+            object! { csr : csr, typeOfCertificate : "ChargingStationCertificate" },
+        )
+    }
+
+    #[test]
+    fn concurrent_identical_csrs_are_signed_once() {
+        let (cs, clock) = central_system();
+        let cs = Arc::new(Mutex::new(cs));
+        let csr = csr_pem("cp1");
+        let retries: Vec<_> = (0..2)
+            .map(|_| {
+                let cs = Arc::clone(&cs);
+                let csr = csr.clone();
+                std::thread::spawn(move || sign_csr(&mut cs.lock().unwrap(), &csr))
+            })
+            .collect();
+        let answers: Vec<_> = retries.into_iter().map(|t| t.join().unwrap()).collect();
+        let mut cs = cs.lock().unwrap();
+
+        assert_eq!(answers[0][1].payload, answers[1][1].payload);
+        let serial = leaf_serial(&answers[0]);
+
+        /* A single serial was used up */
+        let other = sign_csr(&mut cs, &csr_pem("cp1"));
+        assert_eq!(leaf_serial(&other), serial + 1);
+
+        /* The same CSR after the retry window is signed again */
+        clock.advance(chrono::Duration::seconds(SIGN_RETRY_WINDOW));
+        let again = sign_csr(&mut cs, &csr);
+        assert_eq!(leaf_serial(&again), serial + 2);
+    }
+
+    #[test]
+    fn pending_station_is_promoted_and_accepted() {
+        let (mut cs, _) = central_system();
+        cs.set_registration_status(RegistrationStatus::Pending);
+        let rx = connect(&mut cs, "cp1", 1);
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Pending");
+
+        /* No transactions before being accepted */
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::StartTransaction,
+            object! {
+                connectorId : 1,
+                idTag : "TAG1",
+                meterStart : 0,
+                timestamp : "2024-01-01T00:00:00.000Z",
+            },
+        );
+        assert_eq!(answer[0].role, MessageType::CallError);
+        assert_eq!(
+            answer[0].payload.as_ref().unwrap()["errorCode"],
+            "SecurityError"
+        );
+        let answer = call(&mut cs, "cp1", Command::MeterValues, meter_values(None));
+        assert_eq!(answer[0].role, MessageType::CallError);
+
+        /* The promotion triggers a new BootNotification */
+        cs.accept("cp1", None).unwrap();
+        let trigger = rx.try_recv().unwrap();
+        assert_eq!(trigger.command, Some(Command::TriggerMessage));
+        assert_eq!(
+            trigger.payload.as_ref().unwrap()["requestedMessage"],
+            "BootNotification"
+        );
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Accepted");
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().registration,
+            Some(RegistrationStatus::Accepted)
+        );
+        start_transaction(&mut cs, "cp1", 1, 0);
+    }
+
+    #[test]
+    fn accepting_an_unknown_station_fails() {
+        let (mut cs, _) = central_system();
+        assert!(matches!(
+            cs.accept("cp1", None),
+            Err(CsError::UnknownChargePoint(_))
+        ));
+    }
+
+    #[test]
+    fn acknowledged_notifications_get_an_empty_conf() {
+        let (mut cs, _) = central_system();
+        for action in ACKNOWLEDGED_NOTIFICATIONS.iter() {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::Unknown(action.to_string()),
+                object! {},
+            );
+            assert_eq!(answer[0].role, MessageType::CallResult, "{}", action);
+            assert_eq!(answer[0].payload, Some(object! {}));
+        }
+    }
+
+    #[test]
+    fn actions_only_the_central_system_sends_are_not_supported() {
+        let (mut cs, _) = central_system();
+        let answer = call(&mut cs, "cp1", Command::Reset, object! { type : "Hard" });
+        assert_eq!(answer[0].role, MessageType::CallError);
+        assert_eq!(
+            answer[0].payload.as_ref().unwrap()["errorCode"],
+            "NotSupported"
+        );
+
+        /* Unless a default handler answers them */
+        cs.set_default_handler(Some(Box::new(|request: &Message| {
+            Ok(vec![Message::new(
+                MessageType::CallResult,
+                request.id.clone(),
+                None,
+                Some(object! { status : "Accepted" }),
+            )])
+        })));
+        let answer = call(&mut cs, "cp1", Command::Reset, object! { type : "Hard" });
+        assert_eq!(answer[0].role, MessageType::CallResult);
+        assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+    }
+
+    fn status_notification(cs: &mut CentralSystem, error_code: &str, status: &str) {
+        let answer = call(
+            cs,
+            "cp1",
+            Command::StatusNotification,
+            object! {
+                connectorId : 1,
+                errorCode : error_code,
+                status : status,
+                info : "earth leakage",
+                vendorErrorCode : "E42",
+                timestamp : "2024-01-01T00:00:00.000Z",
+            },
+        );
+        assert_eq!(answer[0].role, MessageType::CallResult);
+    }
+
+    #[test]
+    fn ground_failure_is_reported_then_cleared() {
+        let (mut cs, clock) = central_system();
+        assert!(cs.connector_errors("cp1", 1).is_none());
+
+        status_notification(&mut cs, "GroundFailure", "Faulted");
+        let errors = cs.connector_errors("cp1", 1).unwrap();
+        assert!(errors.is_faulted());
+        let current = errors.current.as_ref().unwrap();
+        assert_eq!(current.error_code, "GroundFailure");
+        assert_eq!(current.info.as_deref(), Some("earth leakage"));
+        assert_eq!(current.vendor_error_code.as_deref(), Some("E42"));
+        assert_eq!(current.timestamp, Some(clock.now()));
+
+        status_notification(&mut cs, "NoError", "Available");
+        let errors = cs.connector_errors("cp1", 1).unwrap();
+        assert!(!errors.is_faulted());
+        let codes: Vec<&str> = errors
+            .history
+            .iter()
+            .map(|e| e.error_code.as_str())
+            .collect();
+        assert_eq!(codes, ["GroundFailure", "NoError"]);
+    }
+
+    #[test]
+    fn fault_history_keeps_transitions_only_and_is_bounded() {
+        let (mut cs, _) = central_system();
+        status_notification(&mut cs, "NoError", "Available");
+        status_notification(&mut cs, "NoError", "Preparing");
+        assert_eq!(cs.connector_errors("cp1", 1).unwrap().history.len(), 0);
+
+        for _ in 0..20 {
+            status_notification(&mut cs, "OverCurrentFailure", "Faulted");
+            status_notification(&mut cs, "NoError", "Available");
+        }
+        let history = &cs.connector_errors("cp1", 1).unwrap().history;
+        assert_eq!(history.len(), 16);
+        assert_eq!(history.back().unwrap().error_code, "NoError");
+    }
+
+    #[test]
+    fn certificate_signed_confirmation_is_matched() {
+        let (mut cs, _) = central_system();
+        cs.set_unmatched_responses(UnmatchedResponses::Error);
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let messages = sign_csr(&mut cs, &csr_pem("cp1"));
+
+        /* The answer to the request goes first */
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, MessageType::CallResult);
+        assert_eq!(messages[0].id, "1");
+        assert_eq!(messages[1].role, MessageType::Call);
+        assert_eq!(messages[1].command, Some(Command::CertificateSigned));
+
+        answer(
+            &mut cs,
+            "cp1",
+            &messages[1],
+            object! { status : "Accepted" },
+        );
+        /* Matched once only */
+        let again = Message::new(
+            MessageType::CallResult,
+            messages[1].id.clone(),
+            None,
+            Some(object! { status : "Accepted" }),
+        );
+        assert!(matches!(
+            cs.make_response("cp1", again),
+            Err(CsError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn rejected_sign_certificate_awaits_nothing() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let answer = sign_csr(&mut cs, "not a CSR");
+        assert!(answer.iter().all(|m| m.role != MessageType::Call));
+        assert!(cs.pending.is_empty());
+    }
+
+    #[test]
+    fn ocpp16_authorize_answers_an_id_tag_info() {
+        let (mut cs, _) = central_system();
+        cs.authorization_list().set_parent("TAG1", "FLEET");
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::Authorize,
+            object! { idTag : "TAG1", evseId : 1 },
+        );
+        let payload = answer[0].payload.as_ref().unwrap();
+        assert_eq!(payload.len(), 1);
+        let info = &payload["idTagInfo"];
+        assert_eq!(info["status"], "Accepted");
+        assert_eq!(info["parentIdTag"], "FLEET");
+        assert!(info["expiryDate"].is_string());
+
+        /* The 2.0.1 shape is a violation of a 1.6 call */
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::Authorize,
+            object! { idToken : object! { idToken : "TAG1", type : "ISO14443" } },
+        );
+        assert_eq!(answer[0].role, MessageType::CallError);
+    }
+
+    #[test]
+    fn ocpp201_authorize_answers_an_id_token_info() {
+        let (mut cs, _) = central_system();
+        cs.authorization_list().set_parent("TAG1", "FLEET");
+        cs.authorization_list().set("TAG2", Status::Blocked);
+        let authorize = |cs: &mut CentralSystem, id_token: &str, kind: &str| {
+            let request = Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::Authorize),
+                Some(object! { idToken : object! { idToken : id_token, type : kind } }),
+            )
+            .with_protocol(ProtocolVersion::Ocpp201);
+            cs.make_response("cp1", request).unwrap().remove(0)
+        };
+
+        let answer = authorize(&mut cs, "TAG1", "eMAID");
+        let payload = answer.payload.as_ref().unwrap();
+        assert_eq!(payload.len(), 1);
+        let info = &payload["idTokenInfo"];
+        assert_eq!(info["status"], "Accepted");
+        assert!(info["cacheExpiryDateTime"].is_string());
+        assert_eq!(
+            info["groupIdToken"],
+            object! { idToken : "FLEET", type : "eMAID" }
+        );
+
+        let answer = authorize(&mut cs, "TAG2", "ISO14443");
+        let info = &answer.payload.as_ref().unwrap()["idTokenInfo"];
+        assert_eq!(info["status"], "Blocked");
+        assert!(info["groupIdToken"].is_null());
+
+        let answer = authorize(&mut cs, "TAG1", "Iso14443");
+        assert_eq!(answer.role, MessageType::CallError);
+        assert_eq!(
+            answer.payload.as_ref().unwrap()["errorCode"],
+            "PropertyConstraintViolation"
+        );
+    }
+
+    #[test]
+    fn cached_authorization_is_served_until_cleared() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.authorization_list()
+            .set_default_ttl(chrono::Duration::hours(1));
+        let expiry = |cs: &mut CentralSystem| {
+            authorize(cs, "cp1", "TAG1")["idTagInfo"]["expiryDate"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(expiry(&mut cs), "2024-01-01T01:00:00.000Z");
+        assert_eq!(cs.authorization_cache().entries().len(), 1);
+        /* A hit keeps the expiry of the first lookup */
+        clock.advance(chrono::Duration::minutes(10));
+        assert_eq!(expiry(&mut cs), "2024-01-01T01:00:00.000Z");
+
+        cs.clear_cache("cp1", None).unwrap();
+        assert_eq!(
+            outbound.try_recv().unwrap().command,
+            Some(Command::ClearCache)
+        );
+        assert!(cs.authorization_cache().entries().is_empty());
+        assert_eq!(expiry(&mut cs), "2024-01-01T01:10:00.000Z");
+
+        /* Expired decisions are looked up again */
+        clock.advance(chrono::Duration::hours(1));
+        assert_eq!(expiry(&mut cs), "2024-01-01T02:10:00.000Z");
+        assert_eq!(cs.clear_authorization_cache(), 1);
+    }
+
+    #[test]
+    fn list_changes_drop_cached_decisions() {
+        let (mut cs, _) = central_system();
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG1")["idTagInfo"]["status"],
+            "Accepted"
+        );
+        cs.authorization_list().set("TAG1", Status::Blocked);
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG1")["idTagInfo"]["status"],
+            "Blocked"
+        );
+    }
+
+    /* A CSR whose signature doesn't match its key */
+    fn tampered_csr_pem() -> String {
+        let csr = openssl::x509::X509Req::from_pem(csr_pem("cp1").as_bytes()).unwrap();
+        let mut der = csr.to_der().unwrap();
+        let last = der.len() - 1;
+        der[last] ^= 0x01;
+        let csr = openssl::x509::X509Req::from_der(&der).unwrap();
+        String::from_utf8(csr.to_pem().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn failed_signing_is_rejected_without_a_certificate_signed() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let answer = sign_csr(&mut cs, &tampered_csr_pem());
+        assert_eq!(answer.len(), 1);
+        assert_eq!(answer[0].role, MessageType::CallResult);
+        assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Rejected");
+        assert!(cs.pending.is_empty());
+
+        /* A valid CSR is signed afterwards */
+        let answer = sign_csr(&mut cs, &csr_pem("cp1"));
+        assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+        assert_eq!(answer[1].command, Some(Command::CertificateSigned));
+    }
+
+    #[test]
+    fn heartbeats_are_triggered_at_the_interval() {
+        let (mut cs, clock) = central_system();
+        cs.set_heartbeat_trigger_interval(60);
+        let outbound = connect(&mut cs, "cp1", 1);
+
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+
+        clock.advance(chrono::Duration::seconds(60));
+        cs.sweep();
+        let trigger = outbound.try_recv().unwrap();
+        assert_eq!(trigger.command, Some(Command::TriggerMessage));
+        assert_eq!(
+            trigger.payload.as_ref().unwrap()["requestedMessage"],
+            "Heartbeat"
+        );
+        /* Awaited as any call of the central system */
+        assert!(cs.pending.contains(&trigger.id));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+
+        clock.advance(chrono::Duration::seconds(60));
+        cs.sweep();
+        assert!(outbound.try_recv().is_ok());
+
+        /* Disconnected stations are left alone */
+        OcppCentralSystem::disconnect(&mut cs, "cp1", 1);
+        clock.advance(chrono::Duration::seconds(60));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+    }
+
+    #[test]
+    fn heartbeats_are_not_triggered_by_default() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 1);
+        clock.advance(chrono::Duration::days(1));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+    }
+
+    fn start_with_tag(cs: &mut CentralSystem, connector_id: u32, id_tag: &str) -> json::JsonValue {
+        let answer = call(
+            cs,
+            "cp1",
+            Command::StartTransaction,
+            object! {
+                connectorId : connector_id,
+                idTag : id_tag,
+                meterStart : 0,
+                timestamp : "2024-01-01T00:00:00Z",
+            },
+        );
+        answer[0].payload.clone().unwrap()
+    }
+
+    #[test]
+    fn second_transaction_on_a_connector_is_concurrent() {
+        let (mut cs, _) = central_system();
+        let first = start_transaction(&mut cs, "cp1", 1, 0);
+
+        let second = start_with_tag(&mut cs, 1, "TAG2");
+        assert_eq!(second["idTagInfo"]["status"], "ConcurrentTx");
+        assert_eq!(second["transactionId"], 0);
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().active_transactions.get(&1),
+            Some(&first)
+        );
+
+        /* Other connectors and other stations are free */
+        let other = start_transaction(&mut cs, "cp1", 2, 0);
+        assert_ne!(other, first);
+        start_transaction(&mut cs, "cp2", 1, 0);
+
+        /* Stopping releases the connector */
+        stop_transaction(&mut cs, "cp1", first, 10);
+        let third = start_with_tag(&mut cs, 1, "TAG2");
+        assert_eq!(third["idTagInfo"]["status"], "Accepted");
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().active_transactions.get(&1),
+            Some(&third["transactionId"].as_u32().unwrap())
+        );
+    }
+
+    #[test]
+    fn reserved_connector_is_kept_for_its_id_tag() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.reserve_now("cp1", &reservation(5, 1), None).unwrap();
+        let reserve = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &reserve, object! { status : "Accepted" });
+
+        assert_eq!(
+            start_with_tag(&mut cs, 1, "TAG2")["idTagInfo"]["status"],
+            "Invalid"
+        );
+        assert_eq!(
+            start_with_tag(&mut cs, 2, "TAG2")["idTagInfo"]["status"],
+            "Accepted"
+        );
+        assert_eq!(
+            start_with_tag(&mut cs, 1, "TAG1")["idTagInfo"]["status"],
+            "Accepted"
+        );
+        /* The reservation is used up */
+        assert!(cs.reservations("cp1").is_empty());
+    }
+
+    #[test]
+    fn frames_are_evaluated() {
+        let (mut cs, _) = central_system();
+        assert_eq!(
+            cs.evaluate(r#"[2,"1","Heartbeat",{}]"#).unwrap(),
+            [r#"[3,"1",{"currentTime":"2024-01-01T00:00:00.000Z"}]"#]
+        );
+
+        let answer = cs
+            .evaluate(
+                r#"[2,"2","BootNotification",{"chargePointVendor":"v","chargePointModel":"m"}]"#,
+            )
+            .unwrap();
+        let boot = json::parse(&answer[0]).unwrap();
+        assert_eq!(boot[1], "2");
+        assert_eq!(boot[2]["status"], "Accepted");
+
+        let answer = cs
+            .evaluate(r#"[2,"3","Authorize",{"idTag":"TAG1"}]"#)
+            .unwrap();
+        assert_eq!(
+            json::parse(&answer[0]).unwrap()[2]["idTagInfo"]["status"],
+            "Accepted"
+        );
+
+        /* The answer and the CertificateSigned following it */
+        let frame = array![
+            2,
+            "4",
+            "SignCertificate",
+            object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargePointCertificate" }
+        ];
+        let answer = cs.evaluate(&frame.dump()).unwrap();
+        assert_eq!(answer.len(), 2);
+        assert_eq!(json::parse(&answer[1]).unwrap()[2], "CertificateSigned");
+
+        let answer = cs.evaluate(r#"[2,"5","Authorize",{}]"#).unwrap();
+        assert_eq!(json::parse(&answer[0]).unwrap()[0], 4);
+        assert_eq!(
+            cs.charge_point(EVAL_CP_ID).unwrap().model.as_deref(),
+            Some("m")
+        );
+    }
+
+    #[test]
+    fn malformed_frames_are_parse_errors() {
+        let (mut cs, _) = central_system();
+        for raw in ["", "[2,\"1\"", "{}", "[9,\"1\",\"Heartbeat\",{}]"] {
+            assert!(
+                matches!(cs.evaluate(raw), Err(CsError::ParseError(_))),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn pem_and_base64_der_csrs_are_signed() {
+        let pem = csr_pem("cp1");
+        let der = openssl::x509::X509Req::from_pem(pem.as_bytes())
+            .unwrap()
+            .to_der()
+            .unwrap();
+        /* Wrapped like PEM bodies sometimes are */
+        let encoded = base64::encode_block(&der)
+            .as_bytes()
+            .chunks(64)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(matches!(decode_csr(&pem), Some((_, Format::PEM))));
+        assert!(matches!(decode_csr(&encoded), Some((data, Format::DER)) if data == der));
+
+        for csr in [pem, encoded] {
+            let (mut cs, _) = central_system();
+            let _outbound = connect(&mut cs, "cp1", 0);
+            let answer = sign_csr(&mut cs, &csr);
+            assert_eq!(answer.len(), 2);
+            assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+            let leaf = openssl::x509::X509::from_pem(
+                answer[1].payload.as_ref().unwrap()["cert"][0]
+                    .as_str()
+                    .unwrap()
+                    .as_bytes(),
+            )
+            .unwrap();
+            let common_name = leaf
+                .subject_name()
+                .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+                .next()
+                .unwrap();
+            assert_eq!(common_name.data().to_string().unwrap(), "cp1");
+        }
+    }
+
+    #[test]
+    fn csr_neither_pem_nor_base64_is_a_call_error() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        for csr in ["not a csr!", ""] {
+            let answer = sign_csr(&mut cs, csr);
+            assert_eq!(answer.len(), 1);
+            assert_eq!(answer[0].role, MessageType::CallError);
+            assert_eq!(
+                answer[0].payload.as_ref().unwrap()["errorCode"],
+                "FormationViolation"
+            );
+        }
+        assert!(cs.pending.is_empty());
+    }
+
+    #[test]
+    fn transaction_data_counts_for_the_transaction() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 1000);
+        let mut payload = object! {
+            transactionId : id,
+            meterStop : 2600,
+            timestamp : "2024-01-01T01:00:00Z",
+        };
+        payload["transactionData"] = meter_values(None)["meterValue"].clone();
+        let answer = call(&mut cs, "cp1", Command::StopTransaction, payload);
+        assert_eq!(
+            answer[0].payload.as_ref().unwrap()["idTagInfo"]["status"],
+            "Accepted"
+        );
+
+        let summary = cs.transaction_summary(id).unwrap();
+        assert_eq!(summary.samples, 2);
+        assert_eq!(summary.meter_start, Some(1000));
+        assert_eq!(summary.energy_wh, Some(1600));
+        assert_eq!(
+            cs.transaction_readings(id).unwrap().energy_wh(),
+            Some(2500.0)
+        );
+    }
+
+    #[test]
+    fn data_transfer_returns_the_answer() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let completion: Completion = Box::new(move |result| {
+            let _ = tx.send(result.map(|m| m.payload.clone().unwrap()));
+        });
+        cs.data_transfer(
+            "cp1",
+            "dummy",
+            Some("reboot"),
+            Some("now"),
+            Some(completion),
+        )
+        .unwrap();
+        let transfer = outbound.try_recv().unwrap();
+        assert_eq!(transfer.role, MessageType::Call);
+        assert_eq!(transfer.command, Some(Command::DataTransfer));
+        assert_eq!(
+            transfer.payload.as_ref().unwrap(),
+            &object! { vendorId : "dummy", messageId : "reboot", data : "now" }
+        );
+        answer(
+            &mut cs,
+            "cp1",
+            &transfer,
+            object! { status : "Accepted", data : "done" },
+        );
+        let answer = rx.try_recv().unwrap().unwrap();
+        assert_eq!(answer["status"], "Accepted");
+        assert_eq!(answer["data"], "done");
+
+        /* Optional fields are left out */
+        cs.data_transfer("cp1", "dummy", None, None, None).unwrap();
+        assert_eq!(
+            outbound.try_recv().unwrap().payload.unwrap(),
+            object! { vendorId : "dummy" }
+        );
+    }
+
+    #[test]
+    fn data_transfer_needs_a_vendor_id() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let too_long = "x".repeat(51);
+        for (vendor_id, message_id) in [("", None), ("dummy", Some(too_long.as_str()))] {
+            assert!(matches!(
+                cs.data_transfer("cp1", vendor_id, message_id, None, None),
+                Err(CsError::InvalidArgument(_))
+            ));
+        }
+        assert!(outbound.try_recv().is_err());
+        assert!(matches!(
+            cs.data_transfer("cp2", "dummy", None, None, None),
+            Err(CsError::UnknownChargePoint(_))
+        ));
+    }
+
+    #[test]
+    fn repeated_boot_keeps_the_state() {
+        let (mut cs, _) = central_system();
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Accepted");
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+        cs.charge_points
+            .get_mut("cp1")
+            .unwrap()
+            .update_status(ConnectorTarget::Connector(1), "Charging");
+
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Accepted");
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.active_transactions.get(&1), Some(&id));
+        assert_eq!(cp.connectors[&1].status.as_deref(), Some("Charging"));
+
+        /* Other hardware starts over */
+        assert_eq!(boot(&mut cs, "cp1", "m2")["status"], "Accepted");
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.model.as_deref(), Some("m2"));
+        assert!(cp.active_transactions.is_empty());
+        assert!(cp.connectors.is_empty());
+    }
+
+    #[test]
+    fn boots_are_told_apart() {
+        let mut cp = ChargePoint::default();
+        assert_eq!(
+            cp.update_boot_info("dummy", "m1", Some("S1"), Some("1.0")),
+            Boot::First
+        );
+        assert_eq!(
+            cp.update_boot_info("dummy", "m1", Some("S1"), Some("1.0")),
+            Boot::Repeated
+        );
+        assert_eq!(
+            cp.update_boot_info("dummy", "m1", Some("S1"), Some("1.1")),
+            Boot::Changed("firmware 1.0 -> 1.1".to_string())
+        );
+        assert_eq!(
+            cp.update_boot_info("dummy", "m1", None, Some("1.1")),
+            Boot::Changed("serial number S1 -> none".to_string())
+        );
+        assert_eq!(cp.serial_number, None);
+    }
+
+    #[test]
+    fn start_transaction_details_are_recorded() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.reserve_now("cp1", &reservation(5, 2), None).unwrap();
+        let reserve = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &reserve, object! { status : "Accepted" });
+
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::StartTransaction,
+            object! {
+                connectorId : 2,
+                idTag : "TAG1",
+                meterStart : 1200,
+                reservationId : 5,
+                timestamp : "2024-01-01T00:05:00Z",
+            },
+        );
+        let payload = answer[0].payload.as_ref().unwrap();
+        assert_eq!(payload["idTagInfo"]["status"], "Accepted");
+        let id = payload["transactionId"].as_u32().unwrap();
+
+        let transaction = cs.transaction(id).unwrap();
+        assert!(transaction.active);
+        assert_eq!(transaction.charge_point, "cp1");
+        assert_eq!(transaction.connector_id, 2);
+        assert_eq!(transaction.id_tag.as_deref(), Some("TAG1"));
+        assert_eq!(transaction.meter_start, Some(1200));
+        assert_eq!(transaction.reservation_id, Some(5));
+        assert_eq!(
+            transaction.start_timestamp.as_deref(),
+            Some("2024-01-01T00:05:00Z")
+        );
+        assert!(cs.reservations("cp1").is_empty());
+    }
+
+    #[test]
+    fn unauthorized_start_is_refused() {
+        let (mut cs, _) = central_system();
+        cs.authorization_list().set("TAG2", Status::Blocked);
+        let answer = start_with_tag(&mut cs, 1, "TAG2");
+        assert_eq!(answer["idTagInfo"]["status"], "Blocked");
+        assert_eq!(answer["transactionId"], 0);
+        assert!(cs.transaction(0).is_none());
+        assert!(cs
+            .charge_point("cp1")
+            .is_none_or(|cp| cp.active_transactions.is_empty()));
+        assert!(cs.transaction_starts.is_empty());
+    }
+
+    #[test]
+    fn heartbeat_interval_is_resolved_by_model() {
+        let (mut cs, clock) = central_system();
+        let mut intervals = HeartbeatIntervals::default();
+        intervals.models.insert("slow".to_string(), 300);
+        intervals.models.insert("fast".to_string(), 30);
+        cs.set_heartbeat_intervals(intervals);
+
+        assert_eq!(boot(&mut cs, "cp1", "slow")["interval"], 300);
+        assert_eq!(boot(&mut cs, "cp2", "fast")["interval"], 30);
+        assert_eq!(
+            boot(&mut cs, "cp3", "other")["interval"],
+            DEFAULT_HEARTBEAT_INTERVAL
+        );
+        assert_eq!(
+            cs.charge_point("cp1").unwrap().heartbeat_interval,
+            Some(300)
+        );
+
+        /* Liveness follows the interval of each station */
+        clock.advance(chrono::Duration::seconds(100));
+        assert!(cs.is_online("cp1"));
+        assert!(!cs.is_online("cp2"));
+    }
+
+    #[test]
+    fn injected_call_error_is_sent_verbatim() {
+        let (mut cs, _) = central_system();
+        let fault = Fault::parse(&object! {
+            errorCode : "FormationViolation",
+            errorDescription : "payload is malformed",
+            errorDetails : { field : "chargePointVendor" },
+        })
+        .unwrap();
+        cs.faults().inject("BootNotification", fault, Some(1));
+
+        let frames = cs
+            .evaluate(
+                r#"[2,"b1","BootNotification",{"chargePointVendor":"v","chargePointModel":"m"}]"#,
+            )
+            .unwrap();
+        assert_eq!(
+            frames,
+            [
+                r#"[4,"b1","FormationViolation","payload is malformed",{"field":"chargePointVendor"}]"#
+            ]
+        );
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Accepted");
+
+        /* Unknown codes and details that aren't objects can't be injected */
+        assert!(Fault::parse(&object! { errorCode : "NoSuchError" }).is_none());
+        assert!(
+            Fault::parse(&object! { errorCode : "InternalError", errorDetails : "text" }).is_none()
+        );
+    }
+
+    #[test]
+    fn overlong_id_tags_are_type_constraint_violations() {
+        let (mut cs, _) = central_system();
+        let valid = "T".repeat(20);
+        assert_eq!(
+            authorize(&mut cs, "cp1", &valid)["idTagInfo"]["status"],
+            "Accepted"
+        );
+        for action in [Command::Authorize, Command::StartTransaction] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                action,
+                object! {
+                    connectorId : 1,
+                    idTag : "T".repeat(21),
+                    meterStart : 0,
+                    timestamp : "2024-01-01T00:00:00Z",
+                },
+            );
+            let error = answer[0].error().unwrap();
+            assert_eq!(error.code, "TypeConstraintViolation");
+            assert!(error.description.starts_with("idTag"));
+        }
+        assert!(cs.transaction_starts.is_empty());
+
+        /* OCPP 2.0.1 idTokens are bounded to 36 characters */
+        for (len, accepted) in [(36, true), (37, false)] {
+            let request = Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::Authorize),
+                Some(object! { idToken : object! { idToken : "T".repeat(len), type : "Central" } }),
+            )
+            .with_protocol(ProtocolVersion::Ocpp201);
+            let answer = cs.make_response("cp1", request).unwrap();
+            assert_eq!(
+                answer[0].role == MessageType::CallResult,
+                accepted,
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn second_start_past_a_quota_of_one_is_rejected() {
+        let (mut cs, clock) = central_system();
+        cs.quotas().set_limit("TAG2", 1);
+        cs.quotas().set_window(Some(chrono::Duration::hours(24)));
+
+        let first = start_with_tag(&mut cs, 1, "TAG2");
+        assert_eq!(first["idTagInfo"]["status"], "Accepted");
+        assert_eq!(
+            cs.quotas().usage(),
+            object! { TAG2 : object! { limit : 1, used : 1 } }
+        );
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG2")["idTagInfo"]["status"],
+            "Blocked"
+        );
+        let second = start_with_tag(&mut cs, 2, "TAG2");
+        assert_eq!(second["idTagInfo"]["status"], "Blocked");
+        assert_eq!(second["transactionId"], 0);
+        /* Other idTags have no quota */
+        assert_eq!(
+            start_with_tag(&mut cs, 2, "TAG1")["idTagInfo"]["status"],
+            "Accepted"
+        );
+
+        /* A manual reset or the next window allow a start again */
+        cs.quotas().reset(Some("TAG2"));
+        assert_eq!(
+            start_with_tag(&mut cs, 3, "TAG2")["idTagInfo"]["status"],
+            "Accepted"
+        );
+        assert_eq!(
+            start_with_tag(&mut cs, 4, "TAG2")["idTagInfo"]["status"],
+            "Blocked"
+        );
+        clock.advance(chrono::Duration::hours(25));
+        assert_eq!(
+            start_with_tag(&mut cs, 4, "TAG2")["idTagInfo"]["status"],
+            "Accepted"
+        );
+    }
+
+    #[test]
+    fn boot_notification_answers_have_the_fields_of_their_version() {
+        let v16 = r#"{"chargePointVendor":"v","chargePointModel":"m"}"#;
+        let v201 = r#"{"chargingStation":{"vendorName":"v","model":"m"},"reason":"PowerUp"}"#;
+        for (status, version, expected) in [
+            (
+                RegistrationStatus::Accepted,
+                ProtocolVersion::Ocpp16,
+                r#"[3,"1",{"status":"Accepted","currentTime":"2024-01-01T00:00:00.000Z","interval":60}]"#,
+            ),
+            (
+                RegistrationStatus::Accepted,
+                ProtocolVersion::Ocpp201,
+                r#"[3,"1",{"currentTime":"2024-01-01T00:00:00.000Z","interval":60,"status":"Accepted"}]"#,
+            ),
+            (
+                RegistrationStatus::Pending,
+                ProtocolVersion::Ocpp16,
+                r#"[3,"1",{"status":"Pending","currentTime":"2024-01-01T00:00:00.000Z","interval":10}]"#,
+            ),
+            (
+                RegistrationStatus::Pending,
+                ProtocolVersion::Ocpp201,
+                concat!(
+                    r#"[3,"1",{"currentTime":"2024-01-01T00:00:00.000Z","interval":10,"status":"Pending","#,
+                    r#""statusInfo":{"reasonCode":"Pending","additionalInfo":"awaiting acceptance"}}]"#
+                ),
+            ),
+        ] {
+            let (mut cs, _) = central_system();
+            cs.set_registration_status(status);
+            let payload = match version {
+                ProtocolVersion::Ocpp16 => v16,
+                ProtocolVersion::Ocpp201 => v201,
+            };
+            let request = unpack_message(&format!(r#"[2,"1","BootNotification",{}]"#, payload))
+                .unwrap()
+                .with_protocol(version);
+            let answer = cs.make_response("cp1", request).unwrap().remove(0);
+            assert_eq!(pack_message(answer).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn builder_configures_the_central_system() {
+        let now = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = Arc::new(FixedClock::new(now));
+        let mut authorization = AuthorizationList::new(Status::Invalid);
+        authorization.set("TAG1", Status::Accepted);
+        let ca = InMemoryCertificationAuthority::new(Settings::default().ca).unwrap();
+        let mut cs = CentralSystem::builder()
+            .certification_authority(Box::new(ca))
+            .heartbeat_interval(15)
+            .clock(clock.clone())
+            .authorization_list(authorization)
+            .build()
+            .unwrap();
+
+        let answer = boot(&mut cs, "cp1", "m1");
+        assert_eq!(answer["status"], "Accepted");
+        assert_eq!(answer["interval"], 15);
+        assert_eq!(answer["currentTime"], "2024-06-01T12:00:00.000Z");
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG1")["idTagInfo"]["status"],
+            "Accepted"
+        );
+        assert_eq!(
+            authorize(&mut cs, "cp1", "TAG2")["idTagInfo"]["status"],
+            "Invalid"
+        );
+
+        let mut cs = CentralSystem::builder()
+            .certification_authority(Box::new(
+                InMemoryCertificationAuthority::new(Settings::default().ca).unwrap(),
+            ))
+            .registration_status(RegistrationStatus::Pending)
+            .clock(clock)
+            .build()
+            .unwrap();
+        assert_eq!(boot(&mut cs, "cp1", "m1")["status"], "Pending");
+    }
+
+    fn status_notification_v201(
+        cs: &mut CentralSystem,
+        evse_id: u32,
+        connector_id: u32,
+        status: &str,
+    ) -> Message {
+        let request = Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(Command::StatusNotification),
+            Some(object! {
+                timestamp : "2024-01-01T00:00:00.000Z",
+                connectorStatus : status,
+                evseId : evse_id,
+                connectorId : connector_id,
+            }),
+        )
+        .with_protocol(ProtocolVersion::Ocpp201);
+        cs.make_response("cp1", request).unwrap().remove(0)
+    }
+
+    #[test]
+    fn status_notifications_of_both_versions_are_stored() {
+        let (mut cs, _) = central_system();
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::StatusNotification,
+            object! { connectorId : 1, errorCode : "NoError", status : "Preparing" },
+        );
+        assert_eq!(answer[0].payload.as_ref().unwrap(), &object! {});
+
+        for (evse_id, connector_id, status) in [(1, 1, "Occupied"), (2, 1, "Faulted")] {
+            let answer = status_notification_v201(&mut cs, evse_id, connector_id, status);
+            assert_eq!(answer.role, MessageType::CallResult);
+            assert_eq!(answer.payload.unwrap(), object! {});
+        }
+
+        let cp = cs.charge_point("cp1").unwrap();
+        assert_eq!(cp.connectors[&1].status.as_deref(), Some("Preparing"));
+        assert_eq!(
+            cp.evse_connectors[&(1, 1)].status.as_deref(),
+            Some("Occupied")
+        );
+        assert_eq!(
+            cp.evse_connectors[&(2, 1)].status.as_deref(),
+            Some("Faulted")
+        );
+        assert_eq!(cp.evse_connectors.len(), 2);
+    }
+
+    #[test]
+    fn ocpp16_status_is_refused_over_ocpp201() {
+        let (mut cs, _) = central_system();
+        let answer = status_notification_v201(&mut cs, 1, 1, "Charging");
+        assert_eq!(answer.error().unwrap().code, "FormatViolation");
+        assert!(cs
+            .charge_point("cp1")
+            .is_none_or(|cp| cp.evse_connectors.is_empty()));
+    }
+    #[test]
+    fn certificate_signed_is_pem_text() {
+        let (mut cs, _) = central_system();
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::SignCertificate,
+            object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargingStationCertificate" },
+        );
+        let cert = &answer[1].payload.as_ref().unwrap()["cert"];
+        assert!(cert.members().count() > 0);
+        for pem in cert.members() {
+            assert!(pem
+                .as_str()
+                .unwrap()
+                .starts_with("-----BEGIN CERTIFICATE-----"));
+        }
+
+        let request = Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(Command::SignCertificate),
+            Some(object! { csr : csr_pem("cp1"), certificateType : "ChargingStationCertificate" }),
+        )
+        .with_protocol(ProtocolVersion::Ocpp201);
+        let answer = cs.make_response("cp1", request).unwrap();
+        let signed = answer[1].payload.as_ref().unwrap();
+        assert!(signed["cert"].is_null());
+        assert!(signed["certificateChain"]
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----"));
+    }
+    fn reserve(cs: &mut CentralSystem, outbound: &Receiver<Message>, reservation: &Reservation) {
+        cs.reserve_now("cp1", reservation, None).unwrap();
+        let reserve = outbound.try_recv().unwrap();
+        answer(cs, "cp1", &reserve, object! { status : "Accepted" });
+        call(
+            cs,
+            "cp1",
+            Command::StatusNotification,
+            object! { connectorId : reservation.connector_id, errorCode : "NoError", status : "Reserved" },
+        );
+    }
+
+    fn connector_status(cs: &CentralSystem, connector_id: u32) -> Option<String> {
+        cs.charge_point("cp1").unwrap().connectors[&connector_id]
+            .status
+            .clone()
+    }
+
+    #[test]
+    fn expired_reservation_frees_its_connector() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        let mut short = reservation(5, 1);
+        short.expiry_date = clock.now() + chrono::Duration::seconds(30);
+        reserve(&mut cs, &outbound, &short);
+        reserve(&mut cs, &outbound, &reservation(6, 2));
+
+        clock.advance(chrono::Duration::seconds(29));
+        cs.sweep();
+        assert_eq!(cs.reservations("cp1").len(), 2);
+        assert_eq!(connector_status(&cs, 1).as_deref(), Some("Reserved"));
+
+        clock.advance(chrono::Duration::seconds(1));
+        cs.sweep();
+        assert_eq!(cs.reservations("cp1"), vec![reservation(6, 2)]);
+        assert_eq!(connector_status(&cs, 1).as_deref(), Some("Available"));
+        assert_eq!(connector_status(&cs, 2).as_deref(), Some("Reserved"));
+        assert_eq!(
+            start_with_tag(&mut cs, 1, "TAG2")["idTagInfo"]["status"],
+            "Accepted"
+        );
+    }
+
+    #[test]
+    fn cancelled_reservation_frees_its_connector() {
+        let (mut cs, _) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        reserve(&mut cs, &outbound, &reservation(5, 1));
+
+        cs.cancel_reservation("cp1", 5, None).unwrap();
+        let cancel = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &cancel, object! { status : "Accepted" });
+        assert!(cs.reservations("cp1").is_empty());
+        assert_eq!(connector_status(&cs, 1).as_deref(), Some("Available"));
+    }
+    #[test]
+    fn scenario_calls_follow_the_answer() {
+        let (mut cs, _) = central_system();
+        cs.set_scenario(Some(
+            crate::scenario::Scenario::parse(
+                r#"{"on": "Authorize", "send": [{"action": "GetConfiguration"}, {"action": "Reset", "payload": {"type": "Hard"}}], "times": 2}"#,
+            )
+            .unwrap(),
+        ));
+
+        /* A CallError isn't followed by the scripted calls */
+        let refused = call(&mut cs, "cp1", Command::Authorize, object! {});
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0].role, MessageType::CallError);
+
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::Authorize,
+            object! { idTag : "TAG1" },
+        );
+        let roles: Vec<&MessageType> = answer.iter().map(|m| &m.role).collect();
+        assert_eq!(
+            roles,
+            [
+                &MessageType::CallResult,
+                &MessageType::Call,
+                &MessageType::Call
+            ]
+        );
+        assert_eq!(answer[1].command, Some(Command::GetConfiguration));
+        assert_eq!(answer[2].command, Some(Command::Reset));
+        assert_eq!(
+            answer[2].payload.as_ref().unwrap(),
+            &object! { type : "Hard" }
+        );
+
+        assert_eq!(
+            call(
+                &mut cs,
+                "cp1",
+                Command::Authorize,
+                object! { idTag : "TAG1" }
+            )
+            .len(),
+            3
+        );
+        assert_eq!(
+            call(
+                &mut cs,
+                "cp1",
+                Command::Authorize,
+                object! { idTag : "TAG1" }
+            )
+            .len(),
+            1
+        );
+    }
+    #[test]
+    fn grouped_cards_answer_their_parent() {
+        let settings = Settings::parse(
+            r#"
+            [authorization.parents]
+            CARD1 = "FAMILY1"
+            CARD2 = "FAMILY1"
+            "#,
+        )
+        .unwrap();
+        let mut cs = CentralSystem::dry_run(&settings).unwrap();
+
+        for (connector_id, id_tag) in [(1, "CARD1"), (2, "CARD2")] {
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::Authorize,
+                object! { idTag : id_tag },
+            );
+            let info = &answer[0].payload.as_ref().unwrap()["idTagInfo"];
+            assert_eq!(info["status"], "Accepted");
+            assert_eq!(info["parentIdTag"], "FAMILY1");
+
+            let started = start_with_tag(&mut cs, connector_id, id_tag);
+            assert_eq!(started["idTagInfo"]["status"], "Accepted");
+            assert_eq!(started["idTagInfo"]["parentIdTag"], "FAMILY1");
+        }
+
+        let started = start_with_tag(&mut cs, 3, "CARD3");
+        assert_eq!(started["idTagInfo"]["status"], "Accepted");
+        assert!(!started["idTagInfo"].has_key("parentIdTag"));
+    }
+    #[test]
+    fn unknown_measurand_is_a_property_constraint_violation() {
+        let (mut cs, _) = central_system();
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+        let answer = call(&mut cs, "cp1", Command::MeterValues, meter_values(Some(id)));
+        assert_eq!(answer[0].role, MessageType::CallResult);
+
+        let samples = array![object! {
+            timestamp : "2024-01-01T00:30:00Z",
+            sampledValue : array![object! { value : "3000", measurand : "Energy" }],
+        }];
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::MeterValues,
+            object! { connectorId : 1, transactionId : id, meterValue : samples.clone() },
+        );
+        let error = answer[0].error().unwrap();
+        assert_eq!(error.code, "PropertyConstraintViolation");
+        assert_eq!(error.description, "unknown measurand Energy");
+
+        let answer = call(
+            &mut cs,
+            "cp1",
+            Command::StopTransaction,
+            object! {
+                transactionId : id,
+                meterStop : 3000,
+                timestamp : "2024-01-01T01:00:00Z",
+                transactionData : samples,
+            },
+        );
+        assert_eq!(
+            answer[0].error().unwrap().code,
+            "PropertyConstraintViolation"
+        );
+    }
+    #[test]
+    fn unmatched_answers_are_ignored_by_default() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let unmatched = || {
+            [
+                Message::new(
+                    MessageType::CallResult,
+                    "nobody-asked".to_string(),
+                    None,
+                    Some(object! { status : "Accepted" }),
+                ),
+                Message::call_error(
+                    "nobody-asked".to_string(),
+                    OcppErrorCode::InternalError,
+                    "",
+                    object! {},
+                ),
+            ]
+        };
+        for message in unmatched() {
+            assert!(cs.make_response("cp1", message).unwrap().is_empty());
+        }
+
+        cs.set_unmatched_responses(UnmatchedResponses::Warn);
+        for message in unmatched() {
+            assert!(cs.make_response("cp1", message).unwrap().is_empty());
+        }
+
+        cs.set_unmatched_responses(UnmatchedResponses::Error);
+        for message in unmatched() {
+            assert!(matches!(
+                cs.make_response("cp1", message),
+                Err(CsError::ParseError(_))
+            ));
+        }
+        /* Calls of the charge point are still answered */
+        assert_eq!(heartbeat(&mut cs, "cp1", "2").role, MessageType::CallResult);
+    }
+    #[test]
+    fn heartbeat_answers_only_the_current_time_in_both_versions() {
+        let (mut cs, clock) = central_system();
+        clock.advance(chrono::Duration::milliseconds(90_250));
+        for version in [ProtocolVersion::Ocpp16, ProtocolVersion::Ocpp201] {
+            let request = unpack_message(r#"[2,"h1","Heartbeat",{}]"#)
+                .unwrap()
+                .with_protocol(version);
+            let answer = cs.make_response("cp1", request).unwrap();
+            assert_eq!(answer.len(), 1);
+            assert_eq!(
+                pack_message(answer[0].clone()).unwrap(),
+                r#"[3,"h1",{"currentTime":"2024-01-01T00:01:30.250Z"}]"#
+            );
+        }
+    }
+
+    #[test]
+    fn heartbeats_asked_for_keep_the_booted_interval() {
+        let (mut cs, clock) = central_system();
+        cs.set_heartbeat_trigger_interval(45);
+        let outbound = connect(&mut cs, "cp1", 1);
+        assert_eq!(boot(&mut cs, "cp1", "m1")["interval"], 60);
+
+        /* Triggered heartbeats don't change the advertised interval */
+        clock.advance(chrono::Duration::seconds(45));
+        cs.sweep();
+        let trigger = outbound.try_recv().unwrap();
+        answer(&mut cs, "cp1", &trigger, object! { status : "Accepted" });
+        assert_eq!(
+            heartbeat(&mut cs, "cp1", "h1").payload.unwrap(),
+            object! { currentTime : "2024-01-01T00:00:45.000Z" }
+        );
+        assert_eq!(boot(&mut cs, "cp1", "m1")["interval"], 60);
+    }
+    #[test]
+    fn overdue_transaction_is_stopped_remotely_once() {
+        let (mut cs, clock) = central_system();
+        cs.set_max_transaction_duration(300);
+        let outbound = connect(&mut cs, "cp1", 0);
+        let id = start_transaction(&mut cs, "cp1", 1, 0);
+
+        clock.advance(chrono::Duration::seconds(299));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+
+        clock.advance(chrono::Duration::seconds(1));
+        cs.sweep();
+        let stop = outbound.try_recv().unwrap();
+        assert_eq!(stop.command, Some(Command::RemoteStopTransaction));
+        assert_eq!(
+            stop.payload.as_ref().unwrap(),
+            &object! { transactionId : id }
+        );
+        assert!(cs.auto_stops.contains(&id));
+
+        /* Asked once, even when the station doesn't stop */
+        answer(&mut cs, "cp1", &stop, object! { status : "Rejected" });
+        clock.advance(chrono::Duration::seconds(300));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+
+        assert_eq!(
+            stop_transaction(&mut cs, "cp1", id, 10)["idTagInfo"]["status"],
+            "Accepted"
+        );
+        assert!(cs.auto_stops.is_empty());
+    }
+
+    #[test]
+    fn transactions_of_disconnected_stations_are_not_stopped() {
+        let (mut cs, clock) = central_system();
+        cs.set_max_transaction_duration(300);
+        start_transaction(&mut cs, "cp1", 1, 0);
+        clock.advance(chrono::Duration::seconds(600));
+        cs.sweep();
+        assert!(cs.auto_stops.is_empty());
+
+        /* Stopped once the station is back */
+        let outbound = connect(&mut cs, "cp1", 0);
+        cs.sweep();
+        assert_eq!(
+            outbound.try_recv().unwrap().command,
+            Some(Command::RemoteStopTransaction)
+        );
+    }
+
+    #[test]
+    fn transactions_are_not_limited_by_default() {
+        let (mut cs, clock) = central_system();
+        let outbound = connect(&mut cs, "cp1", 0);
+        start_transaction(&mut cs, "cp1", 1, 0);
+        clock.advance(chrono::Duration::days(30));
+        cs.sweep();
+        assert!(outbound.try_recv().is_err());
+    }
+    #[test]
+    fn sign_certificate_type_that_is_no_string_is_refused() {
+        for certificate_type in [
+            json::JsonValue::from(7),
+            array!["ChargingStationCertificate"],
+            object! { type : "ChargingStationCertificate" },
+            json::JsonValue::from(""),
+        ] {
+            let (mut cs, _) = central_system();
+            let answer = call(
+                &mut cs,
+                "cp1",
+                Command::SignCertificate,
+                object! { csr : csr_pem("cp1"), typeOfCertificate : certificate_type },
+            );
+            assert_eq!(answer.len(), 1);
+            assert_eq!(
+                answer[0].error().unwrap().code,
+                "PropertyConstraintViolation"
+            );
+        }
+    }
+    #[test]
+    fn full_outbound_queue_sheds_calls() {
+        let (mut cs, _) = central_system();
+        let (tx, outbound) = sync_channel(2);
+        OcppCentralSystem::connect(&mut cs, "cp1", ProtocolVersion::Ocpp16, tx, 0);
+
+        let first = cs.reset("cp1", false, None).unwrap();
+        let second = cs.reset("cp1", false, None).unwrap();
+        assert!(matches!(
+            cs.reset("cp1", false, None),
+            Err(CsError::QueueFull(_))
+        ));
+        /* Shed calls aren't awaited, the queued ones are */
+        assert!(cs.pending.contains(&first));
+        assert!(cs.pending.contains(&second));
+        assert_eq!(cs.pending.len(), 2);
+        assert!(cs.is_connected("cp1"));
+
+        /* Room again once the connection took the queued calls */
+        assert_eq!(outbound.try_recv().unwrap().id, first);
+        assert!(cs.reset("cp1", false, None).is_ok());
+    }
+    #[test]
+    fn prepared_signing_is_answered_without_signing_again() {
+        let (mut cs, _) = central_system();
+        let _outbound = connect(&mut cs, "cp1", 0);
+        let request = Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(Command::SignCertificate),
+            Some(
+                object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargingStationCertificate" },
+            ),
+        );
+        let work = cs.prepare("cp1", &request).unwrap();
+        /* Run without the central system */
+        let apply = work();
+        apply(&mut cs);
+        let answer = cs.make_response("cp1", request).unwrap();
+        assert_eq!(answer[0].payload.as_ref().unwrap()["status"], "Accepted");
+        assert_eq!(answer[1].command, Some(Command::CertificateSigned));
+        assert_eq!(leaf_serial(&answer), 1);
+        assert!(cs.presigned.is_none());
+
+        /* The next signing gets the next serial */
+        let other = sign_csr(&mut cs, &csr_pem("cp1"));
+        assert_eq!(leaf_serial(&other), 2);
+    }
+
+    #[test]
+    fn refused_requests_are_not_prepared() {
+        let (mut cs, _) = central_system();
+        let sign = |csr: &str, certificate_type: &str| {
+            Message::new(
+                MessageType::Call,
+                "1".to_string(),
+                Some(Command::SignCertificate),
+                Some(object! { csr : csr, typeOfCertificate : certificate_type }),
+            )
+        };
+        let csr = csr_pem("cp1");
+        assert!(cs
+            .prepare("cp1", &sign("garbage", "ChargingStationCertificate"))
+            .is_none());
+        assert!(cs.prepare("cp1", &sign(&csr, "Bogus")).is_none());
+        assert!(cs
+            .prepare(
+                "cp1",
+                &Message::new(
+                    MessageType::Call,
+                    "1".to_string(),
+                    Some(Command::Heartbeat),
+                    Some(object! {}),
+                )
+            )
+            .is_none());
+
+        /* Injected faults answer instead */
+        cs.faults().inject(
+            "SignCertificate",
+            Fault::Status("Rejected".to_string()),
+            Some(1),
+        );
+        assert!(cs
+            .prepare("cp1", &sign(&csr, "ChargingStationCertificate"))
+            .is_none());
     }
 }
@@ -0,0 +1,170 @@
+use crate::cs::CentralSystem;
+use crate::error::CsError;
+use std::fs::{create_dir_all, remove_file, rename, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+pub const DEFAULT_DIRECTORY: &str = "/tmp/dummy-central-system/diagnostics/";
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct UploadSettings {
+    pub directory: PathBuf,
+    /* Bodies beyond it are refused, in bytes */
+    pub max_size: u64,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        UploadSettings {
+            directory: PathBuf::from(DEFAULT_DIRECTORY),
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+impl UploadSettings {
+    pub fn from_env() -> UploadSettings {
+        let defaults = UploadSettings::default();
+        UploadSettings {
+            directory: std::env::var("CS_DIAGNOSTICS_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(defaults.directory),
+            max_size: std::env::var("CS_DIAGNOSTICS_MAX_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_size),
+        }
+    }
+}
+
+/* Receives the diagnostics charge points upload to the location of
+ * GetDiagnostics, e.g. http://<host>:8084/cp1/:
+ *
+ * PUT or POST /{cp id}/{file name}   stored as <directory>/<cp id>/<file name>
+ * PUT or POST /{cp id}/              named by the fileName the charge point
+ *                                    answered GetDiagnostics with
+ *
+ * Bodies are streamed to disk, each upload on its own thread, and answered
+ * 201 with {"path": ...}. Bodies beyond max_size get 413 and nothing is
+ * kept, unknown charge points 404. */
+pub fn serve(
+    address: &str,
+    settings: UploadSettings,
+    cs: Arc<Mutex<CentralSystem>>,
+) -> Result<(), CsError> {
+    let server = Server::http(address).map_err(|e| CsError::InvalidArgument(e.to_string()))?;
+    let settings = Arc::new(settings);
+    spawn(move || {
+        for request in server.incoming_requests() {
+            let settings = Arc::clone(&settings);
+            let cs = Arc::clone(&cs);
+            spawn(move || upload(request, &settings, &cs));
+        }
+    });
+    Ok(())
+}
+
+fn upload(mut request: Request, settings: &UploadSettings, cs: &Mutex<CentralSystem>) {
+    let (code, body) = match store(&mut request, settings, cs) {
+        Ok(path) => (201, object! { path : path }),
+        Err((code, error)) => {
+            warn!("diagnostics upload {}: {}", request.url(), error);
+            (code, object! { error : error })
+        }
+    };
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(body.dump())
+        .with_status_code(code)
+        .with_header(header);
+    let _ = request.respond(response);
+}
+
+/* The path of the stored file or the status and reason of the refusal */
+fn store(
+    request: &mut Request,
+    settings: &UploadSettings,
+    cs: &Mutex<CentralSystem>,
+) -> Result<String, (u16, String)> {
+    if request.method() != &Method::Put && request.method() != &Method::Post {
+        return Err((405, "method not allowed".to_string()));
+    }
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or_default();
+    let (cp_id, file_name) = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .unwrap_or((path.trim_start_matches('/'), ""));
+    if !is_safe_name(cp_id) {
+        return Err((400, "invalid charge point id".to_string()));
+    }
+
+    let answered = match cs.lock().unwrap().charge_point(cp_id) {
+        Some(cp) => cp.diagnostics_file.clone(),
+        None => return Err((404, format!("unknown charge point {}", cp_id))),
+    };
+    let file_name = match (file_name, answered) {
+        ("", Some(answered)) => answered,
+        ("", None) => return Err((400, "file name is missing".to_string())),
+        (file_name, _) => file_name.to_string(),
+    };
+    if !is_safe_name(&file_name) {
+        return Err((400, "invalid file name".to_string()));
+    }
+
+    if request
+        .body_length()
+        .is_some_and(|l| l as u64 > settings.max_size)
+    {
+        return Err((413, "diagnostics are too large".to_string()));
+    }
+
+    let directory = settings.directory.join(cp_id);
+    create_dir_all(&directory).map_err(internal)?;
+    let target = directory.join(&file_name);
+    let partial = directory.join(file_name + ".part");
+    /* One byte beyond the limit tells a body of chunks that is too large */
+    let copied = File::create(&partial)
+        .and_then(|mut file| {
+            let copied = io::copy(
+                &mut request.as_reader().take(settings.max_size + 1),
+                &mut file,
+            )?;
+            file.sync_all()?;
+            Ok(copied)
+        })
+        .map_err(|e| {
+            let _ = remove_file(&partial);
+            internal(e)
+        })?;
+    if copied > settings.max_size {
+        let _ = remove_file(&partial);
+        return Err((413, "diagnostics are too large".to_string()));
+    }
+    rename(&partial, &target).map_err(internal)?;
+
+    let path = target.display().to_string();
+    info!(
+        "diagnostics of {} stored as {}, {} bytes",
+        cp_id, path, copied
+    );
+    cs.lock().unwrap().diagnostics_uploaded(cp_id, &path);
+    Ok(path)
+}
+
+/* A single path segment that can't leave the directory */
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+fn internal(e: io::Error) -> (u16, String) {
+    (500, e.to_string())
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum CsError {
+    /* Malformed OCPP-J frames or payloads */
+    ParseError(String),
+    UnsupportedCommand(String),
+    /* Arguments a call to the charge point can't be built from */
+    InvalidArgument(String),
+    UnknownChargePoint(String),
+    Disconnected(String),
+    /* The calls waiting to be written to the charge point reached the limit */
+    QueueFull(String),
+    Timeout,
+    CaError(String),
+    Storage(String),
+    /* Settings that can't be read or deserialized */
+    Config(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for CsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsError::ParseError(reason) => f.write_str(reason),
+            CsError::UnsupportedCommand(action) => write!(f, "unsupported command {}", action),
+            CsError::InvalidArgument(reason) => f.write_str(reason),
+            CsError::UnknownChargePoint(cp_id) => write!(f, "unknown charge point {}", cp_id),
+            CsError::Disconnected(cp_id) => write!(f, "charge point {} is disconnected", cp_id),
+            CsError::QueueFull(cp_id) => write!(f, "outbound queue of {} is full", cp_id),
+            CsError::Timeout => f.write_str("timeout"),
+            CsError::CaError(reason) => f.write_str(reason),
+            CsError::Storage(reason) => f.write_str(reason),
+            CsError::Config(reason) => f.write_str(reason),
+            CsError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CsError {
+    fn from(e: io::Error) -> Self {
+        CsError::Io(e)
+    }
+}
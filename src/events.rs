@@ -0,0 +1,57 @@
+use crate::ocpp::{Message, MessageType};
+use crate::recorder::Direction;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+/* Events a slow subscriber may lag behind before it misses some */
+const EVENT_CAPACITY: usize = 1024;
+
+/* A message seen on a connection */
+#[derive(Clone, Debug)]
+pub struct OcppEvent {
+    pub cp_id: String,
+    pub direction: Direction,
+    pub kind: MessageType,
+    /* Only calls carry the action */
+    pub action: Option<String>,
+    pub message_id: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/* Publishes the messages of all connections to subscribers, e.g. dashboards
+ * or test assertions. Nothing is built while nobody is subscribed. */
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<OcppEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        EventBus { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OcppEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, cp_id: &str, direction: Direction, message: &Message) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let _ = self.sender.send(OcppEvent {
+            cp_id: cp_id.to_string(),
+            direction,
+            kind: message.role,
+            action: message.command.as_ref().map(|c| c.to_string()),
+            message_id: message.id.clone(),
+            timestamp: Utc::now(),
+        });
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
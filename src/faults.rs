@@ -0,0 +1,120 @@
+use crate::ocpp::OcppErrorCode;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/* What an action is answered with instead of the normal response */
+#[derive(Clone, PartialEq, Debug)]
+pub enum Fault {
+    /* The normal response with its status replaced, e.g. Rejected */
+    Status(String),
+    CallError {
+        code: OcppErrorCode,
+        description: String,
+        details: json::JsonValue,
+    },
+}
+
+impl Fault {
+    pub fn parse(value: &json::JsonValue) -> Option<Fault> {
+        if let Some(status) = value["status"].as_str() {
+            return Some(Fault::Status(status.to_string()));
+        }
+        let code = value["errorCode"].as_str()?;
+        let details = match &value["errorDetails"] {
+            json::JsonValue::Null => json::JsonValue::new_object(),
+            details if details.is_object() => details.clone(),
+            _ => return None,
+        };
+        Some(Fault::CallError {
+            code: OcppErrorCode::try_from(code).ok()?,
+            description: value["errorDescription"]
+                .as_str()
+                .unwrap_or("injected fault")
+                .to_string(),
+            details,
+        })
+    }
+}
+
+impl From<&Fault> for json::JsonValue {
+    fn from(fault: &Fault) -> Self {
+        match fault {
+            Fault::Status(status) => object! { status : status.as_str() },
+            Fault::CallError {
+                code,
+                description,
+                details,
+            } => object! {
+                errorCode : code.as_str(),
+                errorDescription : description.as_str(),
+                errorDetails : details.clone(),
+            },
+        }
+    }
+}
+
+struct InjectedFault {
+    fault: Fault,
+    /* None keeps the fault until it is cleared */
+    remaining: Option<u32>,
+}
+
+/* Faults to answer calls with, keyed by action */
+#[derive(Default)]
+pub struct FaultInjector {
+    faults: HashMap<String, InjectedFault>,
+}
+
+impl FaultInjector {
+    pub fn inject(&mut self, action: &str, fault: Fault, count: Option<u32>) {
+        if count == Some(0) {
+            self.clear(action);
+            return;
+        }
+        self.faults.insert(
+            action.to_string(),
+            InjectedFault {
+                fault,
+                remaining: count,
+            },
+        );
+    }
+
+    pub fn clear(&mut self, action: &str) -> bool {
+        self.faults.remove(action).is_some()
+    }
+
+    pub fn clear_all(&mut self) {
+        self.faults.clear();
+    }
+
+    pub fn is_injected(&self, action: &str) -> bool {
+        self.faults.contains_key(action)
+    }
+
+    /* The fault for the next call of the action. The normal path resumes
+     * once the injected count is used up. */
+    pub fn take(&mut self, action: &str) -> Option<Fault> {
+        let injected = self.faults.get_mut(action)?;
+        let fault = injected.fault.clone();
+        if let Some(remaining) = injected.remaining.as_mut() {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.faults.remove(action);
+            }
+        }
+        Some(fault)
+    }
+
+    pub fn faults(&self) -> json::JsonValue {
+        let mut faults = json::JsonValue::new_object();
+        for (action, injected) in self.faults.iter() {
+            let mut value: json::JsonValue = (&injected.fault).into();
+            if let Some(remaining) = injected.remaining {
+                value["count"] = remaining.into();
+            }
+            faults[action.as_str()] = value;
+        }
+        faults
+    }
+}
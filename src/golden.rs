@@ -0,0 +1,116 @@
+use crate::clock::FixedClock;
+use crate::cs::{CentralSystem, EVAL_CP_ID};
+use crate::error::CsError;
+use crate::ocpp::{pack_message, unpack_message, CentralSystem as _, ProtocolVersion};
+use crate::settings::Settings;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/* The time of the central system while the goldens are answered */
+pub const GOLDEN_TIME: &str = "2024-01-01T00:00:00Z";
+
+/* An answer that differs from the checked-in one */
+pub struct Mismatch {
+    pub file: String,
+    pub step: usize,
+    pub expected: json::JsonValue,
+    pub actual: json::JsonValue,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} step {}: expected {}, got {}",
+            self.file,
+            self.step,
+            self.expected.dump(),
+            self.actual.dump()
+        )
+    }
+}
+
+/* Golden files hold the steps of one session of a fresh charge point:
+ * [{"request": [2, ...], "response": [[3, ...], ...]}, ...]
+ * Requests arrive over OCPP 1.6 unless a step has "protocol": "ocpp2.0.1".
+ * Each file is answered by a new central system with default settings whose
+ * clock stands at GOLDEN_TIME. With update the responses are rewritten
+ * instead of compared. */
+pub fn check(directory: &str, update: bool) -> Result<Vec<Mismatch>, CsError> {
+    let mut mismatches = Vec::new();
+    for path in golden_files(directory)? {
+        mismatches.extend(check_file(&path, update)?);
+    }
+    Ok(mismatches)
+}
+
+fn golden_files(directory: &str) -> Result<Vec<PathBuf>, CsError> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn check_file(path: &Path, update: bool) -> Result<Vec<Mismatch>, CsError> {
+    let file = path.display().to_string();
+    let mut steps = json::parse(fs::read_to_string(path)?.as_str())
+        .map_err(|e| CsError::ParseError(format!("{}: {}", file, e)))?;
+    if !steps.is_array() {
+        return Err(CsError::ParseError(format!("{}: not an array", file)));
+    }
+
+    let now = DateTime::parse_from_rfc3339(GOLDEN_TIME)
+        .unwrap()
+        .with_timezone(&Utc);
+    let mut cs =
+        CentralSystem::dry_run_with_clock(&Settings::default(), Arc::new(FixedClock::new(now)))?;
+
+    let mut mismatches = Vec::new();
+    for (index, step) in steps.members_mut().enumerate() {
+        let mut request = unpack_message(step["request"].dump().as_str())?;
+        if let Some(subprotocol) = step["protocol"].as_str() {
+            let version = ProtocolVersion::from_subprotocol(subprotocol).ok_or_else(|| {
+                CsError::ParseError(format!("{}: unsupported protocol {}", file, subprotocol))
+            })?;
+            request = request.with_protocol(version);
+        }
+
+        let mut actual = json::JsonValue::new_array();
+        for message in cs.make_response(EVAL_CP_ID, request)? {
+            let frame = json::parse(pack_message(message)?.as_str())
+                .map_err(|e| CsError::ParseError(format!("{}: {}", file, e)))?;
+            let _ = actual.push(normalize(frame));
+        }
+        if update {
+            step["response"] = actual;
+        } else if step["response"] != actual {
+            mismatches.push(Mismatch {
+                file: file.clone(),
+                step: index + 1,
+                expected: step["response"].clone(),
+                actual,
+            });
+        }
+    }
+
+    if update {
+        fs::write(path, json::stringify_pretty(steps, 2) + "\n")?;
+    }
+    Ok(mismatches)
+}
+
+/* Calls of the central system get a new id each time */
+fn normalize(mut frame: json::JsonValue) -> json::JsonValue {
+    if frame[0].as_u8() == Some(2) {
+        frame[1] = "<id>".into();
+    }
+    frame
+}
@@ -0,0 +1,54 @@
+use crate::error::CsError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+use tiny_http::{Header, Response, Server};
+
+/* What the central system needs before charge points can connect */
+#[derive(Default)]
+pub struct Readiness {
+    /* The certification authority is initialized */
+    pub ca: AtomicBool,
+    /* The WebSocket listeners are bound */
+    pub listening: AtomicBool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.ca.load(Ordering::Relaxed) && self.listening.load(Ordering::Relaxed)
+    }
+}
+
+/* Probes for container orchestration:
+ *
+ * GET /healthz   200 while the process runs
+ * GET /readyz    200 once the CA is initialized and the server listens,
+ *                503 otherwise */
+pub fn serve(address: &str, readiness: Arc<Readiness>) -> Result<(), CsError> {
+    let server = Server::http(address).map_err(|e| CsError::InvalidArgument(e.to_string()))?;
+    spawn(move || {
+        for request in server.incoming_requests() {
+            let (code, body) = match request.url() {
+                "/healthz" => (200, object! { status : "ok" }),
+                "/readyz" => {
+                    let code = if readiness.is_ready() { 200 } else { 503 };
+                    (
+                        code,
+                        object! {
+                            ca : readiness.ca.load(Ordering::Relaxed),
+                            listening : readiness.listening.load(Ordering::Relaxed),
+                        },
+                    )
+                }
+                _ => (404, object! { error : "not found" }),
+            };
+            let header =
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+            let response = Response::from_string(body.dump())
+                .with_status_code(code)
+                .with_header(header);
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,45 @@
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod authorization;
+pub mod charge_point;
+pub mod charging;
+pub mod client;
+pub mod clock;
+#[cfg(feature = "control")]
+pub mod control;
+pub mod credentials;
+pub mod cs;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod error;
+pub mod events;
+pub mod faults;
+pub mod golden;
+#[cfg(feature = "health")]
+pub mod health;
+pub mod maintenance;
+pub mod messages;
+pub mod metering;
+pub mod metrics;
+#[cfg(feature = "test-util")]
+pub mod mock;
+pub mod ocpp;
+#[cfg(feature = "ocsp")]
+pub mod ocsp;
+pub mod pending;
+pub mod recorder;
+pub mod scenario;
+pub mod schema;
+pub mod server;
+pub mod settings;
+pub mod stats;
+pub mod tls;
+pub mod transaction;
+pub mod trust_store;
+pub mod x509;
+
+#[macro_use]
+extern crate json;
+
+pub use server::{ConnectionSettings, Server, ServerHandle};
+pub use settings::Settings;
@@ -1,74 +1,502 @@
-pub mod cs;
-pub mod ocpp;
-pub mod x509;
+#[cfg(feature = "admin")]
+use dummy_central_system::admin;
+#[cfg(feature = "control")]
+use dummy_central_system::control;
+#[cfg(feature = "diagnostics")]
+use dummy_central_system::diagnostics;
+#[cfg(feature = "health")]
+use dummy_central_system::health;
+#[cfg(feature = "metrics")]
+use dummy_central_system::metrics;
+#[cfg(feature = "ocsp")]
+use dummy_central_system::ocsp;
+#[cfg(feature = "sqlite")]
+use dummy_central_system::transaction;
+use dummy_central_system::{
+    charge_point, client, credentials, cs, error, golden, ocpp, pending, recorder, scenario,
+    server, settings, tls, x509,
+};
 
-use std::net::TcpListener;
+use std::net::SocketAddr;
+#[cfg(feature = "health")]
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
-use std::thread::spawn;
 
 use chrono::prelude::*;
+use clap::{Parser, Subcommand};
 
-use tungstenite::accept_hdr;
-use tungstenite::handshake::server::{Request, Response};
-
-#[macro_use]
-extern crate json;
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
 
 fn get_rfc_now() -> String {
-    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+    ocpp::format_timestamp(&Utc::now(), ocpp::DEFAULT_SECONDS_FORMAT)
+}
+
+/* Options given on the command line win over the settings file */
+#[derive(Parser)]
+#[command(version, about = "Dummy OCPP central system")]
+struct Cli {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML settings file, see settings.example.toml"
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Address the WebSocket server listens on, e.g. 127.0.0.1:9000"
+    )]
+    bind: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory of the certification authority"
+    )]
+    ca_dir: Option<String>,
+    #[arg(
+        long = "credential",
+        value_name = "ID:PASSWORD",
+        help = "Basic authentication password of a charge point, may be repeated"
+    )]
+    credentials: Vec<String>,
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "Log filter like info or debug, RUST_LOG applies when unset"
+    )]
+    log_level: Option<String>,
+    #[arg(
+        long = "log-action",
+        value_name = "ACTION=LEVEL",
+        help = "Level the frames of an action are logged at: off, trace, debug, info or warn, * for any other; may be repeated"
+    )]
+    log_actions: Vec<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "JSON-lines file of calls sent after answering the calls of charge points"
+    )]
+    scenario: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "SQLite file the transactions are kept in, with the sqlite feature"
+    )]
+    database: Option<String>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Heartbeat interval sent in BootNotification answers, in seconds"
+    )]
+    heartbeat_interval: Option<u32>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the address the server listens on to FILE, useful with port 0"
+    )]
+    address_file: Option<String>,
+    #[command(subcommand)]
+    command: Option<Action>,
+}
+
+/* Without a subcommand the server runs */
+#[derive(Subcommand)]
+enum Action {
+    #[command(
+        about = "Print the answers to raw OCPP frames sent by one charge point, nothing is served or persisted",
+        after_help = "Example: dummy-central-system eval '[2,\"1\",\"Heartbeat\",{}]'"
+    )]
+    Eval {
+        #[arg(value_name = "FRAME", required = true)]
+        frames: Vec<String>,
+    },
+    #[command(about = "Connect as a charge point, send one call and print the answer")]
+    Client {
+        #[arg(long, value_name = "URL", help = "e.g. ws://127.0.0.1:8080/cp1")]
+        url: String,
+        #[arg(long, value_name = "ACTION", default_value = "BootNotification")]
+        action: String,
+        #[arg(
+            long,
+            value_name = "JSON",
+            help = "Payload of the call, a default one is sent when unset"
+        )]
+        payload: Option<String>,
+        #[arg(long, value_name = "SUBPROTOCOL", default_value = "ocpp1.6")]
+        protocol: String,
+    },
+    #[command(about = "Compare the answers to the golden files with the checked-in ones")]
+    Golden {
+        #[arg(value_name = "DIR", default_value = "fixtures/golden")]
+        directory: String,
+        #[arg(long, help = "Rewrite the golden files with the current answers")]
+        update: bool,
+    },
+    #[command(about = "Inspect the certification authority")]
+    Ca {
+        #[command(subcommand)]
+        action: CaAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum CaAction {
+    #[command(about = "Print the subject, validity, key and fingerprint of the root certificate")]
+    Show,
 }
 
-fn ws_cycle(cs: Box<dyn ocpp::CentralSystem + Send>) {
-    let server = TcpListener::bind("0.0.0.0:8080").unwrap();
-
-    let shared_cs = Arc::new(Mutex::new(cs));
-
-    for stream in server.incoming() {
-        let current_cs = Arc::clone(&shared_cs);
-        spawn(move || {
-            let callback = |_: &Request, mut resp: Response| {
-                println!("Received a new WS handshake");
-                let headers = resp.headers_mut();
-                headers.append("Sec-WebSocket-Protocol", "ocpp1.6".parse().unwrap());
-                Ok(resp)
-            };
-
-            let mut websocket = accept_hdr(stream.unwrap(), callback).unwrap();
-
-            loop {
-                let input = websocket.read_message();
-                if input.is_err() {
-                    println!("Close connection");
-                    let _ = websocket.close(None);
-                    break;
-                }
-
-                let msg_in = input.unwrap();
-                if msg_in.is_text() {
-                    println!();
-                    println!("[{}] CP: {}", get_rfc_now(), msg_in);
-                    println!();
-
-                    let ocpp_req = ocpp::unpack_message(msg_in.to_text().unwrap()).unwrap();
-                    let mut cs = current_cs.lock().unwrap();
-                    if let Ok(ocpp_resp) = cs.make_response(ocpp_req) {
-                        for r in ocpp_resp {
-                            let msg_out = tungstenite::protocol::Message::Text(
-                                ocpp::pack_message(r).unwrap(),
-                            );
-                            println!();
-                            println!("[{}] CS: {}", get_rfc_now(), msg_out);
-                            println!();
-                            let _ = websocket.write_message(msg_out);
-                        }
-                    }
-                }
+impl Cli {
+    fn settings(&self) -> Result<settings::Settings, error::CsError> {
+        let mut settings = match self.config.as_deref() {
+            Some(path) => settings::Settings::load(path)?,
+            None => settings::Settings::default(),
+        };
+        /* A single listener from the command line */
+        if let Some(bind) = self.bind.as_ref() {
+            settings.server.bind = bind.clone();
+            settings.server.listeners.clear();
+        }
+        if let Some(directory) = self.ca_dir.as_ref() {
+            settings.ca.directory = directory.clone();
+        }
+        if let Some(interval) = self.heartbeat_interval {
+            settings.heartbeat_interval = interval;
+        }
+        for credential in self.credentials.iter() {
+            let (cp_id, password) = credential.split_once(':').ok_or_else(|| {
+                error::CsError::Config(format!("credential {} is not ID:PASSWORD", credential))
+            })?;
+            settings
+                .server
+                .credentials
+                .insert(cp_id.to_string(), password.to_string());
+        }
+        if let Some(scenario) = self.scenario.as_ref() {
+            settings.scenario = Some(scenario.clone());
+        }
+        if let Some(database) = self.database.as_ref() {
+            settings.database = database.clone();
+        }
+        for entry in self.log_actions.iter() {
+            let (action, level) = entry
+                .split_once('=')
+                .and_then(|(action, level)| Some((action, server::FrameLogLevel::parse(level)?)))
+                .ok_or_else(|| {
+                    error::CsError::Config(format!("log action {} is not ACTION=LEVEL", entry))
+                })?;
+            settings.log_levels.insert(action.to_string(), level);
+        }
+        for listener in settings.server.listeners().iter() {
+            if let Err(e) = listener.bind.parse::<SocketAddr>() {
+                return Err(error::CsError::Config(format!(
+                    "invalid bind address {}: {}",
+                    listener.bind, e
+                )));
             }
-        });
+        }
+        Ok(settings)
     }
 }
 
-fn main() {
-    let cs = cs::CentralSystem::build().unwrap();
-    ws_cycle(cs)
+/* Log lines carry the same timestamps as the messages sent to charge points */
+struct LogTime;
+
+impl FormatTime for LogTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", get_rfc_now())
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn build_central_system(
+    settings: &settings::Settings,
+) -> Result<cs::CentralSystem, error::CsError> {
+    cs::CentralSystem::with_settings(settings)
+}
+
+#[cfg(feature = "sqlite")]
+fn build_central_system(
+    settings: &settings::Settings,
+) -> Result<cs::CentralSystem, error::CsError> {
+    if let Some(directory) = std::path::Path::new(&settings.database).parent() {
+        let _ = std::fs::create_dir_all(directory);
+    }
+    let store = transaction::SqliteTransactionStore::open(&settings.database)?;
+    let mut cs = cs::CentralSystem::with_settings(settings)?;
+    cs.set_transaction_store(Box::new(store));
+    Ok(cs)
+}
+
+fn load_scenario(
+    settings: &settings::Settings,
+    cs: &mut cs::CentralSystem,
+) -> Result<(), error::CsError> {
+    if let Some(path) = settings.scenario.as_deref() {
+        cs.set_scenario(Some(scenario::Scenario::load(path)?));
+        info!("calls are scripted by {}", path);
+    }
+    Ok(())
+}
+
+/* Answers go to stdout, one frame per line */
+fn evaluate(settings: &settings::Settings, frames: &[String]) -> Result<(), error::CsError> {
+    let mut cs = cs::CentralSystem::dry_run(settings)?;
+    load_scenario(settings, &mut cs)?;
+    for frame in frames.iter() {
+        for answer in cs.evaluate(frame)? {
+            println!("{}", answer);
+        }
+    }
+    Ok(())
+}
+
+/* The roots of the configured directory are read, never generated */
+fn show_root(settings: &settings::Settings) -> Result<(), error::CsError> {
+    use x509::CertificationAuthority;
+
+    let mut ca_settings = settings.ca.clone();
+    ca_settings.new = false;
+    let mut ca = x509::DefaultCertificationAuthority::new(ca_settings);
+    ca.init()?;
+    let info = x509::CertificateInfo::parse(&ca.root_certificate_der()?)?;
+    println!("{}", info);
+    Ok(())
+}
+
+/* Prints the answer, a CallError fails */
+async fn run_client(
+    url: &str,
+    action: &str,
+    payload: Option<&str>,
+    protocol: &str,
+) -> Result<(), error::CsError> {
+    let protocol = ocpp::ProtocolVersion::from_subprotocol(protocol).ok_or_else(|| {
+        error::CsError::InvalidArgument(format!("unsupported subprotocol {}", protocol))
+    })?;
+    let payload = payload
+        .map(json::parse)
+        .transpose()
+        .map_err(|e| error::CsError::InvalidArgument(format!("invalid payload: {}", e)))?;
+    let answer = client::call(url, protocol, action, payload).await?;
+    let failed = answer.role == ocpp::MessageType::CallError;
+    println!("{}", ocpp::pack_message(answer)?);
+    if failed {
+        return Err(error::CsError::InvalidArgument(format!(
+            "{} is answered with a CallError",
+            action
+        )));
+    }
+    Ok(())
+}
+
+/* Startup failures end the process with a message instead of a panic */
+fn or_exit<T>(result: Result<T, error::CsError>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    /* Answers of the subcommands own stdout, only problems are logged */
+    let (default_level, writer) = match cli.command {
+        Some(_) => ("warn", BoxMakeWriter::new(std::io::stderr)),
+        None => ("info", BoxMakeWriter::new(std::io::stdout)),
+    };
+    let filter = match cli.log_level.as_deref() {
+        Some(level) => EnvFilter::try_new(level).unwrap_or_else(|e| {
+            eprintln!("invalid log level {}: {}", level, e);
+            std::process::exit(2);
+        }),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+    };
+    tracing_subscriber::fmt()
+        .with_timer(LogTime)
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .init();
+
+    let settings = cli.settings().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(2);
+    });
+
+    match cli.command.as_ref() {
+        Some(Action::Eval { frames }) => {
+            or_exit(evaluate(&settings, frames));
+            return;
+        }
+        Some(Action::Client {
+            url,
+            action,
+            payload,
+            protocol,
+        }) => {
+            or_exit(run_client(url, action, payload.as_deref(), protocol).await);
+            return;
+        }
+        Some(Action::Golden { directory, update }) => {
+            let mismatches = or_exit(golden::check(directory, *update));
+            for mismatch in mismatches.iter() {
+                error!("{}", mismatch);
+            }
+            std::process::exit(if mismatches.is_empty() { 0 } else { 1 });
+        }
+        Some(Action::Ca {
+            action: CaAction::Show,
+        }) => {
+            or_exit(show_root(&settings));
+            return;
+        }
+        None => {}
+    }
+
+    /* Up before the CA, which may take a while to initialize */
+    #[cfg(feature = "health")]
+    let readiness = {
+        let readiness = Arc::new(health::Readiness::default());
+        let port = std::env::var("CS_HEALTH_PORT").unwrap_or_else(|_| "8082".to_string());
+        or_exit(health::serve(
+            &format!("0.0.0.0:{}", port),
+            Arc::clone(&readiness),
+        ));
+        info!("health probes are served on port {}", port);
+        readiness
+    };
+
+    let mut cs = or_exit(build_central_system(&settings));
+    or_exit(load_scenario(&settings, &mut cs));
+    #[cfg(feature = "health")]
+    readiness.ca.store(true, Ordering::Relaxed);
+    if let Ok(value) = std::env::var("CS_DUPLICATE_IDS") {
+        match charge_point::DuplicateIds::parse(&value) {
+            Some(duplicate_ids) => cs.set_duplicate_ids(duplicate_ids),
+            None => warn!("invalid CS_DUPLICATE_IDS {}", value),
+        }
+    }
+    if let Ok(value) = std::env::var("CS_UNMATCHED_RESPONSES") {
+        match pending::UnmatchedResponses::parse(&value) {
+            Some(unmatched_responses) => cs.set_unmatched_responses(unmatched_responses),
+            None => warn!("invalid CS_UNMATCHED_RESPONSES {}", value),
+        }
+    }
+
+    /* Feed a recorded session back and report the responses that changed */
+    if let Ok(path) = std::env::var("CS_REPLAY") {
+        let divergences = or_exit(recorder::replay(&mut cs, &path));
+        for divergence in divergences.iter() {
+            warn!("{}", divergence);
+        }
+        info!("replayed {}: {} divergences", path, divergences.len());
+        std::process::exit(if divergences.is_empty() { 0 } else { 1 });
+    }
+
+    #[cfg(feature = "metrics")]
+    {
+        let port = std::env::var("CS_METRICS_PORT").unwrap_or_else(|_| "9090".to_string());
+        or_exit(metrics::serve(&format!("0.0.0.0:{}", port)));
+        info!("metrics are served on port {}", port);
+    }
+
+    let credentials = Arc::new(credentials::Credentials::new(
+        settings.server.credentials.clone(),
+    ));
+    let mut listeners = Vec::new();
+    for listener in settings.server.listeners() {
+        /* Client certificates are checked against the roots of the CA */
+        let tls = listener.tls.as_ref().map(|tls| {
+            let roots = or_exit(cs.root_certificate_pem());
+            Arc::new(or_exit(tls::acceptor(tls, &roots)))
+        });
+        let connection_settings = server::ConnectionSettings {
+            credentials: Arc::clone(&credentials),
+            tls,
+            protocol: listener.protocol,
+            ..server::ConnectionSettings::from_env()
+        };
+        listeners.push((listener.bind, connection_settings));
+    }
+
+    let cs = Arc::new(Mutex::new(cs));
+
+    let recorder = std::env::var("CS_RECORD")
+        .ok()
+        .map(|path| Arc::new(or_exit(recorder::Recorder::create(&path))));
+    let mut server = server::Server::new(Arc::clone(&cs))
+        .with_recorder(recorder)
+        .log_levels(server::FrameLogLevels::from(&settings.log_levels))
+        .idle_timeout(
+            settings
+                .server
+                .idle_timeout
+                .map(std::time::Duration::from_secs),
+        );
+    for (bind, connection_settings) in listeners {
+        server = server.listen(&bind, connection_settings);
+    }
+
+    #[cfg(feature = "admin")]
+    {
+        let port = std::env::var("CS_ADMIN_PORT").unwrap_or_else(|_| "8081".to_string());
+        let token = std::env::var("CS_ADMIN_TOKEN").ok();
+        or_exit(admin::serve(
+            &format!("0.0.0.0:{}", port),
+            token,
+            Arc::clone(&cs),
+            server.stats(),
+        ));
+        info!("admin API is served on port {}", port);
+    }
+
+    #[cfg(feature = "control")]
+    {
+        let path = std::env::var("CS_CONTROL_SOCKET")
+            .unwrap_or_else(|_| control::DEFAULT_PATH.to_string());
+        or_exit(control::serve(&path, Arc::clone(&cs), server.stats()));
+        info!("control API is served on {}", path);
+    }
+
+    #[cfg(feature = "ocsp")]
+    {
+        let port = std::env::var("CS_OCSP_PORT").unwrap_or_else(|_| "8083".to_string());
+        or_exit(ocsp::serve(&format!("0.0.0.0:{}", port), Arc::clone(&cs)));
+        info!("OCSP responder is served on port {}", port);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    {
+        let port = std::env::var("CS_DIAGNOSTICS_PORT").unwrap_or_else(|_| "8084".to_string());
+        or_exit(diagnostics::serve(
+            &format!("0.0.0.0:{}", port),
+            diagnostics::UploadSettings::from_env(),
+            Arc::clone(&cs),
+        ));
+        info!("diagnostics uploads are received on port {}", port);
+    }
+
+    let server = or_exit(server.start().await);
+    #[cfg(feature = "health")]
+    readiness.listening.store(true, Ordering::Relaxed);
+    if let Some(path) = cli.address_file.as_deref() {
+        let addresses: Vec<String> = server.addresses().iter().map(|a| a.to_string()).collect();
+        or_exit(std::fs::write(path, addresses.join("\n")).map_err(|e| {
+            error::CsError::Config(format!("can't write the address to {}: {}", path, e))
+        }));
+    }
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM].iter() {
+        signal_hook::flag::register(*signal, server.shutdown_flag()).unwrap();
+    }
+    server.wait().await;
+    #[cfg(feature = "health")]
+    readiness.listening.store(false, Ordering::Relaxed);
+
+    /* Drop the central system so that stores flush their state */
+    drop(cs);
+    info!("stopped");
 }
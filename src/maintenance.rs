@@ -0,0 +1,75 @@
+use crate::error::CsError;
+use crate::ocpp::{format_timestamp, DEFAULT_SECONDS_FORMAT};
+use chrono::{DateTime, Utc};
+use url::Url;
+
+fn validate_location(location: &str) -> Result<(), CsError> {
+    match Url::parse(location) {
+        Ok(url) if url.has_host() => Ok(()),
+        _ => Err(CsError::InvalidArgument(
+            "location is not a valid URL".to_string(),
+        )),
+    }
+}
+
+pub struct FirmwareUpdate {
+    pub location: String,
+    pub retrieve_date: DateTime<Utc>,
+    pub retries: Option<u32>,
+    pub retry_interval: Option<u32>,
+}
+
+impl FirmwareUpdate {
+    pub fn validate(&self) -> Result<(), CsError> {
+        validate_location(self.location.as_str())
+    }
+}
+
+impl From<&FirmwareUpdate> for json::JsonValue {
+    fn from(update: &FirmwareUpdate) -> Self {
+        let mut value = object! {
+            location : update.location.as_str(),
+            retrieveDate : format_timestamp(&update.retrieve_date, DEFAULT_SECONDS_FORMAT),
+        };
+        if let Some(retries) = update.retries {
+            value["retries"] = retries.into();
+        }
+        if let Some(retry_interval) = update.retry_interval {
+            value["retryInterval"] = retry_interval.into();
+        }
+        value
+    }
+}
+
+pub struct DiagnosticsRequest {
+    pub location: String,
+    pub start_time: Option<DateTime<Utc>>,
+    pub stop_time: Option<DateTime<Utc>>,
+    pub retries: Option<u32>,
+    pub retry_interval: Option<u32>,
+}
+
+impl DiagnosticsRequest {
+    pub fn validate(&self) -> Result<(), CsError> {
+        validate_location(self.location.as_str())
+    }
+}
+
+impl From<&DiagnosticsRequest> for json::JsonValue {
+    fn from(request: &DiagnosticsRequest) -> Self {
+        let mut value = object! { location : request.location.as_str() };
+        if let Some(start_time) = request.start_time {
+            value["startTime"] = format_timestamp(&start_time, DEFAULT_SECONDS_FORMAT).into();
+        }
+        if let Some(stop_time) = request.stop_time {
+            value["stopTime"] = format_timestamp(&stop_time, DEFAULT_SECONDS_FORMAT).into();
+        }
+        if let Some(retries) = request.retries {
+            value["retries"] = retries.into();
+        }
+        if let Some(retry_interval) = request.retry_interval {
+            value["retryInterval"] = retry_interval.into();
+        }
+        value
+    }
+}
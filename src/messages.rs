@@ -0,0 +1,401 @@
+use crate::error::CsError;
+use crate::ocpp::{RegistrationStatus, Status};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/* Typed payloads of the calls a charge point sends. The wire keeps using
+ * json::JsonValue, handlers convert with from_json and to_json. */
+
+pub fn from_json<T: DeserializeOwned>(payload: &json::JsonValue) -> Result<T, CsError> {
+    serde_json::from_str(&payload.dump()).map_err(|e| CsError::ParseError(e.to_string()))
+}
+
+pub fn to_json<T: Serialize>(payload: &T) -> json::JsonValue {
+    /* Serializing plain structs can't fail and the output is valid JSON */
+    let raw = serde_json::to_string(payload).expect("payload is serializable");
+    json::parse(&raw).expect("serde_json produces valid JSON")
+}
+
+/* CiString<max> of OCPP 1.6 and the bounded strings of 2.0.1: printable
+ * ASCII of at most max characters */
+pub fn check_ci_string(field: &str, value: &str, max: usize) -> Result<(), CsError> {
+    if value.chars().count() > max {
+        return Err(CsError::InvalidArgument(format!(
+            "{} is longer than {} characters",
+            field, max
+        )));
+    }
+    if !value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return Err(CsError::InvalidArgument(format!(
+            "{} has characters other than printable ASCII",
+            field
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootNotificationRequest {
+    pub charge_point_vendor: String,
+    pub charge_point_model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge_point_serial_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge_box_serial_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iccid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imsi: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meter_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meter_serial_number: Option<String>,
+}
+
+/* OCPP 2.0.1 moves the station details into chargingStation */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootNotificationRequestV201 {
+    pub reason: String,
+    pub charging_station: ChargingStation,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChargingStation {
+    pub vendor_name: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootNotificationResponse {
+    pub status: RegistrationStatus,
+    pub current_time: String,
+    pub interval: u32,
+}
+
+/* Heartbeat.conf of 1.6 and HeartbeatResponse of 2.0.1 only carry the time,
+ * the interval is the one of the BootNotification answer */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatResponse {
+    pub current_time: String,
+}
+
+/* OCPP 2.0.1 may explain the status */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootNotificationResponseV201 {
+    pub current_time: String,
+    pub interval: u32,
+    pub status: RegistrationStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_info: Option<StatusInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusInfo {
+    pub reason_code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional_info: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeRequest {
+    pub id_tag: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeResponse {
+    pub id_tag_info: IdTagInfo,
+}
+
+/* OCPP 2.0.1 reports each connector of an EVSE, without an errorCode */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusNotificationRequestV201 {
+    pub timestamp: String,
+    pub connector_status: ConnectorStatus,
+    pub evse_id: u32,
+    pub connector_id: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ConnectorStatus {
+    Available,
+    Occupied,
+    Reserved,
+    Unavailable,
+    Faulted,
+}
+
+impl ConnectorStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectorStatus::Available => "Available",
+            ConnectorStatus::Occupied => "Occupied",
+            ConnectorStatus::Reserved => "Reserved",
+            ConnectorStatus::Unavailable => "Unavailable",
+            ConnectorStatus::Faulted => "Faulted",
+        }
+    }
+}
+
+/* OCPP 2.0.1 identifies drivers by a typed token */
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeRequestV201 {
+    pub id_token: IdToken,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdToken {
+    pub id_token: String,
+    /* Checked with IdTokenType::parse to answer unknown types properly */
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdTokenType {
+    Central,
+    EMaid,
+    Iso14443,
+    Iso15693,
+    KeyCode,
+    Local,
+    MacAddress,
+    NoAuthorization,
+}
+
+impl IdTokenType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdTokenType::Central => "Central",
+            IdTokenType::EMaid => "eMAID",
+            IdTokenType::Iso14443 => "ISO14443",
+            IdTokenType::Iso15693 => "ISO15693",
+            IdTokenType::KeyCode => "KeyCode",
+            IdTokenType::Local => "Local",
+            IdTokenType::MacAddress => "MacAddress",
+            IdTokenType::NoAuthorization => "NoAuthorization",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<IdTokenType> {
+        match value {
+            "Central" => Some(IdTokenType::Central),
+            "eMAID" => Some(IdTokenType::EMaid),
+            "ISO14443" => Some(IdTokenType::Iso14443),
+            "ISO15693" => Some(IdTokenType::Iso15693),
+            "KeyCode" => Some(IdTokenType::KeyCode),
+            "Local" => Some(IdTokenType::Local),
+            "MacAddress" => Some(IdTokenType::MacAddress),
+            "NoAuthorization" => Some(IdTokenType::NoAuthorization),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeResponseV201 {
+    pub id_token_info: IdTokenInfo,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdTokenInfo {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_expiry_date_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id_token: Option<IdToken>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdTagInfo {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id_tag: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartTransactionRequest {
+    pub connector_id: u32,
+    pub id_tag: String,
+    pub meter_start: i64,
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reservation_id: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartTransactionResponse {
+    pub transaction_id: u32,
+    pub id_tag_info: IdTagInfo,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopTransactionRequest {
+    pub transaction_id: u32,
+    pub meter_stop: i64,
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /* Parsed by the metering module */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_data: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopTransactionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_tag_info: Option<IdTagInfo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Parsing and serializing gives back the payload */
+    fn round_trip<T: DeserializeOwned + Serialize>(payload: json::JsonValue) -> T {
+        let typed: T = from_json(&payload).unwrap();
+        assert_eq!(to_json(&typed), payload);
+        typed
+    }
+
+    #[test]
+    fn boot_notification_round_trip() {
+        let request: BootNotificationRequest = round_trip(object! {
+            chargePointVendor : "dummy",
+            chargePointModel : "m1",
+            chargePointSerialNumber : "s1",
+            firmwareVersion : "1.0.0",
+            meterType : "AC",
+        });
+        assert_eq!(request.charge_point_model, "m1");
+        assert_eq!(request.charge_point_serial_number.as_deref(), Some("s1"));
+        assert_eq!(request.imsi, None);
+
+        let response: BootNotificationResponse = round_trip(object! {
+            status : "Pending",
+            currentTime : "2024-01-01T00:00:00.000Z",
+            interval : 30,
+        });
+        assert_eq!(response.status, RegistrationStatus::Pending);
+
+        round_trip::<BootNotificationRequestV201>(object! {
+            reason : "PowerUp",
+            chargingStation : object! { vendorName : "dummy", model : "m1" },
+        });
+        round_trip::<BootNotificationResponseV201>(object! {
+            currentTime : "2024-01-01T00:00:00.000Z",
+            interval : 300,
+            status : "Rejected",
+            statusInfo : object! { reasonCode : "Unknown" },
+        });
+    }
+
+    #[test]
+    fn authorize_round_trip() {
+        let request: AuthorizeRequest = round_trip(object! { idTag : "TAG1" });
+        assert_eq!(request.id_tag, "TAG1");
+        let response: AuthorizeResponse = round_trip(object! {
+            idTagInfo : object! {
+                status : "Blocked",
+                expiryDate : "2024-01-01T00:00:00.000Z",
+                parentIdTag : "PARENT",
+            },
+        });
+        assert_eq!(response.id_tag_info.status, Status::Blocked);
+
+        let request: AuthorizeRequestV201 = round_trip(object! {
+            idToken : object! { idToken : "TAG1", type : "ISO14443" },
+        });
+        assert_eq!(
+            IdTokenType::parse(&request.id_token.kind),
+            Some(IdTokenType::Iso14443)
+        );
+        round_trip::<AuthorizeResponseV201>(object! {
+            idTokenInfo : object! { status : "Accepted" },
+        });
+    }
+
+    #[test]
+    fn transaction_round_trip() {
+        let request: StartTransactionRequest = round_trip(object! {
+            connectorId : 1,
+            idTag : "TAG1",
+            meterStart : 1000,
+            timestamp : "2024-01-01T00:00:00.000Z",
+            reservationId : 7,
+        });
+        assert_eq!(request.reservation_id, Some(7));
+        round_trip::<StartTransactionResponse>(object! {
+            transactionId : 1,
+            idTagInfo : object! { status : "Accepted" },
+        });
+
+        let request: StopTransactionRequest = round_trip(object! {
+            transactionId : 1,
+            meterStop : 2500,
+            timestamp : "2024-01-01T01:00:00.000Z",
+            reason : "Local",
+            transactionData : array![object! { timestamp : "2024-01-01T01:00:00.000Z" }],
+        });
+        assert_eq!(request.id_tag, None);
+        assert!(request.transaction_data.is_some());
+        round_trip::<StopTransactionResponse>(object! {});
+    }
+
+    #[test]
+    fn missing_or_mistyped_fields_are_parse_errors() {
+        for payload in [
+            object! { chargePointVendor : "dummy" },
+            object! { chargePointVendor : "dummy", chargePointModel : 1 },
+            object! { charge_point_vendor : "dummy", charge_point_model : "m1" },
+        ] {
+            assert!(matches!(
+                from_json::<BootNotificationRequest>(&payload),
+                Err(CsError::ParseError(_))
+            ));
+        }
+        assert!(from_json::<StartTransactionRequest>(&object! {
+            connectorId : -1,
+            idTag : "TAG1",
+            meterStart : 0,
+            timestamp : "2024-01-01T00:00:00.000Z",
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn ci_string_bounds() {
+        assert!(check_ci_string("idTag", "TAG1", 20).is_ok());
+        assert!(check_ci_string("idTag", &"T".repeat(21), 20).is_err());
+        assert!(check_ci_string("idTag", "TAG\n1", 20).is_err());
+        assert!(check_ci_string("idTag", "TÄG1", 20).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::error::CsError;
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/* A vocabulary of OCPP 1.6 sampled values, (de)serialized as its strings.
+ * The default is what the specification assumes for a missing field. */
+macro_rules! vocabulary {
+    ($name:ident, $field:literal, default = $default:ident, $variants:tt) => {
+        vocabulary!($name, $field, $variants);
+
+        impl Default for $name {
+            fn default() -> $name {
+                $name::$default
+            }
+        }
+    };
+    ($name:ident, $field:literal, { $($variant:ident => $text:literal),+ $(,)? }) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $text),+
+                }
+            }
+
+            pub fn parse(value: &str) -> Option<$name> {
+                match value {
+                    $($text => Some($name::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                $name::parse(&value)
+                    .ok_or_else(|| de::Error::custom(format!("unknown {} {}", $field, value)))
+            }
+        }
+    };
+}
+
+vocabulary!(ReadingContext, "context", default = SamplePeriodic, {
+    InterruptionBegin => "Interruption.Begin",
+    InterruptionEnd => "Interruption.End",
+    SampleClock => "Sample.Clock",
+    SamplePeriodic => "Sample.Periodic",
+    TransactionBegin => "Transaction.Begin",
+    TransactionEnd => "Transaction.End",
+    Trigger => "Trigger",
+    Other => "Other",
+});
+
+vocabulary!(ValueFormat, "format", {
+    Raw => "Raw",
+    SignedData => "SignedData",
+});
+
+vocabulary!(Measurand, "measurand", default = EnergyActiveImportRegister, {
+    EnergyActiveExportRegister => "Energy.Active.Export.Register",
+    EnergyActiveImportRegister => "Energy.Active.Import.Register",
+    EnergyReactiveExportRegister => "Energy.Reactive.Export.Register",
+    EnergyReactiveImportRegister => "Energy.Reactive.Import.Register",
+    EnergyActiveExportInterval => "Energy.Active.Export.Interval",
+    EnergyActiveImportInterval => "Energy.Active.Import.Interval",
+    EnergyReactiveExportInterval => "Energy.Reactive.Export.Interval",
+    EnergyReactiveImportInterval => "Energy.Reactive.Import.Interval",
+    PowerActiveExport => "Power.Active.Export",
+    PowerActiveImport => "Power.Active.Import",
+    PowerOffered => "Power.Offered",
+    PowerReactiveExport => "Power.Reactive.Export",
+    PowerReactiveImport => "Power.Reactive.Import",
+    PowerFactor => "Power.Factor",
+    CurrentImport => "Current.Import",
+    CurrentExport => "Current.Export",
+    CurrentOffered => "Current.Offered",
+    Voltage => "Voltage",
+    Frequency => "Frequency",
+    Temperature => "Temperature",
+    SoC => "SoC",
+    Rpm => "RPM",
+});
+
+vocabulary!(Phase, "phase", {
+    L1 => "L1",
+    L2 => "L2",
+    L3 => "L3",
+    N => "N",
+    L1N => "L1-N",
+    L2N => "L2-N",
+    L3N => "L3-N",
+    L1L2 => "L1-L2",
+    L2L3 => "L2-L3",
+    L3L1 => "L3-L1",
+});
+
+vocabulary!(Location, "location", {
+    Cable => "Cable",
+    Ev => "EV",
+    Inlet => "Inlet",
+    Outlet => "Outlet",
+    Body => "Body",
+});
+
+vocabulary!(UnitOfMeasure, "unit", {
+    Wh => "Wh",
+    KWh => "kWh",
+    Varh => "varh",
+    Kvarh => "kvarh",
+    W => "W",
+    KW => "kW",
+    Va => "VA",
+    KVa => "kVA",
+    Var => "var",
+    Kvar => "kvar",
+    A => "A",
+    V => "V",
+    K => "K",
+    /* Misspelt in the specification, accepted as well */
+    Celcius => "Celcius",
+    Celsius => "Celsius",
+    Fahrenheit => "Fahrenheit",
+    Percent => "Percent",
+});
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampledValue {
+    /* Kept as sent, values may be signed data */
+    pub value: String,
+    #[serde(default)]
+    pub context: ReadingContext,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ValueFormat>,
+    #[serde(default)]
+    pub measurand: Measurand,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<Phase>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<UnitOfMeasure>,
+}
+
+impl SampledValue {
+    /* Fails with InvalidArgument for strings outside the vocabularies and
+     * with ParseError for a sample without value */
+    pub fn parse(value: &json::JsonValue) -> Result<SampledValue, CsError> {
+        let text = value["value"]
+            .as_str()
+            .ok_or_else(|| CsError::ParseError("sampledValue has no value".to_string()))?;
+        Ok(SampledValue {
+            value: text.to_string(),
+            context: vocabulary_field(value, "context", ReadingContext::parse)?.unwrap_or_default(),
+            format: vocabulary_field(value, "format", ValueFormat::parse)?,
+            measurand: vocabulary_field(value, "measurand", Measurand::parse)?.unwrap_or_default(),
+            phase: vocabulary_field(value, "phase", Phase::parse)?,
+            location: vocabulary_field(value, "location", Location::parse)?,
+            unit: vocabulary_field(value, "unit", UnitOfMeasure::parse)?,
+        })
+    }
+
+    /* Energy registers in Wh, whatever unit they were reported in */
+    pub fn energy_wh(&self) -> Option<f64> {
+        if !self.measurand.as_str().starts_with("Energy.") {
+            return None;
+        }
+        let value: f64 = self.value.parse().ok()?;
+        match self.unit {
+            None | Some(UnitOfMeasure::Wh) | Some(UnitOfMeasure::Varh) => Some(value),
+            Some(UnitOfMeasure::KWh) | Some(UnitOfMeasure::Kvarh) => Some(value * 1000.0),
+            _ => None,
+        }
+    }
+
+    fn same_series(&self, other: &SampledValue) -> bool {
+        self.measurand == other.measurand
+            && self.phase == other.phase
+            && self.location == other.location
+    }
+}
+
+fn vocabulary_field<T>(
+    value: &json::JsonValue,
+    field: &str,
+    parse: fn(&str) -> Option<T>,
+) -> Result<Option<T>, CsError> {
+    match &value[field] {
+        json::JsonValue::Null => Ok(None),
+        text => text
+            .as_str()
+            .and_then(parse)
+            .map(Some)
+            .ok_or_else(|| CsError::InvalidArgument(format!("unknown {} {}", field, text))),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MeterValue {
+    pub timestamp: DateTime<Utc>,
+    pub sampled_values: Vec<SampledValue>,
+}
+
+impl MeterValue {
+    pub fn parse(value: &json::JsonValue) -> Result<MeterValue, CsError> {
+        let timestamp = value["timestamp"]
+            .as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .ok_or_else(|| CsError::ParseError("meterValue timestamp is invalid".to_string()))?;
+        let sampled_values = value["sampledValue"]
+            .members()
+            .map(SampledValue::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MeterValue {
+            timestamp: timestamp.with_timezone(&Utc),
+            sampled_values,
+        })
+    }
+}
+
+/* The meterValue entries of a MeterValues, StopTransaction transactionData
+ * or TransactionEvent payload */
+pub fn parse_meter_values(values: &json::JsonValue) -> Result<Vec<MeterValue>, CsError> {
+    values.members().map(MeterValue::parse).collect()
+}
+
+/* The latest sample of every measurand, phase and location */
+#[derive(Clone, Debug, Default)]
+pub struct Readings {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub values: Vec<SampledValue>,
+}
+
+impl Readings {
+    pub fn update(&mut self, meter_value: &MeterValue) {
+        /* Samples may arrive out of order after an offline period */
+        if self.timestamp.is_some_and(|t| t > meter_value.timestamp) {
+            return;
+        }
+        self.timestamp = Some(meter_value.timestamp);
+        for sampled in meter_value.sampled_values.iter() {
+            self.values.retain(|v| !v.same_series(sampled));
+            self.values.push(sampled.clone());
+        }
+    }
+
+    pub fn get(&self, measurand: Measurand) -> Option<&SampledValue> {
+        self.values
+            .iter()
+            .find(|v| v.measurand == measurand && v.phase.is_none())
+            .or_else(|| self.values.iter().find(|v| v.measurand == measurand))
+    }
+
+    pub fn energy_wh(&self) -> Option<f64> {
+        self.get(Measurand::EnergyActiveImportRegister)
+            .and_then(|v| v.energy_wh())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter_value(timestamp: &str, samples: json::JsonValue) -> MeterValue {
+        MeterValue::parse(&object! { timestamp : timestamp, sampledValue : samples }).unwrap()
+    }
+
+    #[test]
+    fn energy_is_converted_to_wh() {
+        let sample = |value: &str, unit: &str| {
+            SampledValue::parse(&object! {
+                value : value,
+                measurand : "Energy.Active.Import.Register",
+                unit : unit,
+            })
+            .unwrap()
+        };
+        assert_eq!(sample("1500", "Wh").energy_wh(), Some(1500.0));
+        assert_eq!(sample("1.5", "kWh").energy_wh(), Some(1500.0));
+        assert_eq!(sample("signed", "Wh").energy_wh(), None);
+        /* Energy is the default measurand, Wh the default unit */
+        let default = SampledValue::parse(&object! { value : "42" }).unwrap();
+        assert_eq!(default.energy_wh(), Some(42.0));
+        let power = SampledValue::parse(&object! {
+            value : "7400",
+            measurand : "Power.Active.Import",
+            unit : "W",
+        })
+        .unwrap();
+        assert_eq!(power.energy_wh(), None);
+    }
+
+    #[test]
+    fn invalid_samples_are_refused() {
+        assert!(matches!(
+            SampledValue::parse(&object! { value : "1", measurand : "Energy" }),
+            Err(CsError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            SampledValue::parse(&object! { measurand : "Energy.Active.Import.Register" }),
+            Err(CsError::ParseError(_))
+        ));
+        assert!(matches!(
+            MeterValue::parse(&object! { timestamp : "yesterday", sampledValue : array![] }),
+            Err(CsError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn readings_keep_the_latest_sample_of_each_series() {
+        let mut readings = Readings::default();
+        readings.update(&meter_value(
+            "2024-01-01T00:00:00Z",
+            array![
+                object! { value : "100", unit : "Wh" },
+                object! { value : "7400", measurand : "Power.Active.Import", unit : "W" },
+            ],
+        ));
+        readings.update(&meter_value(
+            "2024-01-01T00:05:00Z",
+            array![object! { value : "700", unit : "Wh" }],
+        ));
+        /* Older samples don't replace newer ones */
+        readings.update(&meter_value(
+            "2024-01-01T00:01:00Z",
+            array![object! { value : "200", unit : "Wh" }],
+        ));
+
+        assert_eq!(readings.energy_wh(), Some(700.0));
+        assert_eq!(
+            readings.get(Measurand::PowerActiveImport).unwrap().value,
+            "7400"
+        );
+        assert_eq!(readings.values.len(), 2);
+    }
+    #[test]
+    fn energy_register_sample_is_parsed() {
+        let sample = SampledValue::parse(&object! {
+            value : "1234.5",
+            context : "Transaction.End",
+            format : "Raw",
+            measurand : "Energy.Active.Import.Register",
+            phase : "L1-N",
+            location : "Outlet",
+            unit : "kWh",
+        })
+        .unwrap();
+        assert_eq!(
+            sample,
+            SampledValue {
+                value: "1234.5".to_string(),
+                context: ReadingContext::TransactionEnd,
+                format: Some(ValueFormat::Raw),
+                measurand: Measurand::EnergyActiveImportRegister,
+                phase: Some(Phase::L1N),
+                location: Some(Location::Outlet),
+                unit: Some(UnitOfMeasure::KWh),
+            }
+        );
+        /* Serialized back to the strings of the vocabularies */
+        let round_trip: SampledValue =
+            serde_json::from_value(serde_json::to_value(&sample).unwrap()).unwrap();
+        assert_eq!(round_trip, sample);
+    }
+
+    #[test]
+    fn strings_outside_the_vocabularies_are_invalid_arguments() {
+        for (field, text) in [
+            ("measurand", "Energy.Active.Import"),
+            ("unit", "kwh"),
+            ("context", "Sample.Hourly"),
+            ("phase", "L4"),
+            ("location", "Roof"),
+            ("format", "Text"),
+        ] {
+            let mut value = object! { value : "1" };
+            value[field] = text.into();
+            match SampledValue::parse(&value) {
+                Err(CsError::InvalidArgument(e)) => {
+                    assert_eq!(e, format!("unknown {} {}", field, text))
+                }
+                other => panic!("{} {} gives {:?}", field, text, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/* Process-wide counters exported in the Prometheus text format */
+static MESSAGES: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+static CALL_ERRORS: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static CERTIFICATES_SIGNED: AtomicU64 = AtomicU64::new(0);
+static TRANSACTIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn message_received(action: &str) {
+    *MESSAGES
+        .lock()
+        .unwrap()
+        .entry(action.to_string())
+        .or_insert(0) += 1;
+}
+
+pub fn call_error_sent() {
+    CALL_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn connection_opened() {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn connection_closed() {
+    decrement(&CONNECTIONS);
+}
+
+pub fn certificate_signed() {
+    CERTIFICATES_SIGNED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn transaction_started() {
+    TRANSACTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn transaction_stopped() {
+    decrement(&TRANSACTIONS);
+}
+
+fn decrement(gauge: &AtomicU64) {
+    let _ = gauge.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(1))
+    });
+}
+
+pub fn render() -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP ocpp_messages_received_total Calls received from charge points\n");
+    out.push_str("# TYPE ocpp_messages_received_total counter\n");
+    for (action, count) in MESSAGES.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "ocpp_messages_received_total{{action=\"{}\"}} {}",
+            action, count
+        );
+    }
+
+    let values = [
+        (
+            "ocpp_call_errors_total",
+            "counter",
+            "CallErrors sent to charge points",
+            &CALL_ERRORS,
+        ),
+        (
+            "ocpp_active_connections",
+            "gauge",
+            "Connected charge points",
+            &CONNECTIONS,
+        ),
+        (
+            "ocpp_certificates_signed_total",
+            "counter",
+            "Certificates issued by the CA",
+            &CERTIFICATES_SIGNED,
+        ),
+        (
+            "ocpp_transactions_in_flight",
+            "gauge",
+            "Started and not yet stopped transactions",
+            &TRANSACTIONS,
+        ),
+    ];
+    for (name, kind, help, value) in values.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+    }
+    out
+}
+
+/* Serves GET /metrics on the given address until the process exits */
+#[cfg(feature = "metrics")]
+pub fn serve(address: &str) -> Result<(), crate::error::CsError> {
+    let server = tiny_http::Server::http(address)
+        .map_err(|e| crate::error::CsError::InvalidArgument(e.to_string()))?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let header = tiny_http::Header::from_bytes(
+                    &b"Content-Type"[..],
+                    &b"text/plain; version=0.0.4"[..],
+                )
+                .unwrap();
+                tiny_http::Response::from_string(render())
+                    .with_header(header)
+                    .boxed()
+            } else {
+                tiny_http::Response::empty(404).boxed()
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::cs::Handler;
+use crate::error::CsError;
+use crate::ocpp::{CentralSystem, Command, Message, MessageType, ProtocolVersion};
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+/* How the mock answers the calls of an action */
+pub enum MockResponse {
+    /* CallResults and CallErrors take the id of the call they answer */
+    Messages(Vec<Message>),
+    /* make_response fails, the server answers with the matching CallError */
+    Error(CsError),
+    Handler(Handler),
+}
+
+/* A central system for the tests of charge point code: the calls of each
+ * action are answered as programmed with set_response, other calls with an
+ * empty CallResult. Every message received is recorded, and the outbound
+ * channel of connected charge points kept for send. */
+#[derive(Default)]
+pub struct MockCentralSystem {
+    responses: HashMap<String, MockResponse>,
+    received: Vec<(String, Message)>,
+    outbound: HashMap<String, (u64, SyncSender<Message>)>,
+}
+
+impl MockCentralSystem {
+    pub fn new() -> MockCentralSystem {
+        MockCentralSystem::default()
+    }
+
+    pub fn set_response(&mut self, command: Command, response: MockResponse) {
+        self.responses.insert(command.to_string(), response);
+    }
+
+    /* The messages received from the charge point, in order */
+    pub fn received(&self, cp_id: &str) -> Vec<&Message> {
+        self.received
+            .iter()
+            .filter(|(id, _)| id == cp_id)
+            .map(|(_, message)| message)
+            .collect()
+    }
+
+    /* Calls of the action received from any charge point */
+    pub fn call_count(&self, command: &Command) -> usize {
+        self.received
+            .iter()
+            .filter(|(_, m)| m.role == MessageType::Call && m.command.as_ref() == Some(command))
+            .count()
+    }
+
+    pub fn is_connected(&self, cp_id: &str) -> bool {
+        self.outbound.contains_key(cp_id)
+    }
+
+    /* Sends a call, or anything else, to a connected charge point */
+    pub fn send(&self, cp_id: &str, message: Message) -> Result<(), CsError> {
+        self.outbound
+            .get(cp_id)
+            .ok_or_else(|| CsError::Disconnected(cp_id.to_string()))?
+            .1
+            .try_send(message)
+            .map_err(|e| match e {
+                TrySendError::Full(_) => CsError::QueueFull(cp_id.to_string()),
+                TrySendError::Disconnected(_) => CsError::Disconnected(cp_id.to_string()),
+            })
+    }
+}
+
+impl CentralSystem for MockCentralSystem {
+    fn make_response(&mut self, cp_id: &str, request: Message) -> Result<Vec<Message>, CsError> {
+        self.received.push((cp_id.to_string(), request.clone()));
+        if request.role != MessageType::Call {
+            return Ok(Vec::new());
+        }
+
+        let action = request
+            .command
+            .as_ref()
+            .map(|c| c.to_string())
+            .unwrap_or_default();
+        match self.responses.get(&action) {
+            Some(MockResponse::Messages(messages)) => Ok(messages
+                .iter()
+                .cloned()
+                .map(|mut message| {
+                    if message.role != MessageType::Call {
+                        message.id = request.id.clone();
+                    }
+                    message
+                })
+                .collect()),
+            Some(MockResponse::Error(e)) => Err(copy_error(e)),
+            Some(MockResponse::Handler(handler)) => handler(&request),
+            None => Ok(vec![Message::new(
+                MessageType::CallResult,
+                request.id,
+                None,
+                Some(object! {}),
+            )]),
+        }
+    }
+
+    fn connect(
+        &mut self,
+        cp_id: &str,
+        _version: ProtocolVersion,
+        outbound: SyncSender<Message>,
+        generation: u64,
+    ) {
+        self.outbound
+            .insert(cp_id.to_string(), (generation, outbound));
+    }
+
+    fn disconnect(&mut self, cp_id: &str, generation: u64) {
+        if self.outbound.get(cp_id).map(|(g, _)| *g) == Some(generation) {
+            self.outbound.remove(cp_id);
+        }
+    }
+}
+
+/* The error is returned for every call, io errors keep their kind */
+fn copy_error(e: &CsError) -> CsError {
+    match e {
+        CsError::ParseError(s) => CsError::ParseError(s.clone()),
+        CsError::UnsupportedCommand(s) => CsError::UnsupportedCommand(s.clone()),
+        CsError::InvalidArgument(s) => CsError::InvalidArgument(s.clone()),
+        CsError::UnknownChargePoint(s) => CsError::UnknownChargePoint(s.clone()),
+        CsError::Disconnected(s) => CsError::Disconnected(s.clone()),
+        CsError::QueueFull(s) => CsError::QueueFull(s.clone()),
+        CsError::Timeout => CsError::Timeout,
+        CsError::CaError(s) => CsError::CaError(s.clone()),
+        CsError::Storage(s) => CsError::Storage(s.clone()),
+        CsError::Config(s) => CsError::Config(s.clone()),
+        CsError::Io(e) => CsError::Io(io::Error::new(e.kind(), e.to_string())),
+    }
+}
@@ -1,5 +1,52 @@
+use crate::error::CsError;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
+use std::sync::mpsc::SyncSender;
 
+pub const DEFAULT_SECONDS_FORMAT: SecondsFormat = SecondsFormat::Millis;
+
+/* Every timestamp sent to charge points or logged goes through here */
+pub fn format_timestamp(time: &DateTime<Utc>, precision: SecondsFormat) -> String {
+    time.to_rfc3339_opts(precision, true)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Deserialize)]
+pub enum ProtocolVersion {
+    #[default]
+    #[serde(rename = "ocpp1.6")]
+    Ocpp16,
+    #[serde(rename = "ocpp2.0.1")]
+    Ocpp201,
+}
+
+impl ProtocolVersion {
+    /* Name used as the WebSocket subprotocol */
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            ProtocolVersion::Ocpp16 => "ocpp1.6",
+            ProtocolVersion::Ocpp201 => "ocpp2.0.1",
+        }
+    }
+
+    pub fn from_subprotocol(value: &str) -> Option<ProtocolVersion> {
+        match value.trim() {
+            "ocpp1.6" => Some(ProtocolVersion::Ocpp16),
+            "ocpp2.0.1" => Some(ProtocolVersion::Ocpp201),
+            _ => None,
+        }
+    }
+
+    /* Picks the first supported protocol from a Sec-WebSocket-Protocol list */
+    pub fn negotiate(offered: &str) -> Option<ProtocolVersion> {
+        offered
+            .split(',')
+            .find_map(ProtocolVersion::from_subprotocol)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MessageType {
     Call,
     CallResult,
@@ -18,16 +65,18 @@ impl TryFrom<u8> for MessageType {
     }
 }
 
-impl ToString for MessageType {
-    fn to_string(&self) -> String {
-        match self {
-            MessageType::Call => "Call".to_string(),
-            MessageType::CallResult => "CallResult".to_string(),
-            MessageType::CallError => "CallError".to_string(),
-        }
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MessageType::Call => "Call",
+            MessageType::CallResult => "CallResult",
+            MessageType::CallError => "CallError",
+        };
+        f.write_str(name)
     }
 }
 
+#[derive(Clone, PartialEq, Debug)]
 pub enum Command {
     BootNotification,
     StatusNotification,
@@ -38,21 +87,75 @@ pub enum Command {
     MeterValues,
     StopTransaction,
     Authorize,
+    Reset,
+    GetConfiguration,
+    ChangeConfiguration,
+    TriggerMessage,
+    ChangeAvailability,
+    UnlockConnector,
+    SetChargingProfile,
+    ClearChargingProfile,
+    GetCompositeSchedule,
+    ReserveNow,
+    CancelReservation,
+    SendLocalList,
+    GetLocalListVersion,
+    FirmwareStatusNotification,
+    DiagnosticsStatusNotification,
+    UpdateFirmware,
+    GetDiagnostics,
+    InstallCertificate,
+    DeleteCertificate,
+    GetInstalledCertificateIds,
+    RemoteStartTransaction,
+    RemoteStopTransaction,
+    Get15118EVCertificate,
+    ClearCache,
+    DataTransfer,
+    /* Keeps the action name of calls the central system doesn't know */
+    Unknown(String),
 }
 
-impl ToString for Command {
-    fn to_string(&self) -> String {
-        match self {
-            Command::BootNotification => "BootNotification".to_string(),
-            Command::StatusNotification => "StatusNotification".to_string(),
-            Command::Heartbeat => "Heartbeat".to_string(),
-            Command::SignCertificate => "SignCertificate".to_string(),
-            Command::CertificateSigned => "CertificateSigned".to_string(),
-            Command::StartTransaction => "StartTransaction".to_string(),
-            Command::MeterValues => "MeterValues".to_string(),
-            Command::StopTransaction => "StopTransaction".to_string(),
-            Command::Authorize => "Authorize".to_string(),
-        }
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Command::BootNotification => "BootNotification",
+            Command::StatusNotification => "StatusNotification",
+            Command::Heartbeat => "Heartbeat",
+            Command::SignCertificate => "SignCertificate",
+            Command::CertificateSigned => "CertificateSigned",
+            Command::StartTransaction => "StartTransaction",
+            Command::MeterValues => "MeterValues",
+            Command::StopTransaction => "StopTransaction",
+            Command::Authorize => "Authorize",
+            Command::Reset => "Reset",
+            Command::GetConfiguration => "GetConfiguration",
+            Command::ChangeConfiguration => "ChangeConfiguration",
+            Command::TriggerMessage => "TriggerMessage",
+            Command::ChangeAvailability => "ChangeAvailability",
+            Command::UnlockConnector => "UnlockConnector",
+            Command::SetChargingProfile => "SetChargingProfile",
+            Command::ClearChargingProfile => "ClearChargingProfile",
+            Command::GetCompositeSchedule => "GetCompositeSchedule",
+            Command::ReserveNow => "ReserveNow",
+            Command::CancelReservation => "CancelReservation",
+            Command::SendLocalList => "SendLocalList",
+            Command::GetLocalListVersion => "GetLocalListVersion",
+            Command::FirmwareStatusNotification => "FirmwareStatusNotification",
+            Command::DiagnosticsStatusNotification => "DiagnosticsStatusNotification",
+            Command::UpdateFirmware => "UpdateFirmware",
+            Command::GetDiagnostics => "GetDiagnostics",
+            Command::InstallCertificate => "InstallCertificate",
+            Command::DeleteCertificate => "DeleteCertificate",
+            Command::GetInstalledCertificateIds => "GetInstalledCertificateIds",
+            Command::RemoteStartTransaction => "RemoteStartTransaction",
+            Command::RemoteStopTransaction => "RemoteStopTransaction",
+            Command::Get15118EVCertificate => "Get15118EVCertificate",
+            Command::ClearCache => "ClearCache",
+            Command::DataTransfer => "DataTransfer",
+            Command::Unknown(action) => action.as_str(),
+        };
+        f.write_str(name)
     }
 }
 
@@ -77,40 +180,282 @@ impl TryFrom<&str> for Command {
             Ok(Command::StopTransaction)
         } else if value.eq_ignore_ascii_case("Authorize") {
             Ok(Command::Authorize)
+        } else if value.eq_ignore_ascii_case("Reset") {
+            Ok(Command::Reset)
+        } else if value.eq_ignore_ascii_case("GetConfiguration") {
+            Ok(Command::GetConfiguration)
+        } else if value.eq_ignore_ascii_case("ChangeConfiguration") {
+            Ok(Command::ChangeConfiguration)
+        } else if value.eq_ignore_ascii_case("TriggerMessage") {
+            Ok(Command::TriggerMessage)
+        } else if value.eq_ignore_ascii_case("ChangeAvailability") {
+            Ok(Command::ChangeAvailability)
+        } else if value.eq_ignore_ascii_case("UnlockConnector") {
+            Ok(Command::UnlockConnector)
+        } else if value.eq_ignore_ascii_case("SetChargingProfile") {
+            Ok(Command::SetChargingProfile)
+        } else if value.eq_ignore_ascii_case("ClearChargingProfile") {
+            Ok(Command::ClearChargingProfile)
+        } else if value.eq_ignore_ascii_case("GetCompositeSchedule") {
+            Ok(Command::GetCompositeSchedule)
+        } else if value.eq_ignore_ascii_case("ReserveNow") {
+            Ok(Command::ReserveNow)
+        } else if value.eq_ignore_ascii_case("CancelReservation") {
+            Ok(Command::CancelReservation)
+        } else if value.eq_ignore_ascii_case("SendLocalList") {
+            Ok(Command::SendLocalList)
+        } else if value.eq_ignore_ascii_case("GetLocalListVersion") {
+            Ok(Command::GetLocalListVersion)
+        } else if value.eq_ignore_ascii_case("FirmwareStatusNotification") {
+            Ok(Command::FirmwareStatusNotification)
+        } else if value.eq_ignore_ascii_case("DiagnosticsStatusNotification") {
+            Ok(Command::DiagnosticsStatusNotification)
+        } else if value.eq_ignore_ascii_case("UpdateFirmware") {
+            Ok(Command::UpdateFirmware)
+        } else if value.eq_ignore_ascii_case("GetDiagnostics") {
+            Ok(Command::GetDiagnostics)
+        } else if value.eq_ignore_ascii_case("InstallCertificate") {
+            Ok(Command::InstallCertificate)
+        } else if value.eq_ignore_ascii_case("DeleteCertificate") {
+            Ok(Command::DeleteCertificate)
+        } else if value.eq_ignore_ascii_case("GetInstalledCertificateIds") {
+            Ok(Command::GetInstalledCertificateIds)
+        } else if value.eq_ignore_ascii_case("RemoteStartTransaction") {
+            Ok(Command::RemoteStartTransaction)
+        } else if value.eq_ignore_ascii_case("RemoteStopTransaction") {
+            Ok(Command::RemoteStopTransaction)
+        } else if value.eq_ignore_ascii_case("Get15118EVCertificate") {
+            Ok(Command::Get15118EVCertificate)
+        } else if value.eq_ignore_ascii_case("ClearCache") {
+            Ok(Command::ClearCache)
+        } else if value.eq_ignore_ascii_case("DataTransfer") {
+            Ok(Command::DataTransfer)
         } else {
             Err(())
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
 pub enum Status {
     Accepted,
     Rejected,
+    Blocked,
+    Expired,
+    Invalid,
+    ConcurrentTx,
+    RebootRequired,
+    NotSupported,
+    NotImplemented,
+    Scheduled,
+    Unlocked,
+    UnlockFailed,
+    Unknown,
+    Faulted,
+    Occupied,
+    Unavailable,
+    Failed,
+    VersionMismatch,
+    NotFound,
 }
 
-impl ToString for Status {
-    fn to_string(&self) -> String {
-        match self {
-            Status::Accepted => "Accepted".to_string(),
-            Status::Rejected => "Rejected".to_string(),
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Status::Accepted => "Accepted",
+            Status::Rejected => "Rejected",
+            Status::Blocked => "Blocked",
+            Status::Expired => "Expired",
+            Status::Invalid => "Invalid",
+            Status::ConcurrentTx => "ConcurrentTx",
+            Status::RebootRequired => "RebootRequired",
+            Status::NotSupported => "NotSupported",
+            Status::NotImplemented => "NotImplemented",
+            Status::Scheduled => "Scheduled",
+            Status::Unlocked => "Unlocked",
+            Status::UnlockFailed => "UnlockFailed",
+            Status::Unknown => "Unknown",
+            Status::Faulted => "Faulted",
+            Status::Occupied => "Occupied",
+            Status::Unavailable => "Unavailable",
+            Status::Failed => "Failed",
+            Status::VersionMismatch => "VersionMismatch",
+            Status::NotFound => "NotFound",
+        };
+        f.write_str(name)
+    }
+}
+
+impl TryFrom<&str> for Status {
+    type Error = ();
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "Accepted" => Ok(Status::Accepted),
+            "Rejected" => Ok(Status::Rejected),
+            "Blocked" => Ok(Status::Blocked),
+            "Expired" => Ok(Status::Expired),
+            "Invalid" => Ok(Status::Invalid),
+            "ConcurrentTx" => Ok(Status::ConcurrentTx),
+            "RebootRequired" => Ok(Status::RebootRequired),
+            "NotSupported" => Ok(Status::NotSupported),
+            "NotImplemented" => Ok(Status::NotImplemented),
+            "Scheduled" => Ok(Status::Scheduled),
+            "Unlocked" => Ok(Status::Unlocked),
+            "UnlockFailed" => Ok(Status::UnlockFailed),
+            "Unknown" => Ok(Status::Unknown),
+            "Faulted" => Ok(Status::Faulted),
+            "Occupied" => Ok(Status::Occupied),
+            "Unavailable" => Ok(Status::Unavailable),
+            "Failed" => Ok(Status::Failed),
+            "VersionMismatch" => Ok(Status::VersionMismatch),
+            "NotFound" => Ok(Status::NotFound),
+            _ => Err(()),
         }
     }
 }
 
 impl From<Status> for json::JsonValue {
     fn from(status: Status) -> Self {
-        match status {
-            Status::Accepted => json::JsonValue::String("Accepted".to_string()),
-            Status::Rejected => json::JsonValue::String("Rejected".to_string()),
+        json::JsonValue::String(status.to_string())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+pub enum RegistrationStatus {
+    Accepted,
+    Pending,
+    Rejected,
+}
+
+impl fmt::Display for RegistrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RegistrationStatus::Accepted => "Accepted",
+            RegistrationStatus::Pending => "Pending",
+            RegistrationStatus::Rejected => "Rejected",
+        };
+        f.write_str(name)
+    }
+}
+
+impl From<RegistrationStatus> for json::JsonValue {
+    fn from(status: RegistrationStatus) -> Self {
+        json::JsonValue::String(status.to_string())
+    }
+}
+
+/* Error codes of CallError frames. OCPP 2.0.1 renamed FormationViolation
+ * to FormatViolation, for_version picks the name a charge point expects. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OcppErrorCode {
+    NotImplemented,
+    NotSupported,
+    InternalError,
+    ProtocolError,
+    SecurityError,
+    FormationViolation,
+    FormatViolation,
+    PropertyConstraintViolation,
+    OccurrenceConstraintViolation,
+    TypeConstraintViolation,
+    GenericError,
+}
+
+impl OcppErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OcppErrorCode::NotImplemented => "NotImplemented",
+            OcppErrorCode::NotSupported => "NotSupported",
+            OcppErrorCode::InternalError => "InternalError",
+            OcppErrorCode::ProtocolError => "ProtocolError",
+            OcppErrorCode::SecurityError => "SecurityError",
+            OcppErrorCode::FormationViolation => "FormationViolation",
+            OcppErrorCode::FormatViolation => "FormatViolation",
+            OcppErrorCode::PropertyConstraintViolation => "PropertyConstraintViolation",
+            /* Misspelled as in the OCPP 1.6 specification */
+            OcppErrorCode::OccurrenceConstraintViolation => "OccurenceConstraintViolation",
+            OcppErrorCode::TypeConstraintViolation => "TypeConstraintViolation",
+            OcppErrorCode::GenericError => "GenericError",
+        }
+    }
+
+    pub fn for_version(self, version: ProtocolVersion) -> OcppErrorCode {
+        match (self, version) {
+            (OcppErrorCode::FormationViolation, ProtocolVersion::Ocpp201) => {
+                OcppErrorCode::FormatViolation
+            }
+            (OcppErrorCode::FormatViolation, ProtocolVersion::Ocpp16) => {
+                OcppErrorCode::FormationViolation
+            }
+            (code, _) => code,
         }
     }
 }
 
+impl TryFrom<&str> for OcppErrorCode {
+    type Error = ();
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value {
+            "NotImplemented" => Ok(OcppErrorCode::NotImplemented),
+            "NotSupported" => Ok(OcppErrorCode::NotSupported),
+            "InternalError" => Ok(OcppErrorCode::InternalError),
+            "ProtocolError" => Ok(OcppErrorCode::ProtocolError),
+            "SecurityError" => Ok(OcppErrorCode::SecurityError),
+            "FormationViolation" => Ok(OcppErrorCode::FormationViolation),
+            "FormatViolation" => Ok(OcppErrorCode::FormatViolation),
+            "PropertyConstraintViolation" => Ok(OcppErrorCode::PropertyConstraintViolation),
+            "OccurenceConstraintViolation" | "OccurrenceConstraintViolation" => {
+                Ok(OcppErrorCode::OccurrenceConstraintViolation)
+            }
+            "TypeConstraintViolation" => Ok(OcppErrorCode::TypeConstraintViolation),
+            "GenericError" => Ok(OcppErrorCode::GenericError),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for OcppErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/* The code a call failing with the error is answered with */
+impl From<&CsError> for OcppErrorCode {
+    fn from(error: &CsError) -> Self {
+        match error {
+            CsError::ParseError(_) => OcppErrorCode::FormationViolation,
+            CsError::UnsupportedCommand(_) => OcppErrorCode::NotImplemented,
+            CsError::InvalidArgument(_) => OcppErrorCode::PropertyConstraintViolation,
+            CsError::UnknownChargePoint(_) => OcppErrorCode::SecurityError,
+            CsError::Disconnected(_) | CsError::QueueFull(_) | CsError::Timeout => {
+                OcppErrorCode::GenericError
+            }
+            CsError::CaError(_) | CsError::Storage(_) | CsError::Config(_) | CsError::Io(_) => {
+                OcppErrorCode::InternalError
+            }
+        }
+    }
+}
+
+/* The fields of a CallError frame. Codes are kept as sent, charge points
+ * may use ones OcppErrorCode doesn't know. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallErrorFields {
+    pub code: String,
+    pub description: String,
+    pub details: json::JsonValue,
+}
+
+#[derive(Clone, Debug)]
 pub struct Message {
     pub role: MessageType,
     pub id: String,
     pub command: Option<Command>,
     pub payload: Option<json::JsonValue>,
+    /* Negotiated by the connection the message arrived over, None for
+     * messages that didn't arrive over one */
+    pub protocol: Option<ProtocolVersion>,
 }
 
 impl Message {
@@ -125,61 +470,271 @@ impl Message {
             id,
             command,
             payload,
+            protocol: None,
+        }
+    }
+
+    pub fn with_protocol(mut self, protocol: ProtocolVersion) -> Message {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    pub fn call_error(
+        id: String,
+        code: OcppErrorCode,
+        description: &str,
+        details: json::JsonValue,
+    ) -> Message {
+        let payload = object! {
+            errorCode : code.as_str(),
+            errorDescription : description,
+            errorDetails : details,
+        };
+        Message::new(MessageType::CallError, id, None, Some(payload))
+    }
+
+    /* The errorCode, errorDescription and errorDetails of a CallError, None
+     * for the other types */
+    pub fn error(&self) -> Option<CallErrorFields> {
+        if self.role != MessageType::CallError {
+            return None;
         }
+        let payload = self.payload.as_ref()?;
+        Some(CallErrorFields {
+            code: payload["errorCode"].as_str()?.to_string(),
+            description: payload["errorDescription"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            details: payload["errorDetails"].clone(),
+        })
+    }
+
+    /* The status field most of the confirmations carry */
+    pub fn status(&self) -> Option<Status> {
+        self.payload
+            .as_ref()
+            .and_then(|p| p["status"].as_str())
+            .and_then(|s| Status::try_from(s).ok())
     }
 }
 
+/* Run by the server without the lock of the central system, what it returns
+ * is applied under the lock again, right before make_response */
+pub type Work<C> = Box<dyn FnOnce() -> Box<dyn FnOnce(&mut C) + Send> + Send>;
+
 pub trait CentralSystem {
-    fn make_response(&mut self, request: Message) -> Result<Vec<Message>, &str>;
+    fn make_response(&mut self, cp_id: &str, request: Message) -> Result<Vec<Message>, CsError>;
+
+    /* Work of the request that would hold the central system for long, like
+     * signing a certificate. None when make_response does it all. */
+    fn prepare(&self, _cp_id: &str, _request: &Message) -> Option<Work<Self>>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /* Outbound is used to deliver calls initiated by the central system.
+     * generation tells the sessions of a charge point apart: a station may
+     * reconnect before its previous session is over. */
+    fn connect(
+        &mut self,
+        _cp_id: &str,
+        _version: ProtocolVersion,
+        _outbound: SyncSender<Message>,
+        _generation: u64,
+    ) {
+    }
+
+    /* Ignored unless the session of the generation is the current one */
+    fn disconnect(&mut self, _cp_id: &str, _generation: u64) {}
+
+    /* Called periodically by the server to run time-based housekeeping */
+    fn sweep(&mut self) {}
 }
 
-pub fn unpack_message(raw: &str) -> Result<Message, &str> {
+/* Message ids are at most 36 characters, the length of a UUID used for the
+ * calls initiated by the central system */
+pub const MAX_MESSAGE_ID_LEN: usize = 36;
+
+/* Bounds of the frames unpack_message_with_limits accepts. Both are checked
+ * on the raw text, before the parser allocates anything. */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ParseLimits {
+    /* Bytes */
+    pub max_len: usize,
+    /* Nested arrays and objects, the frame itself included */
+    pub max_depth: usize,
+}
+
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1024 * 1024;
+/* OCPP payloads nest a handful of levels, charging profiles the most */
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 32;
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_len: DEFAULT_MAX_FRAME_LEN,
+            max_depth: DEFAULT_MAX_JSON_DEPTH,
+        }
+    }
+}
+
+impl ParseLimits {
+    pub fn check(&self, raw: &str) -> Result<(), CsError> {
+        if raw.len() > self.max_len {
+            return Err(CsError::ParseError(format!(
+                "frame is longer than {} bytes",
+                self.max_len
+            )));
+        }
+
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        for byte in raw.bytes() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'[' | b'{' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(CsError::ParseError(format!(
+                            "frame is nested deeper than {} levels",
+                            self.max_depth
+                        )));
+                    }
+                }
+                b']' | b'}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn unpack_message(raw: &str) -> Result<Message, CsError> {
+    unpack_message_with_limits(raw, &ParseLimits::default())
+}
+
+/* Frames beyond the limits are refused with a ParseError. The server answers
+ * them with a FormationViolation when recover_call_id finds the id. */
+pub fn unpack_message_with_limits(raw: &str, limits: &ParseLimits) -> Result<Message, CsError> {
     const TYPE_INDEX: usize = 0;
     const ID_INDEX: usize = 1;
     const COMMAND_INDEX: usize = 2;
     const PAYLOAD_INDEX: usize = 3;
+    const RESULT_PAYLOAD_INDEX: usize = 2;
+    const ERROR_CODE_INDEX: usize = 2;
+    const ERROR_DESCRIPTION_INDEX: usize = 3;
+    const ERROR_DETAILS_INDEX: usize = 4;
 
-    let payload = json::parse(raw);
+    let parse_error = |reason: &str| CsError::ParseError(reason.to_string());
 
-    if payload.is_err() {
-        return Err("can't parse");
-    }
-
-    let mut data = payload.unwrap();
+    limits.check(raw)?;
+    let mut data = json::parse(raw).map_err(|_| parse_error("can't parse"))?;
     if data.len() <= ID_INDEX {
-        return Err("invalid len");
+        return Err(parse_error("invalid len"));
     }
 
-    let type_raw = data[TYPE_INDEX].as_u8().ok_or("type is invalid").unwrap();
-    let id_raw = data[ID_INDEX].as_str().ok_or("id is invalid").unwrap();
+    let type_raw = data[TYPE_INDEX]
+        .as_u8()
+        .ok_or_else(|| parse_error("type is invalid"))?;
+    let id_raw = data[ID_INDEX]
+        .as_str()
+        .ok_or_else(|| parse_error("id is invalid"))?;
     if id_raw.is_empty() {
-        return Err("id is empty");
+        return Err(parse_error("id is empty"));
+    }
+    if id_raw.chars().count() > MAX_MESSAGE_ID_LEN {
+        return Err(parse_error("id is too long"));
     }
 
-    let msg_type = MessageType::try_from(type_raw)
-        .or(Err("type is invalid"))
-        .unwrap();
+    let msg_type = MessageType::try_from(type_raw).map_err(|_| parse_error("type is invalid"))?;
     let msg_id = id_raw.to_string();
-    let msg_command = if data.len() > COMMAND_INDEX {
-        if let Some(unpacked) = data[COMMAND_INDEX].as_str() {
-            Command::try_from(unpacked).ok()
-        } else {
-            None
+
+    match msg_type {
+        MessageType::Call => {
+            let msg_command = if data.len() > COMMAND_INDEX {
+                data[COMMAND_INDEX].as_str().map(|unpacked| {
+                    Command::try_from(unpacked)
+                        .unwrap_or_else(|_| Command::Unknown(unpacked.to_string()))
+                })
+            } else {
+                None
+            };
+
+            let msg_payload = if data.len() > PAYLOAD_INDEX {
+                Some(data[PAYLOAD_INDEX].take())
+            } else {
+                None
+            };
+
+            Ok(Message::new(msg_type, msg_id, msg_command, msg_payload))
         }
-    } else {
-        None
-    };
+        MessageType::CallResult => {
+            let msg_payload = if data.len() > RESULT_PAYLOAD_INDEX {
+                Some(data[RESULT_PAYLOAD_INDEX].take())
+            } else {
+                None
+            };
 
-    let msg_payload = if data.len() > PAYLOAD_INDEX {
-        Some(data[PAYLOAD_INDEX].take())
-    } else {
-        None
-    };
+            Ok(Message::new(msg_type, msg_id, None, msg_payload))
+        }
+        /* [4, id, errorCode, errorDescription, errorDetails], there is no
+         * action: the error fields are kept in the payload, see error() */
+        MessageType::CallError => {
+            if !data[ERROR_CODE_INDEX].is_string() {
+                return Err(parse_error("errorCode is invalid"));
+            }
+            let description = match data[ERROR_DESCRIPTION_INDEX].take() {
+                json::JsonValue::Null => "".into(),
+                description if description.is_string() => description,
+                _ => return Err(parse_error("errorDescription is invalid")),
+            };
+            let details = match data[ERROR_DETAILS_INDEX].take() {
+                json::JsonValue::Null => json::JsonValue::new_object(),
+                details if details.is_object() => details,
+                _ => return Err(parse_error("errorDetails is invalid")),
+            };
+            let msg_payload = object! {
+                errorCode : data[ERROR_CODE_INDEX].take(),
+                errorDescription : description,
+                errorDetails : details,
+            };
+
+            Ok(Message::new(msg_type, msg_id, None, Some(msg_payload)))
+        }
+    }
+}
 
-    Ok(Message::new(msg_type, msg_id, msg_command, msg_payload))
+/* The id of a call that can't be unpacked, read from the start of the raw
+ * text alone so frames beyond the limits can be answered too */
+pub fn recover_call_id(raw: &str) -> Option<String> {
+    let rest = raw.trim_start().strip_prefix('[')?.trim_start();
+    let rest = rest.strip_prefix('2')?.trim_start();
+    let rest = rest.strip_prefix(',')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    match rest
+        .char_indices()
+        .take(MAX_MESSAGE_ID_LEN + 1)
+        .find(|(_, c)| *c == '"' || *c == '\\')
+    {
+        Some((end, '"')) if end > 0 => Some(rest[..end].to_string()),
+        _ => None,
+    }
 }
 
-pub fn pack_message(message: Message) -> Result<String, ()> {
+pub fn pack_message(message: Message) -> Result<String, CsError> {
     let msg_type = match message.role {
         MessageType::Call => 2,
         MessageType::CallResult => 3,
@@ -192,9 +747,321 @@ pub fn pack_message(message: Message) -> Result<String, ()> {
         let _ = data.push(cmd.to_string());
     }
 
+    if message.role == MessageType::CallError {
+        let mut payload = message
+            .payload
+            .ok_or_else(|| CsError::ParseError("error payload is empty".to_string()))?;
+        let _ = data.push(payload["errorCode"].take());
+        let _ = data.push(payload["errorDescription"].take());
+        let details = payload["errorDetails"].take();
+        let _ = data.push(if details.is_null() {
+            object! {}
+        } else {
+            details
+        });
+        return Ok(json::stringify(data));
+    }
+
     if let Some(mut payload) = message.payload {
         let _ = data.push(payload.take());
     }
 
     Ok(json::stringify(data))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /* Refusals are bounded: a check far slower than this would be a blowup */
+    const BOUND: Duration = Duration::from_secs(1);
+
+    fn unpack_bounded(raw: &str, limits: &ParseLimits) -> Result<Message, CsError> {
+        let started = Instant::now();
+        let unpacked = unpack_message_with_limits(raw, limits);
+        assert!(started.elapsed() < BOUND);
+        unpacked
+    }
+
+    #[test]
+    fn deeply_nested_arrays_are_refused() {
+        let depth = 1_000_000;
+        let raw = format!(
+            r#"[2,"1","Heartbeat",{}{}]"#,
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        let limits = ParseLimits {
+            max_len: raw.len(),
+            ..ParseLimits::default()
+        };
+        match unpack_bounded(&raw, &limits) {
+            Err(e @ CsError::ParseError(_)) => {
+                assert_eq!(OcppErrorCode::from(&e), OcppErrorCode::FormationViolation)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unterminated_nesting_is_refused() {
+        let raw = format!(r#"[2,"1","Heartbeat",{}"#, "{\"a\":".repeat(100_000));
+        let limits = ParseLimits {
+            max_len: raw.len(),
+            ..ParseLimits::default()
+        };
+        assert!(matches!(
+            unpack_bounded(&raw, &limits),
+            Err(CsError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn brackets_in_strings_are_not_nesting() {
+        let raw = format!(
+            r#"[2,"1","DataTransfer",{{"data":"{}"}}]"#,
+            "[{".repeat(1000)
+        );
+        let unpacked = unpack_bounded(&raw, &ParseLimits::default()).unwrap();
+        assert_eq!(
+            unpacked.payload.unwrap()["data"].as_str().unwrap().len(),
+            2000
+        );
+    }
+
+    #[test]
+    fn nesting_at_the_limit_is_accepted() {
+        let limits = ParseLimits::default();
+        /* The frame and the payload are two of the levels */
+        let inner = limits.max_depth - 2;
+        let raw = format!(
+            r#"[2,"1","DataTransfer",{{"data":{}{}}}]"#,
+            "[".repeat(inner),
+            "]".repeat(inner)
+        );
+        assert!(unpack_bounded(&raw, &limits).is_ok());
+
+        let raw = format!(
+            r#"[2,"1","DataTransfer",{{"data":{}{}}}]"#,
+            "[".repeat(inner + 1),
+            "]".repeat(inner + 1)
+        );
+        assert!(unpack_bounded(&raw, &limits).is_err());
+    }
+
+    #[test]
+    fn very_large_strings_are_refused() {
+        let raw = format!(
+            r#"[2,"1","DataTransfer",{{"data":"{}"}}]"#,
+            "x".repeat(64 * 1024 * 1024)
+        );
+        match unpack_bounded(&raw, &ParseLimits::default()) {
+            Err(CsError::ParseError(reason)) => assert!(reason.contains("longer than")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escaped_quotes_keep_the_string_open() {
+        let raw = format!(
+            r#"[2,"1","DataTransfer",{{"data":"\"{}"}}]"#,
+            "[".repeat(100)
+        );
+        assert!(unpack_bounded(&raw, &ParseLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn call_id_is_recovered_from_frames_that_cant_be_unpacked() {
+        assert_eq!(
+            recover_call_id(r#"[2,"42","Heartbeat",[[[["#).as_deref(),
+            Some("42")
+        );
+        assert_eq!(recover_call_id(r#" [ 2 , "a b" ,"#).as_deref(), Some("a b"));
+        assert_eq!(recover_call_id(r#"[3,"42",{}]"#), None);
+        assert_eq!(recover_call_id(r#"[23,"42"]"#), None);
+        assert_eq!(recover_call_id(r#"[2,"",{}]"#), None);
+        assert_eq!(recover_call_id(r#"[2,"a\"b"]"#), None);
+        assert_eq!(recover_call_id(r#"[2,42]"#), None);
+        assert_eq!(recover_call_id("garbage"), None);
+        let long = format!(r#"[2,"{}"]"#, "x".repeat(MAX_MESSAGE_ID_LEN + 1));
+        assert_eq!(recover_call_id(&long), None);
+        let longest = "x".repeat(MAX_MESSAGE_ID_LEN);
+        assert_eq!(
+            recover_call_id(&format!(r#"[2,"{}"]"#, longest)),
+            Some(longest)
+        );
+    }
+
+    #[test]
+    fn unknown_action_name_survives_a_round_trip() {
+        let raw = r#"[2,"1","SetDisplayMessage",{"message":"hello"}]"#;
+        let message = unpack_message(raw).unwrap();
+        assert_eq!(
+            message.command,
+            Some(Command::Unknown("SetDisplayMessage".to_string()))
+        );
+        assert_eq!(pack_message(message).unwrap(), raw);
+    }
+
+    #[test]
+    fn timestamps_have_the_chosen_precision() {
+        let time = DateTime::parse_from_rfc3339("2024-01-01T12:34:56.789123456Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            format_timestamp(&time, DEFAULT_SECONDS_FORMAT),
+            "2024-01-01T12:34:56.789Z"
+        );
+        assert_eq!(
+            format_timestamp(&time, SecondsFormat::Secs),
+            "2024-01-01T12:34:56Z"
+        );
+        assert_eq!(
+            format_timestamp(&time, SecondsFormat::Micros),
+            "2024-01-01T12:34:56.789123Z"
+        );
+    }
+
+    #[test]
+    fn over_length_id_is_refused() {
+        let longest = "x".repeat(MAX_MESSAGE_ID_LEN);
+        let raw = format!(r#"[2,"{}","Heartbeat",{{}}]"#, longest);
+        assert_eq!(unpack_message(&raw).unwrap().id, longest);
+
+        let raw = format!(r#"[2,"{}x","Heartbeat",{{}}]"#, longest);
+        assert!(matches!(
+            unpack_message(&raw),
+            Err(CsError::ParseError(reason)) if reason.contains("too long")
+        ));
+        /* The bound counts characters, not bytes */
+        let raw = format!(
+            r#"[2,"{}","Heartbeat",{{}}]"#,
+            "é".repeat(MAX_MESSAGE_ID_LEN)
+        );
+        assert!(unpack_message(&raw).is_ok());
+    }
+
+    #[test]
+    fn central_system_ids_fit_the_bound() {
+        let id = uuid::Uuid::new_v4().to_string();
+        assert!(id.chars().count() <= MAX_MESSAGE_ID_LEN);
+        let raw = format!(r#"[2,"{}","Reset",{{"type":"Hard"}}]"#, id);
+        assert_eq!(unpack_message(&raw).unwrap().id, id);
+    }
+
+    #[test]
+    fn errors_map_to_their_documented_codes() {
+        for (error, code) in [
+            (CsError::ParseError("".into()), "FormationViolation"),
+            (CsError::UnsupportedCommand("".into()), "NotImplemented"),
+            (
+                CsError::InvalidArgument("".into()),
+                "PropertyConstraintViolation",
+            ),
+            (CsError::UnknownChargePoint("".into()), "SecurityError"),
+            (CsError::Disconnected("".into()), "GenericError"),
+            (CsError::QueueFull("".into()), "GenericError"),
+            (CsError::Timeout, "GenericError"),
+            (CsError::CaError("".into()), "InternalError"),
+            (CsError::Storage("".into()), "InternalError"),
+            (CsError::Config("".into()), "InternalError"),
+            (CsError::Io(std::io::Error::other("io")), "InternalError"),
+        ] {
+            assert_eq!(OcppErrorCode::from(&error).to_string(), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn error_codes_round_trip_and_follow_the_version() {
+        for code in [
+            "NotImplemented",
+            "NotSupported",
+            "InternalError",
+            "ProtocolError",
+            "SecurityError",
+            "FormationViolation",
+            "FormatViolation",
+            "PropertyConstraintViolation",
+            "OccurenceConstraintViolation",
+            "TypeConstraintViolation",
+            "GenericError",
+        ] {
+            assert_eq!(OcppErrorCode::try_from(code).unwrap().as_str(), code);
+        }
+        assert_eq!(
+            OcppErrorCode::try_from("OccurrenceConstraintViolation").unwrap(),
+            OcppErrorCode::OccurrenceConstraintViolation
+        );
+        assert!(OcppErrorCode::try_from("NoSuchError").is_err());
+
+        let formation = OcppErrorCode::FormationViolation;
+        assert_eq!(
+            formation.for_version(ProtocolVersion::Ocpp201),
+            OcppErrorCode::FormatViolation
+        );
+        assert_eq!(
+            OcppErrorCode::FormatViolation.for_version(ProtocolVersion::Ocpp16),
+            formation
+        );
+    }
+
+    #[test]
+    fn call_error_code_is_the_third_element() {
+        let error = Message::call_error(
+            "7".to_string(),
+            OcppErrorCode::NotSupported,
+            "Reset is sent by the central system",
+            object! {},
+        );
+        assert_eq!(
+            pack_message(error).unwrap(),
+            r#"[4,"7","NotSupported","Reset is sent by the central system",{}]"#
+        );
+    }
+    #[test]
+    fn inbound_call_error_fields_are_unpacked() {
+        let raw = r#"[4,"c2","SecurityError","certificate rejected",{"reason":"expired"}]"#;
+        let message = unpack_message(raw).unwrap();
+        assert_eq!(message.role, MessageType::CallError);
+        assert_eq!(message.id, "c2");
+        assert_eq!(message.command, None);
+        assert_eq!(
+            message.error(),
+            Some(CallErrorFields {
+                code: "SecurityError".to_string(),
+                description: "certificate rejected".to_string(),
+                details: object! { reason : "expired" },
+            })
+        );
+        assert_eq!(pack_message(message).unwrap(), raw);
+
+        /* Codes aren't restricted, missing description and details are empty */
+        let error = unpack_message(r#"[4,"c3","VendorSpecificError"]"#)
+            .unwrap()
+            .error()
+            .unwrap();
+        assert_eq!(error.code, "VendorSpecificError");
+        assert_eq!(error.description, "");
+        assert_eq!(error.details, object! {});
+    }
+
+    #[test]
+    fn malformed_call_error_fields_are_refused() {
+        for raw in [
+            r#"[4,"c4"]"#,
+            r#"[4,"c4",7,"description",{}]"#,
+            r#"[4,"c4","InternalError",["description"],{}]"#,
+            r#"[4,"c4","InternalError","description","details"]"#,
+        ] {
+            assert!(
+                matches!(unpack_message(raw), Err(CsError::ParseError(_))),
+                "{}",
+                raw
+            );
+        }
+        /* Only CallErrors have error fields */
+        let result = unpack_message(r#"[3,"c5",{"errorCode":"InternalError"}]"#).unwrap();
+        assert_eq!(result.error(), None);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::cs::CentralSystem;
+use crate::error::CsError;
+use openssl::ocsp::{OcspResponse, OcspResponseStatus};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use tiny_http::{Header, Method, Response, Server};
+use tracing::warn;
+
+/* OCSP responder of the CA (RFC 6960 over HTTP):
+ *
+ * POST /   body is a DER OCSP request, the answer a DER OCSP response
+ *
+ * Failures of the CA are answered with an internalError response. */
+pub fn serve(address: &str, cs: Arc<Mutex<CentralSystem>>) -> Result<(), CsError> {
+    let server = Server::http(address).map_err(|e| CsError::InvalidArgument(e.to_string()))?;
+    spawn(move || {
+        for mut request in server.incoming_requests() {
+            if request.method() != &Method::Post {
+                let _ = request.respond(Response::empty(405));
+                continue;
+            }
+
+            let mut body = Vec::new();
+            let _ = request.as_reader().read_to_end(&mut body);
+            let answer = cs.lock().unwrap().ocsp_response(&body).or_else(|e| {
+                warn!("OCSP: {}", e);
+                OcspResponse::create(OcspResponseStatus::INTERNAL_ERROR, None)
+                    .and_then(|response| response.to_der())
+                    .map_err(|e| CsError::CaError(e.to_string()))
+            });
+            let answer = match answer {
+                Ok(answer) => answer,
+                Err(_) => {
+                    let _ = request.respond(Response::empty(500));
+                    continue;
+                }
+            };
+
+            let header =
+                Header::from_bytes(&b"Content-Type"[..], &b"application/ocsp-response"[..])
+                    .unwrap();
+            let _ = request.respond(Response::from_data(answer).with_header(header));
+        }
+    });
+    Ok(())
+}
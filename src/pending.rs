@@ -0,0 +1,306 @@
+use crate::error::CsError;
+use crate::ocpp::{Command, Message, MessageType};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/* How CallResults and CallErrors that answer no pending call are handled,
+ * e.g. late answers to calls that timed out */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum UnmatchedResponses {
+    /* Logged at info level */
+    #[default]
+    Ignore,
+    Warn,
+    /* make_response fails with a ParseError */
+    Error,
+}
+
+impl UnmatchedResponses {
+    pub fn parse(value: &str) -> Option<UnmatchedResponses> {
+        match value.to_ascii_lowercase().as_str() {
+            "ignore" => Some(UnmatchedResponses::Ignore),
+            "warn" => Some(UnmatchedResponses::Warn),
+            "error" => Some(UnmatchedResponses::Error),
+            _ => None,
+        }
+    }
+}
+
+/* Receives the CallResult/CallError answering the call, or an error when the
+ * call timed out */
+pub type Completion = Box<dyn FnOnce(Result<&Message, CsError>) + Send>;
+
+pub struct PendingCall {
+    pub cp_id: String,
+    pub command: Command,
+    pub payload: json::JsonValue,
+    pub sent: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    completion: Option<Completion>,
+}
+
+#[derive(Default)]
+pub struct PendingCalls {
+    calls: HashMap<String, PendingCall>,
+}
+
+impl PendingCalls {
+    pub fn new() -> PendingCalls {
+        PendingCalls {
+            calls: HashMap::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        cp_id: &str,
+        call: &Message,
+        timeout: u32,
+        completion: Option<Completion>,
+        sent: DateTime<Utc>,
+    ) -> Result<(), CsError> {
+        let command = match (&call.role, &call.command) {
+            (MessageType::Call, Some(command)) => command.clone(),
+            _ => {
+                return Err(CsError::InvalidArgument(
+                    "only calls can be awaited".to_string(),
+                ))
+            }
+        };
+
+        let pending = PendingCall {
+            cp_id: cp_id.to_string(),
+            command,
+            payload: call.payload.clone().unwrap_or(json::JsonValue::Null),
+            sent,
+            deadline: sent + Duration::seconds(timeout as i64),
+            completion,
+        };
+        self.calls.insert(call.id.clone(), pending);
+        Ok(())
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.calls.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /* Matches a CallResult/CallError with the call it answers. Returns the
+     * original call, or None when nothing with this id is pending. */
+    pub fn complete(&mut self, cp_id: &str, response: &Message) -> Option<PendingCall> {
+        match self.calls.get(response.id.as_str()) {
+            Some(call) if call.cp_id == cp_id => {}
+            _ => return None,
+        }
+
+        let mut call = self.calls.remove(response.id.as_str()).unwrap();
+        let outcome = match response.role {
+            MessageType::CallError => "failed",
+            _ => "completed",
+        };
+        info!(
+            "{} {} {}: {}",
+            call.command,
+            response.id,
+            outcome,
+            response
+                .payload
+                .as_ref()
+                .map(|p| p.dump())
+                .unwrap_or_default()
+        );
+
+        if let Some(completion) = call.completion.take() {
+            completion(Ok(response));
+        }
+        Some(call)
+    }
+
+    /* Forgets a call that couldn't be sent, its completion isn't called */
+    pub fn cancel(&mut self, id: &str) -> Option<PendingCall> {
+        self.calls.remove(id)
+    }
+
+    /* Drops calls whose deadline has passed and notifies their owners */
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<PendingCall> {
+        let expired_ids: Vec<String> = self
+            .calls
+            .iter()
+            .filter(|(_, call)| call.deadline <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let mut expired = Vec::new();
+        for id in expired_ids {
+            let mut call = self.calls.remove(id.as_str()).unwrap();
+            warn!(
+                "{} {} to {} timed out without response",
+                call.command, id, call.cp_id
+            );
+            if let Some(completion) = call.completion.take() {
+                completion(Err(CsError::Timeout));
+            }
+            expired.push(call);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Outcomes = Arc<Mutex<Vec<Result<Message, CsError>>>>;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn reset(id: &str) -> Message {
+        Message::new(
+            MessageType::Call,
+            id.to_string(),
+            Some(Command::Reset),
+            Some(object! { type : "Soft" }),
+        )
+    }
+
+    fn recorder() -> (Outcomes, Completion) {
+        let outcomes: Outcomes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&outcomes);
+        let completion: Completion = Box::new(move |outcome| {
+            recorded.lock().unwrap().push(outcome.cloned());
+        });
+        (outcomes, completion)
+    }
+
+    #[test]
+    fn call_result_completes_the_call() {
+        let mut pending = PendingCalls::new();
+        let (outcomes, completion) = recorder();
+        pending
+            .register("cp1", &reset("1"), 30, Some(completion), now())
+            .unwrap();
+
+        let result = Message::new(
+            MessageType::CallResult,
+            "1".to_string(),
+            None,
+            Some(object! { status : "Accepted" }),
+        );
+        let call = pending.complete("cp1", &result).unwrap();
+        assert_eq!(call.command, Command::Reset);
+        assert_eq!(call.payload["type"], "Soft");
+        assert!(pending.is_empty());
+
+        let outcomes = outcomes.lock().unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(
+            outcomes[0].as_ref().unwrap().payload.as_ref().unwrap()["status"],
+            "Accepted"
+        );
+    }
+
+    #[test]
+    fn call_error_completes_the_call() {
+        let mut pending = PendingCalls::new();
+        let (outcomes, completion) = recorder();
+        pending
+            .register("cp1", &reset("1"), 30, Some(completion), now())
+            .unwrap();
+
+        let error = Message::call_error(
+            "1".to_string(),
+            crate::ocpp::OcppErrorCode::NotSupported,
+            "no",
+            object! {},
+        );
+        assert!(pending.complete("cp1", &error).is_some());
+        let outcomes = outcomes.lock().unwrap();
+        assert_eq!(outcomes[0].as_ref().unwrap().role, MessageType::CallError);
+    }
+
+    #[test]
+    fn unmatched_answers_complete_nothing() {
+        let mut pending = PendingCalls::new();
+        let (outcomes, completion) = recorder();
+        pending
+            .register("cp1", &reset("1"), 30, Some(completion), now())
+            .unwrap();
+
+        let unknown = Message::new(MessageType::CallResult, "2".to_string(), None, None);
+        assert!(pending.complete("cp1", &unknown).is_none());
+        /* The id is pending for another charge point */
+        let foreign = Message::new(MessageType::CallResult, "1".to_string(), None, None);
+        assert!(pending.complete("cp2", &foreign).is_none());
+
+        assert!(pending.contains("1"));
+        assert!(outcomes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_calls_are_awaited() {
+        let mut pending = PendingCalls::new();
+        let result = Message::new(MessageType::CallResult, "1".to_string(), None, None);
+        assert!(pending.register("cp1", &result, 30, None, now()).is_err());
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn unanswered_call_times_out_at_its_deadline() {
+        let mut pending = PendingCalls::new();
+        let (outcomes, completion) = recorder();
+        pending
+            .register("cp1", &reset("1"), 30, Some(completion), now())
+            .unwrap();
+
+        assert!(pending.expire(now() + Duration::seconds(29)).is_empty());
+        assert!(outcomes.lock().unwrap().is_empty());
+
+        let expired = pending.expire(now() + Duration::seconds(30));
+        assert_eq!(expired.len(), 1);
+        assert!(pending.is_empty());
+        let outcomes = outcomes.lock().unwrap();
+        assert!(matches!(outcomes[..], [Err(CsError::Timeout)]));
+    }
+
+    #[test]
+    fn late_answer_is_unmatched() {
+        let mut pending = PendingCalls::new();
+        pending
+            .register("cp1", &reset("1"), 30, None, now())
+            .unwrap();
+        pending.expire(now() + Duration::seconds(60));
+
+        let late = Message::new(MessageType::CallResult, "1".to_string(), None, None);
+        assert!(pending.complete("cp1", &late).is_none());
+    }
+    #[test]
+    fn unmatched_responses_are_parsed() {
+        assert_eq!(
+            UnmatchedResponses::parse("ignore"),
+            Some(UnmatchedResponses::Ignore)
+        );
+        assert_eq!(
+            UnmatchedResponses::parse("Warn"),
+            Some(UnmatchedResponses::Warn)
+        );
+        assert_eq!(
+            UnmatchedResponses::parse("ERROR"),
+            Some(UnmatchedResponses::Error)
+        );
+        assert_eq!(UnmatchedResponses::parse("drop"), None);
+        assert_eq!(UnmatchedResponses::default(), UnmatchedResponses::Ignore);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::error::CsError;
+use crate::ocpp::{
+    format_timestamp, pack_message, unpack_message, CentralSystem, Message, MessageType,
+    ProtocolVersion, DEFAULT_SECONDS_FORMAT,
+};
+use crate::server::DEFAULT_OUTBOUND_QUEUE;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc::sync_channel;
+use std::sync::Mutex;
+use tracing::warn;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    /* Sent by the charge point */
+    Inbound,
+    /* Sent by the central system */
+    Outbound,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "CP",
+            Direction::Outbound => "CS",
+        }
+    }
+}
+
+/* Appends every frame of every connection to a newline-delimited JSON file:
+ * {"timestamp": ..., "cpId": ..., "direction": "CP"|"CS", "frame": [...]}
+ * Connections are recorded as {"event": "connect", "protocol": ...} */
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Recorder, CsError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn connect(&self, cp_id: &str, version: ProtocolVersion) {
+        self.write(object! {
+            timestamp : self.timestamp(),
+            cpId : cp_id,
+            event : "connect",
+            protocol : version.subprotocol(),
+        });
+    }
+
+    pub fn record(&self, cp_id: &str, direction: Direction, frame: &str) {
+        let frame = json::parse(frame).unwrap_or_else(|_| frame.into());
+        self.write(object! {
+            timestamp : self.timestamp(),
+            cpId : cp_id,
+            direction : direction.as_str(),
+            frame : frame,
+        });
+    }
+
+    fn timestamp(&self) -> String {
+        format_timestamp(&Utc::now(), DEFAULT_SECONDS_FORMAT)
+    }
+
+    fn write(&self, entry: json::JsonValue) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", entry.dump()) {
+            warn!("can't record a message: {}", e);
+        }
+    }
+}
+
+/* A response produced during replay that differs from the recorded one */
+pub struct Divergence {
+    pub line: usize,
+    pub cp_id: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {} ({}): expected {}, got {}",
+            self.line,
+            self.cp_id,
+            self.expected.as_deref().unwrap_or("nothing"),
+            self.actual.as_deref().unwrap_or("nothing")
+        )
+    }
+}
+
+struct Entry {
+    line: usize,
+    cp_id: String,
+    event: Option<String>,
+    protocol: Option<String>,
+    direction: Option<String>,
+    frame: json::JsonValue,
+}
+
+fn read_entries(path: &str) -> Result<Vec<Entry>, CsError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut value = json::parse(line.as_str())
+            .map_err(|e| CsError::ParseError(format!("line {}: {}", index + 1, e)))?;
+        entries.push(Entry {
+            line: index + 1,
+            cp_id: value["cpId"].as_str().unwrap_or_default().to_string(),
+            event: value["event"].as_str().map(String::from),
+            protocol: value["protocol"].as_str().map(String::from),
+            direction: value["direction"].as_str().map(String::from),
+            frame: value["frame"].take(),
+        });
+    }
+    Ok(entries)
+}
+
+/* Timestamps and the ids of calls initiated by the central system differ
+ * from run to run */
+fn normalize(frame: &json::JsonValue) -> json::JsonValue {
+    fn strip_timestamps(value: &json::JsonValue) -> json::JsonValue {
+        match value {
+            json::JsonValue::Object(object) => {
+                let mut normalized = json::JsonValue::new_object();
+                for (key, value) in object.iter() {
+                    normalized[key] = strip_timestamps(value);
+                }
+                normalized
+            }
+            json::JsonValue::Array(items) => {
+                json::JsonValue::Array(items.iter().map(strip_timestamps).collect())
+            }
+            other => match other.as_str().map(DateTime::parse_from_rfc3339) {
+                Some(Ok(_)) => "<timestamp>".into(),
+                _ => other.clone(),
+            },
+        }
+    }
+
+    let mut normalized = strip_timestamps(frame);
+    if normalized[0].as_u8() == Some(2) {
+        normalized[1] = "<id>".into();
+    }
+    normalized
+}
+
+/* Feeds the charge point calls of a recording to the central system and
+ * compares its responses with the recorded ones */
+pub fn replay(cs: &mut dyn CentralSystem, path: &str) -> Result<Vec<Divergence>, CsError> {
+    let entries = read_entries(path)?;
+    let mut divergences = Vec::new();
+    /* Calls from the central system are not delivered anywhere */
+    let mut outbound = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.event.as_deref() == Some("connect") {
+            let version = entry
+                .protocol
+                .as_deref()
+                .and_then(ProtocolVersion::from_subprotocol)
+                .unwrap_or_default();
+            let (tx, rx) = sync_channel(DEFAULT_OUTBOUND_QUEUE);
+            outbound.push(rx);
+            cs.connect(entry.cp_id.as_str(), version, tx, index as u64);
+            continue;
+        }
+
+        if entry.direction.as_deref() != Some(Direction::Inbound.as_str()) {
+            continue;
+        }
+
+        let request = unpack_message(entry.frame.dump().as_str())?;
+        if request.role != MessageType::Call {
+            continue;
+        }
+
+        let id = request.id.clone();
+        let actual: Vec<Message> = cs
+            .make_response(entry.cp_id.as_str(), request)
+            .unwrap_or_default();
+
+        /* Recorded frames the central system sent in response */
+        let recorded: Vec<&Entry> = entries[index + 1..]
+            .iter()
+            .filter(|e| e.cp_id == entry.cp_id)
+            .take_while(|e| e.direction.as_deref() != Some(Direction::Inbound.as_str()))
+            .filter(|e| e.direction.as_deref() == Some(Direction::Outbound.as_str()))
+            .collect();
+
+        let mut actual: Vec<json::JsonValue> = actual
+            .into_iter()
+            .filter_map(|m| pack_message(m).ok())
+            .filter_map(|f| json::parse(f.as_str()).ok())
+            .collect();
+
+        for expected in recorded {
+            /* Calls initiated by the API are not part of the answer */
+            let answers = expected.frame[1].as_str() == Some(id.as_str());
+            let position = actual
+                .iter()
+                .position(|a| normalize(a) == normalize(&expected.frame));
+            match position {
+                Some(position) => {
+                    actual.remove(position);
+                }
+                None if answers || expected.frame[0].as_u8() == Some(2) => {
+                    let candidate = actual
+                        .iter()
+                        .position(|a| a[1] == expected.frame[1] || a[2] == expected.frame[2]);
+                    divergences.push(Divergence {
+                        line: expected.line,
+                        cp_id: entry.cp_id.clone(),
+                        expected: Some(expected.frame.dump()),
+                        actual: candidate.map(|c| actual.remove(c).dump()),
+                    });
+                }
+                None => {}
+            }
+        }
+
+        for unexpected in actual {
+            divergences.push(Divergence {
+                line: entry.line,
+                cp_id: entry.cp_id.clone(),
+                expected: None,
+                actual: Some(unexpected.dump()),
+            });
+        }
+    }
+
+    drop(outbound);
+    Ok(divergences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+
+    const FIXTURE: &str = "fixtures/sessions/boot_and_transaction.ndjson";
+
+    /* A file removed with the guard */
+    struct Recording(String);
+
+    impl Drop for Recording {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn recording() -> Recording {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "dummy-cs-{}.ndjson",
+                uuid::Uuid::new_v4().to_simple()
+            ))
+            .to_string_lossy()
+            .to_string();
+        Recording(path)
+    }
+
+    fn central_system() -> crate::cs::CentralSystem {
+        crate::cs::CentralSystem::dry_run(&Settings::default()).unwrap()
+    }
+
+    #[test]
+    fn fixture_replays_without_divergences() {
+        let divergences = replay(&mut central_system(), FIXTURE).unwrap();
+        let described: Vec<String> = divergences.iter().map(|d| d.to_string()).collect();
+        assert!(divergences.is_empty(), "{:?}", described);
+    }
+
+    #[test]
+    fn changed_response_is_a_divergence() {
+        let recording = recording();
+        let fixture = std::fs::read_to_string(FIXTURE).unwrap();
+        let changed = fixture.replacen(r#""status":"Accepted""#, r#""status":"Rejected""#, 1);
+        assert_ne!(changed, fixture);
+        std::fs::write(&recording.0, changed).unwrap();
+
+        let divergences = replay(&mut central_system(), &recording.0).unwrap();
+        assert_eq!(divergences.len(), 1);
+        let divergence = &divergences[0];
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.cp_id, "cp1");
+        assert!(divergence.expected.as_ref().unwrap().contains("Rejected"));
+        assert!(divergence.actual.as_ref().unwrap().contains("Accepted"));
+    }
+
+    #[test]
+    fn recorded_session_replays() {
+        let recording = recording();
+        let recorder = Recorder::create(&recording.0).unwrap();
+        let mut cs = central_system();
+        let (tx, _rx) = sync_channel(DEFAULT_OUTBOUND_QUEUE);
+        cs.connect("cp1", ProtocolVersion::Ocpp16, tx, 0);
+        recorder.connect("cp1", ProtocolVersion::Ocpp16);
+        for frame in [
+            r#"[2,"1","BootNotification",{"chargePointVendor":"dummy","chargePointModel":"m1"}]"#,
+            r#"[2,"2","Heartbeat",{}]"#,
+            r#"[2,"3","Authorize",{"idTag":"TAG1"}]"#,
+        ] {
+            recorder.record("cp1", Direction::Inbound, frame);
+            let request = unpack_message(frame).unwrap();
+            for response in cs.make_response("cp1", request).unwrap() {
+                let response = pack_message(response).unwrap();
+                recorder.record("cp1", Direction::Outbound, &response);
+            }
+        }
+        drop(recorder);
+
+        let recorded = std::fs::read_to_string(&recording.0).unwrap();
+        assert_eq!(recorded.lines().count(), 7);
+        assert!(replay(&mut central_system(), &recording.0)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::error::CsError;
+use crate::ocpp::{Command, Message, MessageType};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::read_to_string;
+
+/* A rule fires at most that often per charge point */
+pub const MAX_TIMES: u32 = 100;
+
+/* Calls the central system sends after answering a call of a charge point,
+ * scripted in a JSON-lines file with one rule per line:
+ *
+ * {"on": "BootNotification", "chargePoint": "cp1",
+ *  "match": {"chargePointVendor": "dummy"},
+ *  "send": [{"action": "ChangeConfiguration",
+ *            "payload": {"key": "HeartbeatInterval", "value": "30"}},
+ *           {"action": "Reset", "payload": {"type": "Soft"}}],
+ *  "times": 1}
+ *
+ * on and send are required. chargePoint and match, the fields the payload
+ * must hold with these values, narrow the trigger. A rule fires times
+ * (default 1, at most MAX_TIMES) per charge point, so charge points
+ * answering a scripted call with the trigger don't loop. Strings of the
+ * payloads may hold {{chargePoint}} and {{payload.<field>}}, a top-level
+ * field of the triggering payload; a string that is only a placeholder
+ * takes the type of the field. Blank lines are skipped. */
+#[derive(Default)]
+pub struct Scenario {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    on: Command,
+    charge_point: Option<String>,
+    matcher: json::JsonValue,
+    send: Vec<(Command, json::JsonValue)>,
+    times: u32,
+    /* Firings by charge point */
+    fired: HashMap<String, u32>,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Scenario, CsError> {
+        let raw = read_to_string(path)
+            .map_err(|e| CsError::Config(format!("can't read {}: {}", path, e)))?;
+        Scenario::parse(&raw).map_err(|e| CsError::Config(format!("{}: {}", path, e)))
+    }
+
+    pub fn parse(raw: &str) -> Result<Scenario, CsError> {
+        let mut rules = Vec::new();
+        for (index, line) in raw.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let rule = Rule::parse(line)
+                .map_err(|e| CsError::Config(format!("line {}: {}", index + 1, e)))?;
+            rules.push(rule);
+        }
+        Ok(Scenario { rules })
+    }
+
+    /* The calls that follow the answer to the request, in the order of the
+     * rules and their send lists */
+    pub fn calls(&mut self, cp_id: &str, request: &Message) -> Vec<Message> {
+        let command = match (&request.role, request.command.as_ref()) {
+            (MessageType::Call, Some(command)) => command,
+            _ => return Vec::new(),
+        };
+        let payload = request.payload.as_ref().unwrap_or(&json::JsonValue::Null);
+
+        let mut calls = Vec::new();
+        for rule in self.rules.iter_mut() {
+            if !rule.matches(cp_id, command, payload) {
+                continue;
+            }
+            *rule.fired.entry(cp_id.to_string()).or_default() += 1;
+            for (action, template) in rule.send.iter() {
+                let id = uuid::Uuid::new_v4().to_string();
+                let payload = render(template, cp_id, payload);
+                calls.push(Message::new(
+                    MessageType::Call,
+                    id,
+                    Some(action.clone()),
+                    Some(payload),
+                ));
+            }
+        }
+        calls
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Rule, String> {
+        let value = json::parse(line).map_err(|e| e.to_string())?;
+        let on = parse_action(value["on"].as_str().ok_or("on is missing")?)?;
+        let matcher = match &value["match"] {
+            json::JsonValue::Null => json::JsonValue::new_object(),
+            matcher if matcher.is_object() => matcher.clone(),
+            _ => return Err("match is not an object".to_string()),
+        };
+        if !value["send"].is_array() || value["send"].is_empty() {
+            return Err("send is not a list of calls".to_string());
+        }
+        let mut send = Vec::new();
+        for call in value["send"].members() {
+            let action = call["action"]
+                .as_str()
+                .ok_or("action of a call is missing")?;
+            let action = parse_action(action)?;
+            let payload = match &call["payload"] {
+                json::JsonValue::Null => json::JsonValue::new_object(),
+                payload if payload.is_object() => payload.clone(),
+                _ => return Err(format!("payload of {} is not an object", action)),
+            };
+            send.push((action, payload));
+        }
+        let times = match &value["times"] {
+            json::JsonValue::Null => 1,
+            times => times
+                .as_u32()
+                .filter(|t| (1..=MAX_TIMES).contains(t))
+                .ok_or_else(|| format!("times is not within 1 and {}", MAX_TIMES))?,
+        };
+
+        Ok(Rule {
+            on,
+            charge_point: value["chargePoint"].as_str().map(String::from),
+            matcher,
+            send,
+            times,
+            fired: HashMap::new(),
+        })
+    }
+
+    fn matches(&self, cp_id: &str, command: &Command, payload: &json::JsonValue) -> bool {
+        self.on == *command
+            && self.charge_point.as_deref().is_none_or(|id| id == cp_id)
+            && self.fired.get(cp_id).copied().unwrap_or(0) < self.times
+            && self
+                .matcher
+                .entries()
+                .all(|(field, value)| payload[field] == *value)
+    }
+}
+
+fn parse_action(action: &str) -> Result<Command, String> {
+    Command::try_from(action).map_err(|_| format!("unknown action {}", action))
+}
+
+fn render(template: &json::JsonValue, cp_id: &str, payload: &json::JsonValue) -> json::JsonValue {
+    match template {
+        json::JsonValue::Object(object) => {
+            let mut rendered = json::JsonValue::new_object();
+            for (key, value) in object.iter() {
+                rendered[key] = render(value, cp_id, payload);
+            }
+            rendered
+        }
+        json::JsonValue::Array(values) => json::JsonValue::Array(
+            values
+                .iter()
+                .map(|value| render(value, cp_id, payload))
+                .collect(),
+        ),
+        value => match value.as_str() {
+            Some(text) => render_string(text, cp_id, payload),
+            None => value.clone(),
+        },
+    }
+}
+
+fn render_string(text: &str, cp_id: &str, payload: &json::JsonValue) -> json::JsonValue {
+    let field = |name: &str| match name {
+        "chargePoint" => Some(json::JsonValue::from(cp_id)),
+        name => name
+            .strip_prefix("payload.")
+            .map(|field| payload[field].clone()),
+    };
+
+    /* A lone placeholder keeps the type of the value */
+    if let Some(name) = text.strip_prefix("{{").and_then(|t| t.strip_suffix("}}")) {
+        if !name.contains("{{") {
+            if let Some(value) = field(name.trim()) {
+                return value;
+            }
+        }
+    }
+
+    let mut rendered = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        match field(rest[start + 2..end].trim()) {
+            Some(value) => match value.as_str() {
+                Some(value) => rendered.push_str(value),
+                None => rendered.push_str(&value.dump()),
+            },
+            None => rendered.push_str(&rest[start..end + 2]),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    json::JsonValue::from(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(command: Command, payload: json::JsonValue) -> Message {
+        Message::new(
+            MessageType::Call,
+            "1".to_string(),
+            Some(command),
+            Some(payload),
+        )
+    }
+
+    fn boot(vendor: &str) -> Message {
+        call(
+            Command::BootNotification,
+            object! { chargePointVendor : vendor, chargePointModel : "m1" },
+        )
+    }
+
+    fn actions(calls: &[Message]) -> Vec<Command> {
+        calls.iter().map(|c| c.command.clone().unwrap()).collect()
+    }
+
+    #[test]
+    fn scripted_calls_follow_the_trigger_in_order() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/scenarios/boot_configure_reset.jsonl"
+        );
+        let mut scenario = Scenario::load(path).unwrap();
+
+        let calls = scenario.calls("cp1", &boot("dummy"));
+        assert_eq!(
+            actions(&calls),
+            [Command::ChangeConfiguration, Command::Reset]
+        );
+        assert_eq!(
+            calls[0].payload.as_ref().unwrap(),
+            &object! { key : "HeartbeatInterval", value : "30" }
+        );
+        assert_eq!(
+            calls[1].payload.as_ref().unwrap(),
+            &object! { type : "Soft" }
+        );
+        assert_ne!(calls[0].id, calls[1].id);
+
+        /* Once per charge point, and only for the matching payloads */
+        assert!(scenario.calls("cp1", &boot("dummy")).is_empty());
+        assert_eq!(scenario.calls("cp2", &boot("dummy")).len(), 2);
+        assert!(scenario.calls("cp3", &boot("other")).is_empty());
+    }
+
+    #[test]
+    fn rules_fire_at_most_their_times() {
+        let mut scenario = Scenario::parse(
+            r#"{"on": "StatusNotification", "match": {"status": "Faulted"}, "send": [{"action": "TriggerMessage", "payload": {"requestedMessage": "StatusNotification", "connectorId": "{{payload.connectorId}}", "note": "{{chargePoint}}/{{payload.connectorId}}"}}], "times": 3}"#,
+        )
+        .unwrap();
+        let faulted = call(
+            Command::StatusNotification,
+            object! { connectorId : 2, errorCode : "GroundFailure", status : "Faulted" },
+        );
+        for _ in 0..3 {
+            let calls = scenario.calls("cp1", &faulted);
+            assert_eq!(
+                calls[0].payload.as_ref().unwrap(),
+                &object! {
+                    requestedMessage : "StatusNotification",
+                    connectorId : 2,
+                    note : "cp1/2",
+                }
+            );
+        }
+        assert!(scenario.calls("cp1", &faulted).is_empty());
+    }
+
+    #[test]
+    fn answers_trigger_nothing() {
+        let mut scenario =
+            Scenario::parse(r#"{"on": "Heartbeat", "send": [{"action": "Reset"}]}"#).unwrap();
+        let answer = Message::new(MessageType::CallResult, "1".to_string(), None, None);
+        assert!(scenario.calls("cp1", &answer).is_empty());
+        assert_eq!(
+            actions(&scenario.calls("cp1", &call(Command::Heartbeat, object! {}))),
+            [Command::Reset]
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_refused_with_their_line() {
+        for (raw, reason) in [
+            (r#"{"send": [{"action": "Reset"}]}"#, "on is missing"),
+            (r#"{"on": "Heartbeat"}"#, "send is not a list"),
+            (r#"{"on": "Heartbeat", "send": []}"#, "send is not a list"),
+            (
+                r#"{"on": "Nap", "send": [{"action": "Reset"}]}"#,
+                "unknown action Nap",
+            ),
+            (
+                r#"{"on": "Heartbeat", "send": [{"action": "Reset"}], "times": 0}"#,
+                "times is not within",
+            ),
+            (
+                r#"{"on": "Heartbeat", "send": [{"action": "Reset"}], "times": 101}"#,
+                "times is not within",
+            ),
+        ] {
+            match Scenario::parse(&format!("\n{}", raw)) {
+                Err(CsError::Config(e)) => {
+                    assert!(e.starts_with("line 2: "), "{}", e);
+                    assert!(e.contains(reason), "{}", e);
+                }
+                _ => panic!("{} is accepted", raw),
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::CsError;
+use crate::ocpp::Command;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Validation {
+    /* Payloads are passed to the handlers as is */
+    Off,
+    /* Violations are logged, the call is still processed */
+    #[default]
+    Lenient,
+    /* Violations are answered with a FormationViolation CallError */
+    Strict,
+}
+
+/* OCPP 1.6 schemas for the calls initiated by a charge point */
+const OCPP16_SCHEMAS: [(&str, &str); 10] = [
+    (
+        "Authorize",
+        include_str!("../schemas/ocpp1.6/Authorize.json"),
+    ),
+    (
+        "BootNotification",
+        include_str!("../schemas/ocpp1.6/BootNotification.json"),
+    ),
+    (
+        "DataTransfer",
+        include_str!("../schemas/ocpp1.6/DataTransfer.json"),
+    ),
+    (
+        "DiagnosticsStatusNotification",
+        include_str!("../schemas/ocpp1.6/DiagnosticsStatusNotification.json"),
+    ),
+    (
+        "FirmwareStatusNotification",
+        include_str!("../schemas/ocpp1.6/FirmwareStatusNotification.json"),
+    ),
+    (
+        "Heartbeat",
+        include_str!("../schemas/ocpp1.6/Heartbeat.json"),
+    ),
+    (
+        "MeterValues",
+        include_str!("../schemas/ocpp1.6/MeterValues.json"),
+    ),
+    (
+        "StartTransaction",
+        include_str!("../schemas/ocpp1.6/StartTransaction.json"),
+    ),
+    (
+        "StatusNotification",
+        include_str!("../schemas/ocpp1.6/StatusNotification.json"),
+    ),
+    (
+        "StopTransaction",
+        include_str!("../schemas/ocpp1.6/StopTransaction.json"),
+    ),
+];
+
+pub struct SchemaValidator {
+    validators: HashMap<&'static str, jsonschema::Validator>,
+}
+
+impl SchemaValidator {
+    pub fn new() -> Result<SchemaValidator, CsError> {
+        let mut validators = HashMap::new();
+        for (name, raw) in OCPP16_SCHEMAS.iter() {
+            let schema: serde_json::Value =
+                serde_json::from_str(raw).map_err(|e| CsError::ParseError(e.to_string()))?;
+            let validator = jsonschema::validator_for(&schema)
+                .map_err(|e| CsError::ParseError(format!("invalid {} schema: {}", name, e)))?;
+            validators.insert(*name, validator);
+        }
+        Ok(SchemaValidator { validators })
+    }
+
+    /* Commands without a bundled schema are always accepted */
+    pub fn validate(
+        &self,
+        command: &Command,
+        payload: Option<&json::JsonValue>,
+    ) -> Result<(), String> {
+        let validator = match self.validators.get(command.to_string().as_str()) {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+
+        let raw = payload
+            .map(|p| p.dump())
+            .unwrap_or_else(|| "null".to_string());
+        let instance: serde_json::Value =
+            serde_json::from_str(&raw).or(Err("payload is not valid JSON".to_string()))?;
+
+        let errors: Vec<String> = validator
+            .iter_errors(&instance)
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                if path.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("{} at {}", e, path)
+                }
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_authorize_passes() {
+        let schemas = SchemaValidator::new().unwrap();
+        let payload = object! { idTag : "TAG1" };
+        assert_eq!(
+            schemas.validate(&Command::Authorize, Some(&payload)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn schema_violating_authorize_is_described() {
+        let schemas = SchemaValidator::new().unwrap();
+        let description = schemas
+            .validate(&Command::Authorize, Some(&object! {}))
+            .unwrap_err();
+        assert!(description.contains("idTag"), "{}", description);
+
+        let description = schemas
+            .validate(&Command::Authorize, Some(&object! { idTag : 42 }))
+            .unwrap_err();
+        assert!(description.contains("/idTag"), "{}", description);
+
+        /* idTag is CiString20Type */
+        let long = "T".repeat(21);
+        assert!(schemas
+            .validate(&Command::Authorize, Some(&object! { idTag : long }))
+            .is_err());
+        assert!(schemas.validate(&Command::Authorize, None).is_err());
+    }
+
+    #[test]
+    fn commands_without_a_schema_are_accepted() {
+        let schemas = SchemaValidator::new().unwrap();
+        assert_eq!(
+            schemas.validate(&Command::Reset, Some(&object! { anything : 1 })),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,1607 @@
+use crate::credentials::Credentials;
+use crate::error::CsError;
+use crate::events::{EventBus, OcppEvent};
+use crate::metrics;
+use crate::ocpp;
+use crate::recorder::{Direction, Recorder};
+use crate::stats::Stats;
+use crate::tls;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+
+use openssl::ssl::SslAcceptor;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::task::{spawn_blocking, JoinHandle, JoinSet};
+use tokio::time::{interval, sleep, timeout};
+
+use serde::Deserialize;
+use tracing::{debug, error, event, info, info_span, warn, Instrument, Level};
+
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_PING_INTERVAL: u64 = 30;
+const DEFAULT_PONG_TIMEOUT: u64 = 10;
+const DEFAULT_MAX_MESSAGE_SIZE: u64 = ocpp::DEFAULT_MAX_FRAME_LEN as u64;
+const DEFAULT_MAX_CONNECTIONS: u64 = 1024;
+pub const DEFAULT_OUTBOUND_QUEUE: usize = 64;
+
+/* How the frames of an action are logged, answers like their call */
+#[derive(Clone, Copy, PartialEq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameLogLevel {
+    Off,
+    Trace,
+    Debug,
+    #[default]
+    Info,
+    Warn,
+}
+
+impl FrameLogLevel {
+    pub fn parse(value: &str) -> Option<FrameLogLevel> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(FrameLogLevel::Off),
+            "trace" => Some(FrameLogLevel::Trace),
+            "debug" => Some(FrameLogLevel::Debug),
+            "info" => Some(FrameLogLevel::Info),
+            "warn" => Some(FrameLogLevel::Warn),
+            _ => None,
+        }
+    }
+}
+
+/* Levels by action, * for any other. Frames are logged at info unless
+ * configured otherwise, e.g. Heartbeat at trace to quiet a busy fleet. */
+#[derive(Clone, Default)]
+pub struct FrameLogLevels {
+    default: FrameLogLevel,
+    actions: HashMap<String, FrameLogLevel>,
+}
+
+impl FrameLogLevels {
+    pub fn set(&mut self, action: &str, level: FrameLogLevel) {
+        match action {
+            "*" => self.default = level,
+            action => {
+                self.actions.insert(action.to_string(), level);
+            }
+        }
+    }
+
+    pub fn level(&self, action: &str) -> FrameLogLevel {
+        self.actions.get(action).copied().unwrap_or(self.default)
+    }
+}
+
+impl From<&HashMap<String, FrameLogLevel>> for FrameLogLevels {
+    fn from(levels: &HashMap<String, FrameLogLevel>) -> Self {
+        let mut frame_log_levels = FrameLogLevels::default();
+        for (action, level) in levels.iter() {
+            frame_log_levels.set(action, *level);
+        }
+        frame_log_levels
+    }
+}
+
+/* Answers carry no action, the one of the call they answer is given */
+fn log_frame(
+    levels: &FrameLogLevels,
+    direction: &str,
+    message: &ocpp::Message,
+    answered: Option<&str>,
+    frame: &str,
+) {
+    let action = message
+        .command
+        .as_ref()
+        .map(|c| c.to_string())
+        .or_else(|| answered.map(String::from))
+        .unwrap_or_default();
+    macro_rules! frame_event {
+        ($level:expr) => {
+            event!(
+                $level,
+                direction,
+                kind = %message.role,
+                action = %action,
+                message_id = %message.id,
+                "{}",
+                frame
+            )
+        };
+    }
+    match levels.level(&action) {
+        FrameLogLevel::Off => {}
+        FrameLogLevel::Trace => frame_event!(Level::TRACE),
+        FrameLogLevel::Debug => frame_event!(Level::DEBUG),
+        FrameLogLevel::Info => frame_event!(Level::INFO),
+        FrameLogLevel::Warn => frame_event!(Level::WARN),
+    }
+}
+
+/* Called with the id of the charge point, on the runtime: it must not
+ * block */
+pub type ConnectionHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct ConnectionHooks {
+    on_connect: Vec<ConnectionHook>,
+    on_disconnect: Vec<ConnectionHook>,
+}
+
+/* Runs the disconnect hooks when the connection ends, whatever the exit
+ * path */
+struct Connected {
+    cp_id: String,
+    hooks: ConnectionHooks,
+}
+
+impl Connected {
+    fn new(cp_id: &str, hooks: &ConnectionHooks) -> Connected {
+        for hook in hooks.on_connect.iter() {
+            hook(cp_id);
+        }
+        Connected {
+            cp_id: cp_id.to_string(),
+            hooks: hooks.clone(),
+        }
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        for hook in self.hooks.on_disconnect.iter() {
+            hook(&self.cp_id);
+        }
+    }
+}
+
+/* Everything that sees the frames of the connections besides the log */
+#[derive(Clone)]
+struct Observers {
+    log_levels: Arc<FrameLogLevels>,
+    hooks: ConnectionHooks,
+    recorder: Option<Arc<Recorder>>,
+    events: EventBus,
+    stats: Stats,
+    sessions: Sessions,
+}
+
+/* Close requests of the open sessions, by charge point. The generation
+ * comes from the statistics and tells a reconnection apart. */
+#[derive(Clone, Default)]
+struct Sessions {
+    sessions: Arc<Mutex<HashMap<String, SessionHandle>>>,
+}
+
+struct SessionHandle {
+    generation: u64,
+    close: Arc<AtomicBool>,
+}
+
+impl Sessions {
+    fn open(&self, cp_id: &str, generation: u64) -> Arc<AtomicBool> {
+        let close = Arc::new(AtomicBool::new(false));
+        let handle = SessionHandle {
+            generation,
+            close: Arc::clone(&close),
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(cp_id.to_string(), handle);
+        close
+    }
+
+    fn remove(&self, cp_id: &str, generation: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.get(cp_id).map(|s| s.generation) == Some(generation) {
+            sessions.remove(cp_id);
+        }
+    }
+
+    /* Returns the generation of the session asked to close */
+    fn close(&self, cp_id: &str) -> Option<u64> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(cp_id)?;
+        session.close.store(true, Ordering::Relaxed);
+        Some(session.generation)
+    }
+
+    fn contains(&self, cp_id: &str, generation: u64) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(cp_id)
+            .map(|s| s.generation)
+            == Some(generation)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sessions.lock().unwrap().is_empty()
+    }
+
+    fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.sessions.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+}
+
+impl Observers {
+    fn frame(
+        &self,
+        cp_id: &str,
+        direction: Direction,
+        message: &ocpp::Message,
+        answered: Option<&str>,
+        frame: &str,
+    ) {
+        log_frame(
+            &self.log_levels,
+            direction.as_str(),
+            message,
+            answered,
+            frame,
+        );
+        if let Some(recorder) = &self.recorder {
+            recorder.record(cp_id, direction, frame);
+        }
+        self.events.publish(cp_id, direction, message);
+        self.stats.record(cp_id, direction, message);
+    }
+}
+
+/* Frames of hostile clients can be megabytes long, only their start is logged */
+const LOGGED_FRAME_LEN: usize = 128;
+
+fn excerpt(text: &str) -> String {
+    match text.char_indices().nth(LOGGED_FRAME_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+fn pack_frame(
+    message: ocpp::Message,
+    cp_id: &str,
+    answered: Option<&str>,
+    observers: &Observers,
+) -> WsMessage {
+    /* Handlers of the user may build CallErrors pack_message refuses */
+    let (message, frame) = match ocpp::pack_message(message.clone()) {
+        Ok(frame) => (message, frame),
+        Err(e) => {
+            error!(
+                "can't send {:?}: {}, answer with an InternalError",
+                message, e
+            );
+            let error = ocpp::Message::call_error(
+                message.id,
+                ocpp::OcppErrorCode::InternalError,
+                "internal error",
+                object! {},
+            );
+            let frame = ocpp::pack_message(error.clone()).expect("call_error has a payload");
+            (error, frame)
+        }
+    };
+    observers.frame(
+        cp_id,
+        Direction::Outbound,
+        &message,
+        answered,
+        frame.as_str(),
+    );
+    if message.role == ocpp::MessageType::CallError {
+        metrics::call_error_sent();
+    }
+    WsMessage::Text(frame)
+}
+
+fn get_cp_id(request: &Request) -> String {
+    request
+        .uri()
+        .path()
+        .rsplit('/')
+        .find(|s| !s.is_empty())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/* Artificial delays before answering calls, to test timeouts on the charge
+ * point side. Configured as "Authorize=30,*=0.5" (seconds, * for any). */
+#[derive(Clone, Default)]
+pub struct ResponseDelays {
+    default: Option<Duration>,
+    actions: HashMap<String, Duration>,
+}
+
+impl ResponseDelays {
+    pub fn parse(value: &str) -> ResponseDelays {
+        let mut delays = ResponseDelays::default();
+        for entry in value.split(',').filter(|e| !e.trim().is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(action, seconds)| {
+                let seconds: f64 = seconds.trim().parse().ok()?;
+                let delay = Duration::try_from_secs_f64(seconds).ok()?;
+                Some((action.trim(), delay))
+            });
+            match parsed {
+                Some(("*", delay)) => delays.default = Some(delay),
+                Some((action, delay)) => {
+                    delays.actions.insert(action.to_string(), delay);
+                }
+                None => warn!("invalid response delay {}", entry),
+            }
+        }
+        delays
+    }
+
+    fn delay(&self, action: &str) -> Option<Duration> {
+        self.actions.get(action).copied().or(self.default)
+    }
+}
+
+/* Calls a charge point may send per second, with bursts of up to burst
+ * calls. Configured as "10" or "10/50" (per second/burst). */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    pub fn parse(value: &str) -> Option<RateLimit> {
+        let (per_second, burst) = match value.split_once('/') {
+            Some((per_second, burst)) => (per_second, Some(burst)),
+            None => (value, None),
+        };
+        let per_second: f64 = per_second.trim().parse().ok()?;
+        let burst: f64 = match burst {
+            Some(burst) => burst.trim().parse().ok()?,
+            None => per_second.max(1.0),
+        };
+        if per_second <= 0.0 || burst < 1.0 {
+            return None;
+        }
+        Some(RateLimit { per_second, burst })
+    }
+}
+
+/* Refills at the rate of the limit up to its burst */
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionSettings {
+    /* How often charge points are pinged */
+    pub ping_interval: Duration,
+    /* How long to wait for a pong before dropping the connection */
+    pub pong_timeout: Duration,
+    /* Larger messages or frames close the connection */
+    pub max_message_size: usize,
+    /* Frames nested deeper are refused before they are parsed */
+    pub max_json_depth: usize,
+    /* Handshakes above the limit are refused with 503 */
+    pub max_connections: usize,
+    pub response_delays: ResponseDelays,
+    /* Handshakes without valid credentials are refused with 401 */
+    pub credentials: Arc<Credentials>,
+    /* Serves wss when set */
+    pub tls: Option<Arc<SslAcceptor>>,
+    /* The only subprotocol accepted, any supported one when unset */
+    pub protocol: Option<ocpp::ProtocolVersion>,
+    /* Calls above the limit are answered with a CallError, off when unset */
+    pub rate_limit: Option<RateLimit>,
+    /* Calls of the central system waiting to be written, more are refused */
+    pub outbound_queue: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> ConnectionSettings {
+        ConnectionSettings {
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL),
+            pong_timeout: Duration::from_secs(DEFAULT_PONG_TIMEOUT),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE as usize,
+            max_json_depth: ocpp::DEFAULT_MAX_JSON_DEPTH,
+            max_connections: DEFAULT_MAX_CONNECTIONS as usize,
+            response_delays: ResponseDelays::default(),
+            credentials: Arc::default(),
+            tls: None,
+            protocol: None,
+            rate_limit: None,
+            outbound_queue: DEFAULT_OUTBOUND_QUEUE,
+        }
+    }
+}
+
+impl ConnectionSettings {
+    /* CS_PING_INTERVAL and CS_PONG_TIMEOUT (seconds), CS_MAX_MESSAGE_SIZE
+     * (bytes), CS_MAX_JSON_DEPTH, CS_MAX_CONNECTIONS, CS_OUTBOUND_QUEUE,
+     * CS_RESPONSE_DELAYS and CS_RATE_LIMIT override the defaults */
+    pub fn from_env() -> ConnectionSettings {
+        let number = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        ConnectionSettings {
+            ping_interval: Duration::from_secs(number("CS_PING_INTERVAL", DEFAULT_PING_INTERVAL)),
+            pong_timeout: Duration::from_secs(number("CS_PONG_TIMEOUT", DEFAULT_PONG_TIMEOUT)),
+            max_message_size: number("CS_MAX_MESSAGE_SIZE", DEFAULT_MAX_MESSAGE_SIZE) as usize,
+            max_json_depth: number("CS_MAX_JSON_DEPTH", ocpp::DEFAULT_MAX_JSON_DEPTH as u64)
+                as usize,
+            max_connections: number("CS_MAX_CONNECTIONS", DEFAULT_MAX_CONNECTIONS) as usize,
+            outbound_queue: number("CS_OUTBOUND_QUEUE", DEFAULT_OUTBOUND_QUEUE as u64) as usize,
+            response_delays: std::env::var("CS_RESPONSE_DELAYS")
+                .map(|v| ResponseDelays::parse(&v))
+                .unwrap_or_default(),
+            rate_limit: std::env::var("CS_RATE_LIMIT").ok().and_then(|v| {
+                let limit = RateLimit::parse(&v);
+                if limit.is_none() {
+                    warn!("invalid rate limit {}", v);
+                }
+                limit
+            }),
+            ..ConnectionSettings::default()
+        }
+    }
+
+    fn parse_limits(&self) -> ocpp::ParseLimits {
+        ocpp::ParseLimits {
+            max_len: self.max_message_size,
+            max_depth: self.max_json_depth,
+        }
+    }
+
+    fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: Some(self.max_message_size),
+            max_frame_size: Some(self.max_message_size),
+            ..WebSocketConfig::default()
+        }
+    }
+}
+
+/* Counts a connection until dropped */
+struct ConnectionSlot {
+    counter: Arc<AtomicUsize>,
+    /* Connections including this one */
+    count: usize,
+}
+
+impl ConnectionSlot {
+    fn acquire(counter: &Arc<AtomicUsize>) -> ConnectionSlot {
+        let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        ConnectionSlot {
+            counter: Arc::clone(counter),
+            count,
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/* Sends a close frame and waits a bit for the peer to acknowledge it */
+async fn close_websocket<S: AsyncRead + AsyncWrite + Unpin>(
+    websocket: &mut WebSocketStream<S>,
+    frame: Option<CloseFrame<'static>>,
+) {
+    let _ = websocket.close(frame).await;
+    let _ = timeout(CLOSE_TIMEOUT, async {
+        while let Some(Ok(_)) = websocket.next().await {}
+    })
+    .await;
+}
+
+/* Writes the messages in order. A call following an answer isn't sent when
+ * the answer couldn't be written. answered is the action of the call the
+ * messages answer, if any. */
+async fn send_all<S: AsyncRead + AsyncWrite + Unpin>(
+    websocket: &mut WebSocketStream<S>,
+    messages: Vec<ocpp::Message>,
+    cp_id: &str,
+    answered: Option<&str>,
+    observers: &Observers,
+) -> Result<(), WsError> {
+    for message in messages {
+        websocket
+            .send(pack_frame(message, cp_id, answered, observers))
+            .await?;
+    }
+    Ok(())
+}
+
+/* The calls queued before the answers are written first */
+async fn send_answers<S: AsyncRead + AsyncWrite + Unpin>(
+    websocket: &mut WebSocketStream<S>,
+    queued: Vec<ocpp::Message>,
+    answers: Vec<ocpp::Message>,
+    cp_id: &str,
+    answered: Option<&str>,
+    observers: &Observers,
+) -> Result<(), WsError> {
+    send_all(websocket, queued, cp_id, None, observers).await?;
+    send_all(websocket, answers, cp_id, answered, observers).await
+}
+
+/* A connection that failed a write is given up, the peer may not even get
+ * the close frame */
+async fn drop_broken<S: AsyncRead + AsyncWrite + Unpin>(
+    websocket: &mut WebSocketStream<S>,
+    error: WsError,
+) {
+    warn!("can't write to the connection: {}, close it", error);
+    let _ = timeout(CLOSE_TIMEOUT, websocket.close(None)).await;
+}
+
+/* Calls into the central system run on the blocking pool: signing a
+ * certificate may take a while and the lock is shared with other threads */
+async fn with_cs<C, R, F>(cs: &Arc<Mutex<C>>, f: F) -> R
+where
+    C: Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(&mut C) -> R + Send + 'static,
+{
+    let cs = Arc::clone(cs);
+    spawn_blocking(move || f(&mut cs.lock().unwrap()))
+        .await
+        .unwrap()
+}
+
+/* with_cs for the handling of a message: a panic is returned as its message
+ * instead of ending the session. It is caught while the lock is held, so
+ * the lock isn't poisoned and the other connections go on. */
+async fn catch_with_cs<C, R, F>(cs: &Arc<Mutex<C>>, f: F) -> Result<R, String>
+where
+    C: Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(&mut C) -> R + Send + 'static,
+{
+    let cs = Arc::clone(cs);
+    let result = spawn_blocking(move || {
+        let mut cs = cs.lock().unwrap();
+        catch_unwind(AssertUnwindSafe(|| f(&mut cs)))
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    result.map_err(panic_message)
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/* Handles a message as catch_with_cs. The work the central system prepares
+ * for it, like signing a certificate, runs in between without the lock, so
+ * the other connections aren't held up meanwhile. */
+async fn handle_message<C>(
+    cs: &Arc<Mutex<C>>,
+    cp_id: &str,
+    request: ocpp::Message,
+) -> Result<Result<Vec<ocpp::Message>, CsError>, String>
+where
+    C: ocpp::CentralSystem + Send + 'static,
+{
+    let id = cp_id.to_string();
+    let prepared = catch_with_cs(cs, move |cs| match cs.prepare(&id, &request) {
+        Some(work) => Err((work, request)),
+        None => Ok(cs.make_response(&id, request)),
+    })
+    .await?;
+    let (work, request) = match prepared {
+        Ok(responses) => return Ok(responses),
+        Err(prepared) => prepared,
+    };
+
+    let apply = spawn_blocking(move || catch_unwind(AssertUnwindSafe(work)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(panic_message)?;
+    let id = cp_id.to_string();
+    catch_with_cs(cs, move |cs| {
+        apply(cs);
+        cs.make_response(&id, request)
+    })
+    .await
+}
+
+/* The WebSocket server of an embedded central system. Every listener has
+ * its own settings and all of them feed the same central system:
+ *
+ * let handle = Server::new(cs)
+ *     .listen("127.0.0.1:0", ConnectionSettings::default())
+ *     .start()
+ *     .await?;
+ * connect to handle.address() ...
+ * handle.shutdown().await;
+ */
+pub struct Server<C> {
+    cs: Arc<Mutex<C>>,
+    recorder: Option<Arc<Recorder>>,
+    events: EventBus,
+    stats: Stats,
+    listeners: Vec<(String, ConnectionSettings)>,
+    idle_timeout: Option<Duration>,
+    log_levels: FrameLogLevels,
+    hooks: ConnectionHooks,
+}
+
+impl<C: ocpp::CentralSystem + Send + 'static> Server<C> {
+    pub fn new(cs: Arc<Mutex<C>>) -> Server<C> {
+        Server {
+            cs,
+            recorder: None,
+            events: EventBus::new(),
+            stats: Stats::default(),
+            listeners: Vec::new(),
+            idle_timeout: None,
+            log_levels: FrameLogLevels::default(),
+            hooks: ConnectionHooks::default(),
+        }
+    }
+
+    /* Called once per connection when its handshake completed, e.g. to
+     * start provisioning a station */
+    pub fn on_connect<F: Fn(&str) + Send + Sync + 'static>(mut self, hook: F) -> Server<C> {
+        self.hooks.on_connect.push(Arc::new(hook));
+        self
+    }
+
+    /* Called once per connection after it ended, however it ended */
+    pub fn on_disconnect<F: Fn(&str) + Send + Sync + 'static>(mut self, hook: F) -> Server<C> {
+        self.hooks.on_disconnect.push(Arc::new(hook));
+        self
+    }
+
+    /* Levels the frames are logged at by action */
+    pub fn log_levels(mut self, log_levels: FrameLogLevels) -> Server<C> {
+        self.log_levels = log_levels;
+        self
+    }
+
+    /* Shuts the server down once no charge point has been connected for
+     * that long, e.g. for test suites that forget to. Off when unset. */
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Server<C> {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn with_recorder(mut self, recorder: Option<Arc<Recorder>>) -> Server<C> {
+        self.recorder = recorder;
+        self
+    }
+
+    /* Statistics of the connected charge points, e.g. for the admin API
+     * started before the server */
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /* Port 0 picks a free port */
+    pub fn listen(mut self, address: &str, settings: ConnectionSettings) -> Server<C> {
+        self.listeners.push((address.to_string(), settings));
+        self
+    }
+
+    /* Binds every listener and serves charge points in the background until
+     * the handle shuts the server down. Nothing is served if any address
+     * can't be bound. */
+    pub async fn start(self) -> Result<ServerHandle, CsError> {
+        if self.listeners.is_empty() {
+            return Err(CsError::Config("no listener is configured".to_string()));
+        }
+
+        let mut bound = Vec::new();
+        for (address, settings) in self.listeners {
+            let listener = TcpListener::bind(address.as_str())
+                .await
+                .map_err(|e| CsError::Config(format!("can't listen on {}: {}", address, e)))?;
+            bound.push((listener, settings));
+        }
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let observers = Observers {
+            log_levels: Arc::new(self.log_levels),
+            hooks: self.hooks,
+            recorder: self.recorder,
+            events: self.events.clone(),
+            stats: self.stats.clone(),
+            sessions: Sessions::default(),
+        };
+        let mut addresses = Vec::new();
+        let mut tasks = Vec::new();
+        for (listener, settings) in bound {
+            let address = listener.local_addr()?;
+            info!(
+                "listening on {}{}{}",
+                address,
+                if settings.tls.is_some() { " (wss)" } else { "" },
+                settings
+                    .protocol
+                    .map(|p| format!(" for {}", p.subprotocol()))
+                    .unwrap_or_default()
+            );
+            addresses.push(address);
+            tasks.push(tokio::spawn(ws_cycle(
+                listener,
+                Arc::clone(&self.cs),
+                observers.clone(),
+                Arc::clone(&shutdown),
+                settings,
+            )));
+        }
+
+        let cs = self.cs;
+        let sweep_shutdown = Arc::clone(&shutdown);
+        tasks.push(tokio::spawn(async move {
+            let mut tick = interval(Duration::from_secs(1));
+            while !sweep_shutdown.load(Ordering::Relaxed) {
+                tick.tick().await;
+                with_cs(&cs, |cs| cs.sweep()).await;
+            }
+        }));
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            let sessions = observers.sessions.clone();
+            let idle_shutdown = Arc::clone(&shutdown);
+            tasks.push(tokio::spawn(async move {
+                let mut idle_since = Instant::now();
+                let mut tick = interval(POLL_INTERVAL);
+                while !idle_shutdown.load(Ordering::Relaxed) {
+                    tick.tick().await;
+                    if !sessions.is_empty() {
+                        idle_since = Instant::now();
+                    } else if idle_since.elapsed() >= idle_timeout {
+                        info!(
+                            "no charge point connected for {:?}, shut down",
+                            idle_timeout
+                        );
+                        idle_shutdown.store(true, Ordering::Relaxed);
+                    }
+                }
+            }));
+        }
+
+        Ok(ServerHandle {
+            addresses,
+            shutdown,
+            events: self.events,
+            stats: self.stats,
+            sessions: observers.sessions,
+            tasks,
+        })
+    }
+}
+
+pub struct ServerHandle {
+    addresses: Vec<SocketAddr>,
+    shutdown: Arc<AtomicBool>,
+    events: EventBus,
+    stats: Stats,
+    sessions: Sessions,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /* The bound address of the first listener, with the actual port when
+     * started on port 0 */
+    pub fn address(&self) -> SocketAddr {
+        self.addresses[0]
+    }
+
+    /* Bound addresses in the order the listeners were added */
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /* Ids of the connected charge points, sorted */
+    pub fn list_connections(&self) -> Vec<String> {
+        self.sessions.ids()
+    }
+
+    /* Closes the connection of a charge point with a close frame and waits
+     * until its session has ended */
+    pub async fn disconnect(&self, cp_id: &str) -> Result<(), CsError> {
+        let generation = self
+            .sessions
+            .close(cp_id)
+            .ok_or_else(|| CsError::UnknownChargePoint(cp_id.to_string()))?;
+        while self.sessions.contains(cp_id, generation) {
+            sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /* Every message received or sent from now on */
+    pub fn subscribe(&self) -> broadcast::Receiver<OcppEvent> {
+        self.events.subscribe()
+    }
+
+    /* Setting the flag stops the server, e.g. from a signal handler */
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /* Waits until the server is stopped and its sessions are closed */
+    pub async fn wait(self) {
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+
+    pub async fn shutdown(self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.wait().await;
+    }
+}
+
+/* Serves the charge points of a listener until shutdown is set. Active
+ * sessions are closed before returning. */
+async fn ws_cycle<C: ocpp::CentralSystem + Send + 'static>(
+    server: TcpListener,
+    shared_cs: Arc<Mutex<C>>,
+    observers: Observers,
+    shutdown: Arc<AtomicBool>,
+    settings: ConnectionSettings,
+) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let mut sessions = JoinSet::new();
+    /* Wake up regularly so that shutdown is noticed without a new connection */
+    let mut tick = interval(POLL_INTERVAL);
+    while !shutdown.load(Ordering::Relaxed) {
+        let stream = tokio::select! {
+            accepted = server.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("can't accept a connection: {}", e);
+                    continue;
+                }
+            },
+            _ = tick.tick() => continue,
+        };
+
+        while sessions.try_join_next().is_some() {}
+        let slot = ConnectionSlot::acquire(&connections);
+        let cs = Arc::clone(&shared_cs);
+        let observers = observers.clone();
+        let shutdown = Arc::clone(&shutdown);
+        let settings = settings.clone();
+        sessions.spawn(async move {
+            match settings.tls.clone() {
+                Some(acceptor) => match tls::accept(&acceptor, stream).await {
+                    Ok((stream, identity)) => {
+                        session(stream, identity, slot, cs, observers, shutdown, settings).await
+                    }
+                    Err(e) => warn!("TLS handshake failed: {}", e),
+                },
+                None => session(stream, None, slot, cs, observers, shutdown, settings).await,
+            }
+        });
+    }
+
+    info!("shutting down, waiting for {} connections", sessions.len());
+    while sessions.join_next().await.is_some() {}
+}
+
+async fn session<C, S>(
+    stream: S,
+    /* Common name of the verified client certificate */
+    identity: Option<String>,
+    /* Released on every exit path of the session */
+    slot: ConnectionSlot,
+    cs: Arc<Mutex<C>>,
+    observers: Observers,
+    shutdown: Arc<AtomicBool>,
+    settings: ConnectionSettings,
+) where
+    C: ocpp::CentralSystem + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let over_limit = slot.count > settings.max_connections;
+    let mut cp_id = String::new();
+    let mut version = ocpp::ProtocolVersion::default();
+
+    #[allow(clippy::result_large_err)]
+    let callback = |req: &Request, mut resp: Response| {
+        cp_id = get_cp_id(req);
+        if over_limit {
+            warn!(
+                cp_id = %cp_id,
+                "connection limit of {} reached, reject handshake",
+                settings.max_connections
+            );
+            let mut rejection = ErrorResponse::new(Some("too many connections".into()));
+            *rejection.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            return Err(rejection);
+        }
+        let offered = req
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .map(|v| v.to_str().unwrap_or_default());
+        match (settings.protocol, offered) {
+            (None, Some(offered)) => {
+                version = ocpp::ProtocolVersion::negotiate(offered).unwrap_or_default();
+            }
+            (None, None) => {}
+            (Some(accepted), None) => version = accepted,
+            (Some(accepted), Some(offered))
+                if offered
+                    .split(',')
+                    .any(|p| ocpp::ProtocolVersion::from_subprotocol(p) == Some(accepted)) =>
+            {
+                version = accepted
+            }
+            (Some(accepted), Some(offered)) => {
+                warn!(
+                    cp_id = %cp_id,
+                    "{} is not offered in {}, reject handshake",
+                    accepted.subprotocol(),
+                    offered
+                );
+                let mut rejection = ErrorResponse::new(Some("unsupported subprotocol".into()));
+                *rejection.status_mut() = StatusCode::BAD_REQUEST;
+                return Err(rejection);
+            }
+        }
+        info!(
+            cp_id = %cp_id,
+            protocol = version.subprotocol(),
+            "received a new WS handshake"
+        );
+        /* tungstenite implements no extensions, offers like permessage-deflate
+         * are declined by leaving Sec-WebSocket-Extensions out of the answer */
+        if let Some(extensions) = req.headers().get("Sec-WebSocket-Extensions") {
+            debug!(
+                cp_id = %cp_id,
+                "decline extensions {}",
+                extensions.to_str().unwrap_or_default()
+            );
+        }
+        if identity.as_ref().is_some_and(|identity| *identity != cp_id) {
+            warn!(
+                cp_id = %cp_id,
+                "client certificate is issued to {}, reject handshake",
+                identity.as_deref().unwrap_or_default()
+            );
+            let mut rejection = ErrorResponse::new(Some("unauthorized".into()));
+            *rejection.status_mut() = StatusCode::UNAUTHORIZED;
+            return Err(rejection);
+        }
+        let authorization = req
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok());
+        if !settings.credentials.check(&cp_id, authorization) {
+            warn!(cp_id = %cp_id, "invalid credentials, reject handshake");
+            let mut rejection = ErrorResponse::new(Some("unauthorized".into()));
+            *rejection.status_mut() = StatusCode::UNAUTHORIZED;
+            rejection
+                .headers_mut()
+                .append("WWW-Authenticate", "Basic realm=\"OCPP\"".parse().unwrap());
+            return Err(rejection);
+        }
+        let headers = resp.headers_mut();
+        headers.append(
+            "Sec-WebSocket-Protocol",
+            version.subprotocol().parse().unwrap(),
+        );
+        Ok(resp)
+    };
+
+    let websocket =
+        match accept_hdr_async_with_config(stream, callback, Some(settings.websocket_config()))
+            .await
+        {
+            Ok(websocket) => websocket,
+            Err(e) => {
+                warn!("WS handshake failed: {}", e);
+                return;
+            }
+        };
+
+    let span = info_span!("connection", cp_id = %cp_id);
+    serve_connection(websocket, cp_id, version, cs, observers, shutdown, settings)
+        .instrument(span)
+        .await;
+    drop(slot);
+}
+
+async fn serve_connection<C, S>(
+    mut websocket: WebSocketStream<S>,
+    cp_id: String,
+    version: ocpp::ProtocolVersion,
+    cs: Arc<Mutex<C>>,
+    observers: Observers,
+    shutdown: Arc<AtomicBool>,
+    settings: ConnectionSettings,
+) where
+    C: ocpp::CentralSystem + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /* Only this task writes to the websocket. Calls of the central system
+     * go through the bounded queue, answers are written by the task itself
+     * after the calls queued before them: the station sees the frames in
+     * the order they were made. Answers need no queue, the next frame is
+     * only read once the answer to the last one is written. */
+    let (outbound_tx, outbound_rx) = sync_channel(settings.outbound_queue.max(1));
+    let id = cp_id.clone();
+    let generation = observers.stats.connect(&cp_id);
+    with_cs(&cs, move |cs| {
+        cs.connect(&id, version, outbound_tx, generation)
+    })
+    .await;
+    let close = observers.sessions.open(&cp_id, generation);
+    metrics::connection_opened();
+    if let Some(recorder) = &observers.recorder {
+        recorder.connect(&cp_id, version);
+    }
+    let _connected = Connected::new(&cp_id, &observers.hooks);
+
+    let mut last_ping = Instant::now();
+    let mut awaiting_pong: Option<Instant> = None;
+    let mut bucket = settings.rate_limit.map(TokenBucket::new);
+    let parse_limits = settings.parse_limits();
+    /* Calls refused since the limit was last exceeded */
+    let mut throttled = 0u64;
+    /* Wake up regularly to write calls initiated by the CS */
+    let mut tick = interval(POLL_INTERVAL);
+
+    loop {
+        let input = tokio::select! {
+            input = websocket.next() => input,
+            _ = tick.tick() => {
+                if shutdown.load(Ordering::Relaxed) {
+                    info!("close connection on shutdown");
+                    close_websocket(&mut websocket, None).await;
+                    break;
+                }
+                if close.load(Ordering::Relaxed) {
+                    info!("close connection on request");
+                    let frame = CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "disconnected by the central system".into(),
+                    };
+                    close_websocket(&mut websocket, Some(frame)).await;
+                    break;
+                }
+
+                /* A connection that died without FIN is only noticed by
+                 * a missing pong */
+                if let Some(sent) = awaiting_pong {
+                    if sent.elapsed() > settings.pong_timeout {
+                        warn!(
+                            "no pong within {:?}, close connection",
+                            settings.pong_timeout
+                        );
+                        break;
+                    }
+                } else if last_ping.elapsed() >= settings.ping_interval {
+                    if let Err(e) = websocket.send(WsMessage::Ping(Vec::new())).await {
+                        drop_broken(&mut websocket, e).await;
+                        break;
+                    }
+                    last_ping = Instant::now();
+                    awaiting_pong = Some(last_ping);
+                }
+
+                let calls: Vec<ocpp::Message> = outbound_rx.try_iter().collect();
+                if let Err(e) = send_all(&mut websocket, calls, &cp_id, None, &observers).await {
+                    drop_broken(&mut websocket, e).await;
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let msg_in = match input {
+            Some(Ok(msg_in)) => msg_in,
+            Some(Err(WsError::Capacity(e))) => {
+                warn!("{}, close connection", e);
+                let _ = websocket
+                    .close(Some(CloseFrame {
+                        code: CloseCode::Size,
+                        reason: "message too big".into(),
+                    }))
+                    .await;
+                break;
+            }
+            _ => {
+                info!("close connection");
+                let _ = websocket.close(None).await;
+                break;
+            }
+        };
+
+        let text = match msg_in {
+            WsMessage::Text(text) => text,
+            WsMessage::Close(frame) => {
+                info!(
+                    "closed by the charge point{}",
+                    frame
+                        .map(|f| format!(": {} {}", u16::from(f.code), f.reason))
+                        .unwrap_or_default()
+                );
+                /* tungstenite has queued the answer to the close frame */
+                let _ = timeout(CLOSE_TIMEOUT, websocket.flush()).await;
+                break;
+            }
+            WsMessage::Binary(data) => {
+                warn!(
+                    "binary frame of {} bytes, OCPP-J is text, close connection",
+                    data.len()
+                );
+                let frame = CloseFrame {
+                    code: CloseCode::Unsupported,
+                    reason: "binary frames are not supported".into(),
+                };
+                close_websocket(&mut websocket, Some(frame)).await;
+                break;
+            }
+            WsMessage::Pong(_) => {
+                awaiting_pong = None;
+                continue;
+            }
+            /* Pings are answered by tungstenite itself */
+            WsMessage::Ping(_) | WsMessage::Frame(_) => continue,
+        };
+
+        let ocpp_req = match ocpp::unpack_message_with_limits(&text, &parse_limits) {
+            Ok(ocpp_req) => ocpp_req.with_protocol(version),
+            Err(e) => {
+                warn!(
+                    "invalid message of {} bytes: {}: {}",
+                    text.len(),
+                    e,
+                    excerpt(&text)
+                );
+                let id = match ocpp::recover_call_id(&text) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let error = ocpp::Message::call_error(
+                    id,
+                    ocpp::OcppErrorCode::FormationViolation.for_version(version),
+                    &e.to_string(),
+                    object! {},
+                );
+                let queued: Vec<ocpp::Message> = outbound_rx.try_iter().collect();
+                if let Err(e) = send_answers(
+                    &mut websocket,
+                    queued,
+                    vec![error],
+                    &cp_id,
+                    None,
+                    &observers,
+                )
+                .await
+                {
+                    drop_broken(&mut websocket, e).await;
+                    break;
+                }
+                continue;
+            }
+        };
+        observers.frame(&cp_id, Direction::Inbound, &ocpp_req, None, &text);
+
+        /* Answers to the calls of the central system are never throttled */
+        if ocpp_req.role == ocpp::MessageType::Call {
+            if let Some(bucket) = bucket.as_mut() {
+                if !bucket.take(Instant::now()) {
+                    if throttled == 0 {
+                        warn!(
+                            "more than {} calls per second, answer with CallErrors",
+                            bucket.limit.per_second
+                        );
+                    }
+                    throttled += 1;
+                    let error = ocpp::Message::call_error(
+                        ocpp_req.id,
+                        ocpp::OcppErrorCode::GenericError,
+                        "rate limit exceeded",
+                        object! {},
+                    );
+                    let queued: Vec<ocpp::Message> = outbound_rx.try_iter().collect();
+                    if let Err(e) = send_answers(
+                        &mut websocket,
+                        queued,
+                        vec![error],
+                        &cp_id,
+                        None,
+                        &observers,
+                    )
+                    .await
+                    {
+                        drop_broken(&mut websocket, e).await;
+                        break;
+                    }
+                    continue;
+                }
+                if throttled > 0 {
+                    info!("{} calls were refused by the rate limit", throttled);
+                    throttled = 0;
+                }
+            }
+        }
+
+        let action = ocpp_req.command.as_ref().map(|c| c.to_string());
+        let request_id = ocpp_req.id.clone();
+        let is_call = ocpp_req.role == ocpp::MessageType::Call;
+        let responses = match handle_message(&cs, &cp_id, ocpp_req).await {
+            Ok(responses) => responses,
+            Err(panic) => {
+                error!("handling {} panicked: {}", text, panic);
+                let mut answer = Vec::new();
+                if is_call {
+                    answer.push(ocpp::Message::call_error(
+                        request_id,
+                        ocpp::OcppErrorCode::InternalError.for_version(version),
+                        "internal error",
+                        object! {},
+                    ));
+                }
+                let queued: Vec<ocpp::Message> = outbound_rx.try_iter().collect();
+                if let Err(e) = send_answers(
+                    &mut websocket,
+                    queued,
+                    answer,
+                    &cp_id,
+                    action.as_deref(),
+                    &observers,
+                )
+                .await
+                {
+                    drop_broken(&mut websocket, e).await;
+                    break;
+                }
+                continue;
+            }
+        };
+
+        /* A call the central system failed to handle is still answered */
+        let responses = match responses {
+            Err(e) if is_call => {
+                warn!("can't answer {} of {}: {}", request_id, cp_id, e);
+                let code = ocpp::OcppErrorCode::from(&e).for_version(version);
+                Ok(vec![ocpp::Message::call_error(
+                    request_id,
+                    code,
+                    &e.to_string(),
+                    object! {},
+                )])
+            }
+            Err(e) => {
+                warn!("can't handle {} of {}: {}", request_id, cp_id, e);
+                Ok(Vec::new())
+            }
+            responses => responses,
+        };
+
+        /* Only this connection waits */
+        let delay = action
+            .as_deref()
+            .and_then(|a| settings.response_delays.delay(a));
+        if let Some(delay) = delay {
+            info!("delay the answer by {:?}", delay);
+            sleep(delay).await;
+        }
+
+        if let Ok(ocpp_resp) = responses {
+            let queued: Vec<ocpp::Message> = outbound_rx.try_iter().collect();
+            if let Err(e) = send_answers(
+                &mut websocket,
+                queued,
+                ocpp_resp,
+                &cp_id,
+                action.as_deref(),
+                &observers,
+            )
+            .await
+            {
+                drop_broken(&mut websocket, e).await;
+                break;
+            }
+        }
+    }
+
+    let id = cp_id.clone();
+    with_cs(&cs, move |cs| cs.disconnect(&id, generation)).await;
+    observers.stats.disconnect(&cp_id, generation);
+    observers.sessions.remove(&cp_id, generation);
+    metrics::connection_closed();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_delays_are_parsed() {
+        let delays = ResponseDelays::parse("Authorize=30, *=0.5,BootNotification=x,Heartbeat");
+        assert_eq!(delays.delay("Authorize"), Some(Duration::from_secs(30)));
+        assert_eq!(
+            delays.delay("StartTransaction"),
+            Some(Duration::from_millis(500))
+        );
+        /* Invalid entries are skipped */
+        assert_eq!(
+            delays.delay("BootNotification"),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(ResponseDelays::parse("").delay("Authorize"), None);
+        assert_eq!(
+            ResponseDelays::parse("Authorize=-1").delay("Authorize"),
+            None
+        );
+    }
+
+    #[test]
+    fn rate_limits_are_parsed() {
+        assert_eq!(
+            RateLimit::parse("10"),
+            Some(RateLimit {
+                per_second: 10.0,
+                burst: 10.0
+            })
+        );
+        assert_eq!(
+            RateLimit::parse("0.5 / 3"),
+            Some(RateLimit {
+                per_second: 0.5,
+                burst: 3.0
+            })
+        );
+        /* Bursts hold at least one call */
+        assert_eq!(RateLimit::parse("0.5").unwrap().burst, 1.0);
+        for invalid in ["", "x", "0", "-1", "10/0.5", "10/x"] {
+            assert_eq!(RateLimit::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn bucket_refills_at_the_rate_up_to_the_burst() {
+        let mut bucket = TokenBucket::new(RateLimit::parse("2/3").unwrap());
+        let start = bucket.updated;
+        assert!((0..3).all(|_| bucket.take(start)));
+        assert!(!bucket.take(start));
+
+        /* Half a second is one call at 2 per second */
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| bucket.take(much_later)).count(), 3);
+    }
+
+    /* Takes the writes until the first flush, fails the ones after it */
+    struct BreaksAfterFirstFrame {
+        written: Arc<Mutex<Vec<u8>>>,
+        flushed: bool,
+    }
+
+    impl AsyncRead for BreaksAfterFirstFrame {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            _: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for BreaksAfterFirstFrame {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            if self.flushed {
+                let broken = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+                return std::task::Poll::Ready(Err(broken));
+            }
+            self.written.lock().unwrap().extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.flushed = true;
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_after_a_failed_write_are_not_sent() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let stream = BreaksAfterFirstFrame {
+            written: Arc::clone(&written),
+            flushed: false,
+        };
+        let mut websocket = WebSocketStream::from_raw_socket(
+            stream,
+            tokio_tungstenite::tungstenite::protocol::Role::Server,
+            None,
+        )
+        .await;
+        let observers = Observers {
+            log_levels: Arc::default(),
+            hooks: ConnectionHooks::default(),
+            recorder: None,
+            events: EventBus::new(),
+            stats: Stats::default(),
+            sessions: Sessions::default(),
+        };
+        let mut events = observers.events.subscribe();
+        let call = |id: &str| {
+            ocpp::Message::new(
+                ocpp::MessageType::Call,
+                id.to_string(),
+                Some(ocpp::Command::Heartbeat),
+                Some(object! {}),
+            )
+        };
+
+        let result = send_all(
+            &mut websocket,
+            vec![call("first"), call("second"), call("third")],
+            "cp1",
+            None,
+            &observers,
+        )
+        .await;
+        assert!(matches!(result, Err(WsError::Io(_))));
+        let written = String::from_utf8_lossy(&written.lock().unwrap()).to_string();
+        assert!(written.contains("first"));
+        assert!(!written.contains("second"));
+
+        /* The third message isn't even attempted */
+        let mut attempted = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            attempted.push(event.message_id);
+        }
+        assert_eq!(attempted, ["first", "second"]);
+
+        drop_broken(&mut websocket, WsError::ConnectionClosed).await;
+        assert!(websocket.send(WsMessage::Text("{}".into())).await.is_err());
+    }
+    /* The frames logged at info, as the binary does by default */
+    fn logged(levels: &FrameLogLevels, messages: &[ocpp::Message]) -> String {
+        let output = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let writer = Arc::clone(&output);
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::INFO)
+            .with_ansi(false)
+            .with_writer(move || Capture(Arc::clone(&writer)))
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            for message in messages.iter() {
+                let frame = ocpp::pack_message(message.clone()).unwrap();
+                log_frame(levels, "in", message, None, &frame);
+            }
+        });
+        let output = output.lock().unwrap();
+        String::from_utf8(output.clone()).unwrap()
+    }
+
+    struct Capture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn silenced_actions_are_not_logged_at_the_default_level() {
+        let call = |id: &str, command: ocpp::Command| {
+            ocpp::Message::new(
+                ocpp::MessageType::Call,
+                id.to_string(),
+                Some(command),
+                Some(object! {}),
+            )
+        };
+        let messages = [
+            call("1", ocpp::Command::Heartbeat),
+            call("2", ocpp::Command::BootNotification),
+        ];
+
+        let output = logged(&FrameLogLevels::default(), &messages);
+        assert!(output.contains("Heartbeat"));
+        assert!(output.contains("BootNotification"));
+
+        let mut levels = FrameLogLevels::default();
+        levels.set("Heartbeat", FrameLogLevel::Trace);
+        let output = logged(&levels, &messages);
+        assert!(!output.contains("Heartbeat"));
+        assert!(output.contains("BootNotification"));
+
+        levels.set("*", FrameLogLevel::Off);
+        levels.set("BootNotification", FrameLogLevel::Info);
+        let output = logged(&levels, &messages);
+        assert!(!output.contains("Heartbeat"));
+        assert!(output.contains("BootNotification"));
+    }
+
+    #[test]
+    fn frame_log_levels_default_to_info() {
+        let mut levels = FrameLogLevels::default();
+        assert_eq!(levels.level("SignCertificate"), FrameLogLevel::Info);
+        levels.set("*", FrameLogLevel::Debug);
+        levels.set("SignCertificate", FrameLogLevel::Warn);
+        assert_eq!(levels.level("Heartbeat"), FrameLogLevel::Debug);
+        assert_eq!(levels.level("SignCertificate"), FrameLogLevel::Warn);
+        assert_eq!(FrameLogLevel::parse(" TRACE"), Some(FrameLogLevel::Trace));
+        assert_eq!(FrameLogLevel::parse("loud"), None);
+    }
+}
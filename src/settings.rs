@@ -0,0 +1,294 @@
+use crate::authorization::{AuthorizationList, TransactionQuotas};
+use crate::cs::DEFAULT_HEARTBEAT_INTERVAL;
+use crate::error::CsError;
+use crate::ocpp::{ProtocolVersion, RegistrationStatus, Status};
+use crate::server::FrameLogLevel;
+use crate::x509::DefaultCertificationAuthoritySettings;
+use chrono::Duration;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+const DEFAULT_BIND: &str = "0.0.0.0:8080";
+const DEFAULT_DATABASE: &str = "/tmp/dummy-central-system/transactions.db";
+
+/* Everything a TOML settings file can set, missing keys keep the defaults.
+ * See settings.example.toml. */
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub heartbeat_interval: u32,
+    /* Intervals of some charge points instead of heartbeat_interval */
+    pub heartbeat_intervals: HeartbeatIntervals,
+    /* Seconds between Heartbeats triggered by the central system, 0 disables */
+    pub heartbeat_trigger_interval: u32,
+    /* Seconds before a transaction is stopped remotely, 0 for no limit */
+    pub max_transaction_duration: u32,
+    pub registration_status: RegistrationStatus,
+    pub ca: DefaultCertificationAuthoritySettings,
+    pub authorization: AuthorizationSettings,
+    /* Levels the frames of an action are logged at, * for any other */
+    pub log_levels: HashMap<String, FrameLogLevel>,
+    /* JSON-lines file of calls scripted after the answers, see scenario.rs */
+    pub scenario: Option<String>,
+    /* SQLite file of the transactions, used with the sqlite feature */
+    pub database: String,
+}
+
+impl Settings {
+    pub fn load(path: &str) -> Result<Settings, CsError> {
+        let raw = read_to_string(path)
+            .map_err(|e| CsError::Config(format!("can't read {}: {}", path, e)))?;
+        Settings::parse(&raw)
+    }
+
+    pub fn parse(raw: &str) -> Result<Settings, CsError> {
+        toml::from_str(raw).map_err(|e| CsError::Config(e.to_string()))
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            server: ServerSettings::default(),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            heartbeat_intervals: HeartbeatIntervals::default(),
+            heartbeat_trigger_interval: 0,
+            max_transaction_duration: 0,
+            registration_status: RegistrationStatus::Accepted,
+            ca: DefaultCertificationAuthoritySettings::default(),
+            authorization: AuthorizationSettings::default(),
+            log_levels: HashMap::new(),
+            scenario: None,
+            database: DEFAULT_DATABASE.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub bind: String,
+    /* Basic authentication passwords by charge point id, none disables it */
+    pub credentials: HashMap<String, String>,
+    /* Serves wss when set */
+    pub tls: Option<TlsSettings>,
+    /* Replace bind and tls when given */
+    pub listeners: Vec<ListenerSettings>,
+    /* Seconds without a connected charge point after which the server
+     * stops, it runs until stopped when unset */
+    pub idle_timeout: Option<u64>,
+}
+
+impl ServerSettings {
+    pub fn listeners(&self) -> Vec<ListenerSettings> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![ListenerSettings {
+            bind: self.bind.clone(),
+            tls: self.tls.clone(),
+            protocol: None,
+        }]
+    }
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind: DEFAULT_BIND.to_string(),
+            credentials: HashMap::new(),
+            tls: None,
+            listeners: Vec::new(),
+            idle_timeout: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ListenerSettings {
+    pub bind: String,
+    #[serde(default)]
+    pub tls: Option<TlsSettings>,
+    /* The only subprotocol accepted, any supported one when unset */
+    #[serde(default)]
+    pub protocol: Option<ProtocolVersion>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TlsSettings {
+    /* PEM files of the server certificate chain and its key */
+    pub certificate: String,
+    pub key: String,
+    /* Require certificates issued by the CA from charge points */
+    #[serde(default)]
+    pub client_certificates: bool,
+}
+
+/* Heartbeat intervals by charge point id, reported model or vendor, the
+ * first match in this order wins */
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatIntervals {
+    pub charge_points: HashMap<String, u32>,
+    pub models: HashMap<String, u32>,
+    pub vendors: HashMap<String, u32>,
+}
+
+impl HeartbeatIntervals {
+    pub fn resolve(&self, cp_id: &str, vendor: &str, model: &str) -> Option<u32> {
+        self.charge_points
+            .get(cp_id)
+            .or_else(|| self.models.get(model))
+            .or_else(|| self.vendors.get(vendor))
+            .copied()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthorizationSettings {
+    /* Status of the idTags that are not listed */
+    pub default: Status,
+    pub id_tags: HashMap<String, Status>,
+    /* Parent idTag of grouped cards, by idTag */
+    pub parents: HashMap<String, String>,
+    /* How long authorizations are valid, the list keeps its default when unset */
+    pub ttl_days: Option<i64>,
+    /* Transactions an idTag may start */
+    pub quotas: HashMap<String, u32>,
+    /* Usage of the quotas starts over that often, never when unset */
+    pub quota_window_hours: Option<i64>,
+}
+
+impl AuthorizationSettings {
+    pub fn to_list(&self) -> AuthorizationList {
+        let mut list = AuthorizationList::new(self.default);
+        for (id_tag, status) in self.id_tags.iter() {
+            list.set(id_tag, *status);
+        }
+        for (id_tag, parent_id_tag) in self.parents.iter() {
+            list.set_parent(id_tag, parent_id_tag);
+        }
+        if let Some(days) = self.ttl_days {
+            list.set_default_ttl(Duration::days(days));
+        }
+        list
+    }
+
+    pub fn to_quotas(&self) -> TransactionQuotas {
+        let mut quotas = TransactionQuotas::default();
+        for (id_tag, limit) in self.quotas.iter() {
+            quotas.set_limit(id_tag, *limit);
+        }
+        quotas.set_window(self.quota_window_hours.map(Duration::hours));
+        quotas
+    }
+}
+
+impl Default for AuthorizationSettings {
+    fn default() -> Self {
+        AuthorizationSettings {
+            default: Status::Accepted,
+            id_tags: HashMap::new(),
+            parents: HashMap::new(),
+            ttl_days: None,
+            quotas: HashMap::new(),
+            quota_window_hours: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_defaults_to_tmp() {
+        let settings = Settings::parse("").unwrap();
+        assert_eq!(settings.database, DEFAULT_DATABASE);
+    }
+
+    #[test]
+    fn database_is_read_from_the_file() {
+        let settings = Settings::parse(r#"database = "/var/lib/cs/transactions.db""#).unwrap();
+        assert_eq!(settings.database, "/var/lib/cs/transactions.db");
+    }
+
+    #[test]
+    fn example_file_is_read() {
+        let settings = Settings::load("settings.example.toml").unwrap();
+        assert_eq!(settings.heartbeat_interval, 60);
+        assert_eq!(settings.registration_status, RegistrationStatus::Accepted);
+        assert_eq!(settings.server.bind, "0.0.0.0:8080");
+        assert_eq!(settings.ca.validity_days, 365);
+        assert_eq!(settings.ca.subject.organization.as_deref(), Some("Dummy"));
+        assert_eq!(settings.authorization.default, Status::Accepted);
+        assert_eq!(settings.authorization.ttl_days, Some(30));
+        assert_eq!(
+            settings.authorization.id_tags.get("BLOCKED1"),
+            Some(&Status::Blocked)
+        );
+    }
+
+    #[test]
+    fn missing_keys_keep_the_defaults() {
+        let settings = Settings::parse(
+            r#"
+            registration_status = "Pending"
+
+            [server]
+            bind = "127.0.0.1:9000"
+
+            [ca]
+            leaf_validity_days = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.registration_status, RegistrationStatus::Pending);
+        assert_eq!(settings.server.bind, "127.0.0.1:9000");
+        assert_eq!(settings.ca.leaf_validity_days, 1);
+        assert_eq!(settings.heartbeat_interval, DEFAULT_HEARTBEAT_INTERVAL);
+        assert_eq!(settings.ca.validity_days, 365);
+        assert_eq!(
+            settings.ca.directory,
+            DefaultCertificationAuthoritySettings::default().directory
+        );
+    }
+
+    #[test]
+    fn invalid_files_are_config_errors() {
+        for raw in [
+            "heartbeat_interval = \"often\"",
+            "registration_status = \"Maybe\"",
+            "[server",
+        ] {
+            assert!(matches!(Settings::parse(raw), Err(CsError::Config(_))));
+        }
+        assert!(matches!(
+            Settings::load("/nonexistent/settings.toml"),
+            Err(CsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn heartbeat_intervals_prefer_the_charge_point() {
+        let settings = Settings::parse(
+            r#"
+            [heartbeat_intervals.charge_points]
+            cp1 = 10
+            [heartbeat_intervals.models]
+            m1 = 20
+            [heartbeat_intervals.vendors]
+            dummy = 30
+            "#,
+        )
+        .unwrap();
+        let intervals = &settings.heartbeat_intervals;
+        assert_eq!(intervals.resolve("cp1", "dummy", "m1"), Some(10));
+        assert_eq!(intervals.resolve("cp2", "dummy", "m1"), Some(20));
+        assert_eq!(intervals.resolve("cp2", "dummy", "m2"), Some(30));
+        assert_eq!(intervals.resolve("cp2", "other", "m2"), None);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ocpp::{format_timestamp, Message, MessageType, DEFAULT_SECONDS_FORMAT};
+use crate::recorder::Direction;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/* What went over the current connection of a charge point. Messages are
+ * counted by action for calls and by type for answers. */
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    pub connected_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub received: HashMap<String, u64>,
+    pub sent: HashMap<String, u64>,
+    /* Tells a reconnection apart from the connection it replaces */
+    generation: u64,
+}
+
+impl ConnectionStats {
+    pub fn duration(&self, now: DateTime<Utc>) -> Duration {
+        now - self.connected_at
+    }
+
+    fn count(&mut self, direction: Direction, message: &Message, now: DateTime<Utc>) {
+        let kind = match (&message.role, message.command.as_ref()) {
+            (MessageType::Call, Some(command)) => command.to_string(),
+            (role, _) => role.to_string(),
+        };
+        let counters = match direction {
+            Direction::Inbound => {
+                self.last_seen = now;
+                &mut self.received
+            }
+            Direction::Outbound => &mut self.sent,
+        };
+        *counters.entry(kind).or_default() += 1;
+    }
+}
+
+impl From<&ConnectionStats> for json::JsonValue {
+    fn from(stats: &ConnectionStats) -> Self {
+        let counters = |counters: &HashMap<String, u64>| {
+            let mut value = json::JsonValue::new_object();
+            for (kind, count) in counters.iter() {
+                value[kind.as_str()] = (*count).into();
+            }
+            value
+        };
+        object! {
+            connectedAt : format_timestamp(&stats.connected_at, DEFAULT_SECONDS_FORMAT),
+            lastSeen : format_timestamp(&stats.last_seen, DEFAULT_SECONDS_FORMAT),
+            durationSeconds : stats.duration(Utc::now()).num_seconds(),
+            received : counters(&stats.received),
+            sent : counters(&stats.sent),
+        }
+    }
+}
+
+/* Statistics of the connected charge points, shared by the sessions of a
+ * server */
+#[derive(Clone, Default)]
+pub struct Stats {
+    connections: Arc<Mutex<HashMap<String, ConnectionStats>>>,
+    generations: Arc<AtomicU64>,
+}
+
+impl Stats {
+    /* Starts over for a new connection. Returns the generation to pass to
+     * disconnect. */
+    pub fn connect(&self, cp_id: &str) -> u64 {
+        let now = Utc::now();
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let stats = ConnectionStats {
+            connected_at: now,
+            last_seen: now,
+            received: HashMap::new(),
+            sent: HashMap::new(),
+            generation,
+        };
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(cp_id.to_string(), stats);
+        generation
+    }
+
+    pub fn disconnect(&self, cp_id: &str, generation: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.get(cp_id).map(|s| s.generation) == Some(generation) {
+            connections.remove(cp_id);
+        }
+    }
+
+    pub fn record(&self, cp_id: &str, direction: Direction, message: &Message) {
+        if let Some(stats) = self.connections.lock().unwrap().get_mut(cp_id) {
+            stats.count(direction, message, Utc::now());
+        }
+    }
+
+    pub fn get(&self, cp_id: &str) -> Option<ConnectionStats> {
+        self.connections.lock().unwrap().get(cp_id).cloned()
+    }
+
+    pub fn all(&self) -> HashMap<String, ConnectionStats> {
+        self.connections.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,54 @@
+use crate::error::CsError;
+use crate::settings::TlsSettings;
+use openssl::nid::Nid;
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::X509;
+use std::pin::Pin;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+fn tls_error<E: ToString>(e: E) -> CsError {
+    CsError::Config(e.to_string())
+}
+
+/* With client certificates only charge points presenting a certificate
+ * issued by one of the given roots (PEM) are let in, as in OCPP security
+ * profile 3. Expired certificates fail the verification. */
+pub fn acceptor(settings: &TlsSettings, client_roots: &[u8]) -> Result<SslAcceptor, CsError> {
+    let mut builder =
+        SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).map_err(tls_error)?;
+    builder
+        .set_certificate_chain_file(&settings.certificate)
+        .map_err(|e| CsError::Config(format!("can't load {}: {}", settings.certificate, e)))?;
+    builder
+        .set_private_key_file(&settings.key, SslFiletype::PEM)
+        .map_err(|e| CsError::Config(format!("can't load {}: {}", settings.key, e)))?;
+    builder.check_private_key().map_err(tls_error)?;
+
+    if settings.client_certificates {
+        for root in X509::stack_from_pem(client_roots).map_err(tls_error)? {
+            builder.cert_store_mut().add_cert(root).map_err(tls_error)?;
+        }
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    Ok(builder.build())
+}
+
+/* Returns the stream and the common name of the client certificate */
+pub async fn accept(
+    acceptor: &SslAcceptor,
+    stream: TcpStream,
+) -> Result<(SslStream<TcpStream>, Option<String>), CsError> {
+    let ssl = Ssl::new(acceptor.context()).map_err(tls_error)?;
+    let mut stream = SslStream::new(ssl, stream).map_err(tls_error)?;
+    Pin::new(&mut stream).accept().await.map_err(tls_error)?;
+
+    let common_name = stream.ssl().peer_certificate().and_then(|certificate| {
+        certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok())
+    });
+    Ok((stream, common_name))
+}
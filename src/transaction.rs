@@ -0,0 +1,699 @@
+use crate::error::CsError;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub struct Transaction {
+    pub id: u32,
+    pub charge_point: String,
+    pub active: bool,
+    pub connector_id: u32,
+    pub id_tag: Option<String>,
+    pub meter_start: Option<i64>,
+    pub reservation_id: Option<i32>,
+    pub start_timestamp: Option<String>,
+    pub meter_stop: Option<i64>,
+    pub stop_timestamp: Option<String>,
+}
+
+impl Transaction {
+    /* From the StartTransaction payload */
+    pub fn new(id: u32, charge_point: &str, payload: &json::JsonValue) -> Transaction {
+        Transaction {
+            id,
+            charge_point: charge_point.to_string(),
+            active: true,
+            connector_id: payload["connectorId"].as_u32().unwrap_or(0),
+            id_tag: payload["idTag"].as_str().map(String::from),
+            meter_start: payload["meterStart"].as_i64(),
+            reservation_id: payload["reservationId"].as_i32(),
+            start_timestamp: payload["timestamp"].as_str().map(String::from),
+            meter_stop: None,
+            stop_timestamp: None,
+        }
+    }
+
+    pub fn close(&mut self, meter_stop: Option<i64>, timestamp: Option<String>) {
+        self.active = false;
+        self.meter_stop = meter_stop;
+        self.stop_timestamp = timestamp;
+    }
+}
+
+impl From<&Transaction> for json::JsonValue {
+    fn from(transaction: &Transaction) -> json::JsonValue {
+        object! {
+            transactionId : transaction.id,
+            chargePoint : transaction.charge_point.clone(),
+            active : transaction.active,
+            connectorId : transaction.connector_id,
+            idTag : transaction.id_tag.clone(),
+            meterStart : transaction.meter_start,
+            reservationId : transaction.reservation_id,
+            startTimestamp : transaction.start_timestamp.clone(),
+            meterStop : transaction.meter_stop,
+            stopTimestamp : transaction.stop_timestamp.clone(),
+        }
+    }
+}
+
+/* What a stopped transaction delivered. The energy is only known when the
+ * transaction reported both meterStart and meterStop. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionSummary {
+    pub id: u32,
+    pub charge_point: String,
+    pub meter_start: Option<i64>,
+    pub meter_stop: Option<i64>,
+    pub energy_wh: Option<i64>,
+    /* Meter values of transactionData */
+    pub samples: usize,
+}
+
+impl TransactionSummary {
+    pub fn new(
+        id: u32,
+        charge_point: &str,
+        meter_start: Option<i64>,
+        meter_stop: Option<i64>,
+        samples: usize,
+    ) -> TransactionSummary {
+        TransactionSummary {
+            id,
+            charge_point: charge_point.to_string(),
+            meter_start,
+            meter_stop,
+            energy_wh: meter_start
+                .zip(meter_stop)
+                .map(|(start, stop)| stop - start),
+            samples,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransactionEventKind {
+    Start,
+    MeterValues,
+    Stop,
+}
+
+impl TransactionEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionEventKind::Start => "StartTransaction",
+            TransactionEventKind::MeterValues => "MeterValues",
+            TransactionEventKind::Stop => "StopTransaction",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<TransactionEventKind> {
+        match value {
+            "StartTransaction" => Some(TransactionEventKind::Start),
+            "MeterValues" => Some(TransactionEventKind::MeterValues),
+            "StopTransaction" => Some(TransactionEventKind::Stop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TransactionEvent {
+    pub kind: TransactionEventKind,
+    pub charge_point: String,
+    pub timestamp: DateTime<Utc>,
+    pub payload: json::JsonValue,
+}
+
+impl TransactionEvent {
+    pub fn new(
+        kind: TransactionEventKind,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        timestamp: DateTime<Utc>,
+    ) -> TransactionEvent {
+        TransactionEvent {
+            kind,
+            charge_point: charge_point.to_string(),
+            timestamp,
+            payload: payload.clone(),
+        }
+    }
+}
+
+/* Stores are owned by the central system and every call goes through its
+ * lock, so writes coming from different connection threads are serialized.
+ * Events are stamped with the now of the central system's clock. */
+pub trait TransactionStore {
+    fn begin(
+        &mut self,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<u32, CsError>;
+    fn find(&self, id: u32) -> Option<Transaction>;
+    fn finish(
+        &mut self,
+        id: u32,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<(), CsError>;
+    fn add_meter_values(
+        &mut self,
+        id: u32,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<(), CsError>;
+    fn lifecycle(&self, id: u32) -> Vec<TransactionEvent>;
+}
+
+pub struct MemoryTransactionStore {
+    transactions: HashMap<u32, Transaction>,
+    events: HashMap<u32, Vec<TransactionEvent>>,
+    next_id: u32,
+}
+
+impl MemoryTransactionStore {
+    pub fn new() -> MemoryTransactionStore {
+        MemoryTransactionStore {
+            transactions: HashMap::new(),
+            events: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    fn record(&mut self, id: u32, event: TransactionEvent) {
+        self.events.entry(id).or_default().push(event);
+    }
+}
+
+impl Default for MemoryTransactionStore {
+    fn default() -> Self {
+        MemoryTransactionStore::new()
+    }
+}
+
+impl TransactionStore for MemoryTransactionStore {
+    fn begin(
+        &mut self,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<u32, CsError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.transactions
+            .insert(id, Transaction::new(id, charge_point, payload));
+        self.record(
+            id,
+            TransactionEvent::new(TransactionEventKind::Start, charge_point, payload, now),
+        );
+        Ok(id)
+    }
+
+    fn find(&self, id: u32) -> Option<Transaction> {
+        self.transactions.get(&id).cloned()
+    }
+
+    fn finish(
+        &mut self,
+        id: u32,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<(), CsError> {
+        match self.transactions.get_mut(&id) {
+            Some(transaction) if transaction.charge_point != charge_point => {
+                return Err(CsError::Storage(format!(
+                    "transaction belongs to {}",
+                    transaction.charge_point
+                )))
+            }
+            Some(transaction) if transaction.active => {
+                transaction.close(
+                    payload["meterStop"].as_i64(),
+                    payload["timestamp"].as_str().map(String::from),
+                );
+            }
+            Some(_) => {
+                return Err(CsError::Storage(
+                    "transaction is already closed".to_string(),
+                ))
+            }
+            None => return Err(CsError::Storage("unknown transaction".to_string())),
+        }
+
+        self.record(
+            id,
+            TransactionEvent::new(TransactionEventKind::Stop, charge_point, payload, now),
+        );
+        Ok(())
+    }
+
+    fn add_meter_values(
+        &mut self,
+        id: u32,
+        charge_point: &str,
+        payload: &json::JsonValue,
+        now: DateTime<Utc>,
+    ) -> Result<(), CsError> {
+        if !self.transactions.contains_key(&id) {
+            return Err(CsError::Storage("unknown transaction".to_string()));
+        }
+
+        self.record(
+            id,
+            TransactionEvent::new(
+                TransactionEventKind::MeterValues,
+                charge_point,
+                payload,
+                now,
+            ),
+        );
+        Ok(())
+    }
+
+    fn lifecycle(&self, id: u32) -> Vec<TransactionEvent> {
+        self.events.get(&id).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteTransactionStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{Transaction, TransactionEvent, TransactionEventKind, TransactionStore};
+    use crate::error::CsError;
+    use crate::ocpp::{format_timestamp, DEFAULT_SECONDS_FORMAT};
+    use chrono::{DateTime, Utc};
+    use rusqlite::{params, Connection, OptionalExtension};
+
+    pub struct SqliteTransactionStore {
+        connection: Connection,
+    }
+
+    impl SqliteTransactionStore {
+        pub fn open(path: &str) -> Result<SqliteTransactionStore, CsError> {
+            let connection = Connection::open(path)
+                .map_err(|e| CsError::Storage(format!("can't open database: {}", e)))?;
+            connection
+                .execute_batch(
+                    "CREATE TABLE IF NOT EXISTS transactions (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        charge_point TEXT NOT NULL,
+                        active INTEGER NOT NULL,
+                        meter_stop INTEGER,
+                        stop_timestamp TEXT,
+                        connector_id INTEGER NOT NULL DEFAULT 0,
+                        id_tag TEXT,
+                        meter_start INTEGER,
+                        reservation_id INTEGER,
+                        start_timestamp TEXT
+                    );
+                    CREATE TABLE IF NOT EXISTS events (
+                        transaction_id INTEGER NOT NULL,
+                        kind TEXT NOT NULL,
+                        charge_point TEXT NOT NULL,
+                        timestamp TEXT NOT NULL,
+                        payload TEXT NOT NULL
+                    );",
+                )
+                .map_err(|e| CsError::Storage(format!("can't create tables: {}", e)))?;
+            add_start_columns(&connection)
+                .map_err(|e| CsError::Storage(format!("can't add columns: {}", e)))?;
+            Ok(SqliteTransactionStore { connection })
+        }
+
+        fn record(&self, id: u32, event: &TransactionEvent) -> rusqlite::Result<usize> {
+            self.connection.execute(
+                "INSERT INTO events (transaction_id, kind, charge_point, timestamp, payload)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id,
+                    event.kind.as_str(),
+                    event.charge_point,
+                    format_timestamp(&event.timestamp, DEFAULT_SECONDS_FORMAT),
+                    event.payload.dump()
+                ],
+            )
+        }
+    }
+
+    /* Databases of earlier versions only know how transactions stopped */
+    fn add_start_columns(connection: &Connection) -> rusqlite::Result<()> {
+        let mut statement =
+            connection.prepare("SELECT name FROM pragma_table_info('transactions')")?;
+        let columns = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        for (column, kind) in [
+            ("connector_id", "INTEGER NOT NULL DEFAULT 0"),
+            ("id_tag", "TEXT"),
+            ("meter_start", "INTEGER"),
+            ("reservation_id", "INTEGER"),
+            ("start_timestamp", "TEXT"),
+        ] {
+            if !columns.iter().any(|c| c == column) {
+                connection.execute(
+                    &format!("ALTER TABLE transactions ADD COLUMN {} {}", column, kind),
+                    [],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    impl TransactionStore for SqliteTransactionStore {
+        fn begin(
+            &mut self,
+            charge_point: &str,
+            payload: &json::JsonValue,
+            now: DateTime<Utc>,
+        ) -> Result<u32, CsError> {
+            let event =
+                TransactionEvent::new(TransactionEventKind::Start, charge_point, payload, now);
+            let start = Transaction::new(0, charge_point, payload);
+            let tx = self
+                .connection
+                .transaction()
+                .map_err(|e| CsError::Storage(format!("can't begin transaction: {}", e)))?;
+            tx.execute(
+                "INSERT INTO transactions (charge_point, active, connector_id, id_tag,
+                 meter_start, reservation_id, start_timestamp) VALUES (?1, 1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    charge_point,
+                    start.connector_id,
+                    start.id_tag,
+                    start.meter_start,
+                    start.reservation_id,
+                    start.start_timestamp
+                ],
+            )
+            .map_err(|e| CsError::Storage(format!("can't insert transaction: {}", e)))?;
+            let id = tx.last_insert_rowid() as u32;
+            tx.commit()
+                .map_err(|e| CsError::Storage(format!("can't commit transaction: {}", e)))?;
+
+            self.record(id, &event)
+                .map_err(|e| CsError::Storage(format!("can't insert event: {}", e)))?;
+            Ok(id)
+        }
+
+        fn find(&self, id: u32) -> Option<Transaction> {
+            self.connection
+                .query_row(
+                    "SELECT id, charge_point, active, meter_stop, stop_timestamp, connector_id,
+                     id_tag, meter_start, reservation_id, start_timestamp
+                     FROM transactions WHERE id = ?1",
+                    params![id],
+                    |row| {
+                        Ok(Transaction {
+                            id: row.get(0)?,
+                            charge_point: row.get(1)?,
+                            active: row.get(2)?,
+                            meter_stop: row.get(3)?,
+                            stop_timestamp: row.get(4)?,
+                            connector_id: row.get(5)?,
+                            id_tag: row.get(6)?,
+                            meter_start: row.get(7)?,
+                            reservation_id: row.get(8)?,
+                            start_timestamp: row.get(9)?,
+                        })
+                    },
+                )
+                .optional()
+                .unwrap_or(None)
+        }
+
+        fn finish(
+            &mut self,
+            id: u32,
+            charge_point: &str,
+            payload: &json::JsonValue,
+            now: DateTime<Utc>,
+        ) -> Result<(), CsError> {
+            match self.find(id) {
+                Some(transaction) if transaction.charge_point != charge_point => {
+                    return Err(CsError::Storage(format!(
+                        "transaction belongs to {}",
+                        transaction.charge_point
+                    )))
+                }
+                Some(transaction) if transaction.active => {}
+                Some(_) => {
+                    return Err(CsError::Storage(
+                        "transaction is already closed".to_string(),
+                    ))
+                }
+                None => return Err(CsError::Storage("unknown transaction".to_string())),
+            }
+
+            let event =
+                TransactionEvent::new(TransactionEventKind::Stop, charge_point, payload, now);
+            self.connection
+                .execute(
+                    "UPDATE transactions SET active = 0, meter_stop = ?2, stop_timestamp = ?3
+                     WHERE id = ?1",
+                    params![
+                        id,
+                        payload["meterStop"].as_i64(),
+                        payload["timestamp"].as_str()
+                    ],
+                )
+                .map_err(|e| CsError::Storage(format!("can't update transaction: {}", e)))?;
+            self.record(id, &event)
+                .map_err(|e| CsError::Storage(format!("can't insert event: {}", e)))?;
+            Ok(())
+        }
+
+        fn add_meter_values(
+            &mut self,
+            id: u32,
+            charge_point: &str,
+            payload: &json::JsonValue,
+            now: DateTime<Utc>,
+        ) -> Result<(), CsError> {
+            if self.find(id).is_none() {
+                return Err(CsError::Storage("unknown transaction".to_string()));
+            }
+
+            let event = TransactionEvent::new(
+                TransactionEventKind::MeterValues,
+                charge_point,
+                payload,
+                now,
+            );
+            self.record(id, &event)
+                .map_err(|e| CsError::Storage(format!("can't insert event: {}", e)))?;
+            Ok(())
+        }
+
+        fn lifecycle(&self, id: u32) -> Vec<TransactionEvent> {
+            let statement = self.connection.prepare(
+                "SELECT kind, charge_point, timestamp, payload FROM events
+                 WHERE transaction_id = ?1 ORDER BY rowid",
+            );
+
+            let mut statement = match statement {
+                Ok(statement) => statement,
+                Err(_) => return Vec::new(),
+            };
+
+            let rows = statement.query_map(params![id], |row| {
+                let kind: String = row.get(0)?;
+                let charge_point: String = row.get(1)?;
+                let timestamp: String = row.get(2)?;
+                let payload: String = row.get(3)?;
+                Ok((kind, charge_point, timestamp, payload))
+            });
+
+            let mut events = Vec::new();
+            if let Ok(rows) = rows {
+                for (kind, charge_point, timestamp, payload) in rows.flatten() {
+                    let kind = TransactionEventKind::parse(kind.as_str());
+                    let timestamp = DateTime::parse_from_rfc3339(timestamp.as_str());
+                    let payload = json::parse(payload.as_str());
+                    if let (Some(kind), Ok(timestamp), Ok(payload)) = (kind, timestamp, payload) {
+                        events.push(TransactionEvent {
+                            kind,
+                            charge_point,
+                            timestamp: timestamp.with_timezone(&Utc),
+                            payload,
+                        });
+                    }
+                }
+            }
+            events
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn lifecycle_is_recorded(store: &mut dyn TransactionStore) {
+        let start = object! { connectorId : 1, idTag : "TAG1", meterStart : 100, timestamp : "2024-01-01T00:00:00Z" };
+        let id = store.begin("cp1", &start, now()).unwrap();
+        store
+            .add_meter_values(id, "cp1", &object! { transactionId : id }, now())
+            .unwrap();
+        let stop =
+            object! { transactionId : id, meterStop : 250, timestamp : "2024-01-01T01:00:00Z" };
+        store.finish(id, "cp1", &stop, now()).unwrap();
+
+        let transaction = store.find(id).unwrap();
+        assert!(!transaction.active);
+        assert_eq!(transaction.connector_id, 1);
+        assert_eq!(transaction.id_tag.as_deref(), Some("TAG1"));
+        assert_eq!(transaction.meter_start, Some(100));
+        assert_eq!(transaction.meter_stop, Some(250));
+        assert_eq!(
+            transaction.stop_timestamp.as_deref(),
+            Some("2024-01-01T01:00:00Z")
+        );
+
+        let events = store.lifecycle(id);
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionEventKind::Start,
+                TransactionEventKind::MeterValues,
+                TransactionEventKind::Stop
+            ]
+        );
+        assert!(events.iter().all(|event| event.charge_point == "cp1"));
+        assert!(events.iter().all(|event| event.timestamp == now()));
+        assert_eq!(events[2].payload["meterStop"], 250);
+
+        assert!(store.finish(id, "cp1", &stop, now()).is_err());
+        assert!(store
+            .add_meter_values(id + 1, "cp1", &object! {}, now())
+            .is_err());
+        assert!(store.lifecycle(id + 1).is_empty());
+    }
+
+    #[test]
+    fn memory_store_records_the_lifecycle() {
+        lifecycle_is_recorded(&mut MemoryTransactionStore::new());
+    }
+
+    #[test]
+    fn memory_store_refuses_stops_of_other_charge_points() {
+        let mut store = MemoryTransactionStore::new();
+        let id = store.begin("cp1", &object! {}, now()).unwrap();
+        assert!(store.finish(id, "cp2", &object! {}, now()).is_err());
+        assert!(store.find(id).unwrap().active);
+    }
+
+    #[cfg(feature = "sqlite")]
+    mod sqlite {
+        use super::*;
+        use crate::cs::CentralSystem;
+        use crate::ocpp::{CentralSystem as _, Command, Message, MessageType};
+        use crate::settings::Settings;
+        use std::sync::{Arc, Mutex};
+
+        struct Database(String);
+
+        impl Database {
+            fn new() -> Database {
+                Database(
+                    std::env::temp_dir()
+                        .join(format!("dummy-cs-{}.db", uuid::Uuid::new_v4().to_simple()))
+                        .to_string_lossy()
+                        .to_string(),
+                )
+            }
+        }
+
+        impl Drop for Database {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.0);
+            }
+        }
+
+        #[test]
+        fn sqlite_store_records_the_lifecycle() {
+            let database = Database::new();
+            lifecycle_is_recorded(&mut SqliteTransactionStore::open(&database.0).unwrap());
+        }
+
+        #[test]
+        fn sqlite_store_keeps_transactions_after_reopening() {
+            let database = Database::new();
+            let id = SqliteTransactionStore::open(&database.0)
+                .unwrap()
+                .begin("cp1", &object! { connectorId : 2 }, now())
+                .unwrap();
+
+            let mut store = SqliteTransactionStore::open(&database.0).unwrap();
+            assert_eq!(store.find(id).unwrap().connector_id, 2);
+            assert_eq!(store.lifecycle(id).len(), 1);
+            assert!(store.begin("cp1", &object! {}, now()).unwrap() > id);
+        }
+
+        #[test]
+        fn concurrent_starts_get_their_own_transactions() {
+            let database = Database::new();
+            let mut cs = CentralSystem::dry_run(&Settings::default()).unwrap();
+            cs.set_transaction_store(Box::new(SqliteTransactionStore::open(&database.0).unwrap()));
+            let cs = Arc::new(Mutex::new(cs));
+
+            let starts: Vec<_> = (0..8)
+                .map(|i| {
+                    let cs = Arc::clone(&cs);
+                    std::thread::spawn(move || {
+                        let request = Message::new(
+                            MessageType::Call,
+                            "1".to_string(),
+                            Some(Command::StartTransaction),
+                            Some(object! {
+                                connectorId : 1,
+                                idTag : "TAG1",
+                                meterStart : 0,
+                                timestamp : "2024-01-01T00:00:00Z",
+                            }),
+                        );
+                        let answer = cs
+                            .lock()
+                            .unwrap()
+                            .make_response(&format!("cp{}", i), request)
+                            .unwrap();
+                        answer[0].payload.as_ref().unwrap()["transactionId"]
+                            .as_u32()
+                            .unwrap()
+                    })
+                })
+                .collect();
+            let mut ids: Vec<u32> = starts.into_iter().map(|s| s.join().unwrap()).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            assert_eq!(ids.len(), 8);
+
+            let store = SqliteTransactionStore::open(&database.0).unwrap();
+            for id in ids {
+                assert_eq!(store.lifecycle(id).len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn energy_needs_both_meter_readings() {
+        let summary = TransactionSummary::new(1, "cp1", Some(100), Some(250), 0);
+        assert_eq!(summary.energy_wh, Some(150));
+        let summary = TransactionSummary::new(1, "cp1", None, Some(250), 0);
+        assert_eq!(summary.energy_wh, None);
+        assert_eq!(summary.meter_stop, Some(250));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::CsError;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::Id;
+use openssl::x509::X509;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CertificateType {
+    CentralSystemRootCertificate,
+    ManufacturerRootCertificate,
+    CSMSRootCertificate,
+    V2GRootCertificate,
+    MORootCertificate,
+    V2GCertificateChain,
+}
+
+impl CertificateType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificateType::CentralSystemRootCertificate => "CentralSystemRootCertificate",
+            CertificateType::ManufacturerRootCertificate => "ManufacturerRootCertificate",
+            CertificateType::CSMSRootCertificate => "CSMSRootCertificate",
+            CertificateType::V2GRootCertificate => "V2GRootCertificate",
+            CertificateType::MORootCertificate => "MORootCertificate",
+            CertificateType::V2GCertificateChain => "V2GCertificateChain",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<CertificateType> {
+        match value {
+            "CentralSystemRootCertificate" => Some(CertificateType::CentralSystemRootCertificate),
+            "ManufacturerRootCertificate" => Some(CertificateType::ManufacturerRootCertificate),
+            "CSMSRootCertificate" => Some(CertificateType::CSMSRootCertificate),
+            "V2GRootCertificate" => Some(CertificateType::V2GRootCertificate),
+            "MORootCertificate" => Some(CertificateType::MORootCertificate),
+            "V2GCertificateChain" => Some(CertificateType::V2GCertificateChain),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CertificateHashData {
+    pub hash_algorithm: String,
+    pub issuer_name_hash: String,
+    pub issuer_key_hash: String,
+    pub serial_number: String,
+}
+
+impl CertificateHashData {
+    /* Installed certificates are roots, so the certificate is its own issuer */
+    pub fn from_pem(pem: &str) -> Result<CertificateHashData, CsError> {
+        let invalid = |_| CsError::InvalidArgument("certificate is not a valid PEM".to_string());
+        let cert = X509::from_pem(pem.as_bytes()).map_err(invalid)?;
+        let key = cert.public_key().map_err(invalid)?;
+
+        /* The key hash covers the subjectPublicKey bit string only */
+        let key_bits = match key.id() {
+            Id::EC => {
+                let ec = key.ec_key().map_err(invalid)?;
+                let mut ctx = openssl::bn::BigNumContext::new().map_err(invalid)?;
+                ec.public_key()
+                    .to_bytes(
+                        ec.group(),
+                        openssl::ec::PointConversionForm::UNCOMPRESSED,
+                        &mut ctx,
+                    )
+                    .map_err(invalid)?
+            }
+            Id::RSA => key
+                .rsa()
+                .and_then(|rsa| rsa.public_key_to_der_pkcs1())
+                .map_err(invalid)?,
+            _ => key.public_key_to_der().map_err(invalid)?,
+        };
+
+        let name = cert.issuer_name().to_der().map_err(invalid)?;
+        let serial = cert
+            .serial_number()
+            .to_bn()
+            .and_then(|bn| bn.to_hex_str().map(|s| s.to_lowercase()))
+            .map_err(invalid)?;
+
+        Ok(CertificateHashData {
+            hash_algorithm: "SHA256".to_string(),
+            issuer_name_hash: sha256(name.as_slice())?,
+            issuer_key_hash: sha256(key_bits.as_slice())?,
+            serial_number: serial,
+        })
+    }
+
+    pub fn parse(value: &json::JsonValue) -> Option<CertificateHashData> {
+        Some(CertificateHashData {
+            hash_algorithm: value["hashAlgorithm"].as_str()?.to_string(),
+            issuer_name_hash: value["issuerNameHash"].as_str()?.to_string(),
+            issuer_key_hash: value["issuerKeyHash"].as_str()?.to_string(),
+            serial_number: value["serialNumber"].as_str()?.to_string(),
+        })
+    }
+}
+
+impl From<&CertificateHashData> for json::JsonValue {
+    fn from(data: &CertificateHashData) -> Self {
+        object! {
+            hashAlgorithm : data.hash_algorithm.as_str(),
+            issuerNameHash : data.issuer_name_hash.as_str(),
+            issuerKeyHash : data.issuer_key_hash.as_str(),
+            serialNumber : data.serial_number.as_str(),
+        }
+    }
+}
+
+fn sha256(data: &[u8]) -> Result<String, CsError> {
+    hash(MessageDigest::sha256(), data)
+        .map(|digest| hex::encode(&*digest))
+        .map_err(|e| CsError::CaError(e.to_string()))
+}
+
+#[derive(Clone, Debug)]
+pub struct InstalledCertificate {
+    pub certificate_type: CertificateType,
+    pub hash_data: CertificateHashData,
+    /* Only known for certificates installed by this central system */
+    pub certificate: Option<String>,
+}
+
+/* The certificates a charge point reported or accepted for installation */
+#[derive(Default)]
+pub struct TrustStore {
+    certificates: Vec<InstalledCertificate>,
+}
+
+impl TrustStore {
+    pub fn install(&mut self, installed: InstalledCertificate) {
+        self.certificates
+            .retain(|c| c.hash_data != installed.hash_data);
+        self.certificates.push(installed);
+    }
+
+    pub fn remove(&mut self, hash_data: &CertificateHashData) -> bool {
+        let before = self.certificates.len();
+        self.certificates.retain(|c| &c.hash_data != hash_data);
+        before != self.certificates.len()
+    }
+
+    /* Replaces what is known about the given types with a fresh report. An
+     * empty list of types means the report covers every type. */
+    pub fn replace(&mut self, types: &[CertificateType], reported: Vec<InstalledCertificate>) {
+        let previous = std::mem::take(&mut self.certificates);
+        let (replaced, kept): (Vec<_>, Vec<_>) = previous
+            .into_iter()
+            .partition(|c| types.is_empty() || types.contains(&c.certificate_type));
+        self.certificates = kept;
+
+        for mut installed in reported {
+            /* Keep the PEM of certificates installed through this CS */
+            installed.certificate = replaced
+                .iter()
+                .find(|c| c.hash_data == installed.hash_data)
+                .and_then(|c| c.certificate.clone());
+            self.certificates.push(installed);
+        }
+    }
+
+    pub fn certificates(&self) -> &[InstalledCertificate] {
+        self.certificates.as_slice()
+    }
+}
@@ -1,37 +1,384 @@
-use std::fs::{create_dir_all, File};
+use crate::clock::{Clock, SystemClock};
+use crate::error::CsError;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::ocsp::{OcspRequest, OcspResponse, OcspResponseStatus};
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectAlternativeName};
+use openssl::x509::{X509Builder, X509NameBuilder, X509NameRef, X509Req, X509};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, remove_file, rename, write, File};
+use std::io;
 use std::io::prelude::*;
+use std::net::IpAddr;
+use std::path::Path;
 use std::process::Command;
 use std::string::String;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::{debug, info, warn};
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum Format {
     DER,
     PEM,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Default, Deserialize)]
+pub enum KeyAlgorithm {
+    #[default]
+    EcP256,
+    EcP384,
+    Rsa2048,
+    Rsa3072,
+}
+
+/* Certificates chain to a different root depending on their use */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CertificateSigningUse {
+    ChargingStation,
+    V2G,
+}
+
+impl CertificateSigningUse {
+    pub const ALL: [CertificateSigningUse; 2] = [
+        CertificateSigningUse::ChargingStation,
+        CertificateSigningUse::V2G,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificateSigningUse::ChargingStation => "ChargingStationCertificate",
+            CertificateSigningUse::V2G => "V2GCertificate",
+        }
+    }
+
+    /* OCPP 1.6 names the charging station certificate ChargePointCertificate */
+    pub fn parse(value: &str) -> Option<CertificateSigningUse> {
+        match CertificateSigningUse::normalize(value)? {
+            "ChargingStationCertificate" | "ChargePointCertificate" => {
+                Some(CertificateSigningUse::ChargingStation)
+            }
+            _ => Some(CertificateSigningUse::V2G),
+        }
+    }
+
+    /* The spelling of the specification, whatever the casing of value */
+    pub fn normalize(value: &str) -> Option<&'static str> {
+        [
+            "ChargingStationCertificate",
+            "ChargePointCertificate",
+            "V2GCertificate",
+        ]
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(value))
+        .copied()
+    }
+
+    fn file_prefix(&self) -> &'static str {
+        match self {
+            CertificateSigningUse::ChargingStation => "",
+            CertificateSigningUse::V2G => "v2g-",
+        }
+    }
+}
+
 pub trait CertificationAuthority {
-    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, &str>;
+    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, CsError>;
+    fn root_certificate_pem(&self) -> Result<Vec<u8>, CsError>;
+    fn root_certificate_der(&self) -> Result<Vec<u8>, CsError>;
+    fn issuer_certificate_der(&self, usage: CertificateSigningUse) -> Result<Vec<u8>, CsError>;
+    /* DER of the OCSP response to a DER OCSP request */
+    fn ocsp_response(&self, request: &[u8]) -> Result<Vec<u8>, CsError>;
+    fn set_clock(&mut self, clock: Arc<dyn Clock>);
+    /* PEM of the issuers and the certificates they issued, only those valid
+     * now unless include_invalid */
+    fn export_bundle(&self, include_invalid: bool) -> Result<String, CsError>;
 }
 
+#[derive(Clone)]
 pub struct Certificate {
     pub data: Vec<u8>,
     pub format: Format,
 }
 
+impl Certificate {
+    pub fn to_pem(&self) -> Result<String, CsError> {
+        let pem = match self.format {
+            Format::PEM => self.data.clone(),
+            Format::DER => X509::from_der(self.data.as_slice())
+                .and_then(|c| c.to_pem())
+                .map_err(ca_error)?,
+        };
+        String::from_utf8(pem).map_err(|e| CsError::CaError(e.to_string()))
+    }
+}
+
+/* A certificate signed by the CA, the history is rebuilt from the index and
+ * the issued-<serial>.der files on start */
+#[derive(Clone, Debug)]
+pub struct IssuedCertificate {
+    pub serial: u64,
+    pub subject: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub revoked: bool,
+    /* DER */
+    pub data: Vec<u8>,
+}
+
+impl IssuedCertificate {
+    fn parse(serial: u64, subject: &str, revoked: bool, data: Vec<u8>) -> Result<Self, CsError> {
+        let certificate = X509::from_der(data.as_slice()).map_err(ca_error)?;
+        Ok(IssuedCertificate {
+            serial,
+            subject: subject.to_string(),
+            not_before: parse_asn1_time(certificate.not_before())?,
+            not_after: parse_asn1_time(certificate.not_after())?,
+            revoked,
+            data,
+        })
+    }
+}
+
+impl IssuedCertificate {
+    pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        !self.revoked && self.not_before <= now && now < self.not_after
+    }
+}
+
+/* The details of a certificate worth showing when trust issues are debugged */
+#[derive(Clone, Debug, PartialEq)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub issuer: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /* Hex, as printed by openssl */
+    pub serial: String,
+    /* e.g. EC prime256v1 or RSA 2048 */
+    pub key_algorithm: String,
+    /* SHA-256 of the DER, colon separated */
+    pub fingerprint: String,
+}
+
+impl CertificateInfo {
+    pub fn parse(der: &[u8]) -> Result<CertificateInfo, CsError> {
+        let certificate = X509::from_der(der).map_err(ca_error)?;
+        let common_name = certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok());
+        let serial = certificate
+            .serial_number()
+            .to_bn()
+            .and_then(|bn| bn.to_hex_str().map(|s| s.to_string()))
+            .map_err(ca_error)?;
+        let key = certificate.public_key().map_err(ca_error)?;
+        let key_algorithm = match key.ec_key() {
+            Ok(ec) => {
+                let curve = ec
+                    .group()
+                    .curve_name()
+                    .and_then(|nid| nid.short_name().ok())
+                    .unwrap_or("unknown curve");
+                format!("EC {}", curve)
+            }
+            Err(_) => match key.rsa() {
+                Ok(rsa) => format!("RSA {}", rsa.size() * 8),
+                Err(_) => format!("{:?}", key.id()),
+            },
+        };
+        let fingerprint = certificate
+            .digest(MessageDigest::sha256())
+            .map_err(ca_error)?
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(":");
+
+        Ok(CertificateInfo {
+            subject: format_subject(certificate.subject_name()),
+            common_name,
+            issuer: format_subject(certificate.issuer_name()),
+            not_before: parse_asn1_time(certificate.not_before())?,
+            not_after: parse_asn1_time(certificate.not_after())?,
+            serial,
+            key_algorithm,
+            fingerprint,
+        })
+    }
+}
+
+impl fmt::Display for CertificateInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Subject:     {}", self.subject)?;
+        writeln!(f, "Issuer:      {}", self.issuer)?;
+        writeln!(f, "Not before:  {}", self.not_before.to_rfc3339())?;
+        writeln!(f, "Not after:   {}", self.not_after.to_rfc3339())?;
+        writeln!(f, "Serial:      {}", self.serial)?;
+        writeln!(f, "Key:         {}", self.key_algorithm)?;
+        write!(f, "SHA-256:     {}", self.fingerprint)
+    }
+}
+
+/* Issuers first, each once, then the issued certificates */
+fn pem_bundle(
+    issuers: &[Vec<u8>],
+    issued: &[IssuedCertificate],
+    now: DateTime<Utc>,
+    include_invalid: bool,
+) -> Result<String, CsError> {
+    let mut ders: Vec<&[u8]> = Vec::new();
+    for der in issuers.iter() {
+        if !ders.contains(&der.as_slice()) {
+            ders.push(der);
+        }
+    }
+    ders.extend(
+        issued
+            .iter()
+            .filter(|c| include_invalid || c.is_valid(now))
+            .map(|c| c.data.as_slice()),
+    );
+
+    let mut bundle = String::new();
+    for der in ders {
+        let pem = X509::from_der(der)
+            .and_then(|c| c.to_pem())
+            .map_err(ca_error)?;
+        bundle.push_str(&String::from_utf8_lossy(&pem));
+    }
+    Ok(bundle)
+}
+
 pub struct CertificateSignRequest {
     pub data: Vec<u8>,
     pub format: Format,
+    /* Selects the issuer, the root certificates are the ChargingStation ones */
+    pub certificate_type: CertificateSigningUse,
+    /* Entries like "DNS:station.example" or "IP:10.0.0.1". When empty the
+     * SANs requested in the CSR are copied. */
+    pub subject_alt_names: Vec<String>,
 }
 
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Subject {
+    pub common_name: String,
+    pub country: String,
+    pub organization: Option<String>,
+    pub organizational_unit: Option<String>,
+}
+
+impl Subject {
+    /* Formats the subject as expected by openssl -subj */
+    pub fn to_openssl(&self) -> String {
+        let mut subject = "/CN=".to_string() + self.common_name.as_str();
+        if let Some(organization) = &self.organization {
+            subject += "/O=";
+            subject += organization;
+        }
+        if let Some(unit) = &self.organizational_unit {
+            subject += "/OU=";
+            subject += unit;
+        }
+        subject += "/C=";
+        subject += self.country.as_str();
+        subject
+    }
+
+    /* The V2G root gets a name of its own so the issuers can be told apart */
+    fn issuer(&self, certificate_type: CertificateSigningUse) -> Subject {
+        let mut subject = self.clone();
+        if certificate_type == CertificateSigningUse::V2G {
+            subject.common_name += " V2G";
+        }
+        subject
+    }
+}
+
+impl Default for Subject {
+    fn default() -> Self {
+        Subject {
+            common_name: "DefaultCertificationAuthority".to_string(),
+            country: "US".to_string(),
+            organization: None,
+            organizational_unit: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
 pub struct DefaultCertificationAuthoritySettings {
     pub directory: String,
     pub new: bool,
+    /* Remove the files of previous runs before generating new roots */
+    pub reset: bool,
+    /* Validity of the root certificate */
+    pub validity_days: u32,
+    /* Validity of the certificates issued to charge points */
+    pub leaf_validity_days: u32,
+    pub subject: Subject,
+    pub key_algorithm: KeyAlgorithm,
+    /* Attempts after openssl couldn't be started, the delay doubles each time */
+    pub sign_retries: u32,
+    pub sign_retry_delay_ms: u64,
+}
+
+impl Default for DefaultCertificationAuthoritySettings {
+    fn default() -> Self {
+        DefaultCertificationAuthoritySettings {
+            directory: "/tmp/dummy-central-system/ca/".to_string(),
+            new: true,
+            reset: false,
+            validity_days: 365,
+            leaf_validity_days: 100,
+            subject: Subject::default(),
+            key_algorithm: KeyAlgorithm::default(),
+            sign_retries: 2,
+            sign_retry_delay_ms: 100,
+        }
+    }
 }
 
+/* Layout of the CA directory:
+ *
+ * root-key.pem, root-cert.pem          ChargingStationCertificate issuer
+ * v2g-root-key.pem, v2g-root-cert.pem  V2GCertificate issuer
+ * serial      decimal serial of the last issued certificate
+ * index.txt   issued certificates in the openssl ca format, one per line:
+ *             status (V or R), expiry, revocation time, hex serial,
+ *             "unknown" and subject, separated by tabs
+ * crlnumber, crl.pem, ca.cnf           openssl ca state and configuration
+ * issued-<hex serial>.der              the issued certificates
+ *
+ * The serial, the index and crlnumber are replaced through a temporary file
+ * so a crash leaves either the old or the new content. Signings may run
+ * from several threads: serials are allocated under a lock, the index is
+ * rewritten under another one and the csr, cert and ext files of a signing
+ * are named by a uuid. */
 pub struct DefaultCertificationAuthority {
     settings: DefaultCertificationAuthoritySettings,
-    certificates: Vec<CertificateKeyPair>,
+    certificates: HashMap<CertificateSigningUse, CertificateKeyPair>,
+    serial: Mutex<u64>,
+    issued: Mutex<BTreeMap<u64, IssuedCertificate>>,
+    /* Held while index.txt is read and rewritten */
+    index: Mutex<()>,
+    clock: Arc<dyn Clock>,
 }
 
 struct CertificateKeyPair {
@@ -49,53 +396,360 @@ impl CertificateKeyPair {
 }
 
 impl DefaultCertificationAuthority {
-    pub fn new(settings: DefaultCertificationAuthoritySettings) -> DefaultCertificationAuthority {
+    pub fn new(
+        mut settings: DefaultCertificationAuthoritySettings,
+    ) -> DefaultCertificationAuthority {
+        /* File names are appended to the directory */
+        if !settings.directory.ends_with('/') {
+            settings.directory.push('/');
+        }
         DefaultCertificationAuthority {
             settings,
-            certificates: Vec::new(),
+            certificates: HashMap::new(),
+            serial: Mutex::new(0),
+            issued: Mutex::new(BTreeMap::new()),
+            index: Mutex::new(()),
+            clock: Arc::new(SystemClock),
         }
     }
 
-    pub fn init(&mut self) -> Result<(), &str> {
+    pub fn init(&mut self) -> Result<(), CsError> {
+        let version = check_openssl()?;
+        debug!("CA uses {}", version);
+
+        if self.settings.new && self.settings.reset {
+            self.reset_workdir()?;
+        }
+
+        /* Serials survive restarts so certificates are never issued twice
+         * with the same number */
+        self.recover_serial()?;
+
         if !self.settings.new {
-            return Ok(());
+            /* The roots of a previous run are kept */
+            for certificate_type in CertificateSigningUse::ALL {
+                let pair = self.root_pair(certificate_type);
+                if Path::new(pair.get_certificate()).exists() {
+                    self.certificates.insert(certificate_type, pair);
+                }
+            }
+            return self.load_issued();
+        }
+
+        create_dir_all(self.get_workdir())
+            .map_err(|e| CsError::CaError(format!("can't create {}: {}", self.get_workdir(), e)))?;
+
+        for certificate_type in CertificateSigningUse::ALL {
+            let pair = self.root_pair(certificate_type);
+
+            self.generate_key(pair.get_key())?;
+
+            let subject = self.settings.subject.issuer(certificate_type);
+            self.generate_certificate(pair.get_certificate(), pair.get_key(), &subject)?;
+
+            debug!("{}", self.read_key(pair.get_key()));
+            debug!("{}", self.read_certificate(pair.get_certificate()));
+            self.certificates.insert(certificate_type, pair);
+        }
+
+        /* Revocations of the previous root don't apply to the new one */
+        write_atomic(&self.get_index_file(), "")?;
+        write_atomic(&(self.get_workdir().to_string() + "crlnumber"), "01\n")?;
+        self.write_ca_config(self.get_root()?)?;
+        self.generate_crl()?;
+
+        Ok(())
+    }
+
+    fn root_pair(&self, certificate_type: CertificateSigningUse) -> CertificateKeyPair {
+        let prefix = certificate_type.file_prefix();
+        CertificateKeyPair {
+            key: self.get_workdir().to_string() + prefix + "root-key.pem",
+            certificate: self.get_workdir().to_string() + prefix + "root-cert.pem",
         }
+    }
 
-        let key_name = "root-key.pem";
-        let cert_name = "root-cert.pem";
-        let cn = "DefaultCertificationAuthority";
+    pub fn get_workdir(&self) -> &str {
+        self.settings.directory.as_str()
+    }
 
-        let pair = CertificateKeyPair {
-            key: self.get_workdir().to_string() + key_name,
-            certificate: self.get_workdir().to_string() + cert_name,
+    /* Removes what earlier runs generated, serials start over. Directories
+     * without ca.cnf are refused, whatever they hold isn't ours. Files the
+     * CA doesn't know are kept. */
+    fn reset_workdir(&self) -> Result<(), CsError> {
+        let entries = match read_dir(self.get_workdir()) {
+            Ok(entries) => entries.collect::<Result<Vec<_>, _>>()?,
+            Err(_) => return Ok(()),
         };
+        if !entries.is_empty() && !Path::new(&self.get_ca_config_file()).exists() {
+            return Err(CsError::CaError(format!(
+                "refuse to reset {}, it doesn't look like a CA directory",
+                self.get_workdir()
+            )));
+        }
+
+        for entry in entries.iter() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() || !is_generated_file(&name) {
+                warn!("reset keeps {}", name);
+                continue;
+            }
+            remove_file(entry.path())?;
+            info!("reset removed {}", name);
+        }
+        Ok(())
+    }
+
+    pub fn last_serial(&self) -> u64 {
+        *self.serial.lock().unwrap()
+    }
 
-        let _ = create_dir_all(self.get_workdir());
+    /* Ordered by serial */
+    pub fn list_issued(&self) -> Vec<IssuedCertificate> {
+        self.issued.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn find_by_serial(&self, serial: u64) -> Option<IssuedCertificate> {
+        self.issued.lock().unwrap().get(&serial).cloned()
+    }
+
+    /* Marks an issued certificate as revoked and regenerates the CRL */
+    pub fn revoke(&self, serial_number: u64) -> Result<(), CsError> {
+        let serial = format_serial(serial_number);
+        let revoked_at = format_asn1_time(&self.clock.now());
+        let _index = self.index.lock().unwrap();
+        let index = read_to_string(self.get_index_file())?;
+        let mut found = false;
+        let mut updated = String::new();
+
+        for line in index.lines() {
+            let mut fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() == 6 && fields[0] == "V" && fields[3] == serial {
+                fields[0] = "R";
+                fields[2] = revoked_at.as_str();
+                found = true;
+            }
+            updated += fields.join("\t").as_str();
+            updated += "\n";
+        }
+
+        if !found {
+            return Err(CsError::InvalidArgument(
+                "serial is not issued or already revoked".to_string(),
+            ));
+        }
 
-        if !self.generate_key(pair.get_key()) {
-            return Err("can't generate key");
+        write_atomic(&self.get_index_file(), &updated)?;
+        if let Some(issued) = self.issued.lock().unwrap().get_mut(&serial_number) {
+            issued.revoked = true;
         }
+        self.generate_crl()
+    }
+
+    pub fn crl_pem(&self) -> Result<Vec<u8>, CsError> {
+        let mut crl = Vec::new();
+        File::open(self.get_crl_file())?.read_to_end(&mut crl)?;
+        Ok(crl)
+    }
 
-        if !self.generate_certificate(pair.get_certificate(), cn, pair.get_key()) {
-            return Err("can't generate certificate");
+    pub fn crl_der(&self) -> Result<Vec<u8>, CsError> {
+        /* openssl crl -in ca/crl.pem -outform DER */
+        let out = Command::new("openssl")
+            .args([
+                "crl",
+                "-in",
+                self.get_crl_file().as_str(),
+                "-outform",
+                "DER",
+            ])
+            .output()?;
+        if !out.status.success() {
+            return Err(CsError::CaError("failed to export CRL".to_string()));
         }
+        Ok(out.stdout)
+    }
+
+    fn get_index_file(&self) -> String {
+        self.get_workdir().to_string() + "index.txt"
+    }
 
-        println!("{}", self.read_key(pair.get_key()));
-        println!("{}", self.read_certificate(pair.get_certificate()));
+    fn get_crl_file(&self) -> String {
+        self.get_workdir().to_string() + "crl.pem"
+    }
 
-        self.certificates.push(pair);
+    fn get_ca_config_file(&self) -> String {
+        self.get_workdir().to_string() + "ca.cnf"
+    }
 
+    /* openssl ca keeps issued and revoked certificates in the index */
+    fn write_ca_config(&self, pair: &CertificateKeyPair) -> Result<(), CsError> {
+        let config = format!(
+            "[ ca ]\n\
+             default_ca = dummy\n\
+             [ dummy ]\n\
+             database = {}\n\
+             crlnumber = {}crlnumber\n\
+             certificate = {}\n\
+             private_key = {}\n\
+             default_md = sha256\n\
+             default_crl_days = 30\n",
+            self.get_index_file(),
+            self.get_workdir(),
+            pair.get_certificate(),
+            pair.get_key()
+        );
+        write_atomic(&self.get_ca_config_file(), &config)?;
         Ok(())
     }
 
-    pub fn get_workdir(&self) -> &str {
-        self.settings.directory.as_str()
+    fn generate_crl(&self) -> Result<(), CsError> {
+        /* openssl ca -gencrl -config ca/ca.cnf -out ca/crl.pem */
+        let status = Command::new("openssl")
+            .args([
+                "ca",
+                "-gencrl",
+                "-config",
+                self.get_ca_config_file().as_str(),
+                "-out",
+                self.get_crl_file().as_str(),
+            ])
+            .status()?;
+        if !status.success() {
+            return Err(CsError::CaError("failed to generate CRL".to_string()));
+        }
+        Ok(())
+    }
+
+    /* Returns the subject written to the index */
+    fn record_issued(&self, cert: &str, serial: u64) -> Result<String, CsError> {
+        /* openssl x509 -inform DER -in cert -noout -enddate -subject -nameopt compat */
+        let out = Command::new("openssl")
+            .args([
+                "x509", "-inform", "DER", "-in", cert, "-noout", "-enddate", "-subject",
+                "-nameopt", "compat",
+            ])
+            .output()?;
+        let info = String::from_utf8_lossy(out.stdout.as_slice()).to_string();
+
+        let not_after = info
+            .lines()
+            .find_map(|l| l.strip_prefix("notAfter="))
+            .and_then(|d| NaiveDateTime::parse_from_str(d, "%b %e %H:%M:%S %Y GMT").ok())
+            .ok_or_else(|| CsError::CaError("can't read certificate expiry".to_string()))?;
+        let subject = info
+            .lines()
+            .find_map(|l| l.strip_prefix("subject="))
+            .unwrap_or_default();
+
+        let entry = format!(
+            "V\t{}\t\t{}\tunknown\t{}\n",
+            format_asn1_time(&not_after.and_utc()),
+            format_serial(serial),
+            subject
+        );
+        let _index = self.index.lock().unwrap();
+        let index = read_to_string(self.get_index_file()).unwrap_or_default();
+        write_atomic(&self.get_index_file(), &(index + entry.as_str()))?;
+        Ok(subject.to_string())
+    }
+
+    fn get_issued_file(&self, serial: u64) -> String {
+        self.get_workdir().to_string() + "issued-" + format_serial(serial).as_str() + ".der"
+    }
+
+    /* Entries of the index whose certificate file is gone are skipped */
+    fn load_issued(&self) -> Result<(), CsError> {
+        let index = read_to_string(self.get_index_file()).unwrap_or_default();
+        let mut issued = self.issued.lock().unwrap();
+        for line in index.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (status, serial, subject) = match fields.as_slice() {
+                [status, _, _, serial, _, subject] => (*status, *serial, *subject),
+                _ => continue,
+            };
+            let serial = match u64::from_str_radix(serial, 16) {
+                Ok(serial) => serial,
+                Err(_) => continue,
+            };
+            let mut data = Vec::new();
+            if File::open(self.get_issued_file(serial))
+                .and_then(|mut f| f.read_to_end(&mut data))
+                .is_err()
+            {
+                warn!("certificate {} of the index is missing", serial);
+                continue;
+            }
+            let certificate = IssuedCertificate::parse(serial, subject, status == "R", data)?;
+            issued.insert(serial, certificate);
+        }
+        debug!("CA issued {} certificates before", issued.len());
+        Ok(())
+    }
+
+    fn get_serial_file(&self) -> String {
+        self.get_workdir().to_string() + "serial"
+    }
+
+    /* A damaged serial file must not make serials start over, so the highest
+     * serial of the index wins */
+    fn recover_serial(&self) -> Result<(), CsError> {
+        let recorded = read_to_string(self.get_serial_file())
+            .ok()
+            .and_then(|raw| raw.trim().parse::<u64>().ok());
+        let issued = self.repair_index()?;
+        let serial = recorded.unwrap_or(0).max(issued);
+
+        if recorded != Some(serial) && Path::new(&self.get_serial_file()).exists() {
+            warn!("serial file is damaged, continue after {}", serial);
+            write_atomic(&self.get_serial_file(), &serial.to_string())?;
+        }
+        *self.serial.lock().unwrap() = serial;
+        Ok(())
+    }
+
+    /* Drops lines a crash left incomplete. Returns the highest serial. */
+    fn repair_index(&self) -> Result<u64, CsError> {
+        let index = match read_to_string(self.get_index_file()) {
+            Ok(index) => index,
+            Err(_) => return Ok(0),
+        };
+
+        let mut highest = 0;
+        let mut valid = String::new();
+        let mut dropped = 0;
+        for line in index.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let serial = match fields.as_slice() {
+                [status, _, _, serial, _, _] if *status == "V" || *status == "R" => {
+                    u64::from_str_radix(serial, 16).ok()
+                }
+                _ => None,
+            };
+            match serial {
+                Some(serial) => {
+                    highest = highest.max(serial);
+                    valid += line;
+                    valid += "\n";
+                }
+                None => dropped += 1,
+            }
+        }
+
+        if dropped > 0 {
+            warn!("dropped {} damaged lines of the certificate index", dropped);
+            write_atomic(&self.get_index_file(), &valid)?;
+        }
+        Ok(highest)
     }
 
     pub fn read_key(&self, file: &str) -> String {
         /* openssl ec -in ca/root-key.pem -text*/
+        let tool = match self.settings.key_algorithm {
+            KeyAlgorithm::EcP256 | KeyAlgorithm::EcP384 => "ec",
+            KeyAlgorithm::Rsa2048 | KeyAlgorithm::Rsa3072 => "rsa",
+        };
         if let Ok(out) = Command::new("openssl")
-            .args(&["ec", "-text", "-in", file])
+            .args([tool, "-text", "-in", file])
             .output()
         {
             std::str::from_utf8(out.stdout.as_slice())
@@ -109,7 +763,7 @@ impl DefaultCertificationAuthority {
     pub fn read_certificate(&self, file: &str) -> String {
         /* ✗ openssl x509 -in /tmp/rust-cs/ca/root-cert.pem -text*/
         if let Ok(out) = Command::new("openssl")
-            .args(&["x509", "-text", "-in", file])
+            .args(["x509", "-text", "-in", file])
             .output()
         {
             std::str::from_utf8(out.stdout.as_slice())
@@ -120,119 +774,1812 @@ impl DefaultCertificationAuthority {
         }
     }
 
-    fn generate_key(&self, out: &str) -> bool {
+    fn generate_key(&self, out: &str) -> Result<(), CsError> {
         /* openssl ecparam -name prime256v1 -genkey -noout -out test-key-root.pem*/
-        let res = Command::new("openssl")
-            .args(&[
-                "ecparam",
-                "-name",
-                "prime256v1",
-                "-genkey",
-                "-noout",
-                "-out",
-                out,
-            ])
-            .spawn()
-            .unwrap()
-            .wait();
+        /* openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out test-key-root.pem*/
+        let args = match self.settings.key_algorithm {
+            KeyAlgorithm::EcP256 => vec!["ecparam", "-name", "prime256v1", "-genkey", "-noout"],
+            KeyAlgorithm::EcP384 => vec!["ecparam", "-name", "secp384r1", "-genkey", "-noout"],
+            KeyAlgorithm::Rsa2048 => {
+                vec![
+                    "genpkey",
+                    "-algorithm",
+                    "RSA",
+                    "-pkeyopt",
+                    "rsa_keygen_bits:2048",
+                ]
+            }
+            KeyAlgorithm::Rsa3072 => {
+                vec![
+                    "genpkey",
+                    "-algorithm",
+                    "RSA",
+                    "-pkeyopt",
+                    "rsa_keygen_bits:3072",
+                ]
+            }
+        };
+        let res = run_openssl(Command::new("openssl").args(args).args(["-out", out]))
+            .map_err(|e| CsError::CaError(format!("can't generate key: {}", e)));
         self.sync();
-        res.is_ok()
+        res
     }
 
-    fn generate_certificate(&self, out: &str, cn: &str, key: &str) -> bool {
+    fn generate_certificate(&self, out: &str, key: &str, subject: &Subject) -> Result<(), CsError> {
         /* openssl req -x509 -new -key rootCA.key -days 365 -out rootCA.crt -subj "/CN=John Doe /C=US" */
-        let mut subject = "/CN=".to_string() + cn;
-        subject += "/C=US";
-
-        let res = Command::new("openssl")
-            .args(&[
-                "req",
-                "-x509",
-                "-new",
-                "-key",
-                key,
-                "-days",
-                "365",
-                "-out",
-                out,
-                "-subj",
-                subject.as_str(),
-            ])
-            .spawn()
-            .unwrap()
-            .wait();
+        let subject = subject.to_openssl();
+        let days = self.settings.validity_days.to_string();
+
+        let res = run_openssl(Command::new("openssl").args([
+            "req",
+            "-x509",
+            "-new",
+            "-key",
+            key,
+            "-days",
+            days.as_str(),
+            "-out",
+            out,
+            "-subj",
+            subject.as_str(),
+        ]))
+        .map_err(|e| CsError::CaError(format!("can't generate certificate: {}", e)));
         self.sync();
-        res.is_ok()
+        res
     }
 
-    fn sign_certificate_request(&self, csr: &str, pair: &CertificateKeyPair, out: &str) -> bool {
-        /*openssl x509 -req -in csr.pem -CA rootCA.crt -CAkey rootCA.key -CAcreateserial -out out.crt -days 100*/
-        let res = Command::new("openssl")
-            .args(&[
-                "x509",
-                "-req",
-                "-in",
-                csr,
-                "-CA",
-                pair.get_certificate(),
-                "-CAkey",
-                pair.get_key(),
-                "-CAcreateserial",
-                "-days",
-                "100",
-                "-out",
-                out,
-                "-outform",
-                "DER",
-            ])
-            .spawn()
-            .unwrap()
-            .wait();
+    /* Signs with the next serial and returns the serial lock, held until the
+     * certificate is recorded so a failed signing doesn't use up a serial.
+     * It is released while waiting to retry, the other signings go on. */
+    fn sign_certificate_request(
+        &self,
+        csr: &str,
+        pair: &CertificateKeyPair,
+        extensions: Option<&str>,
+        out: &str,
+    ) -> Result<MutexGuard<'_, u64>, CsError> {
+        let days = self.leaf_days(pair)?;
+        retry_transient(
+            self.settings.sign_retries,
+            self.settings.sign_retry_delay_ms,
+            || {
+                let last = self.serial.lock().unwrap();
+                self.run_sign(csr, pair, *last + 1, extensions, out, days)?;
+                Ok(last)
+            },
+        )
+        .map_err(|e| CsError::CaError(format!("failed to sign: {}", e)))
+    }
+
+    fn leaf_days(&self, pair: &CertificateKeyPair) -> Result<u32, CsError> {
+        let pem = read_to_string(pair.get_certificate())?;
+        let issuer = X509::from_pem(pem.as_bytes()).map_err(ca_error)?;
+        let expiry = parse_asn1_time(issuer.not_after())?;
+        clamp_leaf_days(self.settings.leaf_validity_days, expiry, self.clock.now())
+    }
+
+    fn run_sign(
+        &self,
+        csr: &str,
+        pair: &CertificateKeyPair,
+        serial: u64,
+        extensions: Option<&str>,
+        out: &str,
+        days: u32,
+    ) -> Result<(), CsError> {
+        /*openssl x509 -req -in csr.pem -CA rootCA.crt -CAkey rootCA.key -set_serial 1 -out out.crt -days 100*/
+        let days = days.to_string();
+        let serial = serial.to_string();
+        let mut command = Command::new("openssl");
+        command.args([
+            "x509",
+            "-req",
+            "-in",
+            csr,
+            "-CA",
+            pair.get_certificate(),
+            "-CAkey",
+            pair.get_key(),
+            "-set_serial",
+            serial.as_str(),
+            "-days",
+            days.as_str(),
+            "-out",
+            out,
+            "-outform",
+            "DER",
+        ]);
+        /* -copy_extensions needs OpenSSL 3.0, the SANs are written instead */
+        if let Some(file) = extensions {
+            command.args(["-extfile", file]);
+        }
+        let res = run_openssl(&mut command);
         self.sync();
-        res.is_ok()
+        res
+    }
+
+    fn get_root(&self) -> Result<&CertificateKeyPair, CsError> {
+        self.get_issuer(CertificateSigningUse::ChargingStation)
+    }
+
+    fn get_issuer(
+        &self,
+        certificate_type: CertificateSigningUse,
+    ) -> Result<&CertificateKeyPair, CsError> {
+        self.certificates.get(&certificate_type).ok_or_else(|| {
+            CsError::CaError(format!(
+                "{} root certificate is not initialized",
+                certificate_type.as_str()
+            ))
+        })
+    }
+
+    fn export_certificate(&self, file: &str) -> Result<Certificate, CsError> {
+        /* openssl x509 -in ca/root-cert.pem -outform DER */
+        let out = Command::new("openssl")
+            .args(["x509", "-in", file, "-outform", "DER"])
+            .output()?;
+        if !out.status.success() || out.stdout.is_empty() {
+            return Err(CsError::CaError("failed to export certificate".to_string()));
+        }
+        Ok(Certificate {
+            format: Format::DER,
+            data: out.stdout,
+        })
     }
 
     fn sync(&self) {
-        let mut c = Command::new("sync").spawn().unwrap();
-        let _ = c.wait();
+        let _ = Command::new("sync").status();
+    }
+}
+
+/* Files init, sign and openssl ca leave in the CA directory */
+fn is_generated_file(name: &str) -> bool {
+    const FILES: [&str; 10] = [
+        "root-key.pem",
+        "root-cert.pem",
+        "v2g-root-key.pem",
+        "v2g-root-cert.pem",
+        "serial",
+        "index.txt",
+        "crlnumber",
+        "crl.pem",
+        "ca.cnf",
+        "index.txt.attr",
+    ];
+    let base = name
+        .strip_suffix(".tmp")
+        .or_else(|| name.strip_suffix(".old"))
+        .unwrap_or(name);
+    if FILES.contains(&base) {
+        return true;
+    }
+    if base
+        .strip_prefix("issued-")
+        .and_then(|rest| rest.strip_suffix(".der"))
+        .is_some_and(|serial| u64::from_str_radix(serial, 16).is_ok())
+    {
+        return true;
+    }
+    let is_token = |rest: &str| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_hexdigit());
+    /* Files of an OCSP request, named by a uuid or, before, fixed */
+    if ["ocsp-request", "ocsp-response"].iter().any(|prefix| {
+        base.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(".der"))
+            .is_some_and(|rest| rest.is_empty() || rest.strip_prefix('-').is_some_and(is_token))
+    }) {
+        return true;
+    }
+    /* csr, cert and ext files of signing, named by a uuid or, before, by the
+     * time in milliseconds */
+    ["csr", "cert", "ext"]
+        .iter()
+        .any(|prefix| base.strip_prefix(prefix).is_some_and(is_token))
+}
+
+/* Readers see either the old or the new content, never a partial write */
+fn write_atomic(path: &str, contents: &str) -> Result<(), CsError> {
+    let temporary = path.to_string() + ".tmp";
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    rename(&temporary, path)?;
+    Ok(())
+}
+
+/* Failures to start openssl are transient, the ones openssl reports
+ * (e.g. a CSR with an invalid signature) are not. The delay doubles after
+ * each attempt, the thread sleeps meanwhile: callers hold no lock others
+ * wait for. */
+fn retry_transient<T>(
+    retries: u32,
+    delay_ms: u64,
+    mut run: impl FnMut() -> Result<T, CsError>,
+) -> Result<T, CsError> {
+    let mut attempt = 0;
+    loop {
+        match run() {
+            Err(CsError::Io(e)) if attempt < retries => {
+                let delay =
+                    Duration::from_millis(delay_ms.saturating_mul(2u64.saturating_pow(attempt)));
+                attempt += 1;
+                warn!(
+                    "signing failed: {}, retry {} of {} in {:?}",
+                    e, attempt, retries, delay
+                );
+                sleep(delay);
+            }
+            res => return res,
+        }
+    }
+}
+
+/* Fails with an Io error when openssl can't be started and with what it
+ * printed when it doesn't succeed */
+fn run_openssl(command: &mut Command) -> Result<(), CsError> {
+    let out = command.output().map_err(|e| {
+        CsError::Io(io::Error::new(
+            e.kind(),
+            format!("can't run openssl: {}", e),
+        ))
+    })?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(out.stderr.as_slice());
+        return Err(CsError::CaError(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+/* The CA runs the openssl binary, a missing or outdated one is reported
+ * before anything is generated. Returns the version line. */
+fn check_openssl() -> Result<String, CsError> {
+    let out = Command::new("openssl")
+        .arg("version")
+        .output()
+        .map_err(|_| CsError::CaError("openssl >= 1.1 required on PATH; found none".to_string()))?;
+    let version = String::from_utf8_lossy(out.stdout.as_slice())
+        .trim()
+        .to_string();
+    if !out.status.success() || !is_supported_openssl(&version) {
+        return Err(CsError::CaError(format!(
+            "openssl >= 1.1 required on PATH; found {}",
+            if version.is_empty() {
+                "none"
+            } else {
+                version.as_str()
+            }
+        )));
+    }
+    Ok(version)
+}
+
+/* "OpenSSL 3.0.2 15 Mar 2022", LibreSSL is taken as is */
+fn is_supported_openssl(version: &str) -> bool {
+    let mut words = version.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("OpenSSL"), Some(number)) => {
+            let mut parts = number.split('.').map(|p| {
+                p.chars()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect::<String>()
+                    .parse::<u32>()
+                    .unwrap_or(0)
+            });
+            let major = parts.next().unwrap_or(0);
+            let minor = parts.next().unwrap_or(0);
+            (major, minor) >= (1, 1)
+        }
+        (Some("LibreSSL"), Some(_)) => true,
+        _ => false,
+    }
+}
+
+/* The SANs requested in the CSR, as entries of CertificateSignRequest */
+fn requested_alt_names(request: &X509Req) -> Result<Vec<String>, CsError> {
+    let extensions = match request.extensions() {
+        Ok(extensions) => extensions,
+        Err(_) => return Ok(Vec::new()),
+    };
+    /* Requests have no accessor for their SANs, certificates do */
+    let mut builder = X509Builder::new().map_err(ca_error)?;
+    for extension in extensions {
+        builder.append_extension(extension).map_err(ca_error)?;
+    }
+    let names = match builder.build().subject_alt_names() {
+        Some(names) => names,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut alt_names = Vec::new();
+    for name in names.iter() {
+        if let Some(dns) = name.dnsname() {
+            alt_names.push(format!("DNS:{}", dns));
+        } else if let Some(ip) = name.ipaddress() {
+            let address = match (<[u8; 4]>::try_from(ip), <[u8; 16]>::try_from(ip)) {
+                (Ok(v4), _) => IpAddr::from(v4),
+                (_, Ok(v6)) => IpAddr::from(v6),
+                _ => {
+                    return Err(CsError::InvalidArgument(
+                        "CSR requests an invalid IP address".to_string(),
+                    ))
+                }
+            };
+            alt_names.push(format!("IP:{}", address));
+        } else if let Some(uri) = name.uri() {
+            alt_names.push(format!("URI:{}", uri));
+        } else if let Some(email) = name.email() {
+            alt_names.push(format!("email:{}", email));
+        } else {
+            warn!("CSR requests a subject alternative name that isn't supported, skip it");
+        }
+    }
+    Ok(alt_names)
+}
+
+/* The SANs end up in a config file, a separator would add entries or
+ * extensions the CSR didn't ask for */
+fn check_alt_names(alt_names: &[String]) -> Result<(), CsError> {
+    match alt_names
+        .iter()
+        .find(|name| name.contains(|c: char| c == ',' || c.is_control()))
+    {
+        Some(name) => Err(CsError::InvalidArgument(format!(
+            "invalid subject alternative name {:?}",
+            name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/* Serials as openssl ca writes them into the index */
+fn format_serial(serial: u64) -> String {
+    let hex = format!("{:X}", serial);
+    if hex.len() % 2 == 1 {
+        "0".to_string() + hex.as_str()
+    } else {
+        hex
     }
 }
 
+fn format_asn1_time(time: &DateTime<Utc>) -> String {
+    time.format("%y%m%d%H%M%SZ").to_string()
+}
+
+/* Asn1Time prints like "Mar  1 12:00:00 2026 GMT" */
+fn parse_asn1_time(time: &Asn1TimeRef) -> Result<DateTime<Utc>, CsError> {
+    NaiveDateTime::parse_from_str(&time.to_string(), "%b %e %H:%M:%S %Y GMT")
+        .map(|t| t.and_utc())
+        .map_err(|e| CsError::CaError(format!("can't read certificate validity: {}", e)))
+}
+
 impl CertificationAuthority for DefaultCertificationAuthority {
-    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, &str> {
-        if csr.format != Format::PEM {
-            return Err("unsupported format");
+    /* Signing itself runs openssl, which dates certificates by the system
+     * time */
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn root_certificate_pem(&self) -> Result<Vec<u8>, CsError> {
+        let mut pem = Vec::new();
+        File::open(self.get_root()?.get_certificate())?.read_to_end(&mut pem)?;
+        Ok(pem)
+    }
+
+    fn export_bundle(&self, include_invalid: bool) -> Result<String, CsError> {
+        let mut issuers = vec![self.root_certificate_der()?];
+        for usage in CertificateSigningUse::ALL {
+            issuers.push(self.issuer_certificate_der(usage)?);
         }
+        pem_bundle(
+            &issuers,
+            &self.list_issued(),
+            self.clock.now(),
+            include_invalid,
+        )
+    }
+
+    fn root_certificate_der(&self) -> Result<Vec<u8>, CsError> {
+        let root = self.export_certificate(self.get_root()?.get_certificate())?;
+        Ok(root.data)
+    }
+
+    fn issuer_certificate_der(&self, usage: CertificateSigningUse) -> Result<Vec<u8>, CsError> {
+        let issuer = self.export_certificate(self.get_issuer(usage)?.get_certificate())?;
+        Ok(issuer.data)
+    }
+
+    /* Certificates of the index are good or revoked, others unknown. The
+     * root key signs the answers, so V2G certificates are unknown too. */
+    fn ocsp_response(&self, request: &[u8]) -> Result<Vec<u8>, CsError> {
+        if OcspRequest::from_der(request).is_err() {
+            return OcspResponse::create(OcspResponseStatus::MALFORMED_REQUEST, None)
+                .and_then(|response| response.to_der())
+                .map_err(ca_error);
+        }
+
+        /* Unique per request, concurrent requests don't share files */
+        let token = uuid::Uuid::new_v4().to_simple().to_string();
+        let request_file = format!("{}ocsp-request-{}.der", self.get_workdir(), token);
+        let response_file = format!("{}ocsp-response-{}.der", self.get_workdir(), token);
+        let _files = TempFiles(vec![request_file.clone(), response_file.clone()]);
+        write(&request_file, request)?;
+        let root = self.get_root()?;
+        /* openssl ocsp -index ca/index.txt -CA ca/root-cert.pem -rsigner ca/root-cert.pem
+         * -rkey ca/root-key.pem -reqin req.der -respout resp.der -ndays 1 */
+        run_openssl(Command::new("openssl").args([
+            "ocsp",
+            "-index",
+            self.get_index_file().as_str(),
+            "-CA",
+            root.get_certificate(),
+            "-rsigner",
+            root.get_certificate(),
+            "-rkey",
+            root.get_key(),
+            "-reqin",
+            request_file.as_str(),
+            "-respout",
+            response_file.as_str(),
+            "-ndays",
+            "1",
+        ]))
+        .map_err(|e| CsError::CaError(format!("failed to answer OCSP: {}", e)))
+        .and_then(|_| Ok(read(&response_file)?))
+    }
+
+    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, CsError> {
+        let request = match csr.format {
+            Format::PEM => X509Req::from_pem(csr.data.as_slice()),
+            Format::DER => X509Req::from_der(csr.data.as_slice()),
+        }
+        .map_err(ca_error)?;
+        /* openssl x509 -req reads PEM */
+        let data = request.to_pem().map_err(ca_error)?;
+        let alt_names = if csr.subject_alt_names.is_empty() {
+            requested_alt_names(&request)?
+        } else {
+            csr.subject_alt_names.clone()
+        };
+        check_alt_names(&alt_names)?;
+        let root = self.get_issuer(csr.certificate_type)?;
+
+        /* Unique per signing, concurrent signings don't share files */
+        let token = uuid::Uuid::new_v4().to_simple().to_string();
+        let csr_name = self.get_workdir().to_string() + "csr" + token.as_str();
+        let cert_name = self.get_workdir().to_string() + "cert" + token.as_str();
+        let ext_name = self.get_workdir().to_string() + "ext" + token.as_str();
+        let _files = TempFiles(vec![csr_name.clone(), cert_name.clone(), ext_name.clone()]);
+
+        write(csr_name.as_str(), data)?;
+        let extensions = if alt_names.is_empty() {
+            None
+        } else {
+            let ext = "subjectAltName=".to_string() + alt_names.join(",").as_str();
+            write(ext_name.as_str(), ext + "\n")?;
+            Some(ext_name.as_str())
+        };
+
+        let mut last =
+            self.sign_certificate_request(csr_name.as_str(), root, extensions, cert_name.as_str())?;
+        let serial = *last + 1;
+        let input = read(cert_name.as_str())
+            .map_err(|e| CsError::CaError(format!("failed to read certificate: {}", e)))?;
+        let subject = self.record_issued(cert_name.as_str(), serial)?;
+        write_atomic(&self.get_serial_file(), &serial.to_string())?;
+        *last = serial;
+        drop(last);
+
+        rename(&cert_name, self.get_issued_file(serial))?;
+        let issued = IssuedCertificate::parse(serial, &subject, false, input.clone())?;
+        self.issued.lock().unwrap().insert(serial, issued);
+        let cert = Certificate {
+            format: Format::DER,
+            data: input,
+        };
+        let issuer = self.export_certificate(root.get_certificate())?;
+        /* Leaf first, then the certificate it was issued by */
+        Ok(vec![cert, issuer])
+    }
+}
+
+/* Files of a signing, removed whether it succeeds or not. The certificate is
+ * renamed into the CA directory first when it does. */
+struct TempFiles(Vec<String>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for file in self.0.iter() {
+            let _ = remove_file(file);
+        }
+    }
+}
+
+/* Holds the root keys and certificates in memory and signs with the openssl
+ * library, nothing is written to disk */
+pub struct InMemoryCertificationAuthority {
+    settings: DefaultCertificationAuthoritySettings,
+    issuers: HashMap<CertificateSigningUse, Issuer>,
+    serial: Mutex<u64>,
+    issued: Mutex<BTreeMap<u64, IssuedCertificate>>,
+    clock: Arc<dyn Clock>,
+}
+
+struct Issuer {
+    key: PKey<Private>,
+    certificate: X509,
+}
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
+fn ca_error(e: ErrorStack) -> CsError {
+    CsError::CaError(e.to_string())
+}
+
+impl InMemoryCertificationAuthority {
+    pub fn new(
+        settings: DefaultCertificationAuthoritySettings,
+    ) -> Result<InMemoryCertificationAuthority, CsError> {
+        InMemoryCertificationAuthority::with_clock(settings, Arc::new(SystemClock))
+    }
+
+    /* The roots are dated by the clock too, so that they cover the leaves
+     * of a clock that isn't the system time */
+    pub fn with_clock(
+        settings: DefaultCertificationAuthoritySettings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<InMemoryCertificationAuthority, CsError> {
+        let mut issuers = HashMap::new();
+        for certificate_type in CertificateSigningUse::ALL {
+            let subject = settings.subject.issuer(certificate_type);
+            issuers.insert(
+                certificate_type,
+                generate_root(&settings, &subject, clock.now())?,
+            );
+        }
+
+        Ok(InMemoryCertificationAuthority {
+            settings,
+            issuers,
+            serial: Mutex::new(0),
+            issued: Mutex::new(BTreeMap::new()),
+            clock,
+        })
+    }
+
+    /* Ordered by serial, the history is lost with the CA */
+    pub fn list_issued(&self) -> Vec<IssuedCertificate> {
+        self.issued.lock().unwrap().values().cloned().collect()
+    }
+
+    fn get_issuer(&self, certificate_type: CertificateSigningUse) -> &Issuer {
+        /* Every type gets an issuer in new */
+        &self.issuers[&certificate_type]
+    }
+
+    pub fn last_serial(&self) -> u64 {
+        *self.serial.lock().unwrap()
+    }
+}
+
+fn generate_root(
+    settings: &DefaultCertificationAuthoritySettings,
+    subject: &Subject,
+    now: DateTime<Utc>,
+) -> Result<Issuer, CsError> {
+    let key = match settings.key_algorithm {
+        KeyAlgorithm::EcP256 => generate_ec_key(Nid::X9_62_PRIME256V1),
+        KeyAlgorithm::EcP384 => generate_ec_key(Nid::SECP384R1),
+        KeyAlgorithm::Rsa2048 => Rsa::generate(2048).and_then(PKey::from_rsa),
+        KeyAlgorithm::Rsa3072 => Rsa::generate(3072).and_then(PKey::from_rsa),
+    }
+    .map_err(ca_error)?;
+
+    let mut name = X509NameBuilder::new().map_err(ca_error)?;
+    name.append_entry_by_nid(Nid::COMMONNAME, subject.common_name.as_str())
+        .map_err(ca_error)?;
+    if let Some(organization) = &subject.organization {
+        name.append_entry_by_nid(Nid::ORGANIZATIONNAME, organization)
+            .map_err(ca_error)?;
+    }
+    if let Some(unit) = &subject.organizational_unit {
+        name.append_entry_by_nid(Nid::ORGANIZATIONALUNITNAME, unit)
+            .map_err(ca_error)?;
+    }
+    name.append_entry_by_nid(Nid::COUNTRYNAME, subject.country.as_str())
+        .map_err(ca_error)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new().map_err(ca_error)?;
+    builder.set_version(2).map_err(ca_error)?;
+    /* Leaf serials count from 1, the root gets a random one */
+    let mut serial = BigNum::new().map_err(ca_error)?;
+    serial.rand(64, MsbOption::ONE, false).map_err(ca_error)?;
+    let serial = serial.to_asn1_integer().map_err(ca_error)?;
+    builder.set_serial_number(&serial).map_err(ca_error)?;
+    builder.set_subject_name(&name).map_err(ca_error)?;
+    builder.set_issuer_name(&name).map_err(ca_error)?;
+    builder.set_pubkey(&key).map_err(ca_error)?;
+    set_validity(&mut builder, now, settings.validity_days)?;
+    let basic_constraints = BasicConstraints::new()
+        .critical()
+        .ca()
+        .build()
+        .map_err(ca_error)?;
+    builder
+        .append_extension(basic_constraints)
+        .map_err(ca_error)?;
+    let key_usage = KeyUsage::new()
+        .critical()
+        .key_cert_sign()
+        .crl_sign()
+        .build()
+        .map_err(ca_error)?;
+    builder.append_extension(key_usage).map_err(ca_error)?;
+    builder
+        .sign(&key, MessageDigest::sha256())
+        .map_err(ca_error)?;
+
+    Ok(Issuer {
+        key,
+        certificate: builder.build(),
+    })
+}
+
+/* Like the subjects openssl writes to the index, e.g. /CN=cp1/O=Dummy */
+fn format_subject(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().to_string().unwrap_or_default();
+            format!("/{}={}", key, value)
+        })
+        .collect()
+}
+
+fn generate_ec_key(curve: Nid) -> Result<PKey<Private>, ErrorStack> {
+    let group = EcGroup::from_curve_name(curve)?;
+    EcKey::generate(&group).and_then(PKey::from_ec_key)
+}
+
+fn set_serial(builder: &mut X509Builder, serial: u64) -> Result<(), CsError> {
+    let serial = BigNum::from_dec_str(serial.to_string().as_str())
+        .and_then(|bn| bn.to_asn1_integer())
+        .map_err(ca_error)?;
+    builder.set_serial_number(&serial).map_err(ca_error)
+}
+
+/* Leaves must not outlive their issuer, chains with an expired issuer don't
+ * validate */
+fn clamp_leaf_days(
+    days: u32,
+    issuer_expiry: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<u32, CsError> {
+    let remaining = (issuer_expiry - now).num_days();
+    if remaining < 1 {
+        return Err(CsError::CaError(format!(
+            "issuer expires at {}, can't issue certificates",
+            issuer_expiry
+        )));
+    }
+    if i64::from(days) > remaining {
+        warn!(
+            "leaf validity is clamped to {} days, the issuer expires at {}",
+            remaining, issuer_expiry
+        );
+        return Ok(remaining as u32);
+    }
+    Ok(days)
+}
+
+fn set_validity(builder: &mut X509Builder, now: DateTime<Utc>, days: u32) -> Result<(), CsError> {
+    let not_after = now + chrono::Duration::days(i64::from(days));
+    let not_before = Asn1Time::from_unix(now.timestamp()).map_err(ca_error)?;
+    let not_after = Asn1Time::from_unix(not_after.timestamp()).map_err(ca_error)?;
+    builder.set_not_before(&not_before).map_err(ca_error)?;
+    builder.set_not_after(&not_after).map_err(ca_error)
+}
+
+impl CertificationAuthority for InMemoryCertificationAuthority {
+    /* Revocations aren't tracked without the CA directory */
+    fn ocsp_response(&self, _request: &[u8]) -> Result<Vec<u8>, CsError> {
+        Err(CsError::CaError("OCSP needs the CA directory".to_string()))
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, CsError> {
+        let request = match csr.format {
+            Format::PEM => X509Req::from_pem(csr.data.as_slice()),
+            Format::DER => X509Req::from_der(csr.data.as_slice()),
+        }
+        .map_err(ca_error)?;
+        let public_key = request.public_key().map_err(ca_error)?;
+        if !request.verify(&public_key).map_err(ca_error)? {
+            return Err(CsError::CaError("CSR signature is invalid".to_string()));
+        }
+
+        /* Held until the certificate is signed so a failed signing doesn't
+         * use up a serial */
+        let mut last = self.serial.lock().unwrap();
+        let serial = *last + 1;
+        let mut builder = X509Builder::new().map_err(ca_error)?;
+        builder.set_version(2).map_err(ca_error)?;
+        set_serial(&mut builder, serial)?;
+        builder
+            .set_subject_name(request.subject_name())
+            .map_err(ca_error)?;
+        let issuer = self.get_issuer(csr.certificate_type);
+        builder
+            .set_issuer_name(issuer.certificate.subject_name())
+            .map_err(ca_error)?;
+        builder.set_pubkey(&public_key).map_err(ca_error)?;
+        let expiry = parse_asn1_time(issuer.certificate.not_after())?;
+        let now = self.clock.now();
+        let days = clamp_leaf_days(self.settings.leaf_validity_days, expiry, now)?;
+        set_validity(&mut builder, now, days)?;
+
+        /* Of the extensions requested in the CSR only the SANs are copied, a
+         * station doesn't get to make itself a CA */
+        let alt_names = if csr.subject_alt_names.is_empty() {
+            requested_alt_names(&request)?
+        } else {
+            csr.subject_alt_names.clone()
+        };
+        if !alt_names.is_empty() {
+            let mut san = SubjectAlternativeName::new();
+            for entry in alt_names.iter() {
+                match entry.split_once(':') {
+                    Some(("DNS", value)) => san.dns(value),
+                    Some(("IP", value)) => san.ip(value),
+                    Some(("URI", value)) => san.uri(value),
+                    Some(("email", value)) => san.email(value),
+                    _ => {
+                        return Err(CsError::InvalidArgument(format!(
+                            "unsupported subject alternative name {}",
+                            entry
+                        )))
+                    }
+                };
+            }
+            let san = san
+                .build(&builder.x509v3_context(Some(&issuer.certificate), None))
+                .map_err(ca_error)?;
+            builder.append_extension(san).map_err(ca_error)?;
+        }
+        let basic_constraints = BasicConstraints::new()
+            .critical()
+            .build()
+            .map_err(ca_error)?;
+        builder
+            .append_extension(basic_constraints)
+            .map_err(ca_error)?;
+        let key_usage = KeyUsage::new()
+            .critical()
+            .digital_signature()
+            .key_agreement()
+            .build()
+            .map_err(ca_error)?;
+        builder.append_extension(key_usage).map_err(ca_error)?;
+
+        builder
+            .sign(&issuer.key, MessageDigest::sha256())
+            .map_err(ca_error)?;
+        *last = serial;
+        drop(last);
+
+        let leaf = builder.build().to_der().map_err(ca_error)?;
+        let subject = format_subject(request.subject_name());
+        let issued = IssuedCertificate::parse(serial, &subject, false, leaf.clone())?;
+        self.issued.lock().unwrap().insert(serial, issued);
+        Ok(vec![
+            Certificate {
+                format: Format::DER,
+                data: leaf,
+            },
+            Certificate {
+                format: Format::DER,
+                data: issuer.certificate.to_der().map_err(ca_error)?,
+            },
+        ])
+    }
+
+    fn export_bundle(&self, include_invalid: bool) -> Result<String, CsError> {
+        let mut issuers = Vec::new();
+        for usage in CertificateSigningUse::ALL {
+            issuers.push(self.issuer_certificate_der(usage)?);
+        }
+        pem_bundle(
+            &issuers,
+            &self.list_issued(),
+            self.clock.now(),
+            include_invalid,
+        )
+    }
+
+    fn root_certificate_pem(&self) -> Result<Vec<u8>, CsError> {
+        self.get_issuer(CertificateSigningUse::ChargingStation)
+            .certificate
+            .to_pem()
+            .map_err(ca_error)
+    }
+
+    fn root_certificate_der(&self) -> Result<Vec<u8>, CsError> {
+        self.issuer_certificate_der(CertificateSigningUse::ChargingStation)
+    }
+
+    fn issuer_certificate_der(&self, usage: CertificateSigningUse) -> Result<Vec<u8>, CsError> {
+        self.get_issuer(usage)
+            .certificate
+            .to_der()
+            .map_err(ca_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ocsp::{OcspCertId, OcspCertStatus};
+    use openssl::stack::Stack;
+
+    /* A CA directory removed with the CA */
+    struct Workdir(String);
+
+    impl Drop for Workdir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn settings(directory: &str) -> DefaultCertificationAuthoritySettings {
+        DefaultCertificationAuthoritySettings {
+            directory: directory.to_string(),
+            ..DefaultCertificationAuthoritySettings::default()
+        }
+    }
+
+    fn ca() -> (DefaultCertificationAuthority, Workdir) {
+        ca_with(DefaultCertificationAuthoritySettings::default())
+    }
+
+    /* The directory of the settings is replaced by a temporary one */
+    fn ca_with(
+        mut settings: DefaultCertificationAuthoritySettings,
+    ) -> (DefaultCertificationAuthority, Workdir) {
+        let directory = std::env::temp_dir()
+            .join(format!("dummy-cs-{}", uuid::Uuid::new_v4().to_simple()))
+            .to_string_lossy()
+            .to_string()
+            + "/";
+        settings.directory = directory.clone();
+        let mut ca = DefaultCertificationAuthority::new(settings);
+        ca.init().unwrap();
+        (ca, Workdir(directory))
+    }
+
+    fn csr(common_name: &str, alt_names: &[&str]) -> CertificateSignRequest {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        if !alt_names.is_empty() {
+            let mut san = SubjectAlternativeName::new();
+            for entry in alt_names {
+                match entry.split_once(':').unwrap() {
+                    ("DNS", value) => san.dns(value),
+                    ("IP", value) => san.ip(value),
+                    (_, value) => san.uri(value),
+                };
+            }
+            let mut extensions = Stack::new().unwrap();
+            extensions
+                .push(san.build(&builder.x509v3_context(None)).unwrap())
+                .unwrap();
+            builder.add_extensions(&extensions).unwrap();
+        }
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        CertificateSignRequest {
+            data: builder.build().to_pem().unwrap(),
+            format: Format::PEM,
+            certificate_type: CertificateSigningUse::ChargingStation,
+            subject_alt_names: Vec::new(),
+        }
+    }
+
+    fn alt_names(certificate: &Certificate) -> Vec<String> {
+        let certificate = X509::from_der(&certificate.data).unwrap();
+        let names = match certificate.subject_alt_names() {
+            Some(names) => names,
+            None => return Vec::new(),
+        };
+        names
+            .iter()
+            .map(|name| match (name.dnsname(), name.ipaddress()) {
+                (Some(dns), _) => format!("DNS:{}", dns),
+                (_, Some(ip)) => format!("IP:{:?}", ip),
+                _ => format!("URI:{}", name.uri().unwrap()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn requested_san_is_copied() {
+        let (ca, _workdir) = ca();
+        let chain = ca.sign(csr("cp1", &["DNS:station.example"])).unwrap();
+        assert_eq!(alt_names(&chain[0]), vec!["DNS:station.example"]);
+    }
+
+    #[test]
+    fn requested_san_is_copied_in_memory() {
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        let chain = ca.sign(csr("cp1", &["DNS:station.example"])).unwrap();
+        assert_eq!(alt_names(&chain[0]), vec!["DNS:station.example"]);
+    }
+
+    #[test]
+    fn requested_ip_and_uri_are_copied() {
+        let (ca, _workdir) = ca();
+        let chain = ca
+            .sign(csr("cp1", &["IP:10.0.0.1", "URI:urn:station"]))
+            .unwrap();
+        assert_eq!(
+            alt_names(&chain[0]),
+            vec!["IP:[10, 0, 0, 1]", "URI:urn:station"]
+        );
+    }
+
+    #[test]
+    fn given_sans_replace_the_requested_ones() {
+        let (ca, _workdir) = ca();
+        let mut request = csr("cp1", &["DNS:station.example"]);
+        request.subject_alt_names = vec!["DNS:other.example".to_string()];
+        let chain = ca.sign(request).unwrap();
+        assert_eq!(alt_names(&chain[0]), vec!["DNS:other.example"]);
+    }
+
+    #[test]
+    fn certificate_without_requested_san_has_none() {
+        let (ca, _workdir) = ca();
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert!(alt_names(&chain[0]).is_empty());
+    }
+
+    #[test]
+    fn san_that_would_extend_the_config_is_refused() {
+        let (ca, _workdir) = ca();
+        let request = csr("cp1", &["DNS:a.example,DNS:b.example"]);
+        assert!(matches!(ca.sign(request), Err(CsError::InvalidArgument(_))));
+
+        let mut request = csr("cp1", &[]);
+        request.subject_alt_names = vec!["DNS:a.example\nbasicConstraints=CA:TRUE".to_string()];
+        assert!(matches!(ca.sign(request), Err(CsError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn csr_asking_for_a_ca_gets_a_leaf_in_memory() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "cp1").unwrap();
+        let mut builder = X509Req::builder().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        let mut extensions = Stack::new().unwrap();
+        extensions
+            .push(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        extensions
+            .push(KeyUsage::new().key_cert_sign().build().unwrap())
+            .unwrap();
+        builder.add_extensions(&extensions).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let request = CertificateSignRequest {
+            data: builder.build().to_pem().unwrap(),
+            format: Format::PEM,
+            certificate_type: CertificateSigningUse::ChargingStation,
+            subject_alt_names: Vec::new(),
+        };
+
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        let chain = ca.sign(request).unwrap();
+        let leaf = X509::from_der(&chain[0].data).unwrap();
+        let text = String::from_utf8(leaf.to_text().unwrap()).unwrap();
+        assert!(text.contains("CA:FALSE"), "{}", text);
+        assert!(!text.contains("CA:TRUE"), "{}", text);
+        assert!(!text.contains("Certificate Sign"), "{}", text);
+        assert!(text.contains("Digital Signature"), "{}", text);
+    }
+
+    fn leftover_files(directory: &str) -> Vec<String> {
+        read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| ["csr", "cert", "ext"].iter().any(|p| name.starts_with(p)))
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_signings_get_distinct_serials() {
+        let (ca, workdir) = ca();
+        let ca = Arc::new(ca);
+        let signers: Vec<_> = (0..16)
+            .map(|i| {
+                let ca = Arc::clone(&ca);
+                std::thread::spawn(move || ca.sign(csr(&format!("cp{}", i), &[])))
+            })
+            .collect();
+        for signer in signers {
+            signer.join().unwrap().unwrap();
+        }
+
+        let serials: Vec<u64> = ca.list_issued().iter().map(|c| c.serial).collect();
+        assert_eq!(serials, (1..=16).collect::<Vec<u64>>());
+        assert_eq!(ca.last_serial(), 16);
+        assert_eq!(read_to_string(ca.get_serial_file()).unwrap().trim(), "16");
+        assert!(leftover_files(&workdir.0).is_empty());
+    }
+
+    #[test]
+    fn failed_signing_keeps_the_serial_and_no_files() {
+        let (ca, workdir) = ca();
+        ca.sign(csr("cp1", &["DNS:station.example"])).unwrap();
+
+        let key = ca.get_root().unwrap().get_key().to_string();
+        let saved = read(&key).unwrap();
+        write(&key, "not a key").unwrap();
+        assert!(ca.sign(csr("cp2", &["DNS:station.example"])).is_err());
+        assert_eq!(ca.last_serial(), 1);
+        assert!(leftover_files(&workdir.0).is_empty());
+
+        write(&key, saved).unwrap();
+        ca.sign(csr("cp3", &[])).unwrap();
+        assert_eq!(ca.last_serial(), 2);
+    }
+
+    fn ocsp_status(ca: &DefaultCertificationAuthority, leaf: &Certificate) -> OcspCertStatus {
+        let leaf = X509::from_der(&leaf.data).unwrap();
+        let root = ca.root_certificate_pem().unwrap();
+        let root = X509::from_pem(&root).unwrap();
+        let id = || OcspCertId::from_cert(MessageDigest::sha1(), &leaf, &root).unwrap();
+        let mut request = OcspRequest::new().unwrap();
+        request.add_id(id()).unwrap();
+
+        let response = ca.ocsp_response(&request.to_der().unwrap()).unwrap();
+        let response = OcspResponse::from_der(&response).unwrap();
+        assert_eq!(response.status(), OcspResponseStatus::SUCCESSFUL);
+        let basic = response.basic().unwrap();
+        let status = basic.find_status(&id()).unwrap();
+        status.status
+    }
+
+    #[test]
+    fn ocsp_answers_good_then_revoked() {
+        let (ca, workdir) = ca();
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert_eq!(ocsp_status(&ca, &chain[0]), OcspCertStatus::GOOD);
+
+        ca.revoke(1).unwrap();
+        assert_eq!(ocsp_status(&ca, &chain[0]), OcspCertStatus::REVOKED);
+        assert!(read_dir(&workdir.0).unwrap().all(|entry| !entry
             .unwrap()
-            .as_millis();
-        let csr_name = self.get_workdir().to_string() + "csr" + now.to_string().as_str();
-        let cert_name = self.get_workdir().to_string() + "cert" + now.to_string().as_str();
-
-        let mut csr_file = File::create(csr_name.as_str()).unwrap();
-        let _ = csr_file.write(csr.data.as_slice());
-
-        if self.sign_certificate_request(
-            csr_name.as_str(),
-            &self.certificates[0],
-            cert_name.as_str(),
-        ) {
-            let mut cert_file = File::open(cert_name.as_str()).unwrap();
-            let mut input = Vec::<u8>::new();
-
-            if cert_file.read_to_end(&mut input).is_ok() {
-                let cert = Certificate {
-                    format: Format::PEM,
-                    data: input,
+            .file_name()
+            .to_string_lossy()
+            .starts_with("ocsp")));
+    }
+
+    #[test]
+    fn concurrent_ocsp_requests_are_answered() {
+        let (ca, _workdir) = ca();
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        let ca = Arc::new(ca);
+        let requests: Vec<_> = (0..8)
+            .map(|_| {
+                let ca = Arc::clone(&ca);
+                let leaf = chain[0].clone();
+                std::thread::spawn(move || ocsp_status(&ca, &leaf))
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.join().unwrap(), OcspCertStatus::GOOD);
+        }
+    }
+
+    #[test]
+    fn malformed_ocsp_request_is_answered() {
+        let (ca, _workdir) = ca();
+        let response = ca.ocsp_response(b"garbage").unwrap();
+        let response = OcspResponse::from_der(&response).unwrap();
+        assert_eq!(response.status(), OcspResponseStatus::MALFORMED_REQUEST);
+    }
+
+    #[test]
+    fn ocsp_files_of_any_version_are_generated() {
+        assert!(is_generated_file("ocsp-request.der"));
+        assert!(is_generated_file(
+            "ocsp-response-0123456789abcdef0123456789abcdef.der"
+        ));
+        assert!(!is_generated_file("ocsp-request-notes.der"));
+    }
+
+    /* The leaf is issued by the certificate that follows it, the last one is
+     * the root of the certificate type */
+    fn assert_chain(ca: &dyn CertificationAuthority, usage: CertificateSigningUse) {
+        let mut request = csr("cp1", &[]);
+        request.certificate_type = usage;
+        let chain = ca.sign(request).unwrap();
+        assert_eq!(chain.len(), 2);
+        let leaf = X509::from_der(&chain[0].data).unwrap();
+        let issuer = X509::from_der(&chain[1].data).unwrap();
+        assert_eq!(format_subject(leaf.subject_name()), "/CN=cp1");
+        assert_eq!(
+            format_subject(leaf.issuer_name()),
+            format_subject(issuer.subject_name())
+        );
+        assert!(leaf.verify(&issuer.public_key().unwrap()).unwrap());
+        assert_eq!(chain[1].data, ca.issuer_certificate_der(usage).unwrap());
+    }
+
+    #[test]
+    fn chain_is_leaf_then_issuer() {
+        let (ca, _workdir) = ca();
+        assert_chain(&ca, CertificateSigningUse::ChargingStation);
+        assert_chain(&ca, CertificateSigningUse::V2G);
+    }
+
+    #[test]
+    fn chain_is_leaf_then_issuer_in_memory() {
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        assert_chain(&ca, CertificateSigningUse::ChargingStation);
+        assert_chain(&ca, CertificateSigningUse::V2G);
+    }
+
+    fn short_lived() -> DefaultCertificationAuthoritySettings {
+        DefaultCertificationAuthoritySettings {
+            validity_days: 30,
+            leaf_validity_days: 1,
+            subject: Subject {
+                common_name: "Test CA".to_string(),
+                country: "DE".to_string(),
+                organization: Some("Dummy".to_string()),
+                organizational_unit: Some("Testing".to_string()),
+            },
+            ..DefaultCertificationAuthoritySettings::default()
+        }
+    }
+
+    fn not_after(certificate: &Certificate) -> DateTime<Utc> {
+        let certificate = X509::from_der(&certificate.data).unwrap();
+        parse_asn1_time(certificate.not_after()).unwrap()
+    }
+
+    #[test]
+    fn one_day_certificate_expires_a_day_later() {
+        let before = Utc::now();
+        let (ca, _workdir) = ca_with(short_lived());
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        let after = Utc::now();
+
+        /* openssl dates by the system time, asn1 times have no fraction */
+        let expiry = not_after(&chain[0]);
+        assert!(expiry >= before + chrono::Duration::days(1) - chrono::Duration::seconds(1));
+        assert!(expiry <= after + chrono::Duration::days(1));
+        let root = not_after(&chain[1]);
+        assert!(root >= before + chrono::Duration::days(30) - chrono::Duration::seconds(1));
+        assert!(root <= after + chrono::Duration::days(30));
+    }
+
+    #[test]
+    fn one_day_certificate_expires_a_day_later_in_memory() {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut ca = InMemoryCertificationAuthority::new(short_lived()).unwrap();
+        ca.set_clock(Arc::new(crate::clock::FixedClock::new(now)));
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert_eq!(not_after(&chain[0]), now + chrono::Duration::days(1));
+    }
+
+    #[test]
+    fn root_subject_has_the_configured_fields() {
+        let expected = "/CN=Test CA/O=Dummy/OU=Testing/C=DE";
+        let (ca, _workdir) = ca_with(short_lived());
+        let root = X509::from_der(&ca.root_certificate_der().unwrap()).unwrap();
+        assert_eq!(format_subject(root.subject_name()), expected);
+
+        let ca = InMemoryCertificationAuthority::new(short_lived()).unwrap();
+        let root = X509::from_der(&ca.root_certificate_der().unwrap()).unwrap();
+        assert_eq!(format_subject(root.subject_name()), expected);
+    }
+
+    #[test]
+    fn default_validity_is_unchanged() {
+        let settings = DefaultCertificationAuthoritySettings::default();
+        assert_eq!(settings.validity_days, 365);
+        assert_eq!(settings.leaf_validity_days, 100);
+        assert_eq!(
+            settings.subject.to_openssl(),
+            "/CN=DefaultCertificationAuthority/C=US"
+        );
+    }
+
+    fn serial(certificate: &Certificate) -> u64 {
+        let certificate = X509::from_der(&certificate.data).unwrap();
+        let serial = certificate.serial_number().to_bn().unwrap();
+        serial.to_dec_str().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn two_signings_get_distinct_serials() {
+        let (ca, _workdir) = ca();
+        let first = ca.sign(csr("cp1", &[])).unwrap();
+        let second = ca.sign(csr("cp2", &[])).unwrap();
+        assert_eq!(serial(&first[0]), 1);
+        assert_eq!(serial(&second[0]), 2);
+        assert_eq!(ca.last_serial(), 2);
+
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        let first = ca.sign(csr("cp1", &[])).unwrap();
+        let second = ca.sign(csr("cp2", &[])).unwrap();
+        assert_ne!(serial(&first[0]), serial(&second[0]));
+        assert_eq!(ca.last_serial(), 2);
+    }
+
+    #[test]
+    fn serial_survives_a_restart() {
+        let (ca, workdir) = ca();
+        ca.sign(csr("cp1", &[])).unwrap();
+        ca.sign(csr("cp2", &[])).unwrap();
+        drop(ca);
+
+        for new in [false, true] {
+            let mut ca =
+                DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+                    new,
+                    ..settings(&workdir.0)
+                });
+            ca.init().unwrap();
+            let before = ca.last_serial();
+            let chain = ca.sign(csr("cp3", &[])).unwrap();
+            assert_eq!(serial(&chain[0]), before + 1);
+            assert!(before >= 2);
+        }
+    }
+
+    const ALGORITHMS: [KeyAlgorithm; 4] = [
+        KeyAlgorithm::EcP256,
+        KeyAlgorithm::EcP384,
+        KeyAlgorithm::Rsa2048,
+        KeyAlgorithm::Rsa3072,
+    ];
+
+    /* The root key is of the algorithm and signs the leaf */
+    fn assert_root_key(algorithm: KeyAlgorithm, chain: &[Certificate]) {
+        let root = X509::from_der(&chain[1].data).unwrap();
+        let key = root.public_key().unwrap();
+        match algorithm {
+            KeyAlgorithm::EcP256 | KeyAlgorithm::EcP384 => {
+                let curve = key.ec_key().unwrap().group().curve_name();
+                let expected = match algorithm {
+                    KeyAlgorithm::EcP256 => Nid::X9_62_PRIME256V1,
+                    _ => Nid::SECP384R1,
                 };
-                Ok(vec![cert])
-            } else {
-                Err("failed to read certificate")
+                assert_eq!(curve, Some(expected));
             }
-        } else {
-            Err("failed to sign")
+            KeyAlgorithm::Rsa2048 => assert_eq!(key.rsa().unwrap().size() * 8, 2048),
+            KeyAlgorithm::Rsa3072 => assert_eq!(key.rsa().unwrap().size() * 8, 3072),
+        }
+        let leaf = X509::from_der(&chain[0].data).unwrap();
+        assert!(leaf.verify(&key).unwrap());
+    }
+
+    #[test]
+    fn every_key_algorithm_signs() {
+        for algorithm in ALGORITHMS {
+            let (ca, _workdir) = ca_with(DefaultCertificationAuthoritySettings {
+                key_algorithm: algorithm,
+                ..DefaultCertificationAuthoritySettings::default()
+            });
+            assert!(!ca.read_key(ca.get_root().unwrap().get_key()).is_empty());
+            let chain = ca.sign(csr("cp1", &[])).unwrap();
+            assert_root_key(algorithm, &chain);
+        }
+    }
+
+    #[test]
+    fn every_key_algorithm_signs_in_memory() {
+        for algorithm in ALGORITHMS {
+            let ca = InMemoryCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+                key_algorithm: algorithm,
+                ..settings("unused")
+            })
+            .unwrap();
+            let chain = ca.sign(csr("cp1", &[])).unwrap();
+            assert_root_key(algorithm, &chain);
+        }
+    }
+
+    fn revoked_serials(crl: &openssl::x509::X509Crl) -> Vec<u64> {
+        crl.get_revoked()
+            .map(|revoked| {
+                revoked
+                    .iter()
+                    .map(|r| {
+                        let serial = r.serial_number().to_bn().unwrap();
+                        serial.to_dec_str().unwrap().parse().unwrap()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn revoked_serial_is_in_the_crl() {
+        let (ca, _workdir) = ca();
+        let kept = ca.sign(csr("cp1", &[])).unwrap();
+        let revoked = ca.sign(csr("cp2", &[])).unwrap();
+        let crl = openssl::x509::X509Crl::from_der(&ca.crl_der().unwrap()).unwrap();
+        assert!(revoked_serials(&crl).is_empty());
+
+        ca.revoke(serial(&revoked[0])).unwrap();
+        let issuer = X509::from_der(&ca.root_certificate_der().unwrap()).unwrap();
+        for crl in [
+            openssl::x509::X509Crl::from_der(&ca.crl_der().unwrap()).unwrap(),
+            openssl::x509::X509Crl::from_pem(&ca.crl_pem().unwrap()).unwrap(),
+        ] {
+            assert_eq!(revoked_serials(&crl), vec![serial(&revoked[0])]);
+            assert!(crl.verify(&issuer.public_key().unwrap()).unwrap());
+        }
+        assert!(ca.find_by_serial(serial(&revoked[0])).unwrap().revoked);
+        assert!(!ca.find_by_serial(serial(&kept[0])).unwrap().revoked);
+    }
+
+    #[test]
+    fn unknown_or_revoked_serial_cant_be_revoked() {
+        let (ca, _workdir) = ca();
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert!(matches!(ca.revoke(42), Err(CsError::InvalidArgument(_))));
+        ca.revoke(serial(&chain[0])).unwrap();
+        assert!(matches!(
+            ca.revoke(serial(&chain[0])),
+            Err(CsError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn root_certificate_pem_parses() {
+        let (ca, _workdir) = ca();
+        let pem = ca.root_certificate_pem().unwrap();
+        let root = X509::from_pem(&pem).unwrap();
+        assert_eq!(root.to_der().unwrap(), ca.root_certificate_der().unwrap());
+        assert_eq!(
+            format_subject(root.subject_name()),
+            "/CN=DefaultCertificationAuthority/C=US"
+        );
+
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        let root = X509::from_pem(&ca.root_certificate_pem().unwrap()).unwrap();
+        assert_eq!(root.to_der().unwrap(), ca.root_certificate_der().unwrap());
+    }
+
+    #[test]
+    fn in_memory_ca_writes_no_files() {
+        let directory = std::env::temp_dir()
+            .join(format!("dummy-cs-{}", uuid::Uuid::new_v4().to_simple()))
+            .to_string_lossy()
+            .to_string();
+        let ca = InMemoryCertificationAuthority::new(settings(&directory)).unwrap();
+        let chain = ca.sign(csr("cp1", &["DNS:station.example"])).unwrap();
+        ca.sign(csr("cp2", &[])).unwrap();
+        ca.root_certificate_pem().unwrap();
+        ca.export_bundle(true).unwrap();
+
+        assert_eq!(chain.len(), 2);
+        assert_eq!(ca.list_issued().len(), 2);
+        assert!(!Path::new(&directory).exists());
+    }
+
+    #[test]
+    fn unwritable_directory_is_an_error() {
+        /* Not even root can create a directory below a file */
+        let mut ca = DefaultCertificationAuthority::new(settings("/dev/null/ca"));
+        match ca.init() {
+            Err(CsError::CaError(reason)) => assert!(reason.contains("/dev/null/ca"), "{}", reason),
+            Err(e) => panic!("unexpected {}", e),
+            Ok(()) => panic!("CA in /dev/null/ca"),
+        }
+    }
+
+    fn restart(workdir: &Workdir) -> DefaultCertificationAuthority {
+        let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+            new: false,
+            ..settings(&workdir.0)
+        });
+        ca.init().unwrap();
+        ca
+    }
+
+    #[test]
+    fn truncated_serial_file_is_recovered_from_the_index() {
+        let (ca, workdir) = ca();
+        for i in 0..12 {
+            ca.sign(csr(&format!("cp{}", i), &[])).unwrap();
+        }
+        let serial_file = ca.get_serial_file();
+        drop(ca);
+
+        /* Cut while writing "12", then cut before anything was written */
+        for truncated in ["1", ""] {
+            std::fs::write(&serial_file, truncated).unwrap();
+            let ca = restart(&workdir);
+            assert_eq!(ca.last_serial(), 12);
+            assert_eq!(read_to_string(&serial_file).unwrap().trim(), "12");
         }
+        let chain = restart(&workdir).sign(csr("cp12", &[])).unwrap();
+        assert_eq!(serial(&chain[0]), 13);
+    }
+
+    #[test]
+    fn truncated_index_line_is_dropped() {
+        let (ca, workdir) = ca();
+        ca.sign(csr("cp1", &[])).unwrap();
+        ca.sign(csr("cp2", &[])).unwrap();
+        let index_file = ca.get_index_file();
+        drop(ca);
+
+        let index = read_to_string(&index_file).unwrap();
+        std::fs::write(&index_file, index.clone() + "V\t2501").unwrap();
+        let ca = restart(&workdir);
+        assert_eq!(read_to_string(&index_file).unwrap(), index);
+        assert_eq!(ca.last_serial(), 2);
+    }
+
+    #[test]
+    fn openssl_versions_are_checked() {
+        assert!(is_supported_openssl("OpenSSL 3.0.2 15 Mar 2022"));
+        assert!(is_supported_openssl("OpenSSL 1.1.1w  11 Sep 2023"));
+        assert!(is_supported_openssl("LibreSSL 3.3.6"));
+        assert!(!is_supported_openssl("OpenSSL 1.0.2k-fips  26 Jan 2017"));
+        assert!(!is_supported_openssl("OpenSSL 0.9.8"));
+        assert!(!is_supported_openssl(""));
+        assert!(check_openssl().is_ok());
+    }
+
+    fn file_names(directory: &str) -> Vec<String> {
+        let mut names: Vec<String> = read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn reset_leaves_only_fresh_root_files() {
+        let (fresh, fresh_dir) = ca();
+        let fresh_files = file_names(fresh_dir.0.as_str());
+        drop(fresh);
+
+        let (ca, workdir) = ca();
+        ca.sign(csr("cp1", &[])).unwrap();
+        ca.sign(csr("cp2", &[])).unwrap();
+        let old_root = ca.root_certificate_der().unwrap();
+        drop(ca);
+        let junk = [
+            "csr1700000000000",
+            "cert4f2a9c",
+            "ext1700000000000",
+            "ocsp-request.der",
+            "serial.tmp",
+            "index.txt.old",
+        ];
+        for name in junk.iter() {
+            std::fs::write(format!("{}{}", workdir.0, name), "junk").unwrap();
+        }
+        std::fs::write(format!("{}notes.txt", workdir.0), "mine").unwrap();
+
+        let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+            new: true,
+            reset: true,
+            ..settings(&workdir.0)
+        });
+        ca.init().unwrap();
+
+        assert_eq!(ca.last_serial(), 0);
+        assert!(ca.list_issued().is_empty());
+        assert_ne!(ca.root_certificate_der().unwrap(), old_root);
+        /* Files the CA doesn't know are kept */
+        let mut expected = fresh_files;
+        expected.push("notes.txt".to_string());
+        expected.sort();
+        assert_eq!(file_names(&workdir.0), expected);
+    }
+
+    #[test]
+    fn reset_refuses_a_directory_that_isnt_a_ca() {
+        let (ca, workdir) = ca();
+        drop(ca);
+        remove_file(format!("{}ca.cnf", workdir.0)).unwrap();
+        std::fs::write(format!("{}important.txt", workdir.0), "keep").unwrap();
+        let before = file_names(&workdir.0);
+
+        let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+            new: true,
+            reset: true,
+            ..settings(&workdir.0)
+        });
+        match ca.init() {
+            Err(CsError::CaError(reason)) => {
+                assert!(
+                    reason.contains("doesn't look like a CA directory"),
+                    "{}",
+                    reason
+                )
+            }
+            Err(e) => panic!("unexpected {}", e),
+            Ok(()) => panic!("foreign directory was reset"),
+        }
+        assert_eq!(file_names(&workdir.0), before);
+    }
+
+    #[test]
+    fn reset_without_new_keeps_the_serial() {
+        let (ca, workdir) = ca();
+        ca.sign(csr("cp1", &[])).unwrap();
+        drop(ca);
+        let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+            new: false,
+            reset: true,
+            ..settings(&workdir.0)
+        });
+        ca.init().unwrap();
+        assert_eq!(ca.last_serial(), 1);
+    }
+
+    fn assert_history(issued: &[IssuedCertificate], signed: &[Certificate]) {
+        assert_eq!(issued.len(), signed.len());
+        for (entry, certificate) in issued.iter().zip(signed.iter()) {
+            assert_eq!(entry.serial, serial(certificate));
+            assert_eq!(entry.data, certificate.data);
+            assert_eq!(entry.not_after, not_after(certificate));
+            assert!(entry.not_before < entry.not_after);
+            assert!(!entry.revoked);
+        }
+    }
+
+    #[test]
+    fn three_signings_are_in_the_history() {
+        let (ca, workdir) = ca();
+        let signed: Vec<Certificate> = ["cp1", "cp2", "cp3"]
+            .iter()
+            .map(|cn| ca.sign(csr(cn, &[])).unwrap().remove(0))
+            .collect();
+
+        let issued = ca.list_issued();
+        assert_history(&issued, &signed);
+        let subjects: Vec<&str> = issued.iter().map(|c| c.subject.as_str()).collect();
+        assert_eq!(subjects, ["/CN=cp1", "/CN=cp2", "/CN=cp3"]);
+        let serials: Vec<u64> = issued.iter().map(|c| c.serial).collect();
+        assert_eq!(serials, [1, 2, 3]);
+
+        let second = ca.find_by_serial(issued[1].serial).unwrap();
+        assert_eq!(second.subject, "/CN=cp2");
+        assert!(ca.find_by_serial(issued[2].serial + 1).is_none());
+
+        /* Rebuilt from the files on start */
+        drop(ca);
+        let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+            new: false,
+            ..settings(&workdir.0)
+        });
+        ca.init().unwrap();
+        assert_history(&ca.list_issued(), &signed);
+    }
+
+    #[test]
+    fn in_memory_ca_keeps_the_history() {
+        let ca = InMemoryCertificationAuthority::new(settings("unused")).unwrap();
+        let signed: Vec<Certificate> = ["cp1", "cp2", "cp3"]
+            .iter()
+            .map(|cn| ca.sign(csr(cn, &[])).unwrap().remove(0))
+            .collect();
+        assert_history(&ca.list_issued(), &signed);
+    }
+
+    fn transient() -> CsError {
+        CsError::Io(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+    }
+
+    #[test]
+    fn transient_failure_is_retried_within_the_budget() {
+        let mut attempts = 0;
+        let res = retry_transient(2, 1, || {
+            attempts += 1;
+            match attempts {
+                1 => Err(transient()),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(res.unwrap(), 2);
+
+        /* Past the budget the last failure is returned */
+        let mut attempts = 0;
+        let res: Result<(), CsError> = retry_transient(2, 1, || {
+            attempts += 1;
+            Err(transient())
+        });
+        assert!(matches!(res, Err(CsError::Io(_))));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn verification_failure_is_not_retried() {
+        let mut attempts = 0;
+        let res: Result<(), CsError> = retry_transient(2, 1, || {
+            attempts += 1;
+            Err(CsError::CaError("bad signature".to_string()))
+        });
+        assert!(matches!(res, Err(CsError::CaError(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn leaf_validity_is_clamped_to_the_issuer() {
+        let now = Utc::now();
+        let expiry = now + chrono::Duration::days(10);
+        assert_eq!(clamp_leaf_days(5, expiry, now).unwrap(), 5);
+        assert_eq!(clamp_leaf_days(100, expiry, now).unwrap(), 10);
+        assert!(matches!(
+            clamp_leaf_days(1, expiry, expiry),
+            Err(CsError::CaError(_))
+        ));
+    }
+
+    #[test]
+    fn leaf_signed_near_root_expiry_is_clamped() {
+        let long_lived_leaves = || DefaultCertificationAuthoritySettings {
+            leaf_validity_days: 100,
+            ..short_lived()
+        };
+
+        /* The root of 30 days is younger than the 100 days of the leaves */
+        let (ca, _workdir) = ca_with(long_lived_leaves());
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert!(not_after(&chain[0]) <= not_after(&chain[1]));
+        assert!(not_after(&chain[0]) >= Utc::now() + chrono::Duration::days(28));
+
+        /* Five days before the root expires */
+        let mut ca = InMemoryCertificationAuthority::new(long_lived_leaves()).unwrap();
+        let root = X509::from_der(&ca.root_certificate_der().unwrap()).unwrap();
+        let root_expiry = parse_asn1_time(root.not_after()).unwrap();
+        let now = root_expiry - chrono::Duration::days(5);
+        ca.set_clock(Arc::new(crate::clock::FixedClock::new(now)));
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        assert_eq!(not_after(&chain[0]), now + chrono::Duration::days(5));
+
+        /* Past it nothing is issued */
+        ca.set_clock(Arc::new(crate::clock::FixedClock::new(root_expiry)));
+        assert!(matches!(ca.sign(csr("cp1", &[])), Err(CsError::CaError(_))));
+    }
+
+    fn bundle_ders(bundle: &str) -> Vec<Vec<u8>> {
+        X509::stack_from_pem(bundle.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|c| c.to_der().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn bundle_holds_the_root_and_the_valid_certificates() {
+        let (ca, _workdir) = ca();
+        let first = ca.sign(csr("cp1", &[])).unwrap();
+        let second = ca.sign(csr("cp2", &[])).unwrap();
+
+        let ders = bundle_ders(&ca.export_bundle(false).unwrap());
+        let root = ca.root_certificate_der().unwrap();
+        assert_eq!(ders[0], root);
+        assert_eq!(ders.iter().filter(|der| **der == root).count(), 1);
+        assert!(ders.contains(&first[0].data));
+        assert!(ders.contains(&second[0].data));
+
+        /* Revoked certificates are left out unless asked for */
+        ca.revoke(serial(&first[0])).unwrap();
+        let ders = bundle_ders(&ca.export_bundle(false).unwrap());
+        assert!(!ders.contains(&first[0].data));
+        assert!(ders.contains(&second[0].data));
+        let ders = bundle_ders(&ca.export_bundle(true).unwrap());
+        assert!(ders.contains(&first[0].data));
+    }
+
+    #[test]
+    fn expired_certificates_are_left_out_of_the_bundle() {
+        let mut ca = InMemoryCertificationAuthority::new(short_lived()).unwrap();
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        let ders = bundle_ders(&ca.export_bundle(false).unwrap());
+        assert_eq!(ders.len(), 3);
+        assert!(ders.contains(&chain[0].data));
+
+        let later = Utc::now() + chrono::Duration::days(2);
+        ca.set_clock(Arc::new(crate::clock::FixedClock::new(later)));
+        let ders = bundle_ders(&ca.export_bundle(false).unwrap());
+        assert!(!ders.contains(&chain[0].data));
+        assert!(bundle_ders(&ca.export_bundle(true).unwrap()).contains(&chain[0].data));
+    }
+    #[test]
+    fn root_certificate_details_are_parsed() {
+        let (ca, _workdir) = ca();
+        let der = ca.root_certificate_der().unwrap();
+        let info = CertificateInfo::parse(&der).unwrap();
+        assert_eq!(
+            info.common_name.as_deref(),
+            Some("DefaultCertificationAuthority")
+        );
+        /* Self-signed */
+        assert_eq!(info.subject, info.issuer);
+        assert!(info.not_before < info.not_after);
+        assert_eq!(info.fingerprint.split(':').count(), 32);
+        assert!(!info.serial.is_empty());
+
+        let shown = info.to_string();
+        for field in [
+            "Subject:",
+            "Issuer:",
+            "Not after:",
+            "Serial:",
+            "Key:",
+            "SHA-256:",
+        ] {
+            assert!(shown.contains(field), "{} missing in {}", field, shown);
+        }
+        assert!(shown.contains(&info.fingerprint));
+    }
+
+    #[test]
+    fn garbage_is_not_a_certificate() {
+        assert!(matches!(
+            CertificateInfo::parse(b"not a certificate"),
+            Err(CsError::CaError(_))
+        ));
+    }
+    #[test]
+    fn other_signings_go_on_while_one_waits_to_retry() {
+        let (ca, _workdir) = ca();
+        let mut attempts = 0;
+        let res = std::thread::scope(|scope| {
+            /* As sign_certificate_request, failing to start openssl once */
+            retry_transient(1, 1000, || {
+                let last = ca.serial.lock().unwrap();
+                attempts += 1;
+                if attempts == 1 {
+                    scope.spawn(|| ca.sign(csr("cp2", &[])).unwrap());
+                    return Err(transient());
+                }
+                Ok(*last)
+            })
+        });
+        /* The other signing took serial 1 meanwhile */
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(ca.last_serial(), 1);
+    }
+    #[test]
+    fn roots_are_dated_by_the_clock_in_memory() {
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let clock = Arc::new(crate::clock::FixedClock::new(now));
+        let ca = InMemoryCertificationAuthority::with_clock(settings("unused"), clock).unwrap();
+        let root = X509::from_der(&ca.root_certificate_der().unwrap()).unwrap();
+        assert_eq!(parse_asn1_time(root.not_before()).unwrap(), now);
+
+        /* The leaves lie within their issuer */
+        let chain = ca.sign(csr("cp1", &[])).unwrap();
+        let leaf = X509::from_der(&chain[0].data).unwrap();
+        let issuer = X509::from_der(&chain[1].data).unwrap();
+        assert_eq!(parse_asn1_time(leaf.not_before()).unwrap(), now);
+        assert_eq!(parse_asn1_time(issuer.not_before()).unwrap(), now);
+        assert!(not_after(&chain[0]) <= not_after(&chain[1]));
     }
 }
@@ -0,0 +1,52 @@
+#![cfg(feature = "admin")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::admin;
+use dummy_central_system::stats::Stats;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn quota_usage_is_read_and_reset() {
+    let cs = central_system();
+    cs.lock().unwrap().quotas().set_limit("TAG2", 1);
+    let handle = start(&cs).await;
+    let port = free_port();
+    admin::serve(
+        &format!("127.0.0.1:{}", port),
+        None,
+        Arc::clone(&cs),
+        Stats::default(),
+    )
+    .unwrap();
+
+    let mut client = connect(&handle, "cp1").await;
+    let start = object! {
+        connectorId : 1,
+        idTag : "TAG2",
+        meterStart : 0,
+        timestamp : "2024-01-01T00:00:00Z",
+    };
+    let answer = call(&mut client, "1", "StartTransaction", start.clone()).await;
+    assert_eq!(answer[2]["idTagInfo"]["status"], "Accepted");
+    assert_eq!(
+        json_body(&http_get(port, "/quotas")),
+        object! { TAG2 : object! { limit : 1, used : 1 } }
+    );
+
+    let mut second = start.clone();
+    second["connectorId"] = 2.into();
+    let answer = call(&mut client, "2", "StartTransaction", second.clone()).await;
+    assert_eq!(answer[2]["idTagInfo"]["status"], "Blocked");
+
+    let reset = http_request(port, "DELETE", "/quotas/TAG2");
+    assert!(reset.contains(" 200 "), "{}", reset);
+    assert_eq!(json_body(&reset)["TAG2"]["used"], 0);
+    let answer = call(&mut client, "3", "StartTransaction", second).await;
+    assert_eq!(answer[2]["idTagInfo"]["status"], "Accepted");
+    handle.shutdown().await;
+}
@@ -0,0 +1,86 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::credentials::Credentials;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::ocpp::ProtocolVersion;
+use dummy_central_system::{ConnectionSettings, ServerHandle};
+use openssl::base64;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Error;
+
+async fn start_with_password(cs: &Arc<Mutex<CentralSystem>>) -> ServerHandle {
+    let mut passwords = HashMap::new();
+    passwords.insert("cp1".to_string(), "secret".to_string());
+    let settings = ConnectionSettings {
+        credentials: Arc::new(Credentials::new(passwords)),
+        ..ConnectionSettings::default()
+    };
+    start_with(cs, settings).await
+}
+
+/* The client, or the HTTP status the handshake is refused with */
+async fn handshake(
+    handle: &ServerHandle,
+    cp_id: &str,
+    user_password: Option<&str>,
+) -> Result<Client, u16> {
+    let url = format!("ws://{}/{}", handle.address(), cp_id);
+    let mut request = url.into_client_request().unwrap();
+    let headers = request.headers_mut();
+    headers.insert(
+        "Sec-WebSocket-Protocol",
+        ProtocolVersion::Ocpp16.subprotocol().parse().unwrap(),
+    );
+    if let Some(user_password) = user_password {
+        let value = format!("Basic {}", base64::encode_block(user_password.as_bytes()));
+        headers.insert("Authorization", value.parse().unwrap());
+    }
+    match connect_async(request).await {
+        Ok((client, _)) => Ok(client),
+        Err(Error::Http(response)) => Err(response.status().as_u16()),
+        Err(e) => panic!("unexpected {}", e),
+    }
+}
+
+#[tokio::test]
+async fn valid_credentials_are_accepted() {
+    let cs = central_system();
+    let handle = start_with_password(&cs).await;
+    let mut client = handshake(&handle, "cp1", Some("cp1:secret"))
+        .await
+        .ok()
+        .unwrap();
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn missing_credentials_are_refused() {
+    let cs = central_system();
+    let handle = start_with_password(&cs).await;
+    assert_eq!(handshake(&handle, "cp1", None).await.err(), Some(401));
+    assert!(handle.list_connections().is_empty());
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn wrong_credentials_are_refused() {
+    let cs = central_system();
+    let handle = start_with_password(&cs).await;
+    for user_password in ["cp1:wrong", "cp2:secret"] {
+        let refused = handshake(&handle, "cp1", Some(user_password)).await;
+        assert_eq!(refused.err(), Some(401));
+    }
+    /* The password of cp1 doesn't let cp2 in */
+    let refused = handshake(&handle, "cp2", Some("cp2:secret")).await;
+    assert_eq!(refused.err(), Some(401));
+    handle.shutdown().await;
+}
@@ -0,0 +1,132 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use std::process::{Command, Output};
+
+fn central_system() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_dummy-central-system"))
+}
+
+#[test]
+fn ca_init_failure_exits_with_a_message() {
+    let output = central_system()
+        .args(["--ca-dir", "/dev/null/ca", "--bind", "127.0.0.1:0"])
+        .output()
+        .unwrap();
+    /* Logs go to stdout */
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("can't create /dev/null/ca/"), "{}", stdout);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+/* Runs the binary with PATH holding only the given directory */
+fn without_openssl_on_path(path: &std::path::Path) -> String {
+    let ca_dir = path.join("ca");
+    let output = central_system()
+        .args([
+            "--ca-dir",
+            ca_dir.to_str().unwrap(),
+            "--bind",
+            "127.0.0.1:0",
+        ])
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(!ca_dir.exists());
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("dummy-cs-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn missing_openssl_is_reported() {
+    let path = temp_dir("empty");
+    let stdout = without_openssl_on_path(&path);
+    assert!(
+        stdout.contains("openssl >= 1.1 required on PATH; found none"),
+        "{}",
+        stdout
+    );
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn outdated_openssl_is_reported() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_dir("outdated");
+    let openssl = path.join("openssl");
+    std::fs::write(
+        &openssl,
+        "#!/bin/sh\necho 'OpenSSL 1.0.2k-fips  26 Jan 2017'\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&openssl, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let stdout = without_openssl_on_path(&path);
+    assert!(
+        stdout.contains("openssl >= 1.1 required on PATH; found OpenSSL 1.0.2k-fips"),
+        "{}",
+        stdout
+    );
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn eval_prints_the_answers() {
+    let output = central_system()
+        .args([
+            "eval",
+            r#"[2,"1","Heartbeat",{}]"#,
+            r#"[2,"2","Authorize",{"idTag":"TAG1"}]"#,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let frames: Vec<&str> = stdout.lines().filter(|l| l.starts_with('[')).collect();
+    assert_eq!(frames.len(), 2, "{}", stdout);
+    assert!(frames[0].starts_with(r#"[3,"1",{"currentTime":"#));
+    assert!(frames[1].starts_with(r#"[3,"2",{"idTagInfo":{"status":"Accepted""#));
+}
+
+/* Runs the client subcommand off the runtime serving the central system */
+async fn client(args: &[&str]) -> Output {
+    let mut command = central_system();
+    command.arg("client").args(args);
+    tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn client_boots_against_an_embedded_server() {
+    let cs = common::central_system();
+    let handle = start(&cs).await;
+    let url = format!("ws://{}/cp1", handle.address());
+
+    let output = client(&["--url", &url, "--action", "BootNotification"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+    let answer = json::parse(stdout.lines().find(|l| l.starts_with('[')).unwrap()).unwrap();
+    assert_eq!(answer[0], 3);
+    assert_eq!(answer[2]["status"], "Accepted");
+
+    /* A CallError is printed and fails */
+    let output = client(&["--url", &url, "--action", "Authorize", "--payload", "{}"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("[4,"), "{}", stdout);
+    handle.shutdown().await;
+}
@@ -0,0 +1,186 @@
+/* Starts servers on free ports and talks to them as a charge point */
+#![allow(dead_code)]
+
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::ocpp::ProtocolVersion;
+use dummy_central_system::settings::Settings;
+use dummy_central_system::{ConnectionSettings, Server, ServerHandle};
+use futures_util::{SinkExt, StreamExt};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* Signs in memory, nothing is written to disk */
+pub fn central_system() -> Arc<Mutex<CentralSystem>> {
+    Arc::new(Mutex::new(
+        CentralSystem::dry_run(&Settings::default()).unwrap(),
+    ))
+}
+
+pub async fn start(cs: &Arc<Mutex<CentralSystem>>) -> ServerHandle {
+    start_with(cs, ConnectionSettings::default()).await
+}
+
+pub async fn start_with(
+    cs: &Arc<Mutex<CentralSystem>>,
+    settings: ConnectionSettings,
+) -> ServerHandle {
+    Server::new(Arc::clone(cs))
+        .listen("127.0.0.1:0", settings)
+        .start()
+        .await
+        .unwrap()
+}
+
+pub async fn connect(handle: &ServerHandle, cp_id: &str) -> Client {
+    connect_with(handle, cp_id, ProtocolVersion::Ocpp16).await
+}
+
+pub async fn connect_with(handle: &ServerHandle, cp_id: &str, protocol: ProtocolVersion) -> Client {
+    handshake(handle.address(), cp_id, protocol).await.unwrap()
+}
+
+/* The client, or the HTTP status the handshake is refused with */
+pub async fn handshake(
+    address: SocketAddr,
+    cp_id: &str,
+    protocol: ProtocolVersion,
+) -> Result<Client, u16> {
+    let url = format!("ws://{}/{}", address, cp_id);
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        protocol.subprotocol().parse().unwrap(),
+    );
+    match connect_async(request).await {
+        Ok((client, _)) => Ok(client),
+        Err(Error::Http(response)) => Err(response.status().as_u16()),
+        Err(e) => panic!("unexpected {}", e),
+    }
+}
+
+pub async fn send(client: &mut Client, frame: json::JsonValue) {
+    client.send(WsMessage::Text(frame.dump())).await.unwrap();
+}
+
+/* The next text frame, None once the connection is closed */
+pub async fn receive(client: &mut Client) -> Option<json::JsonValue> {
+    timeout(ANSWER_TIMEOUT, async {
+        while let Some(Ok(frame)) = client.next().await {
+            if let WsMessage::Text(text) = frame {
+                return Some(json::parse(&text).unwrap());
+            }
+        }
+        None
+    })
+    .await
+    .expect("no frame within the timeout")
+}
+
+/* Sends a call and returns the first frame answering it */
+pub async fn call(
+    client: &mut Client,
+    id: &str,
+    action: &str,
+    payload: json::JsonValue,
+) -> json::JsonValue {
+    send(client, array![2, id, action, payload]).await;
+    loop {
+        let frame = receive(client).await.expect("connection closed");
+        if frame[1] == id && frame[0] != 2 {
+            return frame;
+        }
+    }
+}
+
+/* The payload of the close frame the server ends the connection with, None
+ * when the connection ends without a close frame */
+pub async fn close_frame(client: &mut Client) -> Option<Option<CloseFrame<'static>>> {
+    timeout(ANSWER_TIMEOUT, async {
+        loop {
+            match client.next().await {
+                Some(Ok(WsMessage::Close(frame))) => return Some(frame),
+                Some(Ok(_)) => continue,
+                _ => return None,
+            }
+        }
+    })
+    .await
+    .expect("connection not closed within the timeout")
+}
+
+/* A CSR of a fresh P-256 key */
+pub fn csr_pem(common_name: &str) -> String {
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::{X509NameBuilder, X509Req};
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", common_name).unwrap();
+    let mut builder = X509Req::builder().unwrap();
+    builder.set_subject_name(&name.build()).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    String::from_utf8(builder.build().to_pem().unwrap()).unwrap()
+}
+
+/* Polls until the condition holds, false when it doesn't within the timeout */
+pub async fn eventually<F: Fn() -> bool>(condition: F) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < ANSWER_TIMEOUT {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+/* A port that was free a moment ago */
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/* The raw response to a GET over HTTP/1.0 */
+pub fn http_get(port: u16, path: &str) -> String {
+    http_request(port, "GET", path)
+}
+
+pub fn http_request(port: u16, method: &str, path: &str) -> String {
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: localhost\r\n\r\n",
+        method, path
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/* The JSON body of a raw HTTP response */
+pub fn json_body(response: &str) -> json::JsonValue {
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    json::parse(body).unwrap()
+}
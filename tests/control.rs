@@ -0,0 +1,127 @@
+#![cfg(feature = "control")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::control;
+use dummy_central_system::stats::Stats;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+fn socket_path() -> String {
+    std::env::temp_dir()
+        .join(format!(
+            "dummy-cs-control-{}.sock",
+            uuid::Uuid::new_v4().to_simple()
+        ))
+        .to_string_lossy()
+        .to_string()
+}
+
+/* Sends the lines and reads as many answers */
+fn request(path: &str, lines: &[&str]) -> Vec<json::JsonValue> {
+    let stream = UnixStream::connect(path).unwrap();
+    stream.set_read_timeout(Some(ANSWER_TIMEOUT * 2)).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    for line in lines.iter() {
+        writeln!(writer, "{}", line).unwrap();
+    }
+    let mut reader = BufReader::new(stream);
+    lines
+        .iter()
+        .map(|_| {
+            let mut answer = String::new();
+            reader.read_line(&mut answer).unwrap();
+            json::parse(&answer).unwrap()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn connections_are_listed_and_reset_over_the_socket() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let path = socket_path();
+    control::serve(&path, Arc::clone(&cs), Stats::default()).unwrap();
+
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+    let answers = request(&path, &[r#"{"id": 1, "method": "connections"}"#]);
+    assert_eq!(
+        answers[0],
+        object! { id : 1, result : object! { chargePoints : array!["cp1"] } }
+    );
+
+    /* The reset is answered once the charge point confirmed it */
+    let reset_path = path.clone();
+    let reset = tokio::task::spawn_blocking(move || {
+        request(
+            &reset_path,
+            &[
+                r#"{"id": "r", "method": "reset", "params": {"chargePoint": "cp1", "type": "Hard"}}"#,
+            ],
+        )
+    });
+    let call = receive(&mut client).await.unwrap();
+    assert_eq!(call[0], 2);
+    assert_eq!(call[2], "Reset");
+    assert_eq!(call[3], object! { type : "Hard" });
+    send(
+        &mut client,
+        array![3, call[1].clone(), object! { status : "Accepted" }],
+    )
+    .await;
+    let answers = reset.await.unwrap();
+    assert_eq!(answers[0]["id"], "r");
+    assert_eq!(answers[0]["result"]["status"], "Accepted");
+    assert_eq!(answers[0]["result"]["messageId"], call[1]);
+
+    handle.shutdown().await;
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn malformed_requests_are_answered_and_the_client_served_on() {
+    let cs = central_system();
+    let path = socket_path();
+    control::serve(&path, Arc::clone(&cs), Stats::default()).unwrap();
+
+    let answers = request(
+        &path,
+        &[
+            "not json",
+            r#"{"id": 2}"#,
+            r#"{"id": 3, "method": "shutdown"}"#,
+            r#"{"id": 4, "method": "reset", "params": []}"#,
+            r#"{"id": 5, "method": "reset", "params": {"chargePoint": "a/b"}}"#,
+            r#"{"id": 6, "method": "reset", "params": {"chargePoint": "cp9"}}"#,
+            r#"{"id": 7, "method": "connections"}"#,
+        ],
+    );
+    let codes: Vec<i32> = answers
+        .iter()
+        .take(6)
+        .map(|a| a["error"]["code"].as_i32().unwrap())
+        .collect();
+    assert_eq!(codes, [-32700, -32600, -32601, -32602, -32602, 404]);
+    assert!(answers[0]["id"].is_null());
+    assert_eq!(answers[5]["id"], 6);
+    assert_eq!(
+        answers[6],
+        object! { id : 7, result : object! { chargePoints : array![] } }
+    );
+
+    /* Clients are served concurrently */
+    let first = UnixStream::connect(&path).unwrap();
+    let answers = request(&path, &[r#"{"id": 8, "method": "quotas"}"#]);
+    assert_eq!(answers[0]["id"], 8);
+    drop(first);
+
+    /* A socket in use isn't taken over */
+    assert!(control::serve(&path, cs, Stats::default()).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,39 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::server::ResponseDelays;
+use dummy_central_system::ConnectionSettings;
+use std::time::{Duration, Instant};
+
+const DELAY: Duration = Duration::from_millis(500);
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn authorize_answer_is_delayed() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        response_delays: ResponseDelays::parse("Authorize=0.5"),
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut delayed = connect(&handle, "cp1").await;
+    let mut other = connect(&handle, "cp2").await;
+
+    let started = Instant::now();
+    let authorize = tokio::spawn(async move {
+        let answer = call(&mut delayed, "1", "Authorize", object! { idTag : "TAG1" }).await;
+        (answer, started.elapsed())
+    });
+
+    /* Other connections and actions are answered meanwhile */
+    let answer = call(&mut other, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert!(started.elapsed() < DELAY);
+
+    let (answer, elapsed) = authorize.await.unwrap();
+    assert_eq!(answer[2]["idTagInfo"]["status"], "Accepted");
+    assert!(elapsed >= DELAY, "answered after {:?}", elapsed);
+    handle.shutdown().await;
+}
@@ -0,0 +1,169 @@
+#![cfg(feature = "diagnostics")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::diagnostics::{self, UploadSettings};
+use dummy_central_system::ocpp::{self, Command, Message, MessageType};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const CHUNK: usize = 64 * 1024;
+
+struct Workdir(PathBuf);
+
+impl Drop for Workdir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/* The upload server of a central system that knows cp1 */
+fn serve(max_size: u64) -> (u16, Arc<Mutex<CentralSystem>>, Workdir) {
+    let cs = central_system();
+    let boot = Message::new(
+        MessageType::Call,
+        "1".to_string(),
+        Some(Command::BootNotification),
+        Some(object! { chargePointVendor : "dummy", chargePointModel : "m1" }),
+    );
+    ocpp::CentralSystem::make_response(&mut *cs.lock().unwrap(), "cp1", boot).unwrap();
+
+    let directory = std::env::temp_dir().join(format!(
+        "dummy-cs-diagnostics-{}",
+        uuid::Uuid::new_v4().to_simple()
+    ));
+    let port = free_port();
+    let settings = UploadSettings {
+        directory: directory.clone(),
+        max_size,
+    };
+    diagnostics::serve(&format!("127.0.0.1:{}", port), settings, Arc::clone(&cs)).unwrap();
+    (port, cs, Workdir(directory))
+}
+
+/* Byte i of an upload, so that misplaced chunks are noticed */
+fn byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+fn request_head(port: u16, path: &str, length: usize) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "PUT {} HTTP/1.0\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+        path, length
+    )
+    .unwrap();
+    stream
+}
+
+/* Writes the bytes from..to of the upload, false once the server stopped
+ * reading */
+fn write_body(stream: &mut TcpStream, from: usize, to: usize) -> bool {
+    let mut chunk = Vec::with_capacity(CHUNK);
+    let mut position = from;
+    while position < to {
+        chunk.clear();
+        chunk.extend((position..to.min(position + CHUNK)).map(byte));
+        if stream.write_all(&chunk).is_err() {
+            return false;
+        }
+        position += chunk.len();
+    }
+    true
+}
+
+fn response(mut stream: TcpStream) -> String {
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    response
+}
+
+/* The status code of a raw HTTP response */
+fn status(response: &str) -> &str {
+    response.split(' ').nth(1).unwrap_or_default()
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+#[tokio::test]
+async fn large_upload_is_streamed_to_disk() {
+    let (port, cs, workdir) = serve(16 * 1024 * 1024);
+    let size = 8 * 1024 * 1024;
+    let mut stream = request_head(port, "/cp1/diagnostics.tar.gz", size);
+
+    /* Half of the body is on disk before the rest is sent */
+    assert!(write_body(&mut stream, 0, size / 2));
+    let partial = workdir.0.join("cp1").join("diagnostics.tar.gz.part");
+    assert!(eventually(|| file_size(&partial) >= (size / 4) as u64).await);
+    assert!(write_body(&mut stream, size / 2, size));
+
+    let response = response(stream);
+    assert_eq!(status(&response), "201", "{}", response);
+    let stored = workdir.0.join("cp1").join("diagnostics.tar.gz");
+    assert_eq!(
+        json_body(&response),
+        object! { path : stored.display().to_string() }
+    );
+    assert!(!partial.exists());
+    let content = std::fs::read(&stored).unwrap();
+    assert_eq!(content.len(), size);
+    assert!(content.iter().enumerate().all(|(i, b)| *b == byte(i)));
+
+    let cs = cs.lock().unwrap();
+    let cp = cs.charge_point("cp1").unwrap();
+    assert_eq!(cp.diagnostics_status.as_deref(), Some("Uploaded"));
+    assert_eq!(
+        cp.diagnostics_upload.as_deref(),
+        Some(stored.display().to_string().as_str())
+    );
+}
+
+#[tokio::test]
+async fn uploads_beyond_the_cap_are_refused() {
+    let (port, cs, workdir) = serve(1024 * 1024);
+    let size = 2 * 1024 * 1024;
+    let mut stream = request_head(port, "/cp1/diagnostics.tar.gz", size);
+    write_body(&mut stream, 0, size);
+    let response = response(stream);
+    assert_eq!(status(&response), "413", "{}", response);
+    assert!(!workdir.0.join("cp1").join("diagnostics.tar.gz").exists());
+    assert!(!workdir
+        .0
+        .join("cp1")
+        .join("diagnostics.tar.gz.part")
+        .exists());
+    assert!(cs
+        .lock()
+        .unwrap()
+        .charge_point("cp1")
+        .unwrap()
+        .diagnostics_upload
+        .is_none());
+}
+
+#[tokio::test]
+async fn uploads_of_unknown_charge_points_or_paths_are_refused() {
+    let (port, _cs, _workdir) = serve(1024 * 1024);
+    for (path, code) in [
+        ("/cp2/diagnostics.tar.gz", "404"),
+        ("/cp1/", "400"),
+        ("/cp1/..", "400"),
+        ("/../diagnostics.tar.gz", "400"),
+    ] {
+        let mut stream = request_head(port, path, 4);
+        write_body(&mut stream, 0, 4);
+        let response = response(stream);
+        assert_eq!(status(&response), code, "{} {}", path, response);
+    }
+    assert_eq!(status(&http_get(port, "/cp1/diagnostics.tar.gz")), "405");
+}
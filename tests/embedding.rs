@@ -0,0 +1,94 @@
+#[macro_use]
+extern crate json;
+
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::error::CsError;
+use dummy_central_system::{ConnectionSettings, Server, Settings};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+/* What a charge point client test embedding the central system does,
+ * through the library API only */
+#[tokio::test]
+async fn embedded_server_answers_a_boot_notification() {
+    let cs = CentralSystem::dry_run(&Settings::default()).unwrap();
+    let handle = Server::new(Arc::new(Mutex::new(cs)))
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .start()
+        .await
+        .unwrap();
+
+    let url = format!("ws://{}/cp1", handle.address());
+    let mut request = url.into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("Sec-WebSocket-Protocol", "ocpp1.6".parse().unwrap());
+    let (mut client, response) = connect_async(request).await.unwrap();
+    assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "ocpp1.6");
+
+    let boot = array![
+        2,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "m1" }
+    ];
+    client.send(Message::Text(boot.dump())).await.unwrap();
+    let answer = loop {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => break json::parse(&text).unwrap(),
+            _ => continue,
+        }
+    };
+    assert_eq!(answer[0], 3);
+    assert_eq!(answer[1], "1");
+    assert_eq!(answer[2]["status"], "Accepted");
+    assert!(answer[2]["currentTime"].is_string());
+    assert!(answer[2]["interval"].as_u32().is_some());
+    assert_eq!(handle.list_connections(), ["cp1"]);
+
+    let address = handle.address();
+    handle.shutdown().await;
+    assert!(connect_async(format!("ws://{}/cp1", address))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn port_zero_reports_the_bound_ports() {
+    let cs = Arc::new(Mutex::new(
+        CentralSystem::dry_run(&Settings::default()).unwrap(),
+    ));
+    let handle = Server::new(Arc::clone(&cs))
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .start()
+        .await
+        .unwrap();
+
+    let addresses = handle.addresses().to_vec();
+    assert_eq!(addresses.len(), 2);
+    assert_eq!(handle.address(), addresses[0]);
+    assert_ne!(addresses[0].port(), 0);
+    assert_ne!(addresses[0].port(), addresses[1].port());
+    for address in addresses.iter() {
+        assert!(address.ip().is_loopback());
+        let url = format!("ws://{}/cp1", address);
+        let mut request = url.into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", "ocpp1.6".parse().unwrap());
+        connect_async(request).await.unwrap();
+    }
+
+    /* A port in use can't be bound again, nothing is served then */
+    let taken = Server::new(cs)
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .listen(&addresses[1].to_string(), ConnectionSettings::default())
+        .start()
+        .await;
+    assert!(matches!(taken, Err(CsError::Config(_))));
+    handle.shutdown().await;
+}
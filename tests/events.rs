@@ -0,0 +1,122 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::events::OcppEvent;
+use dummy_central_system::ocpp::MessageType;
+use dummy_central_system::recorder::Direction;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+
+async fn next_event(events: &mut Receiver<OcppEvent>) -> OcppEvent {
+    timeout(ANSWER_TIMEOUT, events.recv())
+        .await
+        .expect("no event within the timeout")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn boot_notification_is_published_in_and_out() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut events = handle.subscribe();
+    let mut client = connect(&handle, "cp1").await;
+    call(
+        &mut client,
+        "42",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "m1" },
+    )
+    .await;
+
+    let inbound = next_event(&mut events).await;
+    assert_eq!(inbound.cp_id, "cp1");
+    assert_eq!(inbound.direction, Direction::Inbound);
+    assert_eq!(inbound.kind, MessageType::Call);
+    assert_eq!(inbound.action.as_deref(), Some("BootNotification"));
+    assert_eq!(inbound.message_id, "42");
+
+    let outbound = next_event(&mut events).await;
+    assert_eq!(outbound.cp_id, "cp1");
+    assert_eq!(outbound.direction, Direction::Outbound);
+    assert_eq!(outbound.kind, MessageType::CallResult);
+    assert_eq!(outbound.action, None);
+    assert_eq!(outbound.message_id, "42");
+    assert!(outbound.timestamp >= inbound.timestamp);
+
+    assert!(events.try_recv().is_err());
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn every_subscriber_gets_the_events() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut first = handle.subscribe();
+    let mut second = handle.subscribe();
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+
+    for events in [&mut first, &mut second] {
+        assert_eq!(next_event(events).await.direction, Direction::Inbound);
+        assert_eq!(next_event(events).await.direction, Direction::Outbound);
+    }
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn sign_certificate_exchange_is_completed_in_order() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut events = handle.subscribe();
+    let mut client = connect(&handle, "cp1").await;
+
+    let answer = call(
+        &mut client,
+        "1",
+        "SignCertificate",
+        object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargePointCertificate" },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Accepted");
+    let signed = receive(&mut client).await.unwrap();
+    assert_eq!(signed[0], 2);
+    assert_eq!(signed[2], "CertificateSigned");
+    let signed_id = signed[1].as_str().unwrap().to_string();
+    send(
+        &mut client,
+        array![3, signed_id.as_str(), object! { status : "Accepted" }],
+    )
+    .await;
+    /* The connection goes on after the confirmation */
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+
+    let mut seen = Vec::new();
+    for _ in 0..6 {
+        let event = next_event(&mut events).await;
+        seen.push((event.direction, event.kind, event.message_id));
+    }
+    assert_eq!(
+        seen,
+        [
+            (Direction::Inbound, MessageType::Call, "1".to_string()),
+            (
+                Direction::Outbound,
+                MessageType::CallResult,
+                "1".to_string()
+            ),
+            (Direction::Outbound, MessageType::Call, signed_id.clone()),
+            (Direction::Inbound, MessageType::CallResult, signed_id),
+            (Direction::Inbound, MessageType::Call, "2".to_string()),
+            (
+                Direction::Outbound,
+                MessageType::CallResult,
+                "2".to_string()
+            ),
+        ]
+    );
+    handle.shutdown().await;
+}
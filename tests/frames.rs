@@ -0,0 +1,216 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::server::RateLimit;
+use dummy_central_system::ConnectionSettings;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+#[tokio::test]
+async fn nested_call_is_a_formation_violation() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let nested = format!(
+        r#"[2,"1","DataTransfer",{{"data":{}{}}}]"#,
+        "[".repeat(10_000),
+        "]".repeat(10_000)
+    );
+    client.send(WsMessage::Text(nested)).await.unwrap();
+    let answer = receive(&mut client).await.unwrap();
+    assert_eq!(answer[0], 4);
+    assert_eq!(answer[1], "1");
+    assert_eq!(answer[2], "FormationViolation");
+
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn call_that_cant_be_parsed_is_a_formation_violation() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    client
+        .send(WsMessage::Text(r#"[2,"1","Heartbeat",{"#.to_string()))
+        .await
+        .unwrap();
+    let answer = receive(&mut client).await.unwrap();
+    assert_eq!(answer[0], 4);
+    assert_eq!(answer[1], "1");
+    assert_eq!(answer[2], "FormationViolation");
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn frame_without_an_id_is_dropped() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    client
+        .send(WsMessage::Text("garbage".to_string()))
+        .await
+        .unwrap();
+    /* The next answer is the one of the call that follows */
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert_eq!(answer[1], "2");
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn oversized_frame_closes_the_connection() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        max_message_size: 1024,
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let oversized = format!(
+        r#"[2,"1","DataTransfer",{{"data":"{}"}}]"#,
+        "x".repeat(4096)
+    );
+    client.send(WsMessage::Text(oversized)).await.unwrap();
+    let closed = loop {
+        match client.next().await {
+            Some(Ok(WsMessage::Close(frame))) => break frame,
+            Some(Ok(_)) => continue,
+            _ => break None,
+        }
+    };
+    assert_eq!(u16::from(closed.unwrap().code), 1009);
+    handle.shutdown().await;
+}
+
+fn data_transfer(id: &str, data_len: usize) -> String {
+    format!(
+        r#"[2,"{}","DataTransfer",{{"vendorId":"dummy","data":"{}"}}]"#,
+        id,
+        "x".repeat(data_len)
+    )
+}
+
+#[tokio::test]
+async fn frames_up_to_the_default_limit_are_answered() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let overhead = data_transfer("1", 0).len();
+    let frame = data_transfer("1", 1024 * 1024 - overhead);
+    assert_eq!(frame.len(), 1024 * 1024);
+    client.send(WsMessage::Text(frame)).await.unwrap();
+    let answer = receive(&mut client).await.unwrap();
+    assert_eq!(answer[0], 3);
+    assert_eq!(answer[1], "1");
+
+    client
+        .send(WsMessage::Text(data_transfer("2", 1024 * 1024)))
+        .await
+        .unwrap();
+    let closed = close_frame(&mut client).await.unwrap();
+    assert_eq!(u16::from(closed.unwrap().code), 1009);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn close_frame_is_answered() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+
+    client.close(None).await.unwrap();
+    /* The echo of the close frame ends the stream cleanly */
+    let echo = close_frame(&mut client).await;
+    assert!(echo.is_some());
+    assert!(client.next().await.is_none());
+    assert!(eventually(|| handle.list_connections().is_empty()).await);
+    assert!(!cs.lock().unwrap().is_connected("cp1"));
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn binary_frame_closes_the_connection() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    client
+        .send(WsMessage::Binary(b"[2,\"1\",\"Heartbeat\",{}]".to_vec()))
+        .await
+        .unwrap();
+    let frame = close_frame(&mut client).await.unwrap().unwrap();
+    assert_eq!(u16::from(frame.code), 1003);
+    assert_eq!(frame.reason, "binary frames are not supported");
+    assert!(eventually(|| handle.list_connections().is_empty()).await);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn ping_is_answered_with_a_pong() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    client.send(WsMessage::Ping(b"hi".to_vec())).await.unwrap();
+    let pong = tokio::time::timeout(ANSWER_TIMEOUT, async {
+        loop {
+            if let Some(Ok(WsMessage::Pong(data))) = client.next().await {
+                return data;
+            }
+        }
+    })
+    .await
+    .expect("no pong within the timeout");
+    assert_eq!(pong, b"hi");
+
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn calls_above_the_rate_limit_are_refused() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        rate_limit: RateLimit::parse("1/3"),
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    for id in 0..5 {
+        send(
+            &mut client,
+            array![2, id.to_string(), "Heartbeat", object! {}],
+        )
+        .await;
+    }
+    let mut answers = Vec::new();
+    for _ in 0..5 {
+        answers.push(receive(&mut client).await.unwrap());
+    }
+    let refused: Vec<_> = answers.iter().filter(|a| a[0] == 4).collect();
+    assert_eq!(answers.iter().filter(|a| a[0] == 3).count(), 3);
+    assert_eq!(refused.len(), 2);
+    for answer in refused {
+        assert_eq!(answer[2], "GenericError");
+        assert_eq!(answer[3], "rate limit exceeded");
+    }
+
+    /* The connection is kept and calls are answered once tokens refill */
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let answer = call(&mut client, "5", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
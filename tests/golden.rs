@@ -0,0 +1,41 @@
+use dummy_central_system::golden;
+
+fn golden_dir() -> String {
+    format!("{}/fixtures/golden", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn answers_match_the_golden_files() {
+    let mismatches = golden::check(&golden_dir(), false).unwrap();
+    let report: Vec<String> = mismatches.iter().map(|m| m.to_string()).collect();
+    assert!(
+        report.is_empty(),
+        "answers changed, run `cargo run -- golden --update` if intended:\n{}",
+        report.join("\n")
+    );
+}
+
+#[test]
+fn changed_answer_is_reported_then_updated() {
+    let directory = std::env::temp_dir().join(format!("dummy-cs-golden-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let file = directory.join("Heartbeat.json");
+    std::fs::write(
+        &file,
+        r#"[{"request": [2, "1", "Heartbeat", {}], "response": [[3, "1", {}]]}]"#,
+    )
+    .unwrap();
+    let directory = directory.to_string_lossy().to_string();
+
+    let mismatches = golden::check(&directory, false).unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].step, 1);
+    assert_eq!(
+        mismatches[0].actual.dump(),
+        r#"[[3,"1",{"currentTime":"2024-01-01T00:00:00.000Z"}]]"#
+    );
+
+    assert!(golden::check(&directory, true).unwrap().is_empty());
+    assert!(golden::check(&directory, false).unwrap().is_empty());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
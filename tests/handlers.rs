@@ -0,0 +1,115 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::ocpp::{Command, Message, MessageType};
+
+#[tokio::test]
+async fn registered_heartbeat_handler_overrides_the_built_in_one() {
+    let cs = central_system();
+    cs.lock().unwrap().register_handler(
+        Command::Heartbeat,
+        Box::new(|request| {
+            Ok(vec![Message::new(
+                MessageType::CallResult,
+                request.id.clone(),
+                None,
+                Some(object! { currentTime : "2000-01-01T00:00:00Z" }),
+            )])
+        }),
+    );
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(
+        answer,
+        array![3, "1", object! { currentTime : "2000-01-01T00:00:00Z" }]
+    );
+
+    assert!(cs.lock().unwrap().unregister_handler(&Command::Heartbeat));
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_ne!(answer[2]["currentTime"], "2000-01-01T00:00:00Z");
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn default_handler_answers_unknown_actions() {
+    let cs = central_system();
+    cs.lock()
+        .unwrap()
+        .set_default_handler(Some(Box::new(|request| {
+            Ok(vec![Message::new(
+                MessageType::CallResult,
+                request.id.clone(),
+                None,
+                Some(object! { handled : true }),
+            )])
+        })));
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let answer = call(&mut client, "1", "Custom", object! {}).await;
+    assert_eq!(answer, array![3, "1", object! { handled : true }]);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn handler_answer_that_cant_be_sent_is_an_internal_error() {
+    let cs = central_system();
+    cs.lock().unwrap().register_handler(
+        Command::Heartbeat,
+        Box::new(|request| {
+            Ok(vec![Message::new(
+                MessageType::CallError,
+                request.id.clone(),
+                None,
+                None,
+            )])
+        }),
+    );
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 4);
+    assert_eq!(answer[2], "InternalError");
+
+    let answer = call(&mut client, "2", "Authorize", object! { idTag : "TAG1" }).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn panicking_handler_answers_an_internal_error() {
+    let cs = central_system();
+    cs.lock().unwrap().register_handler(
+        Command::DataTransfer,
+        Box::new(|request| {
+            let data = request.payload.as_ref().unwrap()["data"].as_str().unwrap();
+            panic!("can't handle {}", data)
+        }),
+    );
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+    let mut other = connect(&handle, "cp2").await;
+
+    let transfer = object! { vendorId : "dummy", data : "boom" };
+    let answer = call(&mut client, "1", "DataTransfer", transfer).await;
+    assert_eq!(
+        answer,
+        array![4, "1", "InternalError", "internal error", object! {}]
+    );
+
+    /* The station stays connected and the central system usable */
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    let answer = call(&mut other, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert_eq!(handle.list_connections(), ["cp1", "cp2"]);
+    assert!(!cs.is_poisoned());
+    assert!(cs.lock().unwrap().is_connected("cp1"));
+    handle.shutdown().await;
+}
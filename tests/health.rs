@@ -0,0 +1,43 @@
+#![cfg(feature = "health")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::health::{self, Readiness};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+fn status(port: u16, path: &str) -> String {
+    let response = http_get(port, path);
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn readyz_flips_once_the_server_is_ready() {
+    let port = free_port();
+    let readiness = Arc::new(Readiness::default());
+    health::serve(&format!("127.0.0.1:{}", port), Arc::clone(&readiness)).unwrap();
+
+    assert!(status(port, "/healthz").contains("200"));
+    assert!(status(port, "/readyz").contains("503"));
+
+    /* The CA alone doesn't make the central system ready */
+    let cs = central_system();
+    readiness.ca.store(true, Ordering::Relaxed);
+    assert!(status(port, "/readyz").contains("503"));
+
+    let handle = start(&cs).await;
+    readiness.listening.store(true, Ordering::Relaxed);
+    let response = http_get(port, "/readyz");
+    assert!(response.lines().next().unwrap().contains("200"));
+    assert!(response.ends_with(r#"{"ca":true,"listening":true}"#));
+    assert!(status(port, "/healthz").contains("200"));
+    assert!(status(port, "/other").contains("404"));
+
+    handle.shutdown().await;
+    readiness.listening.store(false, Ordering::Relaxed);
+    assert!(status(port, "/readyz").contains("503"));
+}
@@ -0,0 +1,329 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::error::CsError;
+use dummy_central_system::ocpp::ProtocolVersion;
+use dummy_central_system::{ConnectionSettings, Server, ServerHandle};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn shutdown_sends_a_close_frame() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let address = handle.address();
+    let mut client = connect(&handle, "cp1").await;
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+
+    let shutdown = tokio::spawn(handle.shutdown());
+    assert!(close_frame(&mut client).await.is_some());
+    tokio::time::timeout(ANSWER_TIMEOUT, shutdown)
+        .await
+        .expect("shutdown didn't complete")
+        .unwrap();
+
+    /* Nothing listens anymore */
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+fn keepalive() -> ConnectionSettings {
+    ConnectionSettings {
+        ping_interval: Duration::from_millis(100),
+        pong_timeout: Duration::from_millis(200),
+        ..ConnectionSettings::default()
+    }
+}
+
+#[tokio::test]
+async fn connection_without_pong_is_dropped() {
+    let cs = central_system();
+    let handle = start_with(&cs, keepalive()).await;
+    /* Pongs are only sent while the client is read, this one never is */
+    let _silent = connect(&handle, "cp1").await;
+    /* Listed once the central system knows the station */
+    assert!(eventually(|| handle.list_connections() == ["cp1"]).await);
+
+    assert!(eventually(|| handle.list_connections().is_empty()).await);
+    assert!(!cs.lock().unwrap().is_connected("cp1"));
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn connection_answering_pings_is_kept() {
+    let cs = central_system();
+    let handle = start_with(&cs, keepalive()).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    /* Reading the answers answers the pings in between */
+    for id in 0..10 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let answer = call(&mut client, &id.to_string(), "Heartbeat", object! {}).await;
+        assert_eq!(answer[0], 3);
+    }
+    assert_eq!(handle.list_connections(), vec!["cp1".to_string()]);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn connections_above_the_limit_are_refused() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        max_connections: 3,
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut clients = Vec::new();
+    for i in 0..3 {
+        clients.push(connect(&handle, &format!("cp{}", i)).await);
+    }
+    assert_eq!(
+        handshake(handle.address(), "cp3", ProtocolVersion::Ocpp16)
+            .await
+            .err(),
+        Some(503)
+    );
+
+    /* A closed connection frees its slot once its session ends */
+    let mut closed = clients.pop().unwrap();
+    closed.close(None).await.unwrap();
+    let started = Instant::now();
+    let mut client = loop {
+        match handshake(handle.address(), "cp3", ProtocolVersion::Ocpp16).await {
+            Ok(client) => break client,
+            Err(503) if started.elapsed() < ANSWER_TIMEOUT => {
+                tokio::time::sleep(Duration::from_millis(20)).await
+            }
+            Err(status) => panic!("refused with {}", status),
+        }
+    };
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn disconnect_closes_one_station_only() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut first = connect(&handle, "cp1").await;
+    let mut second = connect(&handle, "cp2").await;
+    call(&mut first, "1", "Heartbeat", object! {}).await;
+    call(&mut second, "1", "Heartbeat", object! {}).await;
+    assert_eq!(handle.list_connections(), ["cp1", "cp2"]);
+
+    let (disconnected, frame) = tokio::join!(handle.disconnect("cp1"), close_frame(&mut first));
+    disconnected.unwrap();
+    let frame = frame.unwrap().unwrap();
+    assert_eq!(u16::from(frame.code), 1000);
+    assert_eq!(frame.reason, "disconnected by the central system");
+    assert_eq!(handle.list_connections(), ["cp2"]);
+    assert!(eventually(|| !cs.lock().unwrap().is_connected("cp1")).await);
+
+    /* The other station goes on */
+    let answer = call(&mut second, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert!(cs.lock().unwrap().is_connected("cp2"));
+
+    assert!(matches!(
+        handle.disconnect("cp1").await,
+        Err(dummy_central_system::error::CsError::UnknownChargePoint(_))
+    ));
+    handle.shutdown().await;
+}
+
+fn idle_server(cs: &Arc<Mutex<CentralSystem>>, idle: Duration) -> Server<CentralSystem> {
+    Server::new(Arc::clone(cs))
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .idle_timeout(Some(idle))
+}
+
+#[tokio::test]
+async fn idle_server_stops_itself() {
+    let cs = central_system();
+    let handle = idle_server(&cs, Duration::from_millis(300))
+        .start()
+        .await
+        .unwrap();
+    let address = handle.address();
+    let started = Instant::now();
+    tokio::time::timeout(ANSWER_TIMEOUT, handle.wait())
+        .await
+        .expect("the idle server didn't stop");
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn connected_station_keeps_the_server_up() {
+    let cs = central_system();
+    let handle = idle_server(&cs, Duration::from_millis(300))
+        .start()
+        .await
+        .unwrap();
+    let address = handle.address();
+    let mut client = connect(&handle, "cp1").await;
+    tokio::time::sleep(Duration::from_millis(900)).await;
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+
+    /* The idle window starts over once the station is gone */
+    drop(client);
+    tokio::time::timeout(ANSWER_TIMEOUT, handle.wait())
+        .await
+        .expect("the idle server didn't stop");
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+/* A server recording its connect and disconnect hooks as "+cp1", "-cp1" */
+async fn hooked_server(
+    cs: &Arc<Mutex<CentralSystem>>,
+    settings: ConnectionSettings,
+) -> (ServerHandle, Arc<Mutex<Vec<String>>>) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (connects, disconnects) = (Arc::clone(&calls), Arc::clone(&calls));
+    let handle = Server::new(Arc::clone(cs))
+        .listen("127.0.0.1:0", settings)
+        .on_connect(move |cp_id| connects.lock().unwrap().push(format!("+{}", cp_id)))
+        .on_disconnect(move |cp_id| disconnects.lock().unwrap().push(format!("-{}", cp_id)))
+        .start()
+        .await
+        .unwrap();
+    (handle, calls)
+}
+
+#[tokio::test]
+async fn hooks_fire_once_per_connection() {
+    let cs = central_system();
+    let (handle, calls) = hooked_server(&cs, ConnectionSettings::default()).await;
+    let recorded = || calls.lock().unwrap().clone();
+
+    /* Closed by the charge point */
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(recorded(), ["+cp1"]);
+    client.close(None).await.unwrap();
+    assert!(eventually(|| recorded().len() == 2).await);
+    assert_eq!(recorded(), ["+cp1", "-cp1"]);
+
+    /* Dropped without a close frame */
+    let client = connect(&handle, "cp2").await;
+    drop(client);
+    assert!(eventually(|| recorded().len() == 4).await);
+
+    /* Closed by the central system */
+    let mut client = connect(&handle, "cp3").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+    let (disconnected, _) = tokio::join!(handle.disconnect("cp3"), close_frame(&mut client));
+    disconnected.unwrap();
+    assert!(eventually(|| recorded().len() == 6).await);
+
+    /* Still open at shutdown */
+    let _open: Vec<Client> = futures_util::future::join_all(
+        ["cp4", "cp5", "cp6"]
+            .iter()
+            .map(|cp_id| connect(&handle, cp_id)),
+    )
+    .await;
+
+    handle.shutdown().await;
+    assert!(eventually(|| recorded().len() == 12).await);
+    let mut calls = recorded();
+    assert_eq!(calls[..6], ["+cp1", "-cp1", "+cp2", "-cp2", "+cp3", "-cp3"]);
+    calls[6..].sort();
+    assert_eq!(calls[6..], ["+cp4", "+cp5", "+cp6", "-cp4", "-cp5", "-cp6"]);
+}
+
+#[tokio::test]
+async fn refused_handshake_fires_no_hook() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        max_connections: 1,
+        ..ConnectionSettings::default()
+    };
+    let (handle, calls) = hooked_server(&cs, settings).await;
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(
+        handshake(handle.address(), "cp2", ProtocolVersion::Ocpp16)
+            .await
+            .err(),
+        Some(503)
+    );
+    assert_eq!(*calls.lock().unwrap(), ["+cp1"]);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn flooded_outbound_queue_sheds_calls_and_keeps_the_socket() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        outbound_queue: 4,
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+
+    /* Faster than the connection takes them */
+    let sent: Vec<_> = {
+        let mut cs = cs.lock().unwrap();
+        (0..20).map(|_| cs.reset("cp1", false, None)).collect()
+    };
+    let queued: Vec<String> = sent
+        .iter()
+        .filter_map(|r| r.as_ref().ok().cloned())
+        .collect();
+    assert_eq!(queued.len(), 4);
+    assert!(sent[4..]
+        .iter()
+        .all(|r| matches!(r, Err(CsError::QueueFull(_)))));
+
+    /* The queued calls arrive in order and the station stays connected */
+    for id in queued.iter() {
+        let frame = receive(&mut client).await.unwrap();
+        assert_eq!(frame[1], id.as_str());
+        assert_eq!(frame[2], "Reset");
+    }
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert_eq!(handle.list_connections(), ["cp1"]);
+    assert!(eventually(|| cs.lock().unwrap().reset("cp1", false, None).is_ok()).await);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn answers_follow_the_calls_queued_before_them() {
+    let cs = central_system();
+    let settings = ConnectionSettings {
+        outbound_queue: 4,
+        ..ConnectionSettings::default()
+    };
+    let handle = start_with(&cs, settings).await;
+    let mut client = connect(&handle, "cp1").await;
+    call(&mut client, "1", "Heartbeat", object! {}).await;
+
+    /* A full queue, then a call of the station */
+    let queued: Vec<String> = {
+        let mut cs = cs.lock().unwrap();
+        (0..4)
+            .map(|_| cs.reset("cp1", false, None).unwrap())
+            .collect()
+    };
+    send(&mut client, array![2, "2", "Heartbeat", object! {}]).await;
+
+    let mut frames = Vec::new();
+    for _ in 0..5 {
+        frames.push(receive(&mut client).await.unwrap());
+    }
+    let ids: Vec<&str> = frames.iter().map(|f| f[1].as_str().unwrap()).collect();
+    let mut expected: Vec<&str> = queued.iter().map(String::as_str).collect();
+    expected.push("2");
+    assert_eq!(ids, expected);
+    assert_eq!(frames[4][0], 3);
+    handle.shutdown().await;
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "metrics")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::metrics;
+
+/* The counters are process-wide, the file holds this single test */
+#[tokio::test]
+async fn metrics_count_the_driven_messages() {
+    let port = free_port();
+    metrics::serve(&format!("127.0.0.1:{}", port)).unwrap();
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+
+    call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "m1" },
+    )
+    .await;
+    call(&mut client, "2", "Heartbeat", object! {}).await;
+    call(&mut client, "3", "Heartbeat", object! {}).await;
+    let answer = call(
+        &mut client,
+        "4",
+        "StartTransaction",
+        object! {
+            connectorId : 1,
+            idTag : "TAG1",
+            meterStart : 0,
+            timestamp : "2024-01-01T00:00:00Z",
+        },
+    )
+    .await;
+    assert_eq!(answer[0], 3);
+    let answer = call(&mut client, "5", "Reset", object! { type : "Hard" }).await;
+    assert_eq!(answer[0], 4);
+
+    let response = tokio::task::spawn_blocking(move || http_get(port, "/metrics"))
+        .await
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200") || response.starts_with("HTTP/1.0 200"));
+    assert!(response.contains("text/plain; version=0.0.4"));
+    for line in [
+        "ocpp_messages_received_total{action=\"BootNotification\"} 1",
+        "ocpp_messages_received_total{action=\"Heartbeat\"} 2",
+        "ocpp_messages_received_total{action=\"StartTransaction\"} 1",
+        "ocpp_call_errors_total 1",
+        "ocpp_active_connections 1",
+        "ocpp_transactions_in_flight 1",
+        "ocpp_certificates_signed_total 0",
+    ] {
+        assert!(response.contains(line), "{} in {}", line, response);
+    }
+
+    let response = tokio::task::spawn_blocking(move || http_get(port, "/other"))
+        .await
+        .unwrap();
+    assert!(response.contains(" 404 "));
+}
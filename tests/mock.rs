@@ -0,0 +1,127 @@
+#![cfg(feature = "test-util")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::error::CsError;
+use dummy_central_system::mock::{MockCentralSystem, MockResponse};
+use dummy_central_system::ocpp::{Command, Message, MessageType, ProtocolVersion};
+use dummy_central_system::{ConnectionSettings, Server};
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn mock_answers_as_programmed_and_records_the_calls() {
+    let mut mock = MockCentralSystem::new();
+    mock.set_response(
+        Command::BootNotification,
+        MockResponse::Messages(vec![Message::new(
+            MessageType::CallResult,
+            String::new(),
+            None,
+            Some(object! {
+                status : "Pending",
+                currentTime : "2024-01-01T00:00:00.000Z",
+                interval : 5,
+            }),
+        )]),
+    );
+    mock.set_response(
+        Command::Authorize,
+        MockResponse::Error(CsError::InvalidArgument("idTag".to_string())),
+    );
+    mock.set_response(
+        Command::DataTransfer,
+        MockResponse::Handler(Box::new(|request: &Message| {
+            let data = request.payload.as_ref().unwrap()["data"].clone();
+            Ok(vec![Message::new(
+                MessageType::CallResult,
+                request.id.clone(),
+                None,
+                Some(object! { status : "Accepted", data : data }),
+            )])
+        })),
+    );
+    let mock = Arc::new(Mutex::new(mock));
+    let handle = Server::new(Arc::clone(&mock))
+        .listen("127.0.0.1:0", ConnectionSettings::default())
+        .start()
+        .await
+        .unwrap();
+    let mut client = connect(&handle, "cp1").await;
+
+    let boot = object! { chargePointVendor : "dummy", chargePointModel : "m1" };
+    let answer = call(&mut client, "b1", "BootNotification", boot).await;
+    assert_eq!(
+        answer,
+        array![
+            3,
+            "b1",
+            object! {
+                status : "Pending",
+                currentTime : "2024-01-01T00:00:00.000Z",
+                interval : 5,
+            }
+        ]
+    );
+    let answer = call(&mut client, "a1", "Authorize", object! { idTag : "TAG1" }).await;
+    assert_eq!(answer[0], 4);
+    let transfer = object! { vendorId : "dummy", data : "ping" };
+    let answer = call(&mut client, "d1", "DataTransfer", transfer).await;
+    assert_eq!(answer[2]["data"], "ping");
+    /* Not programmed: an empty CallResult */
+    let answer = call(&mut client, "h1", "Heartbeat", object! {}).await;
+    assert_eq!(answer, array![3, "h1", object! {}]);
+    let answer = call(&mut client, "h2", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+
+    /* The answers to the calls the mock sends are recorded too */
+    let reset = Message::new(
+        MessageType::Call,
+        "r1".to_string(),
+        Some(Command::Reset),
+        Some(object! { type : "Soft" }),
+    );
+    mock.lock().unwrap().send("cp1", reset).unwrap();
+    let sent = receive(&mut client).await.unwrap();
+    assert_eq!(sent, array![2, "r1", "Reset", object! { type : "Soft" }]);
+    send(
+        &mut client,
+        array![3, "r1", object! { status : "Accepted" }],
+    )
+    .await;
+    assert!(eventually(|| mock.lock().unwrap().received("cp1").len() == 6).await);
+
+    {
+        let mock = mock.lock().unwrap();
+        assert!(mock.is_connected("cp1"));
+        assert_eq!(mock.call_count(&Command::BootNotification), 1);
+        assert_eq!(mock.call_count(&Command::Heartbeat), 2);
+        assert_eq!(mock.call_count(&Command::Reset), 0);
+        let ids: Vec<&str> = mock.received("cp1").iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["b1", "a1", "d1", "h1", "h2", "r1"]);
+        assert_eq!(
+            mock.received("cp1")[0].protocol,
+            Some(ProtocolVersion::Ocpp16)
+        );
+        assert!(mock.received("cp2").is_empty());
+    }
+
+    drop(client);
+    assert!(eventually(|| !mock.lock().unwrap().is_connected("cp1")).await);
+    assert!(matches!(
+        mock.lock().unwrap().send(
+            "cp1",
+            Message::new(
+                MessageType::Call,
+                "r2".to_string(),
+                Some(Command::Reset),
+                None
+            )
+        ),
+        Err(CsError::Disconnected(_))
+    ));
+    handle.shutdown().await;
+}
@@ -0,0 +1,188 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::ocpp::{self, Command, Message, MessageType, ProtocolVersion};
+use dummy_central_system::settings::TlsSettings;
+use dummy_central_system::tls;
+use dummy_central_system::{ConnectionSettings, ServerHandle};
+use futures_util::{SinkExt, StreamExt};
+use openssl::asn1::Asn1Time;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::{X509NameBuilder, X509Req, X509};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_openssl::SslStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{client_async, WebSocketStream};
+
+fn key() -> PKey<Private> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+fn self_signed(common_name: &str, key: &PKey<Private>) -> X509 {
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", common_name).unwrap();
+    let name = name.build();
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    builder.sign(key, MessageDigest::sha256()).unwrap();
+    builder.build()
+}
+
+/* Signed by the CA of the central system through SignCertificate */
+fn issued(cs: &Arc<Mutex<CentralSystem>>, common_name: &str, key: &PKey<Private>) -> X509 {
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", common_name).unwrap();
+    let mut builder = X509Req::builder().unwrap();
+    builder.set_subject_name(&name.build()).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder.sign(key, MessageDigest::sha256()).unwrap();
+    let csr = String::from_utf8(builder.build().to_pem().unwrap()).unwrap();
+
+    let request = Message::new(
+        MessageType::Call,
+        "1".to_string(),
+        Some(Command::SignCertificate),
+        Some(object! { csr : csr, typeOfCertificate : "ChargingStationCertificate" }),
+    );
+    let answer =
+        ocpp::CentralSystem::make_response(&mut *cs.lock().unwrap(), common_name, request).unwrap();
+    let chain = &answer[1].payload.as_ref().unwrap()["cert"];
+    X509::from_pem(chain[0].as_str().unwrap().as_bytes()).unwrap()
+}
+
+/* wss requiring client certificates of the CA, the server certificate is
+ * self-signed */
+async fn start_mtls(cs: &Arc<Mutex<CentralSystem>>) -> ServerHandle {
+    let directory =
+        std::env::temp_dir().join(format!("dummy-cs-tls-{}", uuid::Uuid::new_v4().to_simple()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let key = key();
+    let certificate = directory.join("server.pem");
+    let private_key = directory.join("server-key.pem");
+    std::fs::write(
+        &certificate,
+        self_signed("localhost", &key).to_pem().unwrap(),
+    )
+    .unwrap();
+    std::fs::write(&private_key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+    let settings = TlsSettings {
+        certificate: certificate.to_string_lossy().to_string(),
+        key: private_key.to_string_lossy().to_string(),
+        client_certificates: true,
+    };
+    let roots = cs.lock().unwrap().root_certificate_pem().unwrap();
+    let acceptor = tls::acceptor(&settings, &roots).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    start_with(
+        cs,
+        ConnectionSettings {
+            tls: Some(Arc::new(acceptor)),
+            ..ConnectionSettings::default()
+        },
+    )
+    .await
+}
+
+/* A Heartbeat over wss with the client certificate, None when the
+ * connection is refused at any step */
+async fn heartbeat(
+    handle: &ServerHandle,
+    cp_id: &str,
+    certificate: &X509,
+    key: &PKey<Private>,
+) -> Option<json::JsonValue> {
+    let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    connector.set_certificate(certificate).unwrap();
+    connector.set_private_key(key).unwrap();
+    let ssl = connector
+        .build()
+        .configure()
+        .unwrap()
+        .into_ssl("localhost")
+        .unwrap();
+
+    let tcp = TcpStream::connect(handle.address()).await.unwrap();
+    let mut stream = SslStream::new(ssl, tcp).unwrap();
+    Pin::new(&mut stream).connect().await.ok()?;
+
+    let url = format!("wss://{}/{}", handle.address(), cp_id);
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        ProtocolVersion::Ocpp16.subprotocol().parse().unwrap(),
+    );
+    let (mut client, _): (WebSocketStream<_>, _) = client_async(request, stream).await.ok()?;
+    let frame = array![2, "1", "Heartbeat", object! {}];
+    client.send(WsMessage::Text(frame.dump())).await.ok()?;
+    timeout(ANSWER_TIMEOUT, async {
+        while let Some(Ok(frame)) = client.next().await {
+            if let WsMessage::Text(text) = frame {
+                return Some(json::parse(&text).unwrap());
+            }
+        }
+        None
+    })
+    .await
+    .expect("no answer within the timeout")
+}
+
+#[tokio::test]
+async fn certificate_issued_by_the_ca_is_accepted() {
+    let cs = central_system();
+    let handle = start_mtls(&cs).await;
+    let key = key();
+    let certificate = issued(&cs, "cp1", &key);
+    let answer = heartbeat(&handle, "cp1", &certificate, &key).await.unwrap();
+    assert_eq!(answer[0], 3);
+    assert_eq!(handle.list_connections(), ["cp1"]);
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn self_signed_certificate_is_refused() {
+    let cs = central_system();
+    let handle = start_mtls(&cs).await;
+    let key = key();
+    let certificate = self_signed("cp1", &key);
+    assert!(heartbeat(&handle, "cp1", &certificate, &key)
+        .await
+        .is_none());
+    assert!(handle.list_connections().is_empty());
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn certificate_of_another_charge_point_is_refused() {
+    let cs = central_system();
+    let handle = start_mtls(&cs).await;
+    let key = key();
+    let certificate = issued(&cs, "cp2", &key);
+    assert!(heartbeat(&handle, "cp1", &certificate, &key)
+        .await
+        .is_none());
+    handle.shutdown().await;
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "ocsp")]
+
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::ocpp::{self, Command, Message, MessageType};
+use dummy_central_system::ocsp;
+use dummy_central_system::x509::{
+    DefaultCertificationAuthority, DefaultCertificationAuthoritySettings,
+};
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{OcspCertId, OcspCertStatus, OcspRequest, OcspResponse, OcspResponseStatus};
+use openssl::x509::X509;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+struct Workdir(String);
+
+impl Drop for Workdir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ca_settings() -> (DefaultCertificationAuthoritySettings, Workdir) {
+    let directory = std::env::temp_dir()
+        .join(format!(
+            "dummy-cs-ocsp-{}",
+            uuid::Uuid::new_v4().to_simple()
+        ))
+        .to_string_lossy()
+        .to_string();
+    let settings = DefaultCertificationAuthoritySettings {
+        directory: directory.clone(),
+        ..DefaultCertificationAuthoritySettings::default()
+    };
+    (settings, Workdir(directory))
+}
+
+fn sign(cs: &Arc<Mutex<CentralSystem>>) -> X509 {
+    let request = Message::new(
+        MessageType::Call,
+        "1".to_string(),
+        Some(Command::SignCertificate),
+        Some(object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargingStationCertificate" }),
+    );
+    let answer =
+        ocpp::CentralSystem::make_response(&mut *cs.lock().unwrap(), "cp1", request).unwrap();
+    let chain = &answer[1].payload.as_ref().unwrap()["cert"];
+    X509::from_pem(chain[0].as_str().unwrap().as_bytes()).unwrap()
+}
+
+/* The status line and body of a POST */
+fn http_post(port: u16, body: &[u8]) -> (String, Vec<u8>) {
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "POST / HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/ocsp-request\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..end]).to_string();
+    let status = head.lines().next().unwrap().to_string();
+    (status, response[end + 4..].to_vec())
+}
+
+fn status(port: u16, leaf: &X509, root: &X509) -> OcspCertStatus {
+    let id = || OcspCertId::from_cert(MessageDigest::sha1(), leaf, root).unwrap();
+    let mut request = OcspRequest::new().unwrap();
+    request.add_id(id()).unwrap();
+    let (status, body) = http_post(port, &request.to_der().unwrap());
+    assert!(status.contains(" 200 "), "{}", status);
+    let response = OcspResponse::from_der(&body).unwrap();
+    assert_eq!(response.status(), OcspResponseStatus::SUCCESSFUL);
+    let basic = response.basic().unwrap();
+    let status = basic.find_status(&id()).unwrap();
+    status.status
+}
+
+#[test]
+fn issued_certificate_is_good_then_revoked() {
+    let (settings, _workdir) = ca_settings();
+    let cs = Arc::new(Mutex::new(
+        CentralSystem::with_ca_settings(settings.clone()).unwrap(),
+    ));
+    let port = free_port();
+    ocsp::serve(&format!("127.0.0.1:{}", port), Arc::clone(&cs)).unwrap();
+
+    let leaf = sign(&cs);
+    let root = X509::from_pem(&cs.lock().unwrap().root_certificate_pem().unwrap()).unwrap();
+    assert_eq!(status(port, &leaf, &root), OcspCertStatus::GOOD);
+
+    /* Revoked by another process sharing the CA directory, like the CLI */
+    let mut ca = DefaultCertificationAuthority::new(DefaultCertificationAuthoritySettings {
+        new: false,
+        ..settings
+    });
+    ca.init().unwrap();
+    ca.revoke(1).unwrap();
+    assert_eq!(status(port, &leaf, &root), OcspCertStatus::REVOKED);
+
+    /* Only POSTed requests are answered */
+    assert!(http_get(port, "/").contains(" 405 "));
+    let (_, body) = http_post(port, b"not an OCSP request");
+    let response = OcspResponse::from_der(&body).unwrap();
+    assert_ne!(response.status(), OcspResponseStatus::SUCCESSFUL);
+}
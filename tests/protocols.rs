@@ -0,0 +1,224 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::ocpp::{ProtocolVersion, RegistrationStatus};
+use dummy_central_system::{ConnectionSettings, Server};
+use std::sync::Arc;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+#[tokio::test]
+async fn ocpp201_boot_notification_is_answered_in_201_shape() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect_with(&handle, "cp1", ProtocolVersion::Ocpp201).await;
+
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! {
+            chargingStation : object! {
+                vendorName : "dummy",
+                model : "station",
+                serialNumber : "SN-1",
+                firmwareVersion : "2.0.0",
+            },
+            reason : "PowerUp",
+        },
+    )
+    .await;
+    assert_eq!(answer[0], 3);
+    assert_eq!(answer[1], "1");
+    let payload = &answer[2];
+    assert_eq!(payload["status"], "Accepted");
+    assert!(payload["interval"].as_u32().is_some());
+    assert!(payload["currentTime"].is_string());
+    assert!(payload["statusInfo"].is_null());
+
+    let cs = cs.lock().unwrap();
+    let cp = cs.charge_point("cp1").unwrap();
+    assert_eq!(cp.protocol, ProtocolVersion::Ocpp201);
+    assert_eq!(cp.vendor.as_deref(), Some("dummy"));
+    assert_eq!(cp.model.as_deref(), Some("station"));
+}
+
+#[tokio::test]
+async fn ocpp201_pending_boot_carries_status_info() {
+    let cs = central_system();
+    cs.lock()
+        .unwrap()
+        .set_registration_status(RegistrationStatus::Pending);
+    let handle = start(&cs).await;
+    let mut client = connect_with(&handle, "cp1", ProtocolVersion::Ocpp201).await;
+
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! {
+            chargingStation : object! { vendorName : "dummy", model : "station" },
+            reason : "PowerUp",
+        },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Pending");
+    assert_eq!(answer[2]["statusInfo"]["reasonCode"], "Pending");
+}
+
+#[tokio::test]
+async fn ocpp16_boot_notification_is_unchanged() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect_with(&handle, "cp1", ProtocolVersion::Ocpp16).await;
+
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "cp" },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Accepted");
+    assert!(answer[2]["interval"].as_u32().is_some());
+    assert!(answer[2]["currentTime"].is_string());
+    assert!(answer[2]["statusInfo"].is_null());
+
+    /* A 2.0.1 payload is a violation on a 1.6 connection */
+    let answer = call(
+        &mut client,
+        "2",
+        "BootNotification",
+        object! {
+            chargingStation : object! { vendorName : "dummy", model : "station" },
+            reason : "PowerUp",
+        },
+    )
+    .await;
+    assert_eq!(answer[0], 4);
+}
+
+#[tokio::test]
+async fn ocpp201_authorize_answers_an_id_token_info() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect_with(&handle, "cp1", ProtocolVersion::Ocpp201).await;
+
+    let answer = call(
+        &mut client,
+        "1",
+        "Authorize",
+        object! { idToken : object! { idToken : "TAG1", type : "ISO14443" } },
+    )
+    .await;
+    assert_eq!(answer[2]["idTokenInfo"]["status"], "Accepted");
+    assert!(answer[2]["idTagInfo"].is_null());
+
+    let answer = call(
+        &mut client,
+        "2",
+        "Authorize",
+        object! { idToken : object! { idToken : "TAG1", type : "Unknown" } },
+    )
+    .await;
+    assert_eq!(answer[0], 4);
+    assert_eq!(answer[2], "PropertyConstraintViolation");
+}
+
+#[tokio::test]
+async fn listeners_of_each_version_feed_the_same_central_system() {
+    let cs = central_system();
+    let handle = Server::new(Arc::clone(&cs))
+        .listen(
+            "127.0.0.1:0",
+            ConnectionSettings {
+                protocol: Some(ProtocolVersion::Ocpp16),
+                ..ConnectionSettings::default()
+            },
+        )
+        .listen(
+            "127.0.0.1:0",
+            ConnectionSettings {
+                protocol: Some(ProtocolVersion::Ocpp201),
+                ..ConnectionSettings::default()
+            },
+        )
+        .start()
+        .await
+        .unwrap();
+    let (ocpp16, ocpp201) = (handle.addresses()[0], handle.addresses()[1]);
+
+    let mut client = handshake(ocpp16, "cp1", ProtocolVersion::Ocpp16)
+        .await
+        .unwrap();
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "m1" },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Accepted");
+
+    let mut client = handshake(ocpp201, "cp2", ProtocolVersion::Ocpp201)
+        .await
+        .unwrap();
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! {
+            chargingStation : object! { vendorName : "dummy", model : "m1" },
+            reason : "PowerUp",
+        },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Accepted");
+
+    /* Each listener only speaks its version */
+    let refused = handshake(ocpp201, "cp3", ProtocolVersion::Ocpp16).await;
+    assert_eq!(refused.err(), Some(400));
+    let refused = handshake(ocpp16, "cp3", ProtocolVersion::Ocpp201).await;
+    assert_eq!(refused.err(), Some(400));
+
+    let protocols = {
+        let cs = cs.lock().unwrap();
+        (cs.protocol("cp1"), cs.protocol("cp2"))
+    };
+    assert_eq!(
+        protocols,
+        (ProtocolVersion::Ocpp16, ProtocolVersion::Ocpp201)
+    );
+    let mut connections = handle.list_connections();
+    connections.sort();
+    assert_eq!(connections, ["cp1", "cp2"]);
+    handle.shutdown().await;
+}
+
+/* tungstenite implements no extensions, the offer is declined and the
+ * connection goes on uncompressed */
+#[tokio::test]
+async fn permessage_deflate_offer_is_declined() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let url = format!("ws://{}/cp1", handle.address());
+    let mut request = url.into_client_request().unwrap();
+    let headers = request.headers_mut();
+    headers.insert("Sec-WebSocket-Protocol", "ocpp1.6".parse().unwrap());
+    headers.insert(
+        "Sec-WebSocket-Extensions",
+        "permessage-deflate; client_max_window_bits"
+            .parse()
+            .unwrap(),
+    );
+    let (mut client, response) = connect_async(request).await.unwrap();
+    assert!(response.headers().get("Sec-WebSocket-Extensions").is_none());
+    assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "ocpp1.6");
+
+    let answer = call(&mut client, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    handle.shutdown().await;
+}
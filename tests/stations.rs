@@ -0,0 +1,197 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+use dummy_central_system::clock::Clock;
+use dummy_central_system::cs::CentralSystem;
+use dummy_central_system::error::CsError;
+use dummy_central_system::x509::{
+    Certificate, CertificateSignRequest, CertificateSigningUse, CertificationAuthority,
+    InMemoryCertificationAuthority,
+};
+use dummy_central_system::Settings;
+use std::collections::HashSet;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+const STATIONS: usize = 50;
+
+/* Boots, starts and stops a transaction, returns its id */
+async fn drive(mut client: Client, cp_id: String) -> u32 {
+    let answer = call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : cp_id.as_str() },
+    )
+    .await;
+    assert_eq!(answer[2]["status"], "Accepted");
+    let answer = call(&mut client, "2", "Heartbeat", object! {}).await;
+    assert!(answer[2]["currentTime"].is_string());
+
+    let answer = call(
+        &mut client,
+        "3",
+        "StartTransaction",
+        object! {
+            connectorId : 1,
+            idTag : "TAG1",
+            meterStart : 0,
+            timestamp : "2024-01-01T00:00:00Z",
+        },
+    )
+    .await;
+    let id = answer[2]["transactionId"].as_u32().unwrap();
+    let answer = call(
+        &mut client,
+        "4",
+        "StopTransaction",
+        object! {
+            transactionId : id,
+            meterStop : 1000,
+            timestamp : "2024-01-01T01:00:00Z",
+        },
+    )
+    .await;
+    assert_eq!(answer[0], 3, "{} stopping {}", cp_id, id);
+    id
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_stations_are_served() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+
+    let mut clients = Vec::new();
+    for i in 0..STATIONS {
+        let cp_id = format!("cp{}", i);
+        clients.push((connect(&handle, &cp_id).await, cp_id));
+    }
+    assert!(eventually(|| handle.list_connections().len() == STATIONS).await);
+
+    let stations: Vec<_> = clients
+        .into_iter()
+        .map(|(client, cp_id)| tokio::spawn(drive(client, cp_id)))
+        .collect();
+    let mut ids = HashSet::new();
+    for station in stations {
+        ids.insert(station.await.unwrap());
+    }
+    assert_eq!(ids.len(), STATIONS);
+    handle.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_signings_are_answered() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+
+    let mut signers = Vec::new();
+    for i in 0..8 {
+        let cp_id = format!("cp{}", i);
+        let mut client = connect(&handle, &cp_id).await;
+        signers.push(tokio::spawn(async move {
+            let answer = call(
+                &mut client,
+                "1",
+                "SignCertificate",
+                object! {
+                    csr : csr_pem(&cp_id),
+                    typeOfCertificate : "ChargePointCertificate",
+                },
+            )
+            .await;
+            assert_eq!(answer[2]["status"], "Accepted");
+            /* The certificate follows the answer */
+            let signed = receive(&mut client).await.unwrap();
+            assert_eq!(signed[2], "CertificateSigned");
+            signed[3]["cert"].len()
+        }));
+    }
+    for signer in signers {
+        assert_eq!(signer.await.unwrap(), 2);
+    }
+    handle.shutdown().await;
+}
+
+/* Signs once released, so signing is seen to be in progress meanwhile */
+struct HeldCa {
+    ca: InMemoryCertificationAuthority,
+    signing: Mutex<SyncSender<()>>,
+    release: Mutex<Receiver<()>>,
+}
+
+impl CertificationAuthority for HeldCa {
+    fn sign(&self, csr: CertificateSignRequest) -> Result<Vec<Certificate>, CsError> {
+        self.signing.lock().unwrap().send(()).unwrap();
+        self.release.lock().unwrap().recv().unwrap();
+        self.ca.sign(csr)
+    }
+
+    fn root_certificate_pem(&self) -> Result<Vec<u8>, CsError> {
+        self.ca.root_certificate_pem()
+    }
+
+    fn root_certificate_der(&self) -> Result<Vec<u8>, CsError> {
+        self.ca.root_certificate_der()
+    }
+
+    fn issuer_certificate_der(&self, usage: CertificateSigningUse) -> Result<Vec<u8>, CsError> {
+        self.ca.issuer_certificate_der(usage)
+    }
+
+    fn ocsp_response(&self, request: &[u8]) -> Result<Vec<u8>, CsError> {
+        self.ca.ocsp_response(request)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.ca.set_clock(clock)
+    }
+
+    fn export_bundle(&self, include_invalid: bool) -> Result<String, CsError> {
+        self.ca.export_bundle(include_invalid)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn other_stations_are_served_while_signing() {
+    let (signing, signing_started) = sync_channel(1);
+    let (release, released) = sync_channel(1);
+    let ca = HeldCa {
+        ca: InMemoryCertificationAuthority::new(Settings::default().ca).unwrap(),
+        signing: Mutex::new(signing),
+        release: Mutex::new(released),
+    };
+    let cs = Arc::new(Mutex::new(
+        CentralSystem::with_certification_authority(Box::new(ca)).unwrap(),
+    ));
+    let handle = start(&cs).await;
+    let mut signer = connect(&handle, "cp1").await;
+    let mut other = connect(&handle, "cp2").await;
+
+    let sign = array![
+        2,
+        "1",
+        "SignCertificate",
+        object! { csr : csr_pem("cp1"), typeOfCertificate : "ChargePointCertificate" }
+    ];
+    send(&mut signer, sign).await;
+    tokio::task::spawn_blocking(move || signing_started.recv().unwrap())
+        .await
+        .unwrap();
+
+    /* The central system isn't held by the signing */
+    let answer = call(&mut other, "1", "Heartbeat", object! {}).await;
+    assert_eq!(answer[0], 3);
+    assert!(cs.lock().unwrap().charge_point("cp2").is_some());
+
+    release.send(()).unwrap();
+    let answer = receive(&mut signer).await.unwrap();
+    assert_eq!(answer, array![3, "1", object! { status : "Accepted" }]);
+    let signed = receive(&mut signer).await.unwrap();
+    assert_eq!(signed[2], "CertificateSigned");
+    assert_eq!(signed[3]["cert"].len(), 2);
+    handle.shutdown().await;
+}
@@ -0,0 +1,69 @@
+#[macro_use]
+extern crate json;
+
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn counters_match_the_exchanged_messages() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut client = connect(&handle, "cp1").await;
+    call(
+        &mut client,
+        "1",
+        "BootNotification",
+        object! { chargePointVendor : "dummy", chargePointModel : "m1" },
+    )
+    .await;
+    for id in ["2", "3"] {
+        call(&mut client, id, "Heartbeat", object! {}).await;
+    }
+    /* Answered with a CallError */
+    call(&mut client, "4", "Authorize", object! {}).await;
+
+    let stats = handle.stats();
+    assert!(eventually(|| stats.get("cp1").map_or(0, |s| s.sent.values().sum()) == 4).await);
+    let card = stats.get("cp1").unwrap();
+    assert_eq!(card.received.len(), 3);
+    assert_eq!(card.received["BootNotification"], 1);
+    assert_eq!(card.received["Heartbeat"], 2);
+    assert_eq!(card.received["Authorize"], 1);
+    assert_eq!(card.sent.len(), 2);
+    assert_eq!(card.sent["CallResult"], 3);
+    assert_eq!(card.sent["CallError"], 1);
+    assert!(card.last_seen >= card.connected_at);
+
+    let json = json::JsonValue::from(&card);
+    assert_eq!(json["received"]["Heartbeat"], 2);
+    assert_eq!(json["sent"]["CallError"], 1);
+
+    /* The entry goes with the connection */
+    client.close(None).await.unwrap();
+    assert!(eventually(|| stats.get("cp1").is_none()).await);
+    assert!(stats.all().is_empty());
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn reconnection_starts_over() {
+    let cs = central_system();
+    let handle = start(&cs).await;
+    let mut first = connect(&handle, "cp1").await;
+    call(&mut first, "1", "Heartbeat", object! {}).await;
+
+    let mut second = connect(&handle, "cp1").await;
+    call(&mut second, "1", "Heartbeat", object! {}).await;
+    let stats = handle.stats();
+    assert!(
+        eventually(|| stats.get("cp1").map(|s| s.sent.get("CallResult").copied()) == Some(Some(1)))
+            .await
+    );
+
+    /* The replaced connection going away keeps the entry of the new one */
+    drop(first);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(stats.get("cp1").unwrap().received["Heartbeat"], 1);
+    handle.shutdown().await;
+}